@@ -0,0 +1,285 @@
+//! Electronic data sheets (CiA 306), read for the names and types of a
+//! device's objects. DCF files share the format and load the same way.
+
+use crate::errors::CanOpenError;
+use crate::Result;
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+pub const OBJECT_TYPE_VAR: u8 = 0x07;
+pub const OBJECT_TYPE_ARRAY: u8 = 0x08;
+pub const OBJECT_TYPE_RECORD: u8 = 0x09;
+
+/// An object, or one subindex of an array or record
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EdsObject {
+    pub name: String,
+
+    /// `OBJECT_TYPE_VAR`, `OBJECT_TYPE_ARRAY` or `OBJECT_TYPE_RECORD`
+    pub object_type: u8,
+
+    /// Data type index, e.g. 0x0007 for UNSIGNED32
+    pub data_type: Option<u16>,
+
+    /// `ro`, `wo`, `rw`, `rwr`, `rww` or `const`
+    pub access_type: Option<String>,
+    pub default_value: Option<String>,
+    pub pdo_mapping: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Eds {
+    pub vendor_name: Option<String>,
+    pub product_name: Option<String>,
+
+    /// Objects by index and subindex, `None` for the object itself
+    objects: BTreeMap<(u16, Option<u8>), EdsObject>,
+}
+
+/// Object section being read: its index and subindex, the line it starts
+/// on and its entries by lower case key
+type Section = ((u16, Option<u8>), usize, BTreeMap<String, String>);
+
+/// Integers in EDS files are decimal, or hex with a `0x` prefix
+fn parse_int(s: &str) -> Option<u32> {
+    let s = s.trim();
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// Splits an object section name such as `1018` or `1018sub2`
+fn parse_section(section: &str) -> Option<(u16, Option<u8>)> {
+    let lower = section.to_ascii_lowercase();
+    let mut parts = lower.splitn(2, "sub");
+    let index = u16::from_str_radix(parts.next()?, 16).ok()?;
+    match parts.next() {
+        Some(sub) => Some((index, Some(u8::from_str_radix(sub, 16).ok()?))),
+        None => Some((index, None)),
+    }
+}
+
+impl Eds {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Parses the INI style text of an EDS or DCF file. Sections other
+    /// than `DeviceInfo` and the objects themselves are skipped.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut eds = Eds::default();
+        let mut section = String::new();
+        let mut current: Option<Section> = None;
+
+        for (number, line) in text.lines().enumerate() {
+            let number = number + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                if !line.ends_with(']') {
+                    return Err(CanOpenError::EdsError(
+                        number,
+                        "unterminated section".to_owned(),
+                    ));
+                }
+                if let Some(object) = current.take() {
+                    eds.add_object(object)?;
+                }
+                section = line[1..line.len() - 1].trim().to_owned();
+                current = parse_section(&section).map(|key| (key, number, BTreeMap::new()));
+                continue;
+            }
+
+            let equals = line.find('=').ok_or_else(|| {
+                CanOpenError::EdsError(number, format!("expected <key>=<value>, found '{}'", line))
+            })?;
+            let key = line[..equals].trim().to_ascii_lowercase();
+            let value = line[equals + 1..].trim().to_owned();
+
+            if let Some((_, _, entries)) = current.as_mut() {
+                entries.insert(key, value);
+            } else if section.eq_ignore_ascii_case("DeviceInfo") {
+                match key.as_str() {
+                    "vendorname" => eds.vendor_name = Some(value),
+                    "productname" => eds.product_name = Some(value),
+                    _ => {}
+                }
+            }
+        }
+        if let Some(object) = current.take() {
+            eds.add_object(object)?;
+        }
+        Ok(eds)
+    }
+
+    fn add_object(&mut self, (key, line, entries): Section) -> Result<()> {
+        let name = entries.get("parametername").ok_or_else(|| {
+            CanOpenError::EdsError(line, "object without a ParameterName".to_owned())
+        })?;
+        let int = |field: &str| -> Result<Option<u32>> {
+            match entries.get(field) {
+                Some(value) if !value.is_empty() => parse_int(value).map(Some).ok_or_else(|| {
+                    CanOpenError::EdsError(line, format!("invalid {} '{}'", field, value))
+                }),
+                _ => Ok(None),
+            }
+        };
+
+        let object = EdsObject {
+            name: name.clone(),
+            object_type: int("objecttype")?.unwrap_or(OBJECT_TYPE_VAR as u32) as u8,
+            data_type: int("datatype")?.map(|t| t as u16),
+            access_type: entries.get("accesstype").map(|a| a.to_ascii_lowercase()),
+            default_value: entries
+                .get("defaultvalue")
+                .cloned()
+                .filter(|v| !v.is_empty()),
+            pdo_mapping: int("pdomapping")?.is_some_and(|m| m != 0),
+        };
+        self.objects.insert(key, object);
+        Ok(())
+    }
+
+    /// The object at `index` itself, for arrays and records their name
+    pub fn object(&self, index: u16) -> Option<&EdsObject> {
+        self.objects.get(&(index, None))
+    }
+
+    /// Subindex `subindex` of `index`, or the object itself for subindex 0
+    /// of a plain variable
+    pub fn entry(&self, index: u16, subindex: u8) -> Option<&EdsObject> {
+        self.objects.get(&(index, Some(subindex))).or_else(|| {
+            self.object(index)
+                .filter(|object| subindex == 0 && object.object_type == OBJECT_TYPE_VAR)
+        })
+    }
+
+    /// Display name such as `Identity object.Vendor-ID`, qualified by the
+    /// parent's name for subindices of arrays and records
+    pub fn name(&self, index: u16, subindex: u8) -> Option<String> {
+        let entry = self.entry(index, subindex)?;
+        match self.object(index) {
+            Some(object) if object.object_type != OBJECT_TYPE_VAR => {
+                Some(format!("{}.{}", object.name, entry.name))
+            }
+            _ => Some(entry.name.clone()),
+        }
+    }
+
+    /// Every object and subindex in order, with `None` for objects themselves
+    pub fn objects(&self) -> impl Iterator<Item = (u16, Option<u8>, &EdsObject)> {
+        self.objects
+            .iter()
+            .map(|((index, subindex), object)| (*index, *subindex, object))
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EDS: &str = "\
+[FileInfo]
+FileName=widget.eds
+
+[DeviceInfo]
+VendorName=Acme
+ProductName=Widget
+
+; mandatory objects
+[1000]
+ParameterName=Device type
+ObjectType=0x7
+DataType=0x0007
+AccessType=RO
+DefaultValue=0x00000191
+PDOMapping=0
+
+[1018]
+ParameterName=Identity object
+ObjectType=0x9
+SubNumber=2
+
+[1018sub0]
+ParameterName=Highest sub-index supported
+DataType=0x0005
+AccessType=const
+DefaultValue=1
+
+[1018sub1]
+ParameterName=Vendor-ID
+ObjectType=0x7
+DataType=0x0007
+AccessType=ro
+DefaultValue=
+PDOMapping=1
+";
+
+    #[test]
+    fn parse() {
+        let eds = Eds::parse(EDS).unwrap();
+        assert_eq!(eds.vendor_name.as_deref(), Some("Acme"));
+        assert_eq!(eds.product_name.as_deref(), Some("Widget"));
+        assert_eq!(eds.len(), 4);
+
+        let device_type = eds.entry(0x1000, 0).unwrap();
+        assert_eq!(device_type.data_type, Some(0x0007));
+        assert_eq!(device_type.access_type.as_deref(), Some("ro"));
+        assert_eq!(device_type.default_value.as_deref(), Some("0x00000191"));
+        assert!(!device_type.pdo_mapping);
+        assert_eq!(eds.entry(0x1000, 1), None);
+
+        assert_eq!(eds.object(0x1018).unwrap().object_type, OBJECT_TYPE_RECORD);
+        let vendor = eds.entry(0x1018, 1).unwrap();
+        assert_eq!(vendor.default_value, None);
+        assert!(vendor.pdo_mapping);
+        assert_eq!(eds.entry(0x1018, 2), None);
+    }
+
+    #[test]
+    fn names_are_qualified_by_their_record() {
+        let eds = Eds::parse(EDS).unwrap();
+        assert_eq!(eds.name(0x1000, 0).as_deref(), Some("Device type"));
+        assert_eq!(
+            eds.name(0x1018, 1).as_deref(),
+            Some("Identity object.Vendor-ID")
+        );
+        assert_eq!(eds.name(0x2000, 0), None);
+
+        let order: Vec<_> = eds.objects().map(|(i, s, _)| (i, s)).collect();
+        assert_eq!(
+            order,
+            [
+                (0x1000, None),
+                (0x1018, None),
+                (0x1018, Some(0)),
+                (0x1018, Some(1))
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_give_the_line() {
+        let line = |text| match Eds::parse(text) {
+            Err(CanOpenError::EdsError(line, _)) => line,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(line("[1000]\nDataType=0x0007\n"), 1);
+        assert_eq!(line("[1000]\nParameterName=x\nDataType=seven\n"), 1);
+        assert_eq!(line("[DeviceInfo]\n[1000\n"), 2);
+        assert_eq!(line("[DeviceInfo]\nVendorName=Acme\ngarbage\n"), 3);
+    }
+}
@@ -0,0 +1,36 @@
+use quick_error::quick_error;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum CanOpenError {
+        AdaptorError(err: adaptor_core::AdaptorError) {
+            from()
+            display("{}", err)
+        }
+        IoError(err: std::io::Error) {
+            from()
+            display("{}", err)
+        }
+        InvalidNodeId(node: u8) {
+            display("{} is not a node id, expected 1 to 127", node)
+        }
+        Timeout {
+            display("no response from the node")
+        }
+        SdoAbort(index: u16, subindex: u8, code: u32) {
+            display("SDO transfer of {:04X}sub{} aborted: {} ({:#010x})", index, subindex, crate::sdo::abort_description(*code), code)
+        }
+        Toggle {
+            display("toggle bit out of sequence")
+        }
+        InvalidResponse(msg: String) {
+            display("invalid response: {}", msg)
+        }
+        PdoMapping(msg: String) {
+            display("invalid PDO mapping: {}", msg)
+        }
+        EdsError(line: usize, msg: String) {
+            display("EDS line {}: {}", line, msg)
+        }
+    }
+}
@@ -0,0 +1,20 @@
+//! CANopen (CiA 301) master utilities on top of the probe: NMT commands,
+//! heartbeat and node guarding, SDO transfers, PDO mappings and object
+//! names from EDS files.
+//!
+//! Everything talks through `FrameIo`, so an `AdaptorHandle` or any test
+//! double will do.
+
+pub mod eds;
+pub mod errors;
+pub mod nmt;
+pub mod pdo;
+pub mod sdo;
+
+pub type Result<T> = std::result::Result<T, errors::CanOpenError>;
+
+pub use eds::{Eds, EdsObject};
+pub use errors::CanOpenError;
+pub use nmt::{NmtCommand, NmtEvent, NmtState, NodeMonitor};
+pub use pdo::{MappedObject, Pdo, PdoKind};
+pub use sdo::SdoClient;
@@ -0,0 +1,389 @@
+//! Network management: NMT commands, and tracking node states from
+//! heartbeats and node guarding replies.
+
+use crate::errors::CanOpenError;
+use crate::Result;
+
+use adaptor_common::{CANFrame, Frame};
+use adaptor_core::FrameIo;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Identifier of NMT commands from the master
+pub const NMT_ID: u32 = 0x000;
+
+/// Heartbeats, boot-up messages and guarding replies use this plus the node id
+pub const HEARTBEAT_BASE: u32 = 0x700;
+
+const SEND_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Checks a node id is in the 1 to 127 range
+pub fn check_node_id(node: u8) -> Result<u8> {
+    if (1..=127).contains(&node) {
+        Ok(node)
+    } else {
+        Err(CanOpenError::InvalidNodeId(node))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NmtCommand {
+    Start,
+    Stop,
+    EnterPreOperational,
+    ResetNode,
+    ResetCommunication,
+}
+
+impl NmtCommand {
+    pub fn value(self) -> u8 {
+        match self {
+            NmtCommand::Start => 0x01,
+            NmtCommand::Stop => 0x02,
+            NmtCommand::EnterPreOperational => 0x80,
+            NmtCommand::ResetNode => 0x81,
+            NmtCommand::ResetCommunication => 0x82,
+        }
+    }
+}
+
+impl FromStr for NmtCommand {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "start" => Ok(NmtCommand::Start),
+            "stop" => Ok(NmtCommand::Stop),
+            "preop" | "pre-operational" => Ok(NmtCommand::EnterPreOperational),
+            "reset" | "reset-node" => Ok(NmtCommand::ResetNode),
+            "reset-comm" | "reset-communication" => Ok(NmtCommand::ResetCommunication),
+            _ => Err(format!(
+                "'{}' is not an NMT command, expected start, stop, preop, reset or reset-comm",
+                s
+            )),
+        }
+    }
+}
+
+/// State a node reports in its heartbeat
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NmtState {
+    BootUp,
+    Stopped,
+    Operational,
+    PreOperational,
+    Unknown(u8),
+}
+
+impl From<u8> for NmtState {
+    fn from(value: u8) -> Self {
+        match value {
+            0x00 => NmtState::BootUp,
+            0x04 => NmtState::Stopped,
+            0x05 => NmtState::Operational,
+            0x7F => NmtState::PreOperational,
+            other => NmtState::Unknown(other),
+        }
+    }
+}
+
+impl fmt::Display for NmtState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NmtState::BootUp => f.write_str("boot-up"),
+            NmtState::Stopped => f.write_str("stopped"),
+            NmtState::Operational => f.write_str("operational"),
+            NmtState::PreOperational => f.write_str("pre-operational"),
+            NmtState::Unknown(value) => write!(f, "unknown state {:#04x}", value),
+        }
+    }
+}
+
+/// NMT command frame for `node`, or every node if it is 0
+pub fn nmt_frame(command: NmtCommand, node: u8) -> Frame {
+    let data = [command.value(), node, 0, 0, 0, 0, 0, 0];
+    CANFrame::new(NMT_ID, 2, data, false, false, false).into()
+}
+
+/// Sends an NMT command to `node`, or every node if it is 0
+pub fn send_command<I: FrameIo>(io: &mut I, command: NmtCommand, node: u8) -> Result<()> {
+    if node != 0 {
+        check_node_id(node)?;
+    }
+    io.send_frame(nmt_frame(command, node), SEND_TIMEOUT)?;
+    Ok(())
+}
+
+/// Remote frame asking `node` for its state
+pub fn guard_frame(node: u8) -> Frame {
+    CANFrame::new(HEARTBEAT_BASE + node as u32, 1, [0; 8], true, false, false).into()
+}
+
+/// A change noticed by `NodeMonitor`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NmtEvent {
+    BootUp(u8),
+    StateChanged(u8, NmtState),
+
+    /// Nothing heard from the node within the monitor's timeout
+    Lost(u8),
+
+    /// A guarding reply repeated the previous toggle bit
+    ToggleError(u8),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct NodeStatus {
+    /// `None` until the node has answered
+    pub state: Option<NmtState>,
+    pub last_seen: Instant,
+    pub lost: bool,
+
+    /// Toggle bit the next guarding reply should carry, if known
+    toggle: Option<bool>,
+    guarding: bool,
+}
+
+impl NodeStatus {
+    fn new(now: Instant) -> Self {
+        Self {
+            state: None,
+            last_seen: now,
+            lost: false,
+            toggle: None,
+            guarding: false,
+        }
+    }
+}
+
+/// Follows node states from heartbeats and node guarding.
+///
+/// Feed it every received frame with `handle_frame` and call `check`
+/// regularly to notice nodes that went quiet. For nodes without a heartbeat
+/// producer, `guard` sends the remote frame their reply answers.
+#[derive(Debug, Clone)]
+pub struct NodeMonitor {
+    /// Silence after which a node counts as lost, a few heartbeat periods
+    pub timeout: Duration,
+    nodes: BTreeMap<u8, NodeStatus>,
+}
+
+impl NodeMonitor {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            nodes: BTreeMap::new(),
+        }
+    }
+
+    /// Nodes heard from or guarded, by id
+    pub fn nodes(&self) -> &BTreeMap<u8, NodeStatus> {
+        &self.nodes
+    }
+
+    pub fn state(&self, node: u8) -> Option<NmtState> {
+        self.nodes.get(&node).and_then(|status| status.state)
+    }
+
+    /// Updates the node a heartbeat, boot-up or guarding reply came from,
+    /// ignoring any other frame
+    pub fn handle_frame(&mut self, frame: &Frame, now: Instant) -> Option<NmtEvent> {
+        if frame.is_ext() || frame.is_err() || frame.is_rtr() {
+            return None;
+        }
+        let node = frame.id().checked_sub(HEARTBEAT_BASE)?;
+        if !(1..=127).contains(&node) {
+            return None;
+        }
+        let node = node as u8;
+        let byte = *frame.payload().first()?;
+        let state = NmtState::from(byte & 0x7F);
+        let toggle = byte & 0x80 != 0;
+
+        let status = self
+            .nodes
+            .entry(node)
+            .or_insert_with(|| NodeStatus::new(now));
+        let previous = status.state;
+        let was_lost = status.lost;
+        status.state = Some(state);
+        status.last_seen = now;
+        status.lost = false;
+
+        if state == NmtState::BootUp {
+            status.toggle = Some(false);
+            status.guarding = false;
+            return Some(NmtEvent::BootUp(node));
+        }
+
+        if status.guarding {
+            status.guarding = false;
+            let expected = status.toggle.replace(!toggle);
+            if expected.is_some_and(|expected| expected != toggle) {
+                return Some(NmtEvent::ToggleError(node));
+            }
+        }
+
+        if previous != Some(state) || was_lost {
+            Some(NmtEvent::StateChanged(node, state))
+        } else {
+            None
+        }
+    }
+
+    /// Reports each node that has been quiet for longer than `timeout`,
+    /// once until it is heard from again
+    pub fn check(&mut self, now: Instant) -> Vec<NmtEvent> {
+        let timeout = self.timeout;
+        self.nodes
+            .iter_mut()
+            .filter(|(_, status)| {
+                !status.lost && now.saturating_duration_since(status.last_seen) > timeout
+            })
+            .map(|(node, status)| {
+                status.lost = true;
+                NmtEvent::Lost(*node)
+            })
+            .collect()
+    }
+
+    /// Sends a node guarding request, the reply arrives through
+    /// `handle_frame`. A node that never answers is reported by `check`.
+    pub fn guard<I: FrameIo>(&mut self, io: &mut I, node: u8) -> Result<()> {
+        check_node_id(node)?;
+        io.send_frame(guard_frame(node), SEND_TIMEOUT)?;
+
+        let now = Instant::now();
+        self.nodes
+            .entry(node)
+            .or_insert_with(|| NodeStatus::new(now))
+            .guarding = true;
+        Ok(())
+    }
+
+    /// Forgets a node, e.g. after resetting it
+    pub fn remove(&mut self, node: u8) {
+        self.nodes.remove(&node);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use adaptor_core::testing::ScriptedIo;
+
+    const NODE: u8 = 5;
+
+    fn heartbeat(node: u8, byte: u8) -> Frame {
+        let id = HEARTBEAT_BASE + node as u32;
+        CANFrame::new(id, 1, [byte, 0, 0, 0, 0, 0, 0, 0], false, false, false).into()
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!("PreOp".parse(), Ok(NmtCommand::EnterPreOperational));
+        assert!("halt".parse::<NmtCommand>().is_err());
+
+        let frame = nmt_frame(NmtCommand::ResetNode, 0);
+        assert_eq!((frame.id(), frame.payload()), (NMT_ID, &[0x81, 0x00][..]));
+
+        let mut io = ScriptedIo::new();
+        send_command(&mut io, NmtCommand::Start, NODE).unwrap();
+        assert_eq!(io.sent_payloads(), [[0x01, NODE]]);
+        assert!(matches!(
+            send_command(&mut io, NmtCommand::Start, 128),
+            Err(CanOpenError::InvalidNodeId(128))
+        ));
+    }
+
+    #[test]
+    fn boot_up_and_state_changes() {
+        let mut monitor = NodeMonitor::new(Duration::from_secs(1));
+        let now = Instant::now();
+
+        assert_eq!(
+            monitor.handle_frame(&heartbeat(NODE, 0x00), now),
+            Some(NmtEvent::BootUp(NODE))
+        );
+        assert_eq!(monitor.state(NODE), Some(NmtState::BootUp));
+        assert_eq!(
+            monitor.handle_frame(&heartbeat(NODE, 0x7F), now),
+            Some(NmtEvent::StateChanged(NODE, NmtState::PreOperational))
+        );
+        // repeated heartbeats are not news
+        assert_eq!(monitor.handle_frame(&heartbeat(NODE, 0x7F), now), None);
+        assert_eq!(
+            monitor.handle_frame(&heartbeat(NODE, 0x05), now),
+            Some(NmtEvent::StateChanged(NODE, NmtState::Operational))
+        );
+        assert_eq!(monitor.state(NODE), Some(NmtState::Operational));
+    }
+
+    #[test]
+    fn other_frames_are_ignored() {
+        let mut monitor = NodeMonitor::new(Duration::from_secs(1));
+        let now = Instant::now();
+        let pdo = CANFrame::new(0x185, 1, [0; 8], false, false, false).into();
+        assert_eq!(monitor.handle_frame(&pdo, now), None);
+        assert_eq!(monitor.handle_frame(&guard_frame(NODE), now), None);
+        assert_eq!(monitor.handle_frame(&heartbeat(0, 0x05), now), None);
+        assert!(monitor.nodes().is_empty());
+    }
+
+    #[test]
+    fn quiet_nodes_are_lost_once() {
+        let mut monitor = NodeMonitor::new(Duration::from_millis(100));
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        monitor.handle_frame(&heartbeat(NODE, 0x05), start);
+        assert!(monitor.check(at(50)).is_empty());
+        assert_eq!(monitor.check(at(150)), [NmtEvent::Lost(NODE)]);
+        assert!(monitor.check(at(200)).is_empty());
+        assert!(monitor.nodes()[&NODE].lost);
+
+        // the same state is news after being lost
+        assert_eq!(
+            monitor.handle_frame(&heartbeat(NODE, 0x05), at(250)),
+            Some(NmtEvent::StateChanged(NODE, NmtState::Operational))
+        );
+        assert!(monitor.check(at(300)).is_empty());
+    }
+
+    #[test]
+    fn guarding_replies_must_toggle() {
+        let mut monitor = NodeMonitor::new(Duration::from_secs(1));
+        let mut io = ScriptedIo::new();
+        let now = Instant::now();
+
+        monitor.guard(&mut io, NODE).unwrap();
+        let sent = io.sent_frames().last().unwrap();
+        assert!(sent.is_rtr());
+        assert_eq!(sent.id(), HEARTBEAT_BASE + NODE as u32);
+        assert_eq!(monitor.state(NODE), None);
+
+        assert_eq!(
+            monitor.handle_frame(&heartbeat(NODE, 0x05), now),
+            Some(NmtEvent::StateChanged(NODE, NmtState::Operational))
+        );
+        monitor.guard(&mut io, NODE).unwrap();
+        assert_eq!(monitor.handle_frame(&heartbeat(NODE, 0x85), now), None);
+        monitor.guard(&mut io, NODE).unwrap();
+        assert_eq!(
+            monitor.handle_frame(&heartbeat(NODE, 0x85), now),
+            Some(NmtEvent::ToggleError(NODE))
+        );
+
+        // boot-up starts the toggle over
+        monitor.handle_frame(&heartbeat(NODE, 0x00), now);
+        monitor.guard(&mut io, NODE).unwrap();
+        assert_eq!(
+            monitor.handle_frame(&heartbeat(NODE, 0x05), now),
+            Some(NmtEvent::StateChanged(NODE, NmtState::Operational))
+        );
+    }
+}
@@ -0,0 +1,275 @@
+//! Process data objects: reading a node's PDO configuration over SDO and
+//! splitting PDO payloads into the objects they map.
+
+use crate::errors::CanOpenError;
+use crate::sdo::SdoClient;
+use crate::Result;
+
+use adaptor_core::FrameIo;
+
+use std::fmt;
+
+/// COB-ID bit marking a PDO as disabled
+const COB_ID_INVALID: u32 = 1 << 31;
+
+/// COB-ID bit forbidding remote requests for a PDO
+const COB_ID_NO_RTR: u32 = 1 << 30;
+
+/// COB-ID bit selecting a 29 bit identifier
+const COB_ID_EXTENDED: u32 = 1 << 29;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PdoKind {
+    /// Receive PDO, written by the master
+    Rpdo,
+
+    /// Transmit PDO, sent by the node
+    Tpdo,
+}
+
+impl PdoKind {
+    /// Index of the communication parameters of PDO 1
+    fn communication_index(self) -> u16 {
+        match self {
+            PdoKind::Rpdo => 0x1400,
+            PdoKind::Tpdo => 0x1800,
+        }
+    }
+
+    /// Index of the mapping parameters of PDO 1
+    fn mapping_index(self) -> u16 {
+        match self {
+            PdoKind::Rpdo => 0x1600,
+            PdoKind::Tpdo => 0x1A00,
+        }
+    }
+}
+
+impl fmt::Display for PdoKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PdoKind::Rpdo => "RPDO",
+            PdoKind::Tpdo => "TPDO",
+        })
+    }
+}
+
+/// Identifier the predefined connection set gives PDOs 1 to 4 of `node`
+pub fn default_cob_id(kind: PdoKind, number: u16, node: u8) -> Option<u32> {
+    let base = match (kind, number) {
+        (PdoKind::Tpdo, 1..=4) => 0x080 + 0x100 * number as u32,
+        (PdoKind::Rpdo, 1..=4) => 0x100 + 0x100 * number as u32,
+        _ => return None,
+    };
+    Some(base + node as u32)
+}
+
+/// One entry of a mapping, an object and how many bits of it are sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MappedObject {
+    pub index: u16,
+    pub subindex: u8,
+    pub bits: u8,
+}
+
+impl MappedObject {
+    /// Decodes a mapping entry as stored in 0x1600 or 0x1A00 sub 1 onwards
+    pub fn from_u32(value: u32) -> Self {
+        Self {
+            index: (value >> 16) as u16,
+            subindex: (value >> 8) as u8,
+            bits: value as u8,
+        }
+    }
+
+    pub fn to_u32(&self) -> u32 {
+        ((self.index as u32) << 16) | ((self.subindex as u32) << 8) | self.bits as u32
+    }
+}
+
+/// Communication and mapping parameters of one PDO
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pdo {
+    pub kind: PdoKind,
+
+    /// Counting from 1, as in TPDO1
+    pub number: u16,
+
+    /// Identifier without the flag bits
+    pub cob_id: u32,
+    pub is_ext: bool,
+    pub enabled: bool,
+    pub rtr_allowed: bool,
+
+    /// 0 for acyclic synchronous, 1 to 240 for every nth SYNC, 254 and 255
+    /// for event driven
+    pub transmission_type: u8,
+    pub mapping: Vec<MappedObject>,
+}
+
+impl Pdo {
+    /// Reads the configuration of PDO `number`, counting from 1
+    pub fn read<I: FrameIo>(sdo: &mut SdoClient<I>, kind: PdoKind, number: u16) -> Result<Self> {
+        if !(1..=512).contains(&number) {
+            return Err(CanOpenError::PdoMapping(format!(
+                "{}{} does not exist, PDOs are numbered 1 to 512",
+                kind, number
+            )));
+        }
+        let communication = kind.communication_index() + number - 1;
+        let mapping_index = kind.mapping_index() + number - 1;
+
+        let cob_id = sdo.upload_u32(communication, 1)?;
+        let transmission_type = sdo.upload_u32(communication, 2)? as u8;
+        let is_ext = cob_id & COB_ID_EXTENDED != 0;
+        let count = sdo.upload_u32(mapping_index, 0)?;
+        let mapping = (1..=count.min(64) as u8)
+            .map(|sub| {
+                sdo.upload_u32(mapping_index, sub)
+                    .map(MappedObject::from_u32)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            kind,
+            number,
+            cob_id: cob_id & if is_ext { 0x1FFF_FFFF } else { 0x7FF },
+            is_ext,
+            enabled: cob_id & COB_ID_INVALID == 0,
+            rtr_allowed: cob_id & COB_ID_NO_RTR == 0,
+            transmission_type,
+            mapping,
+        })
+    }
+
+    /// Total length of the mapped objects
+    pub fn bits(&self) -> usize {
+        self.mapping.iter().map(|object| object.bits as usize).sum()
+    }
+
+    /// Splits a PDO payload into the raw value of each mapped object,
+    /// packed least significant bit first. Dummy entries, indices below
+    /// 0x1000, are included like any other.
+    pub fn decode(&self, data: &[u8]) -> Result<Vec<(MappedObject, u64)>> {
+        if self.bits() > data.len() * 8 {
+            return Err(CanOpenError::PdoMapping(format!(
+                "{}{} maps {} bits but the payload is {} bytes",
+                self.kind,
+                self.number,
+                self.bits(),
+                data.len()
+            )));
+        }
+
+        let mut offset = 0;
+        let mut values = Vec::with_capacity(self.mapping.len());
+        for object in &self.mapping {
+            if object.bits > 64 {
+                return Err(CanOpenError::PdoMapping(format!(
+                    "{:04X}sub{} is {} bits, longer than an integer",
+                    object.index, object.subindex, object.bits
+                )));
+            }
+            let mut value = 0_u64;
+            for bit in 0..object.bits as usize {
+                let position = offset + bit;
+                if data[position / 8] & (1 << (position % 8)) != 0 {
+                    value |= 1 << bit;
+                }
+            }
+            values.push((*object, value));
+            offset += object.bits as usize;
+        }
+        Ok(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tpdo(mapping: &[(u16, u8, u8)]) -> Pdo {
+        Pdo {
+            kind: PdoKind::Tpdo,
+            number: 1,
+            cob_id: 0x185,
+            is_ext: false,
+            enabled: true,
+            rtr_allowed: false,
+            transmission_type: 0xFF,
+            mapping: mapping
+                .iter()
+                .map(|&(index, subindex, bits)| MappedObject {
+                    index,
+                    subindex,
+                    bits,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn default_cob_ids() {
+        assert_eq!(default_cob_id(PdoKind::Tpdo, 1, 5), Some(0x185));
+        assert_eq!(default_cob_id(PdoKind::Rpdo, 4, 0x7F), Some(0x57F));
+        assert_eq!(default_cob_id(PdoKind::Tpdo, 5, 5), None);
+    }
+
+    #[test]
+    fn mapping_entries() {
+        let object = MappedObject::from_u32(0x6000_0108);
+        assert_eq!(
+            object,
+            MappedObject {
+                index: 0x6000,
+                subindex: 1,
+                bits: 8
+            }
+        );
+        assert_eq!(object.to_u32(), 0x6000_0108);
+    }
+
+    #[test]
+    fn decode_unaligned_mappings() {
+        // 1 + 3 + 12 + 16 bits, least significant bit first
+        let pdo = tpdo(&[
+            (0x6000, 1, 1),
+            (0x6000, 2, 3),
+            (0x6001, 0, 12),
+            (0x6002, 0, 16),
+        ]);
+        assert_eq!(pdo.bits(), 32);
+        let values: Vec<u64> = pdo
+            .decode(&[0xCB, 0xAB, 0x34, 0x12])
+            .unwrap()
+            .into_iter()
+            .map(|(_, value)| value)
+            .collect();
+        assert_eq!(values, [1, 5, 0xABC, 0x1234]);
+    }
+
+    #[test]
+    fn decode_across_bytes_with_a_dummy_entry() {
+        // a 5 bit gap, then 10 bits straddling the first two bytes
+        let pdo = tpdo(&[(0x0005, 0, 5), (0x2000, 1, 10), (0x2000, 2, 1)]);
+        let decoded = pdo.decode(&[0b0010_0000, 0b1100_0000, 0xFF]).unwrap();
+        assert_eq!(decoded[0].1, 0);
+        assert_eq!(decoded[1].1, 0b10_0000_0001);
+        assert_eq!(decoded[2].1, 1);
+    }
+
+    #[test]
+    fn decode_errors() {
+        let pdo = tpdo(&[(0x6000, 1, 16), (0x6000, 2, 9)]);
+        assert!(matches!(
+            pdo.decode(&[0, 0, 0]),
+            Err(CanOpenError::PdoMapping(_))
+        ));
+
+        let pdo = tpdo(&[(0x6000, 1, 72)]);
+        assert!(matches!(
+            pdo.decode(&[0; 9]),
+            Err(CanOpenError::PdoMapping(_))
+        ));
+    }
+}
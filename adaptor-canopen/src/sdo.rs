@@ -0,0 +1,512 @@
+//! Service data objects: reading and writing a node's object dictionary
+//! with expedited and segmented transfers.
+
+use crate::errors::CanOpenError;
+use crate::nmt::check_node_id;
+use crate::Result;
+
+use adaptor_common::{CANFrame, Frame};
+use adaptor_core::FrameIo;
+
+use std::time::{Duration, Instant};
+
+/// Responses from the node use this plus the node id
+pub const SDO_TX_BASE: u32 = 0x580;
+
+/// Requests to the node use this plus the node id
+pub const SDO_RX_BASE: u32 = 0x600;
+
+/// Default time to wait for each response
+pub const SDO_TIMEOUT: Duration = Duration::from_millis(1000);
+
+const SEND_TIMEOUT: Duration = Duration::from_millis(100);
+
+const CCS_DOWNLOAD_SEGMENT: u8 = 0;
+const CCS_INITIATE_DOWNLOAD: u8 = 1;
+const CCS_INITIATE_UPLOAD: u8 = 2;
+const CCS_UPLOAD_SEGMENT: u8 = 3;
+const CS_ABORT: u8 = 4;
+
+const SCS_UPLOAD_SEGMENT: u8 = 0;
+const SCS_DOWNLOAD_SEGMENT: u8 = 1;
+const SCS_INITIATE_UPLOAD: u8 = 2;
+const SCS_INITIATE_DOWNLOAD: u8 = 3;
+
+pub const ABORT_TOGGLE: u32 = 0x0503_0000;
+pub const ABORT_TIMEOUT: u32 = 0x0504_0000;
+pub const ABORT_COMMAND: u32 = 0x0504_0001;
+
+/// Meaning of an SDO abort code from CiA 301
+pub fn abort_description(code: u32) -> &'static str {
+    match code {
+        0x0503_0000 => "toggle bit not alternated",
+        0x0504_0000 => "SDO protocol timed out",
+        0x0504_0001 => "command specifier not valid or unknown",
+        0x0504_0002 => "invalid block size",
+        0x0504_0003 => "invalid sequence number",
+        0x0504_0004 => "CRC error",
+        0x0504_0005 => "out of memory",
+        0x0601_0000 => "unsupported access to an object",
+        0x0601_0001 => "attempt to read a write only object",
+        0x0601_0002 => "attempt to write a read only object",
+        0x0602_0000 => "object does not exist in the object dictionary",
+        0x0604_0041 => "object cannot be mapped to the PDO",
+        0x0604_0042 => "mapped objects would exceed the PDO length",
+        0x0604_0043 => "general parameter incompatibility",
+        0x0604_0047 => "general internal incompatibility in the device",
+        0x0606_0000 => "access failed due to a hardware error",
+        0x0607_0010 => "data type or length does not match",
+        0x0607_0012 => "data type does not match, length too high",
+        0x0607_0013 => "data type does not match, length too low",
+        0x0609_0011 => "sub-index does not exist",
+        0x0609_0030 => "invalid value for parameter",
+        0x0609_0031 => "value of parameter written too high",
+        0x0609_0032 => "value of parameter written too low",
+        0x0609_0036 => "maximum value is less than minimum value",
+        0x060A_0023 => "resource not available: SDO connection",
+        0x0800_0000 => "general error",
+        0x0800_0020 => "data cannot be transferred or stored to the application",
+        0x0800_0021 => "data cannot be transferred or stored because of local control",
+        0x0800_0022 => "data cannot be transferred or stored in the present device state",
+        0x0800_0023 => "object dictionary generation failed or is not present",
+        0x0800_0024 => "no data available",
+        _ => "unknown abort code",
+    }
+}
+
+/// SDO client for one node's default server channel
+pub struct SdoClient<I: FrameIo> {
+    io: I,
+    node: u8,
+    timeout: Duration,
+}
+
+impl<I: FrameIo> SdoClient<I> {
+    pub fn new(io: I, node: u8) -> Result<Self> {
+        Ok(Self {
+            io,
+            node: check_node_id(node)?,
+            timeout: SDO_TIMEOUT,
+        })
+    }
+
+    pub fn node(&self) -> u8 {
+        self.node
+    }
+
+    /// Changes how long each response is waited for
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn io_mut(&mut self) -> &mut I {
+        &mut self.io
+    }
+
+    pub fn into_inner(self) -> I {
+        self.io
+    }
+
+    /// Reads an object, expedited or segmented as the node chooses
+    pub fn upload(&mut self, index: u16, subindex: u8) -> Result<Vec<u8>> {
+        let response = self.exchange(
+            index,
+            subindex,
+            initiate(CCS_INITIATE_UPLOAD << 5, index, subindex),
+        )?;
+        self.check_initiate(&response, SCS_INITIATE_UPLOAD, index, subindex)?;
+
+        let expedited = response[0] & 0x02 != 0;
+        let size_indicated = response[0] & 0x01 != 0;
+        if expedited {
+            let len = if size_indicated {
+                4 - ((response[0] >> 2) & 0x03) as usize
+            } else {
+                4
+            };
+            return Ok(response[4..4 + len].to_vec());
+        }
+
+        let size = if size_indicated {
+            Some(u32::from_le_bytes([response[4], response[5], response[6], response[7]]) as usize)
+        } else {
+            None
+        };
+        // the size is only the node's word, so it does not get to size the buffer
+        let mut data = Vec::with_capacity(size.unwrap_or(0).min(64 * 1024));
+        let mut toggle = false;
+        loop {
+            let request = [
+                (CCS_UPLOAD_SEGMENT << 5) | ((toggle as u8) << 4),
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+            ];
+            let response = self.exchange(index, subindex, request)?;
+            if response[0] >> 5 != SCS_UPLOAD_SEGMENT {
+                return Err(self.unexpected(index, subindex, response[0]));
+            }
+            if (response[0] & 0x10 != 0) != toggle {
+                self.abort(index, subindex, ABORT_TOGGLE)?;
+                return Err(CanOpenError::Toggle);
+            }
+
+            let unused = ((response[0] >> 1) & 0x07) as usize;
+            data.extend_from_slice(&response[1..8 - unused]);
+            if response[0] & 0x01 != 0 {
+                break;
+            }
+            toggle = !toggle;
+        }
+
+        match size {
+            Some(size) if size != data.len() => Err(CanOpenError::InvalidResponse(format!(
+                "{:04X}sub{} announced {} bytes but sent {}",
+                index,
+                subindex,
+                size,
+                data.len()
+            ))),
+            _ => Ok(data),
+        }
+    }
+
+    /// Reads an object of up to four bytes as an unsigned integer
+    pub fn upload_u32(&mut self, index: u16, subindex: u8) -> Result<u32> {
+        let data = self.upload(index, subindex)?;
+        if data.is_empty() || data.len() > 4 {
+            return Err(CanOpenError::InvalidResponse(format!(
+                "{:04X}sub{} is {} bytes, not an integer",
+                index,
+                subindex,
+                data.len()
+            )));
+        }
+        let mut bytes = [0; 4];
+        bytes[..data.len()].copy_from_slice(&data);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    /// Writes an object, expedited if `data` fits in one frame
+    pub fn download(&mut self, index: u16, subindex: u8, data: &[u8]) -> Result<()> {
+        if !data.is_empty() && data.len() <= 4 {
+            let unused = (4 - data.len()) as u8;
+            let mut request = initiate(
+                (CCS_INITIATE_DOWNLOAD << 5) | (unused << 2) | 0x03,
+                index,
+                subindex,
+            );
+            request[4..4 + data.len()].copy_from_slice(data);
+            let response = self.exchange(index, subindex, request)?;
+            return self.check_initiate(&response, SCS_INITIATE_DOWNLOAD, index, subindex);
+        }
+
+        let mut request = initiate((CCS_INITIATE_DOWNLOAD << 5) | 0x01, index, subindex);
+        request[4..8].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        let response = self.exchange(index, subindex, request)?;
+        self.check_initiate(&response, SCS_INITIATE_DOWNLOAD, index, subindex)?;
+
+        if data.is_empty() {
+            // an empty object still takes one final segment
+            return self.download_segment(index, subindex, &[], false, true);
+        }
+        let mut toggle = false;
+        let mut chunks = data.chunks(7).peekable();
+        while let Some(chunk) = chunks.next() {
+            self.download_segment(index, subindex, chunk, toggle, chunks.peek().is_none())?;
+            toggle = !toggle;
+        }
+        Ok(())
+    }
+
+    fn download_segment(
+        &mut self,
+        index: u16,
+        subindex: u8,
+        chunk: &[u8],
+        toggle: bool,
+        last: bool,
+    ) -> Result<()> {
+        let unused = (7 - chunk.len()) as u8;
+        let mut request = [0; 8];
+        request[0] =
+            (CCS_DOWNLOAD_SEGMENT << 5) | ((toggle as u8) << 4) | (unused << 1) | last as u8;
+        request[1..=chunk.len()].copy_from_slice(chunk);
+
+        let response = self.exchange(index, subindex, request)?;
+        if response[0] >> 5 != SCS_DOWNLOAD_SEGMENT {
+            return Err(self.unexpected(index, subindex, response[0]));
+        }
+        if (response[0] & 0x10 != 0) != toggle {
+            self.abort(index, subindex, ABORT_TOGGLE)?;
+            return Err(CanOpenError::Toggle);
+        }
+        Ok(())
+    }
+
+    /// Tells the node to abandon the transfer of an object
+    pub fn abort(&mut self, index: u16, subindex: u8, code: u32) -> Result<()> {
+        let mut request = initiate(CS_ABORT << 5, index, subindex);
+        request[4..8].copy_from_slice(&code.to_le_bytes());
+        self.send(request)
+    }
+
+    fn send(&mut self, data: [u8; 8]) -> Result<()> {
+        let frame = CANFrame::new(SDO_RX_BASE + self.node as u32, 8, data, false, false, false);
+        self.io.send_frame(frame.into(), SEND_TIMEOUT)?;
+        Ok(())
+    }
+
+    /// Sends a request and waits for the node's response, turning aborts
+    /// into errors and aborting the transfer if nothing arrives
+    fn exchange(&mut self, index: u16, subindex: u8, request: [u8; 8]) -> Result<[u8; 8]> {
+        self.send(request)?;
+
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                self.abort(index, subindex, ABORT_TIMEOUT)?;
+                return Err(CanOpenError::Timeout);
+            }
+            let frame = match self.io.recv_frame(deadline - now)? {
+                Some(frame) => frame,
+                None => continue,
+            };
+            if let Some(response) = self.response(&frame) {
+                if response[0] >> 5 == CS_ABORT {
+                    let code =
+                        u32::from_le_bytes([response[4], response[5], response[6], response[7]]);
+                    return Err(CanOpenError::SdoAbort(index, subindex, code));
+                }
+                return Ok(response);
+            }
+        }
+    }
+
+    /// Payload of a frame from this node's SDO server
+    fn response(&self, frame: &Frame) -> Option<[u8; 8]> {
+        if frame.is_ext() || frame.is_rtr() || frame.is_err() {
+            return None;
+        }
+        if frame.id() != SDO_TX_BASE + self.node as u32 || frame.payload().len() < 8 {
+            return None;
+        }
+        let mut response = [0; 8];
+        response.copy_from_slice(&frame.payload()[..8]);
+        Some(response)
+    }
+
+    fn check_initiate(
+        &mut self,
+        response: &[u8; 8],
+        scs: u8,
+        index: u16,
+        subindex: u8,
+    ) -> Result<()> {
+        if response[0] >> 5 != scs {
+            return Err(self.unexpected(index, subindex, response[0]));
+        }
+        let echoed = (u16::from_le_bytes([response[1], response[2]]), response[3]);
+        if echoed != (index, subindex) {
+            return Err(CanOpenError::InvalidResponse(format!(
+                "response for {:04X}sub{} while transferring {:04X}sub{}",
+                echoed.0, echoed.1, index, subindex
+            )));
+        }
+        Ok(())
+    }
+
+    /// Aborts after a response with the wrong command specifier
+    fn unexpected(&mut self, index: u16, subindex: u8, command: u8) -> CanOpenError {
+        if let Err(e) = self.abort(index, subindex, ABORT_COMMAND) {
+            return e;
+        }
+        CanOpenError::InvalidResponse(format!("unexpected command specifier {:#04x}", command))
+    }
+}
+
+/// First frame of a transfer, the command byte and the multiplexer
+fn initiate(command: u8, index: u16, subindex: u8) -> [u8; 8] {
+    let index = index.to_le_bytes();
+    [command, index[0], index[1], subindex, 0, 0, 0, 0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use adaptor_core::testing::ScriptedIo;
+
+    const NODE: u8 = 5;
+
+    /// Client for a node answering each request with whatever `respond`
+    /// returns, nothing for `None`
+    fn server<F>(mut respond: F) -> SdoClient<ScriptedIo>
+    where
+        F: FnMut([u8; 8]) -> Option<[u8; 8]> + 'static,
+    {
+        let io = ScriptedIo::responding(move |frame| {
+            assert_eq!(frame.id(), SDO_RX_BASE + NODE as u32);
+            let mut request = [0; 8];
+            request.copy_from_slice(frame.payload());
+            respond(request)
+                .map(|response| {
+                    let id = SDO_TX_BASE + NODE as u32;
+                    CANFrame::new(id, 8, response, false, false, false).into()
+                })
+                .into_iter()
+                .collect()
+        });
+        let mut client = SdoClient::new(io, NODE).unwrap();
+        client.set_timeout(Duration::from_millis(20));
+        client
+    }
+
+    /// A node uploading `data` in segments, with the toggle bit stuck at
+    /// zero if `stuck_toggle`
+    fn segmented_upload(data: &'static [u8], stuck_toggle: bool) -> SdoClient<ScriptedIo> {
+        let mut offset = 0;
+        server(move |request| match request[0] {
+            0x40 => {
+                let size = (data.len() as u32).to_le_bytes();
+                let [a, b, c, d] = size;
+                Some([0x41, request[1], request[2], request[3], a, b, c, d])
+            }
+            0x60 | 0x70 => {
+                let chunk = &data[offset..(offset + 7).min(data.len())];
+                offset += chunk.len();
+                let toggle = if stuck_toggle { 0 } else { request[0] & 0x10 };
+                let unused = (7 - chunk.len() as u8) << 1;
+                let mut response = [0; 8];
+                response[0] = toggle | unused | (offset == data.len()) as u8;
+                response[1..=chunk.len()].copy_from_slice(chunk);
+                Some(response)
+            }
+            _ => None,
+        })
+    }
+
+    fn abort_frame(index: u16, subindex: u8, code: u32) -> Vec<u8> {
+        let mut frame = initiate(CS_ABORT << 5, index, subindex).to_vec();
+        frame[4..].copy_from_slice(&code.to_le_bytes());
+        frame
+    }
+
+    #[test]
+    fn expedited_upload() {
+        let mut client = server(|request| match request {
+            [0x40, 0x18, 0x10, 0x01, ..] => Some([0x43, 0x18, 0x10, 0x01, 0x78, 0x56, 0x34, 0x12]),
+            // two bytes indicated
+            [0x40, 0x00, 0x10, 0x00, ..] => Some([0x4B, 0x00, 0x10, 0x00, 0x91, 0x01, 0xAA, 0xAA]),
+            _ => None,
+        });
+        assert_eq!(client.upload_u32(0x1018, 1).unwrap(), 0x1234_5678);
+        assert_eq!(client.upload(0x1000, 0).unwrap(), [0x91, 0x01]);
+    }
+
+    #[test]
+    fn segmented_upload_alternates_the_toggle() {
+        let data = b"hello world, CANopen";
+        let mut client = segmented_upload(data, false);
+        assert_eq!(client.upload(0x1008, 0).unwrap(), data);
+
+        let commands: Vec<u8> = client
+            .into_inner()
+            .sent_payloads()
+            .iter()
+            .map(|payload| payload[0])
+            .collect();
+        assert_eq!(commands, [0x40, 0x60, 0x70, 0x60]);
+    }
+
+    #[test]
+    fn repeated_toggle_aborts_the_upload() {
+        let mut client = segmented_upload(b"hello world, CANopen", true);
+        assert!(matches!(
+            client.upload(0x1008, 0),
+            Err(CanOpenError::Toggle)
+        ));
+        let sent = client.into_inner().sent_payloads();
+        assert_eq!(sent.last().unwrap(), &abort_frame(0x1008, 0, ABORT_TOGGLE));
+    }
+
+    #[test]
+    fn expedited_download() {
+        let mut client = server(|request| match request {
+            [0x2B, 0x17, 0x10, 0x00, 0xE8, 0x03, 0x00, 0x00] => {
+                Some([0x60, 0x17, 0x10, 0x00, 0, 0, 0, 0])
+            }
+            _ => None,
+        });
+        client.download(0x1017, 0, &1000_u16.to_le_bytes()).unwrap();
+    }
+
+    #[test]
+    fn segmented_download() {
+        let data: Vec<u8> = (1..=10).collect();
+        let mut client = server(|request| match request[0] {
+            0x21 => Some([0x60, request[1], request[2], request[3], 0, 0, 0, 0]),
+            command if command >> 5 == CCS_DOWNLOAD_SEGMENT => {
+                Some([0x20 | (command & 0x10), 0, 0, 0, 0, 0, 0, 0])
+            }
+            _ => None,
+        });
+        client.download(0x2000, 1, &data).unwrap();
+
+        let sent = client.into_inner().sent_payloads();
+        assert_eq!(sent[0], [0x21, 0x00, 0x20, 0x01, 10, 0, 0, 0]);
+        assert_eq!(sent[1], [0x00, 1, 2, 3, 4, 5, 6, 7]);
+        // toggled, four bytes unused, last
+        assert_eq!(sent[2], [0x19, 8, 9, 10, 0, 0, 0, 0]);
+        assert_eq!(sent.len(), 3);
+    }
+
+    #[test]
+    fn server_aborts_are_errors() {
+        let mut client = server(|request| match request[0] {
+            0x40 => {
+                let [a, b, c, d] = 0x0602_0000_u32.to_le_bytes();
+                Some([0x80, request[1], request[2], request[3], a, b, c, d])
+            }
+            _ => None,
+        });
+        assert!(matches!(
+            client.upload(0x2001, 3),
+            Err(CanOpenError::SdoAbort(0x2001, 3, 0x0602_0000))
+        ));
+        // nothing is sent back
+        assert_eq!(client.into_inner().sent.len(), 1);
+    }
+
+    #[test]
+    fn silence_aborts_the_transfer() {
+        let mut client = server(|_| None);
+        assert!(matches!(
+            client.upload(0x1008, 0),
+            Err(CanOpenError::Timeout)
+        ));
+        let sent = client.into_inner().sent_payloads();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[1], abort_frame(0x1008, 0, ABORT_TIMEOUT));
+    }
+
+    #[test]
+    fn frames_from_other_nodes_are_ignored() {
+        let io = ScriptedIo::responding(|_| {
+            let response = [0x43, 0x18, 0x10, 0x01, 1, 0, 0, 0];
+            vec![
+                CANFrame::new(SDO_TX_BASE + 6, 8, response, false, false, false).into(),
+                CANFrame::new(SDO_TX_BASE + NODE as u32, 8, response, false, false, true).into(),
+                CANFrame::new(SDO_TX_BASE + NODE as u32, 8, response, false, false, false).into(),
+            ]
+        });
+        let mut client = SdoClient::new(io, NODE).unwrap();
+        assert_eq!(client.upload_u32(0x1018, 1).unwrap(), 1);
+        assert!(client.io_mut().incoming.is_empty());
+    }
+}
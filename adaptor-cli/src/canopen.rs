@@ -0,0 +1,371 @@
+//! `canopen` subcommand, NMT, SDO access and PDO mappings of CANopen nodes.
+
+use crate::frame::{parse_hex_bytes, parse_hex_u16};
+use crate::parse_seconds;
+
+use adaptor_canopen::nmt::{self, HEARTBEAT_BASE};
+use adaptor_canopen::{Eds, NmtCommand, NmtEvent, NodeMonitor, Pdo, PdoKind, SdoClient};
+use adaptor_core::FrameIo;
+
+use structopt::StructOpt;
+
+use std::error::Error;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+const RECV_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Parses a subindex, decimal or hex with a `0x` prefix
+fn parse_subindex(s: &str) -> Result<u8, String> {
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u8::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    };
+    parsed.ok_or_else(|| format!("'{}' is not a subindex", s))
+}
+
+fn parse_pdo_kind(s: &str) -> Result<PdoKind, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "tpdo" => Ok(PdoKind::Tpdo),
+        "rpdo" => Ok(PdoKind::Rpdo),
+        _ => Err(format!("'{}' is not tpdo or rpdo", s)),
+    }
+}
+
+/// How object data is shown and parsed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ValueType {
+    Unsigned(usize),
+    Signed(usize),
+    Real32,
+    Str,
+    Hex,
+}
+
+impl ValueType {
+    /// Type of a CiA 301 data type index, hex for the ones not handled
+    fn from_data_type(data_type: u16) -> Self {
+        match data_type {
+            0x0001 | 0x0005 => ValueType::Unsigned(1),
+            0x0006 => ValueType::Unsigned(2),
+            0x0007 => ValueType::Unsigned(4),
+            0x001B => ValueType::Unsigned(8),
+            0x0002 => ValueType::Signed(1),
+            0x0003 => ValueType::Signed(2),
+            0x0004 => ValueType::Signed(4),
+            0x0015 => ValueType::Signed(8),
+            0x0008 => ValueType::Real32,
+            0x0009 => ValueType::Str,
+            _ => ValueType::Hex,
+        }
+    }
+
+    fn format(self, data: &[u8]) -> String {
+        let hex = || {
+            let bytes: Vec<String> = data.iter().map(|byte| format!("{:02X}", byte)).collect();
+            bytes.join(" ")
+        };
+        let mut bytes = [0; 8];
+        match self {
+            ValueType::Unsigned(len) if data.len() == len => {
+                bytes[..len].copy_from_slice(data);
+                let value = u64::from_le_bytes(bytes);
+                format!("{} (0x{:0width$X})", value, value, width = len * 2)
+            }
+            ValueType::Signed(len) if data.len() == len => {
+                bytes[..len].copy_from_slice(data);
+                let shift = 64 - len * 8;
+                let value = (u64::from_le_bytes(bytes) << shift) as i64 >> shift;
+                value.to_string()
+            }
+            ValueType::Real32 if data.len() == 4 => {
+                f32::from_le_bytes([data[0], data[1], data[2], data[3]]).to_string()
+            }
+            ValueType::Str => format!("{:?}", String::from_utf8_lossy(data)),
+            _ => hex(),
+        }
+    }
+
+    fn parse(self, s: &str) -> Result<Vec<u8>, String> {
+        let invalid = || format!("'{}' is not a valid value of the object's type", s);
+        let integer = || match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            Some(hex) => i128::from_str_radix(hex, 16).ok(),
+            None => s.parse::<i128>().ok(),
+        };
+        match self {
+            ValueType::Unsigned(len) => {
+                let value = integer()
+                    .filter(|v| *v >= 0 && *v < 1 << (len * 8))
+                    .ok_or_else(invalid)?;
+                Ok(value.to_le_bytes()[..len].to_vec())
+            }
+            ValueType::Signed(len) => {
+                let half = 1_i128 << (len * 8 - 1);
+                let value = integer()
+                    .filter(|v| *v >= -half && *v < half)
+                    .ok_or_else(invalid)?;
+                Ok(value.to_le_bytes()[..len].to_vec())
+            }
+            ValueType::Real32 => s
+                .parse::<f32>()
+                .map(|v| v.to_le_bytes().to_vec())
+                .map_err(|_| invalid()),
+            ValueType::Str => Ok(s.as_bytes().to_vec()),
+            ValueType::Hex => parse_hex_bytes(s),
+        }
+    }
+}
+
+impl FromStr for ValueType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "u8" => Ok(ValueType::Unsigned(1)),
+            "u16" => Ok(ValueType::Unsigned(2)),
+            "u32" => Ok(ValueType::Unsigned(4)),
+            "u64" => Ok(ValueType::Unsigned(8)),
+            "i8" => Ok(ValueType::Signed(1)),
+            "i16" => Ok(ValueType::Signed(2)),
+            "i32" => Ok(ValueType::Signed(4)),
+            "i64" => Ok(ValueType::Signed(8)),
+            "f32" => Ok(ValueType::Real32),
+            "str" => Ok(ValueType::Str),
+            "hex" => Ok(ValueType::Hex),
+            _ => Err(format!(
+                "'{}' is not a type, expected u8 to u64, i8 to i64, f32, str or hex",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub struct CanOpenOpt {
+    /// Node id, 1 to 127
+    #[structopt(short = "N", long)]
+    node: Option<u8>,
+
+    /// EDS or DCF file naming the node's objects and giving their types
+    #[structopt(long, parse(from_os_str))]
+    eds: Option<PathBuf>,
+
+    /// Seconds to wait for each SDO response
+    #[structopt(long, default_value = "1", parse(try_from_str = parse_seconds))]
+    timeout: Duration,
+
+    #[structopt(subcommand)]
+    cmd: CanOpenCommand,
+}
+
+#[derive(Debug, StructOpt)]
+enum CanOpenCommand {
+    /// Send an NMT command, start, stop, preop, reset or reset-comm, to
+    /// every node if --node is omitted
+    Nmt { command: NmtCommand },
+
+    /// Read an object with SDO, e.g. `read 1018 1`
+    Read {
+        #[structopt(parse(try_from_str = parse_hex_u16))]
+        index: u16,
+
+        #[structopt(default_value = "0", parse(try_from_str = parse_subindex))]
+        subindex: u8,
+
+        /// Show the value as u8 to u64, i8 to i64, f32, str or hex, taken
+        /// from --eds if omitted
+        #[structopt(short = "t", long = "type")]
+        value_type: Option<ValueType>,
+    },
+
+    /// Write an object with SDO, e.g. `write 6040 0 0x0F --type u16`
+    Write {
+        #[structopt(parse(try_from_str = parse_hex_u16))]
+        index: u16,
+
+        #[structopt(parse(try_from_str = parse_subindex))]
+        subindex: u8,
+
+        value: String,
+
+        /// Type of the value, taken from --eds if omitted
+        #[structopt(short = "t", long = "type")]
+        value_type: Option<ValueType>,
+    },
+
+    /// Show the configuration and mapping of a PDO, e.g. `pdo tpdo 1`
+    Pdo {
+        #[structopt(parse(try_from_str = parse_pdo_kind))]
+        kind: PdoKind,
+
+        /// PDO number, counting from 1
+        number: u16,
+    },
+
+    /// Print boot-ups, state changes and lost nodes from heartbeats
+    Monitor {
+        /// Seconds of silence before a node counts as lost
+        #[structopt(long, default_value = "3", parse(try_from_str = parse_seconds))]
+        lost_after: Duration,
+
+        /// Guard --node every this many seconds, for nodes without a heartbeat
+        #[structopt(long, parse(try_from_str = parse_seconds))]
+        guard: Option<Duration>,
+
+        /// Exit after this many events
+        #[structopt(short = "n", long)]
+        count: Option<usize>,
+    },
+}
+
+impl CanOpenOpt {
+    fn node(&self) -> Result<u8, Box<dyn Error>> {
+        self.node
+            .ok_or_else(|| "this command needs a --node".into())
+    }
+
+    /// Type given on the command line, or the object's type in the EDS
+    fn value_type(
+        &self,
+        eds: Option<&Eds>,
+        index: u16,
+        subindex: u8,
+        given: Option<ValueType>,
+    ) -> Option<ValueType> {
+        given.or_else(|| {
+            eds.and_then(|eds| eds.entry(index, subindex))
+                .and_then(|entry| entry.data_type)
+                .map(ValueType::from_data_type)
+        })
+    }
+
+    pub fn run<I: FrameIo>(&self, mut io: I) -> Result<(), Box<dyn Error>> {
+        let eds = match &self.eds {
+            Some(path) => Some(Eds::from_file(path)?),
+            None => None,
+        };
+        let eds = eds.as_ref();
+        let name = |index: u16, subindex: u8| {
+            eds.and_then(|eds| eds.name(index, subindex))
+                .map(|name| format!(" {}", name))
+                .unwrap_or_default()
+        };
+
+        match &self.cmd {
+            CanOpenCommand::Nmt { command } => {
+                nmt::send_command(&mut io, *command, self.node.unwrap_or(0))?;
+            }
+            CanOpenCommand::Read {
+                index,
+                subindex,
+                value_type,
+            } => {
+                let mut sdo = SdoClient::new(io, self.node()?)?;
+                sdo.set_timeout(self.timeout);
+                let data = sdo.upload(*index, *subindex)?;
+                let value_type = self
+                    .value_type(eds, *index, *subindex, *value_type)
+                    .unwrap_or(ValueType::Hex);
+                println!(
+                    "{:04X}sub{}{} = {}",
+                    index,
+                    subindex,
+                    name(*index, *subindex),
+                    value_type.format(&data)
+                );
+            }
+            CanOpenCommand::Write {
+                index,
+                subindex,
+                value,
+                value_type,
+            } => {
+                let value_type = self
+                    .value_type(eds, *index, *subindex, *value_type)
+                    .ok_or("the value needs a --type, or an --eds giving the object's type")?;
+                let data = value_type.parse(value)?;
+                let mut sdo = SdoClient::new(io, self.node()?)?;
+                sdo.set_timeout(self.timeout);
+                sdo.download(*index, *subindex, &data)?;
+            }
+            CanOpenCommand::Pdo { kind, number } => {
+                let mut sdo = SdoClient::new(io, self.node()?)?;
+                sdo.set_timeout(self.timeout);
+                let pdo = Pdo::read(&mut sdo, *kind, *number)?;
+                println!(
+                    "{}{}  COB-ID {:0width$X}{}{}  transmission type {}",
+                    pdo.kind,
+                    pdo.number,
+                    pdo.cob_id,
+                    if pdo.enabled { "" } else { " (disabled)" },
+                    if pdo.rtr_allowed { "" } else { " (no RTR)" },
+                    pdo.transmission_type,
+                    width = if pdo.is_ext { 8 } else { 3 }
+                );
+                let mut offset = 0;
+                for object in &pdo.mapping {
+                    println!(
+                        "    bits {:>2}..{:<2}  {:04X}sub{}{}",
+                        offset,
+                        offset + object.bits as usize,
+                        object.index,
+                        object.subindex,
+                        name(object.index, object.subindex)
+                    );
+                    offset += object.bits as usize;
+                }
+            }
+            CanOpenCommand::Monitor {
+                lost_after,
+                guard,
+                count,
+            } => {
+                let guarded = match guard {
+                    Some(_) => Some(self.node()?),
+                    None => None,
+                };
+                let mut monitor = NodeMonitor::new(*lost_after);
+                let mut next_guard = Instant::now();
+                let mut seen = 0;
+                while count.is_none_or(|count| seen < count) {
+                    if let (Some(node), Some(interval)) = (guarded, guard) {
+                        if Instant::now() >= next_guard {
+                            monitor.guard(&mut io, node)?;
+                            next_guard += *interval;
+                        }
+                    }
+
+                    let mut events = Vec::new();
+                    if let Some(frame) = io.recv_frame(RECV_TIMEOUT)? {
+                        if self
+                            .node
+                            .is_none_or(|node| frame.id() == HEARTBEAT_BASE + node as u32)
+                        {
+                            events.extend(monitor.handle_frame(&frame, Instant::now()));
+                        }
+                    }
+                    events.extend(monitor.check(Instant::now()));
+
+                    for event in events
+                        .into_iter()
+                        .take(count.map_or(usize::MAX, |c| c - seen))
+                    {
+                        match event {
+                            NmtEvent::BootUp(node) => println!("node {:>3}  boot-up", node),
+                            NmtEvent::StateChanged(node, state) => {
+                                println!("node {:>3}  {}", node, state)
+                            }
+                            NmtEvent::Lost(node) => println!("node {:>3}  lost", node),
+                            NmtEvent::ToggleError(node) => {
+                                println!("node {:>3}  guarding toggle error", node)
+                            }
+                        }
+                        seen += 1;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+//! The probe forgets its settings when it is reopened, so the CLI keeps
+//! them in a small TOML file and applies them every time it opens a probe.
+
+use crate::frame::parse_filter;
+
+use adaptor_core::{AdaptorError, AdaptorHandle, AdaptorSettings, Transport};
+
+use serde::{Deserialize, Serialize};
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProbeConfig {
+    /// Nominal bit rate, the firmware default is used if unset
+    pub bitrate: Option<u32>,
+
+    /// Data phase bit rate, setting it enables CAN FD
+    pub data_bitrate: Option<u32>,
+
+    pub leds: bool,
+
+    /// Acceptance filters in `<can_id>:<can_mask>` form
+    pub filters: Vec<String>,
+}
+
+impl Default for ProbeConfig {
+    fn default() -> Self {
+        Self {
+            bitrate: None,
+            data_bitrate: None,
+            leds: true,
+            filters: Vec::new(),
+        }
+    }
+}
+
+impl ProbeConfig {
+    /// `$XDG_CONFIG_HOME/can-probe/config.toml`, falling back to `~/.config`
+    pub fn default_path() -> PathBuf {
+        let base = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+            .unwrap_or_else(|| PathBuf::from("."));
+        base.join("can-probe").join("config.toml")
+    }
+
+    /// Loads the config at `path`, or the defaults if there is no file yet
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Ok(toml::from_str(&text)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Settings that can be built without knowing the probe's hardware
+    pub fn settings(&self) -> Result<AdaptorSettings, Box<dyn Error>> {
+        let mut settings = AdaptorSettings::default();
+        settings.set_leds(self.leds);
+        for filter in &self.filters {
+            settings
+                .add_filter(parse_filter(filter)?)
+                .map_err(AdaptorError::from)?;
+        }
+        Ok(settings)
+    }
+
+    /// Applies the bit rates, which depend on the probe's peripheral clock
+    pub fn apply<T: Transport>(
+        &self,
+        handle: &mut AdaptorHandle<T>,
+        timeout: Duration,
+    ) -> Result<(), Box<dyn Error>> {
+        if let Some(bitrate) = self.bitrate {
+            handle.set_bitrate(bitrate, timeout)?;
+        }
+        if let Some(bitrate) = self.data_bitrate {
+            handle.set_data_bitrate(bitrate, timeout)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,120 @@
+//! Frame syntax shared with can-utils, so commands can be pasted between
+//! `cansend`/`candump` and this tool.
+
+use adaptor_common::{Filter, Frame, MAX_EXT_ID, MAX_STD_ID};
+use adaptor_diag::image::decode_hex;
+
+use std::fmt::Write;
+
+pub use adaptor_log::candump::parse_frame;
+
+fn parse_id(s: &str) -> Result<(u32, bool), String> {
+    let id = u32::from_str_radix(s, 16).map_err(|_| format!("invalid CAN id '{}'", s))?;
+    match s.len() {
+        3 if id <= MAX_STD_ID => Ok((id, false)),
+        8 if id <= MAX_EXT_ID => Ok((id, true)),
+        _ => Err(format!(
+            "CAN id '{}' must be 3 (standard) or 8 (extended) hex digits",
+            s
+        )),
+    }
+}
+
+fn strip_hex_prefix(s: &str) -> &str {
+    s.trim_start_matches("0x").trim_start_matches("0X")
+}
+
+/// Parses a bare hex identifier, with or without a `0x` prefix
+pub fn parse_hex_id(s: &str) -> Result<u32, String> {
+    u32::from_str_radix(strip_hex_prefix(s), 16)
+        .ok()
+        .filter(|id| *id <= MAX_EXT_ID)
+        .ok_or_else(|| format!("invalid CAN id '{}'", s))
+}
+
+pub fn parse_hex_u8(s: &str) -> Result<u8, String> {
+    u8::from_str_radix(strip_hex_prefix(s), 16).map_err(|_| format!("'{}' is not a hex byte", s))
+}
+
+pub fn parse_hex_u16(s: &str) -> Result<u16, String> {
+    u16::from_str_radix(strip_hex_prefix(s), 16)
+        .map_err(|_| format!("'{}' is not a 16 bit hex number", s))
+}
+
+pub fn parse_hex_u24(s: &str) -> Result<u32, String> {
+    u32::from_str_radix(strip_hex_prefix(s), 16)
+        .ok()
+        .filter(|value| *value <= 0xFF_FFFF)
+        .ok_or_else(|| format!("'{}' is not a 24 bit hex number", s))
+}
+
+pub fn parse_hex_u32(s: &str) -> Result<u32, String> {
+    u32::from_str_radix(strip_hex_prefix(s), 16)
+        .map_err(|_| format!("'{}' is not a 32 bit hex number", s))
+}
+
+/// Parses hex bytes such as `DEADBEEF`, `de ad be ef` or `de:ad:be:ef`
+pub fn parse_hex_bytes(s: &str) -> Result<Vec<u8>, String> {
+    let digits: String = s
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != ':')
+        .collect();
+    if digits.is_ascii() && !digits.len().is_multiple_of(2) {
+        return Err(format!("'{}' has an odd number of hex digits", s));
+    }
+    decode_hex(&digits).ok_or_else(|| format!("'{}' is not hex data", s))
+}
+
+/// Parses a `candump` style `<can_id>:<can_mask>` filter, the id width
+/// selects standard or extended identifiers as in frames
+pub fn parse_filter(s: &str) -> Result<Filter, String> {
+    let colon = s
+        .find(':')
+        .ok_or_else(|| format!("filter '{}' must look like <can_id>:<can_mask>", s))?;
+    let (id, is_ext) = parse_id(&s[..colon])?;
+    let mask = u32::from_str_radix(&s[colon + 1..], 16)
+        .map_err(|_| format!("invalid filter mask '{}'", &s[colon + 1..]))?;
+    Ok(Filter::mask(id, mask, is_ext))
+}
+
+/// Parses a `<signal>=<value>` pair for DBC encoding
+pub fn parse_signal(s: &str) -> Result<(String, f64), String> {
+    let equals = s
+        .find('=')
+        .ok_or_else(|| format!("signal '{}' must look like <signal>=<value>", s))?;
+    let value = s[equals + 1..]
+        .parse()
+        .map_err(|_| format!("invalid signal value '{}'", &s[equals + 1..]))?;
+    Ok((s[..equals].to_owned(), value))
+}
+
+/// Formats a frame the way `candump` prints it, without the interface name
+pub fn format_frame(frame: &Frame) -> String {
+    let mut out = if frame.is_ext() {
+        format!("{:08X}", frame.id())
+    } else {
+        format!("{:>8}", format!("{:03X}", frame.id()))
+    };
+
+    if frame.is_err() {
+        out.push_str("   ERRORFRAME ");
+    }
+
+    match frame {
+        Frame::Classic(f) if f.is_rtr => {
+            let _ = write!(out, "   [{}]  remote request", f.dlc);
+            return out;
+        }
+        Frame::Classic(f) => {
+            let _ = write!(out, "   [{}] ", f.dlc);
+        }
+        Frame::Fd(f) => {
+            let _ = write!(out, "  [{:02}] ", f.len());
+        }
+    }
+
+    for byte in frame.payload() {
+        let _ = write!(out, " {:02X}", byte);
+    }
+    out
+}
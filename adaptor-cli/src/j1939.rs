@@ -0,0 +1,100 @@
+//! `j1939` subcommand, prints J1939 messages by parameter group name.
+
+use crate::frame::{parse_hex_id, parse_hex_u8};
+
+use adaptor_core::FrameIo;
+use adaptor_j1939::{pgn, Dm1, J1939Config, J1939Node, Message, GLOBAL_ADDRESS};
+
+use structopt::StructOpt;
+
+use std::error::Error;
+use std::time::Duration;
+
+const RECV_TIMEOUT: Duration = Duration::from_millis(500);
+
+fn format_message(message: &Message) -> String {
+    let destination = if message.destination == GLOBAL_ADDRESS {
+        "all".to_owned()
+    } else {
+        format!("{:02X}", message.destination)
+    };
+    let data: Vec<String> = message
+        .data
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect();
+    format!(
+        "{} {:05X} {:<8} {:02X} -> {:<3}  [{}] {}",
+        message.priority,
+        message.pgn,
+        pgn::acronym(message.pgn).unwrap_or("-"),
+        message.source,
+        destination,
+        message.data.len(),
+        data.join(" ")
+    )
+}
+
+#[derive(Debug, StructOpt)]
+pub struct J1939Opt {
+    /// Only print these hex PGNs, may be repeated
+    #[structopt(long, parse(try_from_str = parse_hex_id))]
+    pgn: Vec<u32>,
+
+    /// Exit after this many messages
+    #[structopt(short = "n", long)]
+    count: Option<usize>,
+
+    /// Claim this hex address, so transfers to it are acknowledged
+    #[structopt(long, parse(try_from_str = parse_hex_u8))]
+    address: Option<u8>,
+
+    /// Print the full name of each PGN
+    #[structopt(short, long)]
+    verbose: bool,
+}
+
+impl J1939Opt {
+    pub fn run<I: FrameIo>(&self, io: I) -> Result<(), Box<dyn Error>> {
+        let config = J1939Config {
+            preferred_address: self.address.unwrap_or_default(),
+            ..Default::default()
+        };
+        let mut node = J1939Node::new(io, config);
+        if self.address.is_some() {
+            println!("Claimed address {:02X}", node.claim_address()?);
+        }
+
+        let mut seen = 0;
+        while self.count.is_none_or(|count| seen < count) {
+            let message = match node.recv(RECV_TIMEOUT)? {
+                Some(message) => message,
+                None => continue,
+            };
+            if !self.pgn.is_empty() && !self.pgn.contains(&message.pgn) {
+                continue;
+            }
+
+            println!("{}", format_message(&message));
+            if self.verbose {
+                if let Some(description) = pgn::description(message.pgn) {
+                    println!("    {}", description);
+                }
+            }
+            if message.pgn == pgn::DM1 || message.pgn == pgn::DM2 {
+                if let Some(dm) = Dm1::parse(&message.data) {
+                    let lamps = dm.lamps;
+                    println!(
+                        "    MIL {:?}, red stop {:?}, amber warning {:?}, protect {:?}",
+                        lamps.malfunction, lamps.red_stop, lamps.amber_warning, lamps.protect
+                    );
+                    for dtc in dm.dtcs {
+                        println!("    {}", dtc);
+                    }
+                }
+            }
+            seen += 1;
+        }
+        Ok(())
+    }
+}
@@ -1,3 +1,551 @@
-fn main() {
-    println!("Hello, world!");
+mod canopen;
+mod config;
+mod frame;
+mod j1939;
+mod obd;
+mod uds;
+
+use config::ProbeConfig;
+use frame::{format_frame, parse_filter, parse_frame, parse_hex_id, parse_signal};
+
+use adaptor_common::{Filter, Frame};
+use adaptor_core::{AdaptorError, AdaptorHandle, AdaptorSettings};
+use adaptor_dbc::Dbc;
+use adaptor_log::{LogEntry, LogFormat, LogWriter, Replay, ReplayOptions};
+
+use structopt::StructOpt;
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long `dump` blocks on a read before checking for Ctrl-C
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// USB location of a probe as `<bus>:<address>`, see `list`
+#[derive(Debug, Clone, Copy)]
+struct UsbPath {
+    bus: u8,
+    address: u8,
+}
+
+impl FromStr for UsbPath {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("'{}' is not a <bus>:<address> path", s);
+        let mut parts = s.splitn(2, ':');
+        let bus = parts
+            .next()
+            .and_then(|p| p.parse().ok())
+            .ok_or_else(invalid)?;
+        let address = parts
+            .next()
+            .and_then(|p| p.parse().ok())
+            .ok_or_else(invalid)?;
+        Ok(Self { bus, address })
+    }
+}
+
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    match s.parse::<f64>() {
+        Ok(secs) if secs >= 0.0 && secs.is_finite() => Ok(Duration::from_secs_f64(secs)),
+        _ => Err(format!("'{}' is not a number of seconds", s)),
+    }
+}
+
+/// Positive multiplier, or 0 for as fast as possible
+fn parse_speed(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(speed) if speed >= 0.0 && speed.is_finite() => Ok(speed),
+        _ => Err(format!(
+            "'{}' is not a speed, expected 0 or a positive number",
+            s
+        )),
+    }
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "can-probe",
+    about = "Utility for reading and writing CAN frames to a Bus"
+)]
+struct Opt {
+    /// Open the probe with this USB serial number
+    #[structopt(short, long)]
+    serial: Option<String>,
+
+    /// Open the probe at this <bus>:<address>
+    #[structopt(short, long)]
+    path: Option<UsbPath>,
+
+    /// Settings file, defaults to ~/.config/can-probe/config.toml
+    #[structopt(short, long, parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    cmd: Command,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// List connected probes
+    List,
+
+    /// Print received frames
+    Dump {
+        /// Only receive frames matching <can_id>:<can_mask>, may be repeated
+        #[structopt(short, long, parse(try_from_str = parse_filter))]
+        filter: Vec<Filter>,
+
+        /// Exit after this many frames
+        #[structopt(short = "n", long)]
+        count: Option<usize>,
+
+        /// Prefix each frame with the probe's timestamp
+        #[structopt(short, long)]
+        timestamps: bool,
+
+        /// Also write received frames to this trace file
+        #[structopt(short, long, parse(from_os_str))]
+        log: Option<PathBuf>,
+
+        /// Trace format, candump, asc, blf, pcap or pcapng, guessed from the extension if omitted
+        #[structopt(long)]
+        format: Option<LogFormat>,
+
+        /// Decode frames into signals with this DBC database
+        #[structopt(long, parse(from_os_str))]
+        dbc: Option<PathBuf>,
+    },
+
+    /// Send a frame, e.g. 123#DEADBEEF, 1F334455#R or 123##1112233, or with
+    /// --dbc a message name followed by <signal>=<value> pairs
+    Send {
+        frame: String,
+
+        /// Physical signal values for the message, needs --dbc
+        #[structopt(parse(try_from_str = parse_signal))]
+        signals: Vec<(String, f64)>,
+
+        /// Build the frame from a message in this DBC database
+        #[structopt(long, parse(from_os_str))]
+        dbc: Option<PathBuf>,
+    },
+
+    /// Play a trace file back onto the bus with its original timing
+    Replay {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+
+        /// Trace format, guessed from the extension if omitted
+        #[structopt(long)]
+        format: Option<LogFormat>,
+
+        /// Playback speed multiplier, 0 sends frames back to back
+        #[structopt(short, long, parse(try_from_str = parse_speed), default_value = "1")]
+        speed: f64,
+
+        /// Times to play the trace, 0 repeats until interrupted
+        #[structopt(short, long, default_value = "1")]
+        loops: u32,
+
+        /// Only send frames with this hex identifier, may be repeated
+        #[structopt(short, long, parse(try_from_str = parse_hex_id))]
+        id: Vec<u32>,
+
+        /// Skip frames logged before this many seconds into the trace
+        #[structopt(long, parse(try_from_str = parse_seconds), default_value = "0")]
+        start: Duration,
+
+        /// Skip frames logged after this many seconds into the trace
+        #[structopt(long, parse(try_from_str = parse_seconds))]
+        end: Option<Duration>,
+    },
+
+    /// Show or change the settings applied whenever a probe is opened
+    Config {
+        /// Nominal bit rate in bit/s
+        #[structopt(long)]
+        bitrate: Option<u32>,
+
+        /// Data phase bit rate in bit/s, enables CAN FD
+        #[structopt(long)]
+        data_bitrate: Option<u32>,
+
+        /// Disable CAN FD
+        #[structopt(long)]
+        classic: bool,
+
+        /// Turn the status LEDs on or off
+        #[structopt(long)]
+        leds: Option<bool>,
+
+        /// Add an acceptance filter as <can_id>:<can_mask>, may be repeated
+        #[structopt(long = "filter")]
+        filters: Vec<String>,
+
+        /// Remove all acceptance filters
+        #[structopt(long)]
+        clear_filters: bool,
+    },
+
+    /// Convert a trace file between the candump, asc, blf, pcap and pcapng formats
+    Convert {
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+
+        #[structopt(parse(from_os_str))]
+        output: PathBuf,
+
+        /// Format of the input, guessed from the extension if omitted
+        #[structopt(long)]
+        from: Option<LogFormat>,
+
+        /// Format of the output, guessed from the extension if omitted
+        #[structopt(long)]
+        to: Option<LogFormat>,
+    },
+
+    /// Reset the probe
+    Reset,
+
+    /// Show the probe's error register
+    Status,
+
+    /// Send UDS diagnostic requests to an ECU over ISO-TP
+    Uds(uds::UdsOpt),
+
+    /// Read OBD-II live data, trouble codes and the VIN
+    Obd(obd::ObdOpt),
+
+    /// Print J1939 messages by parameter group, reassembling transfers
+    J1939(j1939::J1939Opt),
+
+    /// Send NMT commands, access objects with SDO and show PDO mappings of
+    /// CANopen nodes
+    Canopen(canopen::CanOpenOpt),
+}
+
+fn open(
+    opt: &Opt,
+    config: &ProbeConfig,
+    settings: AdaptorSettings,
+) -> Result<AdaptorHandle, Box<dyn Error>> {
+    let mut handle = match (&opt.serial, &opt.path) {
+        (Some(serial), _) => AdaptorHandle::open_by_serial(serial, settings)?,
+        (None, Some(path)) => AdaptorHandle::open_by_path(path.bus, path.address, settings)?,
+        (None, None) => AdaptorHandle::new(settings)?,
+    };
+    config.apply(&mut handle, TIMEOUT)?;
+    Ok(handle)
+}
+
+/// Falls back to the file extension, then to candump
+fn log_format(path: &Path, format: Option<LogFormat>) -> LogFormat {
+    format
+        .or_else(|| LogFormat::from_path(path))
+        .unwrap_or(LogFormat::Candump)
+}
+
+fn list() -> Result<(), Box<dyn Error>> {
+    let probes = adaptor_core::enumerate()?;
+    if probes.is_empty() {
+        println!("No probes found");
+    }
+
+    for probe in probes {
+        println!(
+            "{:03}:{:03}  {:04x}  {:<4}  {}",
+            probe.bus,
+            probe.address,
+            probe.pid,
+            probe.version,
+            probe.serial.as_deref().unwrap_or("-")
+        );
+    }
+    Ok(())
+}
+
+fn dump(
+    opt: &Opt,
+    config: &ProbeConfig,
+    filters: &[Filter],
+    count: Option<usize>,
+    timestamps: bool,
+    mut log: Option<Box<dyn LogWriter>>,
+    dbc: Option<&Dbc>,
+) -> Result<(), Box<dyn Error>> {
+    let mut settings = config.settings()?;
+    for filter in filters {
+        settings.add_filter(*filter).map_err(AdaptorError::from)?;
+    }
+
+    let mut handle = open(opt, config, settings)?;
+
+    // Ctrl-C ends the dump normally so the log's trailer still gets written
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let flag = interrupted.clone();
+    ctrlc::set_handler(move || flag.store(true, Ordering::Relaxed))?;
+
+    handle.start(TIMEOUT)?;
+
+    let mut seen = 0;
+    while count.is_none_or(|count| seen < count) && !interrupted.load(Ordering::Relaxed) {
+        let received = match handle.read_received(READ_TIMEOUT) {
+            Ok(received) => received,
+            Err(e) if e.is_timeout() => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        for frame in received.iter().take(count.map_or(usize::MAX, |c| c - seen)) {
+            if timestamps {
+                let time = frame.timestamp.duration_since(UNIX_EPOCH)?;
+                print!("({}.{:06})  ", time.as_secs(), time.subsec_micros());
+            }
+            println!("{}", format_frame(&frame.frame));
+            if let Some((message, signals)) = dbc.and_then(|dbc| dbc.decode(&frame.frame)) {
+                println!("    {}", message.name);
+                for signal in signals {
+                    println!("        {}", signal);
+                }
+            }
+            if let Some(log) = log.as_mut() {
+                log.write_entry(&LogEntry::from(*frame))?;
+            }
+            seen += 1;
+        }
+    }
+
+    handle.stop(TIMEOUT)?;
+    if let Some(log) = log.as_mut() {
+        log.finish()?;
+    }
+    Ok(())
+}
+
+fn send(opt: &Opt, config: &ProbeConfig, frame: Frame) -> Result<(), Box<dyn Error>> {
+    let mut handle = open(opt, config, config.settings()?)?;
+    handle.start(TIMEOUT)?;
+    handle.write_frame(frame, TIMEOUT)?;
+    handle.stop(TIMEOUT)?;
+    Ok(())
+}
+
+fn replay(
+    opt: &Opt,
+    config: &ProbeConfig,
+    file: &Path,
+    format: Option<LogFormat>,
+    options: ReplayOptions,
+) -> Result<(), Box<dyn Error>> {
+    let replay = Replay::from_file(file, log_format(file, format), options)?;
+    println!(
+        "Replaying {} frames, {:.3} s per pass",
+        replay.len(),
+        replay.duration().as_secs_f64()
+    );
+
+    let mut handle = open(opt, config, config.settings()?)?;
+    handle.start(TIMEOUT)?;
+    let stats = replay.run(&mut handle, TIMEOUT)?;
+    handle.stop(TIMEOUT)?;
+
+    println!(
+        "Sent {} frames in {} passes, at most {:.3} ms behind schedule",
+        stats.sent,
+        stats.loops,
+        stats.max_lag.as_secs_f64() * 1000.0
+    );
+    Ok(())
+}
+
+fn convert(
+    input: &Path,
+    output: &Path,
+    from: Option<LogFormat>,
+    to: Option<LogFormat>,
+) -> Result<(), Box<dyn Error>> {
+    let reader = adaptor_log::open(input, log_format(input, from))?;
+    let mut writer = adaptor_log::create(output, log_format(output, to))?;
+
+    let mut converted = 0;
+    for entry in reader {
+        writer.write_entry(&entry?)?;
+        converted += 1;
+    }
+    writer.finish()?;
+
+    println!("Converted {} frames", converted);
+    Ok(())
+}
+
+fn status(opt: &Opt, config: &ProbeConfig) -> Result<(), Box<dyn Error>> {
+    let handle = open(opt, config, config.settings()?)?;
+    println!("version:        {}", handle.info().version);
+    println!("error register: 0x{:02X}", handle.get_error(TIMEOUT)?);
+    println!("clock drift:    {:.1} ppm", handle.clock().drift_ppm());
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    env_logger::init();
+
+    let opt = Opt::from_args();
+    let config_path = opt.config.clone().unwrap_or_else(ProbeConfig::default_path);
+    let mut config = ProbeConfig::load(&config_path)?;
+
+    match &opt.cmd {
+        Command::List => list(),
+        Command::Dump {
+            filter,
+            count,
+            timestamps,
+            log,
+            format,
+            dbc,
+        } => {
+            let log = match log {
+                Some(path) => Some(adaptor_log::create(path, log_format(path, *format))?),
+                None => None,
+            };
+            let dbc = match dbc {
+                Some(path) => Some(Dbc::from_file(path)?),
+                None => None,
+            };
+            dump(
+                &opt,
+                &config,
+                filter,
+                *count,
+                *timestamps,
+                log,
+                dbc.as_ref(),
+            )
+        }
+        Command::Send {
+            frame,
+            signals,
+            dbc,
+        } => {
+            let frame = match dbc {
+                Some(path) => {
+                    let values: Vec<(&str, f64)> = signals
+                        .iter()
+                        .map(|(name, value)| (name.as_str(), *value))
+                        .collect();
+                    Dbc::from_file(path)?.encode(frame, &values)?
+                }
+                None if signals.is_empty() => parse_frame(frame)?,
+                None => return Err("signal values need a --dbc database".into()),
+            };
+            send(&opt, &config, frame)
+        }
+        Command::Replay {
+            file,
+            format,
+            speed,
+            loops,
+            id,
+            start,
+            end,
+        } => {
+            let options = ReplayOptions {
+                speed: *speed,
+                loops: if *loops == 0 { None } else { Some(*loops) },
+                ids: id.clone(),
+                start: *start,
+                end: *end,
+            };
+            replay(&opt, &config, file, *format, options)
+        }
+        Command::Config {
+            bitrate,
+            data_bitrate,
+            classic,
+            leds,
+            filters,
+            clear_filters,
+        } => {
+            let before = toml::to_string(&config)?;
+
+            if bitrate.is_some() {
+                config.bitrate = *bitrate;
+            }
+            if data_bitrate.is_some() {
+                config.data_bitrate = *data_bitrate;
+            }
+            if *classic {
+                config.data_bitrate = None;
+            }
+            if let Some(leds) = leds {
+                config.leds = *leds;
+            }
+            if *clear_filters {
+                config.filters.clear();
+            }
+            for filter in filters {
+                parse_filter(filter)?;
+                config.filters.push(filter.clone());
+            }
+
+            // catches too many filters before anything is saved
+            let settings = config.settings()?;
+
+            let after = toml::to_string(&config)?;
+            if after != before {
+                config.save(&config_path)?;
+            }
+
+            println!("# {}", config_path.display());
+            print!("{}", after);
+            log::debug!("{:#?}", settings);
+            Ok(())
+        }
+        Command::Convert {
+            input,
+            output,
+            from,
+            to,
+        } => convert(input, output, *from, *to),
+        Command::Reset => {
+            let handle = open(&opt, &config, config.settings()?)?;
+            handle.reset(TIMEOUT)?;
+            Ok(())
+        }
+        Command::Status => status(&opt, &config),
+        Command::Uds(uds) => {
+            let mut handle = open(&opt, &config, config.settings()?)?;
+            handle.start(TIMEOUT)?;
+            let result = uds.run(&mut handle);
+            handle.stop(TIMEOUT)?;
+            result
+        }
+        Command::Obd(obd) => {
+            let mut handle = open(&opt, &config, config.settings()?)?;
+            handle.start(TIMEOUT)?;
+            let result = obd.run(&mut handle);
+            handle.stop(TIMEOUT)?;
+            result
+        }
+        Command::J1939(j1939) => {
+            let mut handle = open(&opt, &config, config.settings()?)?;
+            handle.start(TIMEOUT)?;
+            let result = j1939.run(&mut handle);
+            handle.stop(TIMEOUT)?;
+            result
+        }
+        Command::Canopen(canopen) => {
+            let mut handle = open(&opt, &config, config.settings()?)?;
+            handle.start(TIMEOUT)?;
+            let result = canopen.run(&mut handle);
+            handle.stop(TIMEOUT)?;
+            result
+        }
+    }
 }
@@ -0,0 +1,159 @@
+//! `obd` subcommand, OBD-II live data and trouble codes from one ECU.
+
+use crate::frame::parse_hex_u8;
+use crate::parse_seconds;
+
+use adaptor_core::FrameIo;
+use adaptor_diag::obd::{find_pid, PIDS};
+use adaptor_diag::uds::format_dtc;
+use adaptor_diag::{IsoTp, ObdAddress, ObdClient, Pid};
+
+use structopt::StructOpt;
+
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+fn parse_pid(s: &str) -> Result<&'static Pid, String> {
+    find_pid(s).ok_or_else(|| format!("unknown PID '{}', see `obd pids --all`", s))
+}
+
+fn format_value(value: f64) -> String {
+    if value.fract() == 0.0 {
+        format!("{:.0}", value)
+    } else {
+        format!("{:.2}", value)
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ObdOpt {
+    /// Use 29 bit identifiers
+    #[structopt(long)]
+    ext: bool,
+
+    /// ECU to query, 0 to 7 with 11 bit identifiers or a hex address with 29
+    /// bit ones, the engine ECU if omitted
+    #[structopt(long, parse(try_from_str = parse_hex_u8))]
+    ecu: Option<u8>,
+
+    #[structopt(subcommand)]
+    cmd: ObdCommand,
+}
+
+#[derive(Debug, StructOpt)]
+enum ObdCommand {
+    /// Poll PIDs given by name or hex number, e.g. `live RPM SPEED COOLANT`
+    Live {
+        #[structopt(required = true, parse(try_from_str = parse_pid))]
+        pids: Vec<&'static Pid>,
+
+        /// Seconds between polls
+        #[structopt(short, long, default_value = "0.5", parse(try_from_str = parse_seconds))]
+        interval: Duration,
+
+        /// Exit after this many polls
+        #[structopt(short = "n", long)]
+        count: Option<usize>,
+    },
+
+    /// List the PIDs the ECU supports
+    Pids {
+        /// List every PID this tool can decode instead
+        #[structopt(long)]
+        all: bool,
+    },
+
+    /// Show the MIL and stored trouble codes
+    Dtc {
+        /// Show pending codes instead of stored ones
+        #[structopt(long)]
+        pending: bool,
+    },
+
+    /// Read the vehicle identification number
+    Vin,
+}
+
+impl ObdOpt {
+    fn address(&self) -> ObdAddress {
+        if self.ext {
+            ObdAddress::Extended(self.ecu.unwrap_or(0x10))
+        } else {
+            ObdAddress::Standard(self.ecu.unwrap_or(0))
+        }
+    }
+
+    pub fn run<I: FrameIo>(&self, io: I) -> Result<(), Box<dyn Error>> {
+        if let ObdCommand::Pids { all: true } = self.cmd {
+            for pid in PIDS {
+                println!(
+                    "{:02X} {:<16} {} ({})",
+                    pid.pid, pid.name, pid.description, pid.unit
+                );
+            }
+            return Ok(());
+        }
+
+        let mut client = ObdClient::new(IsoTp::new(io, self.address().isotp_config()));
+
+        match &self.cmd {
+            ObdCommand::Live {
+                pids,
+                interval,
+                count,
+            } => {
+                let mut polls = 0;
+                while count.is_none_or(|count| polls < count) {
+                    let start = Instant::now();
+                    let values: Vec<String> = pids
+                        .iter()
+                        .map(|pid| match client.read(pid) {
+                            Ok(value) => {
+                                format!("{} {} {}", pid.name, format_value(value), pid.unit)
+                            }
+                            Err(e) => {
+                                log::warn!("Failed to read {}: {}", pid.name, e);
+                                format!("{} -", pid.name)
+                            }
+                        })
+                        .collect();
+                    println!("{}", values.join("  "));
+
+                    polls += 1;
+                    if let Some(wait) = interval.checked_sub(start.elapsed()) {
+                        std::thread::sleep(wait);
+                    }
+                }
+            }
+            ObdCommand::Pids { .. } => {
+                for number in client.supported_pids()? {
+                    match PIDS.iter().find(|pid| pid.pid == number) {
+                        Some(pid) => println!(
+                            "{:02X} {:<16} {} ({})",
+                            pid.pid, pid.name, pid.description, pid.unit
+                        ),
+                        None => println!("{:02X}", number),
+                    }
+                }
+            }
+            ObdCommand::Dtc { pending } => {
+                let (mil, stored) = client.monitor_status()?;
+                println!(
+                    "MIL {}, {} stored codes",
+                    if mil { "on" } else { "off" },
+                    stored
+                );
+                let codes = if *pending {
+                    client.pending_dtcs()?
+                } else {
+                    client.stored_dtcs()?
+                };
+                for code in codes {
+                    println!("{}", format_dtc(code));
+                }
+            }
+            ObdCommand::Vin => println!("{}", client.vin()?),
+        }
+        Ok(())
+    }
+}
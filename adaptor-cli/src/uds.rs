@@ -0,0 +1,366 @@
+//! `uds` subcommand, talking to one ECU with the UDS client over ISO-TP.
+
+use crate::frame::{
+    parse_hex_bytes, parse_hex_id, parse_hex_u16, parse_hex_u24, parse_hex_u32, parse_hex_u8,
+};
+use crate::parse_seconds;
+
+use adaptor_core::FrameIo;
+use adaptor_diag::{
+    Checkpoint, DiagError, FlashConfig, Flasher, Image, IsoTp, IsoTpConfig, ResetType,
+    RoutineAction, Session, UdsClient, UdsConfig,
+};
+
+use structopt::StructOpt;
+
+use std::error::Error;
+use std::path::PathBuf;
+use std::process;
+use std::time::{Duration, Instant};
+
+fn parse_session(s: &str) -> Result<Session, String> {
+    match s {
+        "default" => Ok(Session::Default),
+        "programming" => Ok(Session::Programming),
+        "extended" => Ok(Session::Extended),
+        "safety" => Ok(Session::SafetySystem),
+        _ => parse_hex_u8(s).map(Session::from),
+    }
+}
+
+fn parse_reset(s: &str) -> Result<ResetType, String> {
+    match s {
+        "hard" => Ok(ResetType::Hard),
+        "key-off-on" => Ok(ResetType::KeyOffOn),
+        "soft" => Ok(ResetType::Soft),
+        _ => parse_hex_u8(s).map(ResetType::from),
+    }
+}
+
+fn parse_routine_action(s: &str) -> Result<RoutineAction, String> {
+    match s {
+        "start" => Ok(RoutineAction::Start),
+        "stop" => Ok(RoutineAction::Stop),
+        "results" => Ok(RoutineAction::RequestResults),
+        _ => Err(format!("'{}' is not start, stop or results", s)),
+    }
+}
+
+/// Formats data as hex followed by its printable ASCII characters
+fn format_data(data: &[u8]) -> String {
+    let hex: Vec<String> = data.iter().map(|b| format!("{:02X}", b)).collect();
+    let ascii: String = data
+        .iter()
+        .map(|b| {
+            if b.is_ascii_graphic() || *b == b' ' {
+                *b as char
+            } else {
+                '.'
+            }
+        })
+        .collect();
+    format!("{}  |{}|", hex.join(" "), ascii)
+}
+
+#[derive(Debug, StructOpt)]
+pub struct UdsOpt {
+    /// Hex identifier requests are sent to
+    #[structopt(long, default_value = "7E0", parse(try_from_str = parse_hex_id))]
+    tx: u32,
+
+    /// Hex identifier the ECU responds on
+    #[structopt(long, default_value = "7E8", parse(try_from_str = parse_hex_id))]
+    rx: u32,
+
+    /// Use 29 bit identifiers
+    #[structopt(long)]
+    ext: bool,
+
+    /// Switch to this session first, default, programming, extended, safety
+    /// or a hex number
+    #[structopt(long, parse(try_from_str = parse_session))]
+    session: Option<Session>,
+
+    /// Unlock this security level, the odd seed request in hex, before the command
+    #[structopt(long, parse(try_from_str = parse_hex_u8))]
+    security_level: Option<u8>,
+
+    /// Program computing the security key, run with the level and seed in hex
+    /// as arguments and printing the key in hex
+    #[structopt(long)]
+    key_command: Option<String>,
+
+    #[structopt(subcommand)]
+    cmd: UdsCommand,
+}
+
+#[derive(Debug, StructOpt)]
+enum UdsCommand {
+    /// Switch diagnostic session, default, programming, extended, safety or a hex number
+    Session {
+        #[structopt(parse(try_from_str = parse_session))]
+        session: Session,
+    },
+
+    /// Reset the ECU, hard, key-off-on, soft or a hex number
+    Reset {
+        #[structopt(default_value = "hard", parse(try_from_str = parse_reset))]
+        reset: ResetType,
+    },
+
+    /// Read data identifiers, given in hex
+    Read {
+        #[structopt(required = true, parse(try_from_str = parse_hex_u16))]
+        dids: Vec<u16>,
+    },
+
+    /// Write hex data to a data identifier
+    Write {
+        #[structopt(parse(try_from_str = parse_hex_u16))]
+        did: u16,
+
+        #[structopt(parse(try_from_str = parse_hex_bytes))]
+        data: HexData,
+    },
+
+    /// Start, stop or request the results of a routine
+    Routine {
+        #[structopt(parse(try_from_str = parse_routine_action))]
+        action: RoutineAction,
+
+        #[structopt(parse(try_from_str = parse_hex_u16))]
+        id: u16,
+
+        /// Routine parameters in hex
+        #[structopt(parse(try_from_str = parse_hex_bytes))]
+        params: Option<HexData>,
+    },
+
+    /// List DTCs matching a status mask
+    Dtc {
+        /// Status mask in hex, FF lists every stored DTC
+        #[structopt(long, default_value = "FF", parse(try_from_str = parse_hex_u8))]
+        mask: u8,
+    },
+
+    /// Clear DTCs, all of them unless a hex group is given
+    ClearDtc {
+        #[structopt(default_value = "FFFFFF", parse(try_from_str = parse_hex_u24))]
+        group: u32,
+    },
+
+    /// Download an Intel HEX, S-record or binary image, usually with
+    /// --session programming and --security-level
+    Flash(FlashOpt),
+
+    /// Send tester present, repeating to hold the session open for a while
+    TesterPresent {
+        /// Keep the session open for this many seconds
+        #[structopt(long, parse(try_from_str = parse_seconds))]
+        duration: Option<Duration>,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+struct FlashOpt {
+    /// Image file, .hex, .s19, .s28, .s37, .srec or .mot, anything else is binary
+    #[structopt(parse(from_os_str))]
+    file: PathBuf,
+
+    /// Load address of a binary image in hex
+    #[structopt(long, default_value = "0", parse(try_from_str = parse_hex_u32))]
+    base: u32,
+
+    /// Largest TransferData payload in bytes, the ECU's limit if omitted
+    #[structopt(long)]
+    block_size: Option<usize>,
+
+    /// RequestDownload data format in hex, 0 for plain data
+    #[structopt(long, default_value = "0", parse(try_from_str = parse_hex_u8))]
+    data_format: u8,
+
+    /// Routine erasing each segment, in hex
+    #[structopt(long, default_value = "FF00", parse(try_from_str = parse_hex_u16))]
+    erase_routine: u16,
+
+    /// Write without erasing first
+    #[structopt(long)]
+    no_erase: bool,
+
+    /// Routine checking the CRC-32 of each segment, in hex
+    #[structopt(long, default_value = "0202", parse(try_from_str = parse_hex_u16))]
+    check_routine: u16,
+
+    /// Skip the CRC check
+    #[structopt(long)]
+    no_check: bool,
+
+    /// Times to pick a dropped transfer back up, re-entering the session
+    /// and security level first
+    #[structopt(long, default_value = "3")]
+    retries: u32,
+
+    /// Continue an earlier transfer from the <segment>:<offset> it reported
+    #[structopt(long)]
+    resume: Option<Checkpoint>,
+
+    /// Hard reset the ECU once the image is written and checked
+    #[structopt(long)]
+    reset: bool,
+}
+
+impl FlashOpt {
+    fn config(&self) -> FlashConfig {
+        FlashConfig {
+            block_size: self.block_size,
+            data_format: self.data_format,
+            erase_routine: Some(self.erase_routine).filter(|_| !self.no_erase),
+            check_routine: Some(self.check_routine).filter(|_| !self.no_check),
+            ..FlashConfig::default()
+        }
+    }
+}
+
+/// Keeps structopt from treating the bytes as repeated arguments
+type HexData = Vec<u8>;
+
+/// Runs the key command for `seed` and parses the key it prints
+fn run_key_command(command: &str, level: u8, seed: &[u8]) -> adaptor_diag::Result<Vec<u8>> {
+    let seed: String = seed.iter().map(|b| format!("{:02X}", b)).collect();
+    let output = process::Command::new(command)
+        .arg(format!("{:02X}", level))
+        .arg(&seed)
+        .output()
+        .map_err(|e| DiagError::SecurityKey(format!("{}: {}", command, e)))?;
+    if !output.status.success() {
+        return Err(DiagError::SecurityKey(format!(
+            "{} exited with {}",
+            command, output.status
+        )));
+    }
+    parse_hex_bytes(String::from_utf8_lossy(&output.stdout).trim()).map_err(DiagError::SecurityKey)
+}
+
+impl UdsOpt {
+    fn isotp_config(&self) -> IsoTpConfig {
+        IsoTpConfig {
+            tx_id: self.tx,
+            rx_id: self.rx,
+            is_ext: self.ext,
+            ..IsoTpConfig::default()
+        }
+    }
+
+    /// Enters the session and unlocks the security level asked for
+    fn prepare<I: FrameIo>(&self, client: &mut UdsClient<I>) -> Result<(), Box<dyn Error>> {
+        if let Some(session) = self.session {
+            client.diagnostic_session_control(session)?;
+        }
+        if let Some(level) = self.security_level {
+            let command = self
+                .key_command
+                .as_ref()
+                .ok_or("--security-level needs a --key-command")?;
+            client.security_access(level, |seed| run_key_command(command, level, seed))?;
+        }
+        Ok(())
+    }
+
+    fn flash<I: FrameIo>(
+        &self,
+        client: &mut UdsClient<I>,
+        opt: &FlashOpt,
+    ) -> Result<(), Box<dyn Error>> {
+        let image = Image::from_file(&opt.file, opt.base)?;
+        for segment in &image.segments {
+            println!("{:#010x} {} bytes", segment.address, segment.data.len());
+        }
+
+        let mut flasher = Flasher::resume(&image, opt.config(), opt.resume.unwrap_or_default());
+        let mut percent = None;
+        let mut attempt = 0;
+        loop {
+            let result = flasher.run(client, |progress| {
+                let done = (progress.written * 100 / progress.total.max(1)) as u32;
+                if percent != Some(done) {
+                    percent = Some(done);
+                    println!("{:3}% {}/{} bytes", done, progress.written, progress.total);
+                }
+            });
+
+            match result {
+                Ok(()) => break,
+                // rewriting will not fix an image the ECU has checked and rejected
+                Err(e @ DiagError::CheckFailed(_)) | Err(e @ DiagError::NegativeResponse(..))
+                    if flasher.is_written() =>
+                {
+                    return Err(e.into())
+                }
+                Err(e) if attempt < opt.retries => {
+                    attempt += 1;
+                    log::warn!(
+                        "Transfer stopped at {}: {}, resuming",
+                        flasher.checkpoint(),
+                        e
+                    );
+                    self.prepare(client)?;
+                }
+                Err(e) => {
+                    eprintln!(
+                        "Transfer stopped, continue with --resume {}",
+                        flasher.checkpoint()
+                    );
+                    return Err(e.into());
+                }
+            }
+        }
+
+        if opt.reset {
+            client.ecu_reset(ResetType::Hard)?;
+        }
+        Ok(())
+    }
+
+    pub fn run<I: FrameIo>(&self, io: I) -> Result<(), Box<dyn Error>> {
+        let isotp = IsoTp::new(io, self.isotp_config());
+        let mut client = UdsClient::new(isotp, UdsConfig::default());
+        self.prepare(&mut client)?;
+
+        match &self.cmd {
+            UdsCommand::Session { session } => client.diagnostic_session_control(*session)?,
+            UdsCommand::Reset { reset } => client.ecu_reset(*reset)?,
+            UdsCommand::Read { dids } => {
+                for did in dids {
+                    let data = client.read_data_by_identifier(*did)?;
+                    println!("{:04X}: {}", did, format_data(&data));
+                }
+            }
+            UdsCommand::Write { did, data } => client.write_data_by_identifier(*did, data)?,
+            UdsCommand::Routine { action, id, params } => {
+                let params = params.as_deref().unwrap_or_default();
+                let status = client.routine_control(*action, *id, params)?;
+                if !status.is_empty() {
+                    println!("{}", format_data(&status));
+                }
+            }
+            UdsCommand::Dtc { mask } => {
+                for dtc in client.read_dtc_by_status_mask(*mask)? {
+                    println!("{}", dtc);
+                }
+            }
+            UdsCommand::ClearDtc { group } => client.clear_diagnostic_information(*group)?,
+            UdsCommand::Flash(opt) => self.flash(&mut client, opt)?,
+            UdsCommand::TesterPresent { duration } => {
+                client.tester_present()?;
+                if let Some(duration) = duration {
+                    let start = Instant::now();
+                    while start.elapsed() < *duration {
+                        client.keep_alive()?;
+                        std::thread::sleep(Duration::from_millis(100));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,260 @@
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::CANFrame;
+
+/// Payload length for each of the 16 DLC values of an FD frame
+pub const FD_LENGTHS: [u8; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 12, 16, 20, 24, 32, 48, 64];
+
+/// Largest payload of an FD frame
+pub const FD_MAX_LEN: usize = 64;
+
+/// Payload length encoded by an FD DLC, values above 15 are treated as 15
+pub fn dlc_to_len(dlc: u8) -> usize {
+    FD_LENGTHS[dlc.min(15) as usize] as usize
+}
+
+/// Smallest DLC able to carry `len` bytes, `None` if it does not fit in an FD frame
+pub fn len_to_dlc(len: usize) -> Option<u8> {
+    FD_LENGTHS
+        .iter()
+        .position(|l| *l as usize >= len)
+        .map(|dlc| dlc as u8)
+}
+
+/// CAN FD frame with up to 64 bytes of payload
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct FdFrame {
+    pub id: u32,
+    pub dlc: u8,
+    pub data: [u8; FD_MAX_LEN],
+
+    /// Bit rate switch, the data phase is sent at the data bit rate
+    pub brs: bool,
+
+    /// Error state indicator, set when the transmitter is error passive
+    pub esi: bool,
+
+    pub is_ext: bool,
+}
+
+impl FdFrame {
+    /// Builds a frame around `data`, padding it with zeros up to the next valid
+    /// FD length. Returns `None` if `data` is longer than 64 bytes.
+    pub fn new(id: u32, data: &[u8], brs: bool, esi: bool, is_ext: bool) -> Option<Self> {
+        let dlc = len_to_dlc(data.len())?;
+        let mut buf = [0_u8; FD_MAX_LEN];
+        buf[..data.len()].copy_from_slice(data);
+        Some(Self {
+            id,
+            dlc,
+            data: buf,
+            brs,
+            esi,
+            is_ext,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        dlc_to_len(self.dlc)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The payload, trimmed to the length given by the DLC
+    pub fn payload(&self) -> &[u8] {
+        &self.data[..self.len()]
+    }
+
+    pub fn payload_mut(&mut self) -> &mut [u8] {
+        let len = self.len();
+        &mut self.data[..len]
+    }
+}
+
+impl Default for FdFrame {
+    fn default() -> Self {
+        Self {
+            id: 0,
+            dlc: 0,
+            data: [0; FD_MAX_LEN],
+            brs: false,
+            esi: false,
+            is_ext: false,
+        }
+    }
+}
+
+/// Wire form of `FdFrame`, only the bytes covered by the DLC are sent
+#[derive(Serialize, Deserialize)]
+struct FdFrameWire<'a> {
+    id: u32,
+    dlc: u8,
+    data: &'a [u8],
+    brs: bool,
+    esi: bool,
+    is_ext: bool,
+}
+
+impl Serialize for FdFrame {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        FdFrameWire {
+            id: self.id,
+            dlc: self.dlc,
+            data: self.payload(),
+            brs: self.brs,
+            esi: self.esi,
+            is_ext: self.is_ext,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for FdFrame {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let wire = FdFrameWire::deserialize(deserializer)?;
+        if wire.dlc > 15 || wire.data.len() != dlc_to_len(wire.dlc) {
+            return Err(D::Error::custom("FD payload length does not match DLC"));
+        }
+
+        let mut data = [0_u8; FD_MAX_LEN];
+        data[..wire.data.len()].copy_from_slice(wire.data);
+        Ok(Self {
+            id: wire.id,
+            dlc: wire.dlc,
+            data,
+            brs: wire.brs,
+            esi: wire.esi,
+            is_ext: wire.is_ext,
+        })
+    }
+}
+
+/// Either a classic or an FD frame, as carried over the bulk endpoints
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Frame {
+    Classic(CANFrame),
+    Fd(FdFrame),
+}
+
+impl Frame {
+    pub fn id(&self) -> u32 {
+        match self {
+            Frame::Classic(f) => f.id,
+            Frame::Fd(f) => f.id,
+        }
+    }
+
+    pub fn is_ext(&self) -> bool {
+        match self {
+            Frame::Classic(f) => f.is_ext,
+            Frame::Fd(f) => f.is_ext,
+        }
+    }
+
+    pub fn is_fd(&self) -> bool {
+        matches!(self, Frame::Fd(_))
+    }
+
+    pub fn is_err(&self) -> bool {
+        matches!(self, Frame::Classic(f) if f.is_err)
+    }
+
+    pub fn is_rtr(&self) -> bool {
+        matches!(self, Frame::Classic(f) if f.is_rtr)
+    }
+
+    pub fn dlc(&self) -> u8 {
+        match self {
+            Frame::Classic(f) => f.dlc,
+            Frame::Fd(f) => f.dlc,
+        }
+    }
+
+    /// The payload, trimmed to the length given by the DLC
+    pub fn payload(&self) -> &[u8] {
+        match self {
+            Frame::Classic(f) => f.payload(),
+            Frame::Fd(f) => f.payload(),
+        }
+    }
+
+    pub fn payload_mut(&mut self) -> &mut [u8] {
+        match self {
+            Frame::Classic(f) => f.payload_mut(),
+            Frame::Fd(f) => f.payload_mut(),
+        }
+    }
+
+    pub fn as_classic(&self) -> Option<&CANFrame> {
+        match self {
+            Frame::Classic(f) => Some(f),
+            Frame::Fd(_) => None,
+        }
+    }
+
+    pub fn as_fd(&self) -> Option<&FdFrame> {
+        match self {
+            Frame::Classic(_) => None,
+            Frame::Fd(f) => Some(f),
+        }
+    }
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Frame::Classic(CANFrame::default())
+    }
+}
+
+impl From<CANFrame> for Frame {
+    fn from(frame: CANFrame) -> Self {
+        Frame::Classic(frame)
+    }
+}
+
+impl From<FdFrame> for Frame {
+    fn from(frame: FdFrame) -> Self {
+        Frame::Fd(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_fd_length_maps_to_its_dlc_and_back() {
+        for (dlc, len) in FD_LENGTHS.iter().enumerate() {
+            assert_eq!(len_to_dlc(*len as usize), Some(dlc as u8));
+            assert_eq!(dlc_to_len(dlc as u8), *len as usize);
+        }
+        assert_eq!(dlc_to_len(0xFF), FD_MAX_LEN);
+    }
+
+    #[test]
+    fn lengths_between_fd_lengths_round_up() {
+        assert_eq!(len_to_dlc(9), Some(9));
+        assert_eq!(len_to_dlc(13), Some(10));
+        assert_eq!(len_to_dlc(33), Some(14));
+        assert_eq!(len_to_dlc(49), Some(15));
+    }
+
+    #[test]
+    fn lengths_past_64_are_rejected() {
+        assert_eq!(len_to_dlc(FD_MAX_LEN + 1), None);
+        assert_eq!(len_to_dlc(usize::MAX), None);
+        assert!(FdFrame::new(0x123, &[0; FD_MAX_LEN + 1], false, false, false).is_none());
+    }
+
+    #[test]
+    fn new_pads_the_payload_to_the_next_fd_length() {
+        let frame = FdFrame::new(0x123, &[0xAA; 10], true, false, false).unwrap();
+        assert_eq!(frame.dlc, 9);
+        assert_eq!(frame.len(), 12);
+        assert_eq!(&frame.payload()[..10], &[0xAA; 10]);
+        assert_eq!(&frame.payload()[10..], &[0, 0]);
+    }
+}
@@ -0,0 +1,145 @@
+use serde::{Deserialize, Serialize};
+
+/// Largest standard (11 bit) identifier
+pub const MAX_STD_ID: u32 = 0x7FF;
+
+/// Largest extended (29 bit) identifier
+pub const MAX_EXT_ID: u32 = 0x1FFF_FFFF;
+
+#[derive(defmt::Format, Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum FilterMode {
+    /// Accept identifiers where `id & mask == frame_id & mask`
+    Mask { id: u32, mask: u32 },
+
+    /// Accept exactly these two identifiers
+    List { ids: [u32; 2] },
+}
+
+/// One element of the probe's acceptance filter
+#[derive(defmt::Format, Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Filter {
+    pub mode: FilterMode,
+
+    /// Match extended rather than standard identifiers
+    pub extended: bool,
+
+    pub enabled: bool,
+}
+
+impl Filter {
+    pub fn mask(id: u32, mask: u32, extended: bool) -> Self {
+        Self {
+            mode: FilterMode::Mask { id, mask },
+            extended,
+            enabled: true,
+        }
+    }
+
+    pub fn list(first: u32, second: u32, extended: bool) -> Self {
+        Self {
+            mode: FilterMode::List {
+                ids: [first, second],
+            },
+            extended,
+            enabled: true,
+        }
+    }
+
+    /// Whether the identifiers in the filter fit in its identifier type
+    pub fn is_valid(&self) -> bool {
+        let max = if self.extended {
+            MAX_EXT_ID
+        } else {
+            MAX_STD_ID
+        };
+
+        match self.mode {
+            FilterMode::Mask { id, mask } => id <= max && mask <= max,
+            FilterMode::List { ids } => ids.iter().all(|id| *id <= max),
+        }
+    }
+
+    pub fn matches(&self, id: u32, is_ext: bool) -> bool {
+        if !self.enabled || self.extended != is_ext {
+            return false;
+        }
+
+        match self.mode {
+            FilterMode::Mask {
+                id: filter_id,
+                mask,
+            } => id & mask == filter_id & mask,
+            FilterMode::List { ids } => ids.contains(&id),
+        }
+    }
+}
+
+impl Default for Filter {
+    /// A disabled filter
+    fn default() -> Self {
+        Self {
+            mode: FilterMode::Mask { id: 0, mask: 0 },
+            extended: false,
+            enabled: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mask_compares_only_the_masked_bits() {
+        let filter = Filter::mask(0x120, 0x7F0, false);
+        assert!(filter.matches(0x120, false));
+        assert!(filter.matches(0x12F, false));
+        assert!(!filter.matches(0x130, false));
+        assert!(!filter.matches(0x220, false));
+
+        // a zero mask accepts everything of its identifier type
+        let any = Filter::mask(0x123, 0, true);
+        assert!(any.matches(0, true));
+        assert!(any.matches(MAX_EXT_ID, true));
+    }
+
+    #[test]
+    fn list_accepts_exactly_its_ids() {
+        let filter = Filter::list(0x100, 0x200, false);
+        assert!(filter.matches(0x100, false));
+        assert!(filter.matches(0x200, false));
+        assert!(!filter.matches(0x101, false));
+    }
+
+    #[test]
+    fn standard_and_extended_ids_never_match_each_other() {
+        let std = Filter::mask(0x123, MAX_STD_ID, false);
+        let ext = Filter::list(0x123, 0x456, true);
+        assert!(!std.matches(0x123, true));
+        assert!(!ext.matches(0x123, false));
+        assert!(ext.matches(0x123, true));
+    }
+
+    #[test]
+    fn disabled_filters_match_nothing() {
+        let filter = Filter {
+            enabled: false,
+            ..Filter::mask(0, 0, false)
+        };
+        assert!(!filter.matches(0, false));
+        assert!(!Filter::default().matches(0, false));
+    }
+
+    #[test]
+    fn ids_must_fit_the_identifier_type() {
+        assert!(Filter::mask(MAX_STD_ID, MAX_STD_ID, false).is_valid());
+        assert!(!Filter::mask(MAX_STD_ID + 1, 0, false).is_valid());
+        assert!(!Filter::mask(0, MAX_STD_ID + 1, false).is_valid());
+        assert!(!Filter::list(0x100, MAX_STD_ID + 1, false).is_valid());
+
+        assert!(Filter::mask(MAX_EXT_ID, MAX_EXT_ID, true).is_valid());
+        assert!(Filter::list(MAX_STD_ID + 1, MAX_EXT_ID, true).is_valid());
+        assert!(!Filter::mask(MAX_EXT_ID + 1, 0, true).is_valid());
+        assert!(!Filter::list(0, MAX_EXT_ID + 1, true).is_valid());
+    }
+}
@@ -5,9 +5,24 @@ use serde::{Deserialize, Serialize};
 use getset::{Getters, Setters};
 
 pub const VENDOR_ID: u16 = 0x69;
+
+/// Size of the probe's control transfer buffer, the most a `Settings` or
+/// `SetFilter` request may carry
 pub const CMD_PACKET_SIZE: usize = 16;
 
-use num_enum;
+/// Filter slots carried in `AdaptorSettings`, hardware may support fewer
+pub const MAX_FILTERS: usize = 32;
+
+mod fd;
+mod filter;
+mod periodic;
+pub mod socketcan;
+mod timing;
+
+pub use fd::{dlc_to_len, len_to_dlc, FdFrame, Frame, FD_LENGTHS, FD_MAX_LEN};
+pub use filter::{Filter, FilterMode, MAX_EXT_ID, MAX_STD_ID};
+pub use periodic::{Checksum, ChecksumKind, Counter, PeriodicTx, MAX_PERIODIC};
+pub use timing::{BitTiming, BitTimingError, Bitrate, HardwareLimits, TimingLimits};
 
 #[repr(u8)]
 #[derive(
@@ -21,39 +36,167 @@ use num_enum;
 )]
 pub enum UsbRequests {
     NOP = 0x0,
+    /// Replaces the settings and disables every filter, which follow with `SetFilter`
     Settings,
     Reset,
     Run,
     LedEnable,
     GetError,
+    GetTimestamp,
+    /// Sets the acceptance filter in the slot given by `wValue`
+    SetFilter,
+    /// Starts a periodic transmission in the slot given by `wValue`
+    SetPeriodic,
+    /// Stops the periodic transmission in the slot given by `wValue`
+    ClearPeriodic,
 }
 
 #[derive(defmt::Format, Debug, Clone, Copy, Serialize, Deserialize, Setters, Getters)]
 pub struct AdaptorSettings {
-    #[getset(get, set)]
-    rx_mask: u32,
+    /// Acceptance filter, a frame is received if any enabled filter matches it.
+    /// With no filters enabled every frame is received. Too large for one
+    /// control transfer, so each enabled filter is sent with its own `SetFilter`.
+    #[serde(skip)]
+    #[getset(get = "pub", set = "pub")]
+    filters: [Filter; MAX_FILTERS],
 
-    #[getset(get, set)]
+    #[getset(get = "pub", set = "pub")]
     leds: bool,
 
-    #[getset(get, set)]
-    can_id: u32,
+    /// Nominal bit timing, `None` keeps the firmware's default
+    #[getset(get = "pub", set = "pub")]
+    bit_timing: Option<BitTiming>,
 
-    #[getset(get, set)]
-    can_config: u32,
+    /// Accept and send FD frames
+    #[getset(get = "pub", set = "pub")]
+    fd_enabled: bool,
+
+    /// Bit timing used for the data phase of FD frames with BRS set,
+    /// `None` keeps the firmware's default
+    #[getset(get = "pub", set = "pub")]
+    data_bit_timing: Option<BitTiming>,
 }
 
 impl Default for AdaptorSettings {
     fn default() -> Self {
         Self {
-            rx_mask: 0,
+            filters: [Filter::default(); MAX_FILTERS],
             leds: true,
-            can_id: 0,
-            can_config: 0,
+            bit_timing: None,
+            fd_enabled: false,
+            data_bit_timing: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SettingsError {
+    BitTiming(BitTimingError),
+    DataBitTiming(BitTimingError),
+    /// FD was enabled on hardware without an FD capable peripheral
+    FdUnsupported,
+    /// The filter at this index holds an identifier too large for its type
+    InvalidFilter(usize),
+    /// More filters are enabled than the hardware has banks for
+    TooManyFilters {
+        extended: bool,
+        max: u8,
+    },
+}
+
+impl core::fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SettingsError::BitTiming(e) => write!(f, "nominal bit timing: {}", e),
+            SettingsError::DataBitTiming(e) => write!(f, "data bit timing: {}", e),
+            SettingsError::FdUnsupported => f.write_str("hardware does not support CAN FD"),
+            SettingsError::InvalidFilter(i) => write!(f, "filter {} has an invalid identifier", i),
+            SettingsError::TooManyFilters { extended, max } => write!(
+                f,
+                "hardware supports at most {} {} filters",
+                max,
+                if *extended { "extended" } else { "standard" }
+            ),
         }
     }
 }
 
+impl AdaptorSettings {
+    /// Enables `filter` in the first free slot
+    pub fn add_filter(&mut self, filter: Filter) -> Result<(), SettingsError> {
+        let slot =
+            self.filters
+                .iter_mut()
+                .find(|f| !f.enabled)
+                .ok_or(SettingsError::TooManyFilters {
+                    extended: filter.extended,
+                    max: MAX_FILTERS as u8,
+                })?;
+        *slot = Filter {
+            enabled: true,
+            ..filter
+        };
+        Ok(())
+    }
+
+    /// Disables every filter, so all frames are received
+    pub fn clear_filters(&mut self) {
+        self.filters = [Filter::default(); MAX_FILTERS];
+    }
+
+    /// Whether a frame with this identifier makes it through the acceptance filter
+    pub fn accepts(&self, id: u32, is_ext: bool) -> bool {
+        let mut enabled = self.filters.iter().filter(|f| f.enabled).peekable();
+        enabled.peek().is_none() || enabled.any(|f| f.matches(id, is_ext))
+    }
+
+    /// Checks the settings against what the probe's hardware can do
+    pub fn validate(&self, limits: &HardwareLimits) -> Result<(), SettingsError> {
+        let mut std_filters = 0;
+        let mut ext_filters = 0;
+        for (i, filter) in self.filters.iter().enumerate().filter(|(_, f)| f.enabled) {
+            if !filter.is_valid() {
+                return Err(SettingsError::InvalidFilter(i));
+            }
+            if filter.extended {
+                ext_filters += 1;
+            } else {
+                std_filters += 1;
+            }
+        }
+
+        if std_filters > limits.std_filters as usize {
+            return Err(SettingsError::TooManyFilters {
+                extended: false,
+                max: limits.std_filters,
+            });
+        }
+        if ext_filters > limits.ext_filters as usize {
+            return Err(SettingsError::TooManyFilters {
+                extended: true,
+                max: limits.ext_filters,
+            });
+        }
+
+        if let Some(timing) = &self.bit_timing {
+            timing
+                .validate(&limits.nominal)
+                .map_err(SettingsError::BitTiming)?;
+        }
+
+        if self.fd_enabled {
+            let data_limits = limits.data.ok_or(SettingsError::FdUnsupported)?;
+            if let Some(timing) = &self.data_bit_timing {
+                timing
+                    .validate(&data_limits)
+                    .map_err(SettingsError::DataBitTiming)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
 // Can Frame impl
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Default, Serialize, Deserialize)]
 pub struct CANFrame {
@@ -76,4 +219,65 @@ impl CANFrame {
             is_ext,
         }
     }
+
+    /// The payload, trimmed to the length given by the DLC
+    pub fn payload(&self) -> &[u8] {
+        &self.data[..(self.dlc as usize).min(self.data.len())]
+    }
+
+    pub fn payload_mut(&mut self) -> &mut [u8] {
+        let len = (self.dlc as usize).min(self.data.len());
+        &mut self.data[..len]
+    }
+}
+
+/// Frame as sent by the probe on the IN endpoint, stamped with the
+/// probe's free running microsecond counter when it came off the bus
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default, Serialize, Deserialize)]
+pub struct RxFrame {
+    pub timestamp: u64,
+    pub frame: Frame,
+}
+
+impl RxFrame {
+    pub fn new(timestamp: u64, frame: Frame) -> Self {
+        Self { timestamp, frame }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded_len<T: Serialize>(value: &T) -> usize {
+        let mut buf = [0_u8; 256];
+        postcard::to_slice(value, &mut buf).unwrap().len()
+    }
+
+    #[test]
+    fn settings_and_filters_fit_a_control_transfer() {
+        let timing = BitTiming {
+            prescaler: u16::MAX,
+            seg1: u8::MAX,
+            seg2: u8::MAX,
+            sjw: u8::MAX,
+        };
+        let mut settings = AdaptorSettings::default();
+        settings.set_bit_timing(Some(timing));
+        settings.set_fd_enabled(true);
+        settings.set_data_bit_timing(Some(timing));
+        for _ in 0..MAX_FILTERS {
+            settings
+                .add_filter(Filter::mask(MAX_EXT_ID, MAX_EXT_ID, true))
+                .unwrap();
+        }
+        assert!(encoded_len(&settings) <= CMD_PACKET_SIZE);
+
+        for filter in &[
+            Filter::mask(u32::MAX, u32::MAX, true),
+            Filter::list(u32::MAX, u32::MAX, true),
+        ] {
+            assert!(encoded_len(filter) <= CMD_PACKET_SIZE);
+        }
+    }
 }
@@ -0,0 +1,130 @@
+use serde::{Deserialize, Serialize};
+
+use crate::Frame;
+
+/// Periodic transmissions the probe can run at once, see `UsbRequests::SetPeriodic`
+pub const MAX_PERIODIC: usize = 16;
+
+/// Rolling counter held in some bits of one payload byte
+#[derive(defmt::Format, Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Counter {
+    pub byte: u8,
+
+    /// Contiguous bits of the byte holding the counter, e.g. `0x0F` for the low nibble
+    pub mask: u8,
+}
+
+impl Counter {
+    /// Adds one to the counter in `payload`, wrapping within its mask
+    pub fn increment(&self, payload: &mut [u8]) {
+        if self.mask == 0 {
+            return;
+        }
+
+        if let Some(byte) = payload.get_mut(self.byte as usize) {
+            let shift = self.mask.trailing_zeros();
+            let value = ((*byte & self.mask) >> shift).wrapping_add(1);
+            *byte = (*byte & !self.mask) | ((value << shift) & self.mask);
+        }
+    }
+}
+
+#[derive(defmt::Format, Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ChecksumKind {
+    /// XOR of the other payload bytes
+    Xor,
+
+    /// Sum of the other payload bytes, modulo 256
+    Sum,
+
+    /// CRC-8 SAE J1850 of the other payload bytes, polynomial 0x1D with
+    /// initial value and final XOR 0xFF
+    Crc8,
+}
+
+/// Checksum over the rest of the payload, stored in one byte of it
+#[derive(defmt::Format, Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Checksum {
+    pub byte: u8,
+    pub kind: ChecksumKind,
+}
+
+fn crc8(bytes: impl Iterator<Item = u8>) -> u8 {
+    let mut crc = 0xFF_u8;
+    for byte in bytes {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x1D
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc ^ 0xFF
+}
+
+impl Checksum {
+    /// Fills in the checksum byte of `payload`
+    pub fn apply(&self, payload: &mut [u8]) {
+        let index = self.byte as usize;
+        if index >= payload.len() {
+            return;
+        }
+
+        let others = payload
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != index)
+            .map(|(_, b)| *b);
+        let checksum = match self.kind {
+            ChecksumKind::Xor => others.fold(0, |acc, b| acc ^ b),
+            ChecksumKind::Sum => others.fold(0, |acc: u8, b| acc.wrapping_add(b)),
+            ChecksumKind::Crc8 => crc8(others),
+        };
+        payload[index] = checksum;
+    }
+}
+
+/// A frame sent every `period_us`, either by the probe itself when written
+/// with `UsbRequests::SetPeriodic` or by a host side scheduler
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PeriodicTx {
+    pub frame: Frame,
+    pub period_us: u32,
+
+    /// Delay before the first transmission, counted from when the job is
+    /// added or the probe is started, to spread jobs sharing a period
+    pub phase_us: u32,
+
+    /// Incremented after every transmission
+    pub counter: Option<Counter>,
+
+    /// Recalculated before every transmission, after the counter
+    pub checksum: Option<Checksum>,
+}
+
+impl PeriodicTx {
+    pub fn new(frame: impl Into<Frame>, period_us: u32) -> Self {
+        Self {
+            frame: frame.into(),
+            period_us,
+            phase_us: 0,
+            counter: None,
+            checksum: None,
+        }
+    }
+
+    /// The frame to send now with its checksum filled in, advancing the
+    /// counter for the next transmission
+    pub fn next_frame(&mut self) -> Frame {
+        let mut frame = self.frame;
+        if let Some(checksum) = self.checksum {
+            checksum.apply(frame.payload_mut());
+        }
+        if let Some(counter) = self.counter {
+            counter.increment(self.frame.payload_mut());
+        }
+        frame
+    }
+}
@@ -0,0 +1,249 @@
+//! Linux SocketCAN `can_frame` and `canfd_frame` layout, shared by raw CAN
+//! sockets and `LINKTYPE_CAN_SOCKETCAN` captures.
+//!
+//! Both start with the identifier and its EFF/RTR/ERR flag bits, then the
+//! payload length, FD flags and two reserved bytes before the payload.
+//! Sockets use host byte order for the identifier, captures big endian.
+
+use core::convert::TryInto;
+use core::fmt;
+
+use crate::{CANFrame, FdFrame, Frame, FD_MAX_LEN, MAX_EXT_ID, MAX_STD_ID};
+
+/// Extended frame format flag in a SocketCAN identifier
+pub const CAN_EFF_FLAG: u32 = 0x8000_0000;
+
+/// Remote transmission request flag in a SocketCAN identifier
+pub const CAN_RTR_FLAG: u32 = 0x4000_0000;
+
+/// Error frame flag in a SocketCAN identifier
+pub const CAN_ERR_FLAG: u32 = 0x2000_0000;
+
+pub const CANFD_BRS: u8 = 0x01;
+pub const CANFD_ESI: u8 = 0x02;
+/// Marks a `canfd_frame`, older captures leave it unset and rely on the length
+pub const CANFD_FDF: u8 = 0x04;
+
+/// Size of a `can_frame`
+pub const CAN_MTU: usize = 16;
+
+/// Size of a `canfd_frame`
+pub const CANFD_MTU: usize = 72;
+
+/// Byte order of the identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdOrder {
+    /// As read from and written to a socket
+    Host,
+    /// As stored in PCAP and PCAPNG captures
+    Network,
+}
+
+impl IdOrder {
+    fn write(self, id: u32) -> [u8; 4] {
+        match self {
+            IdOrder::Host => id.to_ne_bytes(),
+            IdOrder::Network => id.to_be_bytes(),
+        }
+    }
+
+    fn read(self, bytes: [u8; 4]) -> u32 {
+        match self {
+            IdOrder::Host => u32::from_ne_bytes(bytes),
+            IdOrder::Network => u32::from_be_bytes(bytes),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// Fewer bytes than the identifier and length fields take
+    TooShort(usize),
+    InvalidLength(usize),
+    InvalidFdLength(usize),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::TooShort(len) => {
+                write!(f, "{} byte packet is too short for a CAN frame", len)
+            }
+            DecodeError::InvalidLength(len) => write!(f, "invalid CAN length {}", len),
+            DecodeError::InvalidFdLength(len) => write!(f, "invalid CAN FD length {}", len),
+        }
+    }
+}
+
+/// Encodes a frame into `buf`, returning the length used: `CAN_MTU` for
+/// classic frames and `CANFD_MTU` for FD frames
+pub fn encode(frame: &Frame, order: IdOrder, buf: &mut [u8; CANFD_MTU]) -> usize {
+    let mut id = if frame.is_ext() {
+        (frame.id() & MAX_EXT_ID) | CAN_EFF_FLAG
+    } else {
+        frame.id() & MAX_STD_ID
+    };
+    if frame.is_rtr() {
+        id |= CAN_RTR_FLAG;
+    }
+    if frame.is_err() {
+        id = (frame.id() & MAX_EXT_ID) | CAN_ERR_FLAG;
+    }
+
+    buf[..4].copy_from_slice(&order.write(id));
+    match frame {
+        Frame::Classic(f) => {
+            buf[4..8].copy_from_slice(&[f.dlc.min(8), 0, 0, 0]);
+            buf[8..CAN_MTU].copy_from_slice(&f.data);
+            CAN_MTU
+        }
+        Frame::Fd(f) => {
+            let flags =
+                CANFD_FDF | if f.brs { CANFD_BRS } else { 0 } | if f.esi { CANFD_ESI } else { 0 };
+            buf[4..8].copy_from_slice(&[f.len() as u8, flags, 0, 0]);
+            buf[8..CANFD_MTU].copy_from_slice(&f.data);
+            CANFD_MTU
+        }
+    }
+}
+
+/// Decodes a `can_frame` or `canfd_frame`. A truncated payload is padded
+/// with zeros, as captures may be cut short by their snapshot length.
+pub fn decode(data: &[u8], order: IdOrder) -> Result<Frame, DecodeError> {
+    if data.len() < 8 {
+        return Err(DecodeError::TooShort(data.len()));
+    }
+
+    let raw_id = order.read(data[..4].try_into().unwrap());
+    let len = data[4] as usize;
+    let flags = data[5];
+    let payload = &data[8..];
+
+    let is_ext = raw_id & CAN_EFF_FLAG != 0;
+    let id = if is_ext {
+        raw_id & MAX_EXT_ID
+    } else {
+        raw_id & MAX_STD_ID
+    };
+
+    if data.len() == CANFD_MTU || flags & CANFD_FDF != 0 {
+        if len > FD_MAX_LEN {
+            return Err(DecodeError::InvalidFdLength(len));
+        }
+        let mut buf = [0_u8; FD_MAX_LEN];
+        let copied = payload.len().min(len);
+        buf[..copied].copy_from_slice(&payload[..copied]);
+        let frame = FdFrame::new(
+            id,
+            &buf[..len],
+            flags & CANFD_BRS != 0,
+            flags & CANFD_ESI != 0,
+            is_ext,
+        )
+        .ok_or(DecodeError::InvalidFdLength(len))?;
+        return Ok(frame.into());
+    }
+
+    if len > 8 {
+        return Err(DecodeError::InvalidLength(len));
+    }
+
+    let mut buf = [0_u8; 8];
+    let copied = payload.len().min(8);
+    buf[..copied].copy_from_slice(&payload[..copied]);
+
+    if raw_id & CAN_ERR_FLAG != 0 {
+        let frame = CANFrame::new(raw_id & MAX_EXT_ID, len as u8, buf, false, true, false);
+        return Ok(frame.into());
+    }
+
+    let is_rtr = raw_id & CAN_RTR_FLAG != 0;
+    Ok(CANFrame::new(id, len as u8, buf, is_rtr, false, is_ext).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(frame: Frame, order: IdOrder) -> (usize, Frame) {
+        let mut buf = [0_u8; CANFD_MTU];
+        let len = encode(&frame, order, &mut buf);
+        (len, decode(&buf[..len], order).unwrap())
+    }
+
+    fn frames() -> [Frame; 5] {
+        [
+            CANFrame::new(0x123, 3, [1, 2, 3, 0, 0, 0, 0, 0], false, false, false).into(),
+            CANFrame::new(0x1234_5678, 8, [0xFF; 8], false, false, true).into(),
+            CANFrame::new(0x7FF, 4, [0; 8], true, false, false).into(),
+            CANFrame::new(0x004, 8, [0, 0x10, 0, 0, 0, 0, 0, 0], false, true, false).into(),
+            FdFrame::new(0x1ABC_DEF0, &[0xA5; 20], true, true, true)
+                .unwrap()
+                .into(),
+        ]
+    }
+
+    #[test]
+    fn frames_round_trip_in_both_orders() {
+        for order in &[IdOrder::Host, IdOrder::Network] {
+            for frame in &frames() {
+                let (len, decoded) = round_trip(*frame, *order);
+                let expected = match frame {
+                    Frame::Classic(_) => CAN_MTU,
+                    Frame::Fd(_) => CANFD_MTU,
+                };
+                assert_eq!(len, expected);
+                assert_eq!(decoded, *frame);
+            }
+        }
+    }
+
+    #[test]
+    fn network_order_is_big_endian() {
+        let frame: Frame = CANFrame::new(0x1234_5678, 0, [0; 8], true, false, true).into();
+        let mut buf = [0_u8; CANFD_MTU];
+        encode(&frame, IdOrder::Network, &mut buf);
+        assert_eq!(buf[..4], [0xD2, 0x34, 0x56, 0x78]);
+
+        encode(&frame, IdOrder::Host, &mut buf);
+        assert_eq!(buf[..4], 0xD234_5678_u32.to_ne_bytes());
+    }
+
+    #[test]
+    fn truncated_payloads_are_padded() {
+        let mut buf = [0_u8; CANFD_MTU];
+        let classic: Frame = CANFrame::new(0x123, 8, [0xAA; 8], false, false, false).into();
+        encode(&classic, IdOrder::Network, &mut buf);
+        let decoded = decode(&buf[..10], IdOrder::Network).unwrap();
+        assert_eq!(decoded.payload(), &[0xAA, 0xAA, 0, 0, 0, 0, 0, 0]);
+
+        let fd: Frame = FdFrame::new(0x123, &[0xAA; 64], false, false, false)
+            .unwrap()
+            .into();
+        encode(&fd, IdOrder::Network, &mut buf);
+        let decoded = decode(&buf[..40], IdOrder::Network).unwrap();
+        assert_eq!(decoded.payload().len(), 64);
+        assert_eq!(decoded.payload()[..32], [0xAA; 32]);
+        assert_eq!(decoded.payload()[32..], [0; 32]);
+    }
+
+    #[test]
+    fn invalid_packets_are_rejected() {
+        assert_eq!(
+            decode(&[0; 7], IdOrder::Network),
+            Err(DecodeError::TooShort(7))
+        );
+
+        let mut buf = [0_u8; CANFD_MTU];
+        buf[4] = 9;
+        assert_eq!(
+            decode(&buf[..CAN_MTU], IdOrder::Network),
+            Err(DecodeError::InvalidLength(9))
+        );
+        buf[4] = 65;
+        assert_eq!(
+            decode(&buf, IdOrder::Network),
+            Err(DecodeError::InvalidFdLength(65))
+        );
+    }
+}
@@ -0,0 +1,311 @@
+use core::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Bit timing of the CAN peripheral, in time quanta.
+///
+/// A bit is one sync quantum followed by `seg1` quanta (propagation and
+/// phase 1) and `seg2` quanta (phase 2), with the sample point in between.
+#[derive(defmt::Format, Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct BitTiming {
+    pub prescaler: u16,
+    pub seg1: u8,
+    pub seg2: u8,
+    pub sjw: u8,
+}
+
+/// Ranges the peripheral accepts for each `BitTiming` field
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct TimingLimits {
+    pub prescaler: (u16, u16),
+    pub seg1: (u8, u8),
+    pub seg2: (u8, u8),
+    pub sjw: (u8, u8),
+}
+
+impl TimingLimits {
+    /// Nominal (arbitration phase) bit timing of an FDCAN peripheral
+    pub const FDCAN_NOMINAL: Self = Self {
+        prescaler: (1, 512),
+        seg1: (1, 255),
+        seg2: (1, 128),
+        sjw: (1, 128),
+    };
+
+    /// Data phase bit timing of an FDCAN peripheral
+    pub const FDCAN_DATA: Self = Self {
+        prescaler: (1, 32),
+        seg1: (1, 31),
+        seg2: (1, 16),
+        sjw: (1, 16),
+    };
+}
+
+/// Common bit rates
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Bitrate {
+    K125,
+    K250,
+    K500,
+    M1,
+}
+
+impl Bitrate {
+    pub fn bits_per_second(self) -> u32 {
+        match self {
+            Bitrate::K125 => 125_000,
+            Bitrate::K250 => 250_000,
+            Bitrate::K500 => 500_000,
+            Bitrate::M1 => 1_000_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum BitTimingError {
+    PrescalerOutOfRange,
+    Seg1OutOfRange,
+    Seg2OutOfRange,
+    SjwOutOfRange,
+    /// The resynchronization jump width may not exceed phase segment 2
+    SjwLongerThanSeg2,
+    /// No valid timing divides the clock down to the requested bit rate
+    Unreachable,
+}
+
+impl fmt::Display for BitTimingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            BitTimingError::PrescalerOutOfRange => "prescaler out of range",
+            BitTimingError::Seg1OutOfRange => "time segment 1 out of range",
+            BitTimingError::Seg2OutOfRange => "time segment 2 out of range",
+            BitTimingError::SjwOutOfRange => "sync jump width out of range",
+            BitTimingError::SjwLongerThanSeg2 => "sync jump width longer than time segment 2",
+            BitTimingError::Unreachable => "bit rate cannot be reached from the peripheral clock",
+        };
+        f.write_str(msg)
+    }
+}
+
+fn within<T: PartialOrd>(value: T, (min, max): (T, T)) -> bool {
+    value >= min && value <= max
+}
+
+impl BitTiming {
+    /// Sample point aimed for by `calculate` and the presets, in tenths of a percent
+    pub const DEFAULT_SAMPLE_POINT: u16 = 875;
+
+    pub const fn new(prescaler: u16, seg1: u8, seg2: u8, sjw: u8) -> Self {
+        Self {
+            prescaler,
+            seg1,
+            seg2,
+            sjw,
+        }
+    }
+
+    /// Time quanta in one bit
+    pub fn quanta(&self) -> u32 {
+        1 + self.seg1 as u32 + self.seg2 as u32
+    }
+
+    /// Bit rate produced from a peripheral clock of `clock` Hz
+    pub fn bitrate(&self, clock: u32) -> u32 {
+        clock / (self.prescaler as u32 * self.quanta())
+    }
+
+    /// Position of the sample point within the bit, in tenths of a percent
+    pub fn sample_point(&self) -> u16 {
+        ((1 + self.seg1 as u32) * 1000 / self.quanta()) as u16
+    }
+
+    pub fn validate(&self, limits: &TimingLimits) -> Result<(), BitTimingError> {
+        if !within(self.prescaler, limits.prescaler) {
+            return Err(BitTimingError::PrescalerOutOfRange);
+        }
+        if !within(self.seg1, limits.seg1) {
+            return Err(BitTimingError::Seg1OutOfRange);
+        }
+        if !within(self.seg2, limits.seg2) {
+            return Err(BitTimingError::Seg2OutOfRange);
+        }
+        if !within(self.sjw, limits.sjw) {
+            return Err(BitTimingError::SjwOutOfRange);
+        }
+        if self.sjw > self.seg2 {
+            return Err(BitTimingError::SjwLongerThanSeg2);
+        }
+        Ok(())
+    }
+
+    /// Finds a valid timing hitting `bitrate` exactly from a `clock` Hz peripheral clock,
+    /// with the sample point (in tenths of a percent) as close to `sample_point` as possible.
+    /// Among equally good candidates the one with the most quanta per bit wins.
+    pub fn calculate(
+        bitrate: u32,
+        clock: u32,
+        sample_point: u16,
+        limits: &TimingLimits,
+    ) -> Result<Self, BitTimingError> {
+        if bitrate == 0 {
+            return Err(BitTimingError::Unreachable);
+        }
+
+        let min_quanta = 1 + limits.seg1.0 as u32 + limits.seg2.0 as u32;
+        let max_quanta = 1 + limits.seg1.1 as u32 + limits.seg2.1 as u32;
+
+        let mut best: Option<(u32, Self)> = None;
+
+        for quanta in (min_quanta..=max_quanta).rev() {
+            let divisor = match bitrate.checked_mul(quanta) {
+                Some(divisor) if clock.is_multiple_of(divisor) => divisor,
+                _ => continue,
+            };
+
+            let prescaler = clock / divisor;
+            if prescaler > limits.prescaler.1 as u32 || prescaler < limits.prescaler.0 as u32 {
+                continue;
+            }
+
+            // quanta before the sample point, including the sync quantum
+            let before = (quanta * sample_point as u32 + 500) / 1000;
+            let seg1 = before
+                .saturating_sub(1)
+                .max(limits.seg1.0 as u32)
+                .min(limits.seg1.1 as u32);
+            let seg2 = quanta - 1 - seg1;
+            if !within(seg2, (limits.seg2.0 as u32, limits.seg2.1 as u32)) {
+                continue;
+            }
+
+            let sjw = seg2.min(limits.sjw.1 as u32);
+            let timing = Self::new(prescaler as u16, seg1 as u8, seg2 as u8, sjw as u8);
+            let error = (timing.sample_point() as i32 - sample_point as i32).unsigned_abs();
+
+            if best.is_none_or(|(best_error, _)| error < best_error) {
+                best = Some((error, timing));
+            }
+        }
+
+        best.map(|(_, timing)| timing)
+            .ok_or(BitTimingError::Unreachable)
+    }
+
+    /// Timing for one of the common bit rates at the default sample point
+    pub fn preset(
+        bitrate: Bitrate,
+        clock: u32,
+        limits: &TimingLimits,
+    ) -> Result<Self, BitTimingError> {
+        Self::calculate(
+            bitrate.bits_per_second(),
+            clock,
+            Self::DEFAULT_SAMPLE_POINT,
+            limits,
+        )
+    }
+}
+
+/// What a particular probe's CAN peripheral can do, used to validate settings on the host
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct HardwareLimits {
+    /// Peripheral clock in Hz
+    pub can_clock: u32,
+
+    pub nominal: TimingLimits,
+
+    /// Data phase limits, `None` if the hardware cannot do CAN FD
+    pub data: Option<TimingLimits>,
+
+    /// Filter banks for standard identifiers
+    pub std_filters: u8,
+
+    /// Filter banks for extended identifiers
+    pub ext_filters: u8,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLOCK: u32 = 80_000_000;
+
+    #[test]
+    fn presets_at_80_mhz() {
+        let nominal = &TimingLimits::FDCAN_NOMINAL;
+        assert_eq!(
+            BitTiming::preset(Bitrate::K500, CLOCK, nominal),
+            Ok(BitTiming::new(1, 139, 20, 20))
+        );
+        assert_eq!(
+            BitTiming::preset(Bitrate::M1, CLOCK, nominal),
+            Ok(BitTiming::new(1, 69, 10, 10))
+        );
+        // 40 quanta would need seg1 past 31, so 8 quanta hit 87.5% exactly
+        assert_eq!(
+            BitTiming::calculate(
+                2_000_000,
+                CLOCK,
+                BitTiming::DEFAULT_SAMPLE_POINT,
+                &TimingLimits::FDCAN_DATA
+            ),
+            Ok(BitTiming::new(5, 6, 1, 1))
+        );
+    }
+
+    #[test]
+    fn calculated_timings_hit_the_bitrate_and_validate() {
+        for bitrate in &[Bitrate::K125, Bitrate::K250, Bitrate::K500, Bitrate::M1] {
+            let timing = BitTiming::preset(*bitrate, CLOCK, &TimingLimits::FDCAN_NOMINAL).unwrap();
+            assert_eq!(timing.bitrate(CLOCK), bitrate.bits_per_second());
+            assert_eq!(timing.sample_point(), BitTiming::DEFAULT_SAMPLE_POINT);
+            assert_eq!(timing.validate(&TimingLimits::FDCAN_NOMINAL), Ok(()));
+        }
+    }
+
+    #[test]
+    fn unreachable_bitrates_are_rejected() {
+        let nominal = &TimingLimits::FDCAN_NOMINAL;
+        let sample_point = BitTiming::DEFAULT_SAMPLE_POINT;
+        assert_eq!(
+            BitTiming::calculate(0, CLOCK, sample_point, nominal),
+            Err(BitTimingError::Unreachable)
+        );
+        // 3 does not divide the clock
+        assert_eq!(
+            BitTiming::calculate(3_000_000, CLOCK, sample_point, nominal),
+            Err(BitTimingError::Unreachable)
+        );
+        // fewer quanta per bit than the minimum
+        assert_eq!(
+            BitTiming::calculate(CLOCK / 2, CLOCK, sample_point, nominal),
+            Err(BitTimingError::Unreachable)
+        );
+        // needs more than 512 * 384 clocks per bit
+        assert_eq!(
+            BitTiming::calculate(100, CLOCK, sample_point, nominal),
+            Err(BitTimingError::Unreachable)
+        );
+    }
+
+    #[test]
+    fn validate_checks_every_field() {
+        let limits = &TimingLimits::FDCAN_DATA;
+        assert_eq!(
+            BitTiming::new(33, 10, 5, 5).validate(limits),
+            Err(BitTimingError::PrescalerOutOfRange)
+        );
+        assert_eq!(
+            BitTiming::new(1, 32, 5, 5).validate(limits),
+            Err(BitTimingError::Seg1OutOfRange)
+        );
+        assert_eq!(
+            BitTiming::new(1, 10, 0, 0).validate(limits),
+            Err(BitTimingError::Seg2OutOfRange)
+        );
+        assert_eq!(
+            BitTiming::new(1, 10, 5, 6).validate(limits),
+            Err(BitTimingError::SjwLongerThanSeg2)
+        );
+    }
+}
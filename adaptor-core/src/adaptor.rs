@@ -1,33 +1,53 @@
+use crate::clock::{ClockSync, ReceivedFrame};
+use crate::transport::{Transport, UsbTransport};
 use crate::AdaptorError;
 use crate::Result;
 
-use adaptor_common::{AdaptorSettings, CANFrame, UsbRequests};
-use adaptor_common::{CMD_PACKET_SIZE, VENDOR_ID};
+use adaptor_common::{AdaptorSettings, Frame, PeriodicTx, RxFrame, UsbRequests};
+use adaptor_common::{BitTiming, HardwareLimits, SettingsError, TimingLimits};
+use adaptor_common::{CMD_PACKET_SIZE, MAX_PERIODIC};
 
 use lazy_static::lazy_static;
 
 use rusb;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Instant, SystemTime};
+
+/// Size of a single bulk transfer from the IN endpoint,
+/// which may hold several postcard encoded frames back to back
+const READ_BUFFER_SIZE: usize = 256;
+
+/// Round trips made when syncing to the probe's clock
+const CLOCK_SYNC_SAMPLES: usize = 8;
 
 #[derive(Debug, Clone)]
-pub(crate) struct AdaptorInfo {
+pub struct AdaptorInfo {
     pub version: String,
     pub pid: u16,
     pub in_ep: u8,
     pub out_ep: u8,
     pub swo_ep: u8,
+    pub limits: HardwareLimits,
 }
 
 impl AdaptorInfo {
     pub const VID: u16 = adaptor_common::VENDOR_ID;
-    pub(crate) fn new(version: String, pid: u16, in_ep: u8, out_ep: u8, swo_ep: u8) -> Self {
+    pub(crate) fn new(
+        version: String,
+        pid: u16,
+        in_ep: u8,
+        out_ep: u8,
+        swo_ep: u8,
+        limits: HardwareLimits,
+    ) -> Self {
         Self {
             version,
             pid,
             in_ep,
             out_ep,
             swo_ep,
+            limits,
         }
     }
 }
@@ -40,106 +60,198 @@ lazy_static! {
         let mut m = HashMap::new();
         m.insert(
             0x69,
-            AdaptorInfo::new("V1".to_owned(), 0x69, 0x1, 0x81, 0x82),
+            AdaptorInfo::new(
+                "V1".to_owned(),
+                0x69,
+                0x1,
+                0x81,
+                0x82,
+                HardwareLimits {
+                    can_clock: 80_000_000,
+                    nominal: TimingLimits::FDCAN_NOMINAL,
+                    data: Some(TimingLimits::FDCAN_DATA),
+                    std_filters: 28,
+                    ext_filters: 8,
+                },
+            ),
         );
         m
     };
 }
 
+/// A probe found on the USB bus, see `enumerate`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdaptorDescriptor {
+    pub bus: u8,
+    pub address: u8,
+    pub pid: u16,
+
+    /// Hardware/firmware version from `AdaptorInfo`
+    pub version: String,
+
+    /// USB serial number, `None` if the device could not be opened to read it
+    pub serial: Option<String>,
+}
+
+/// Lists every probe currently connected
+pub fn enumerate() -> Result<Vec<AdaptorDescriptor>> {
+    let context = rusb::Context::new()?;
+    probe_devices(&context)?.iter().map(describe).collect()
+}
+
+/// All devices with our vendor id and a known product id
+fn probe_devices(context: &rusb::Context) -> Result<Vec<rusb::Device<rusb::Context>>> {
+    use rusb::UsbContext;
+    Ok(context
+        .devices()?
+        .iter()
+        .filter(|d| {
+            if let Ok(desc) = d.device_descriptor() {
+                desc.vendor_id() == AdaptorInfo::VID && VERSIONS.contains_key(&desc.product_id())
+            } else {
+                false
+            }
+        })
+        .collect())
+}
+
+fn describe(device: &rusb::Device<rusb::Context>) -> Result<AdaptorDescriptor> {
+    let desc = device.device_descriptor()?;
+    let info = VERSIONS
+        .get(&desc.product_id())
+        .ok_or(AdaptorError::NoDeviceError)?;
+
+    let serial = device
+        .open()
+        .and_then(|handle| handle.read_serial_number_string_ascii(&desc))
+        .ok();
+
+    Ok(AdaptorDescriptor {
+        bus: device.bus_number(),
+        address: device.address(),
+        pid: desc.product_id(),
+        version: info.version.clone(),
+        serial,
+    })
+}
+
 use getset::{Getters, Setters};
 
 /// Adaptor handle struct
-/// this is more or less a wrapper around `rusb::DeviceHandle`
+/// this is more or less a wrapper around a `Transport`
 /// with aditional fields for settings and info
 #[derive(Getters, Setters)]
-pub struct AdaptorHandle {
-    handle: rusb::DeviceHandle<rusb::Context>,
+pub struct AdaptorHandle<T: Transport = UsbTransport> {
+    transport: T,
 
-    #[getset(get)]
+    #[getset(get = "pub")]
     settings: AdaptorSettings,
 
-    #[getset(get)]
+    #[getset(get = "pub")]
     info: AdaptorInfo,
 
-    #[getset(get)]
+    #[getset(get = "pub")]
     running: bool,
+
+    /// Frames decoded from a bulk transfer but not yet handed out
+    pending: VecDeque<ReceivedFrame>,
+
+    clock: ClockSync,
 }
 
 impl AdaptorHandle {
     pub fn new_with_default_settings() -> Result<Self> {
         let settings = AdaptorSettings::default();
-        Ok(Self::new(settings)?)
+        Self::new(settings)
     }
 
+    /// Opens the first probe found on the bus
     pub fn new(settings: AdaptorSettings) -> Result<Self> {
-        use rusb::UsbContext;
         let context = rusb::Context::new()?;
-        let device = context
-            .devices()?
-            .iter()
-            .filter(|d| {
-                if let Ok(desc) = d.device_descriptor() {
-                    desc.vendor_id() == AdaptorInfo::VID
-                        && VERSIONS.contains_key(&desc.product_id())
-                } else {
-                    false
-                }
-            })
-            .find_map(|d| {
-                if let Ok(desc) = d.device_descriptor() {
-                    if desc.vendor_id() == AdaptorInfo::VID {
-                        Some(d)
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
+        let device = probe_devices(&context)?
+            .into_iter()
+            .next()
+            .ok_or(AdaptorError::NoDeviceError)?;
+
+        Self::open_device(&device, settings)
+    }
+
+    /// Opens the probe reporting the given USB serial number
+    pub fn open_by_serial(serial: &str, settings: AdaptorSettings) -> Result<Self> {
+        let context = rusb::Context::new()?;
+        let device = probe_devices(&context)?
+            .into_iter()
+            .find(|d| {
+                describe(d)
+                    .map(|desc| desc.serial.as_deref() == Some(serial))
+                    .unwrap_or(false)
             })
-            .map_or(Err(AdaptorError::NoDeviceError), Ok)?;
+            .ok_or(AdaptorError::NoDeviceError)?;
+
+        Self::open_device(&device, settings)
+    }
+
+    /// Opens the probe at the given bus number and device address
+    pub fn open_by_path(bus: u8, address: u8, settings: AdaptorSettings) -> Result<Self> {
+        let context = rusb::Context::new()?;
+        let device = probe_devices(&context)?
+            .into_iter()
+            .find(|d| d.bus_number() == bus && d.address() == address)
+            .ok_or(AdaptorError::NoDeviceError)?;
+
+        Self::open_device(&device, settings)
+    }
+
+    /// Opens a probe previously returned by `enumerate`
+    pub fn open(descriptor: &AdaptorDescriptor, settings: AdaptorSettings) -> Result<Self> {
+        Self::open_by_path(descriptor.bus, descriptor.address, settings)
+    }
 
-        let mut handle = device.open()?;
-        let config = device.active_config_descriptor()?;
+    fn open_device(
+        device: &rusb::Device<rusb::Context>,
+        settings: AdaptorSettings,
+    ) -> Result<Self> {
         let descriptor = device.device_descriptor()?;
 
         let info = VERSIONS
             .get(&descriptor.product_id())
-            .expect("This should never happen")
-            .clone();
+            .expect("This should never happen");
 
-        handle.claim_interface(0)?;
-
-        let mut has_in_ep = false;
-        let mut has_out_ep = false;
-
-        if let Some(iface) = config.interfaces().next() {
-            if let Some(desc) = iface.descriptors().next() {
-                for endpoint in desc.endpoint_descriptors() {
-                    let addr = endpoint.address();
-                    if addr == info.out_ep {
-                        has_out_ep = true;
-                    } else if addr == info.in_ep {
-                        has_in_ep = true;
-                    }
-                }
-            }
-        }
+        let transport = UsbTransport::open(device, info)?;
 
-        if !has_in_ep {
-            return Err(AdaptorError::NoEndpointError);
-        }
+        Self::with_transport(transport, settings)
+    }
+}
 
-        if !has_out_ep {
-            return Err(AdaptorError::NoEndpointError);
-        }
+impl<T: Transport> AdaptorHandle<T> {
+    /// Creates a handle on top of an already opened transport
+    /// and pushes `settings` to the probe
+    pub fn with_transport(transport: T, settings: AdaptorSettings) -> Result<Self> {
+        let info = VERSIONS
+            .get(&transport.product_id())
+            .ok_or(AdaptorError::NoDeviceError)?
+            .clone();
 
-        let settings = AdaptorSettings::default();
+        settings.validate(&info.limits)?;
+
+        let clock = match measure_clock(&transport, std::time::Duration::from_secs_f32(1.0)) {
+            Ok((device_time, host_time)) => ClockSync::new(device_time, host_time),
+            Err(AdaptorError::RusbError(e @ rusb::Error::Pipe))
+            | Err(AdaptorError::RusbError(e @ rusb::Error::Timeout))
+            | Err(AdaptorError::RusbError(e @ rusb::Error::NotSupported)) => {
+                log::warn!("Probe clock unavailable ({}), using host timestamps", e);
+                ClockSync::host_only()
+            }
+            Err(e) => return Err(e),
+        };
 
         let temp = Self {
-            handle,
+            transport,
             settings,
             info,
             running: false,
+            pending: VecDeque::new(),
+            clock,
         };
 
         temp.write_settings(std::time::Duration::from_secs_f32(1.0))?;
@@ -147,23 +259,142 @@ impl AdaptorHandle {
         Ok(temp)
     }
 
-    pub fn read_frame(&mut self, timeout: std::time::Duration) -> Result<CANFrame> {
-        let mut buffer = [0_u8; 256];
-        let _bytes = self
-            .handle
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Reads a single frame, returning frames left over from a
+    /// previous batched transfer before touching the bus
+    pub fn read_frame(&mut self, timeout: std::time::Duration) -> Result<Frame> {
+        if self.pending.is_empty() {
+            self.read_transfer(timeout)?;
+        }
+
+        // a zero-length transfer carries no frames, which is no different from waiting in vain
+        self.pending
+            .pop_front()
+            .map(|received| received.frame)
+            .ok_or_else(|| rusb::Error::Timeout.into())
+    }
+
+    /// Reads every frame packed into one bulk transfer
+    pub fn read_frames(&mut self, timeout: std::time::Duration) -> Result<Vec<Frame>> {
+        if self.pending.is_empty() {
+            self.read_transfer(timeout)?;
+        }
+
+        Ok(self
+            .pending
+            .drain(..)
+            .map(|received| received.frame)
+            .collect())
+    }
+
+    /// Like `read_frames` but keeps the probe's timestamps, re-syncing
+    /// the clock first if it has not been synced recently
+    pub fn read_received(&mut self, timeout: std::time::Duration) -> Result<Vec<ReceivedFrame>> {
+        if self.clock.is_stale() {
+            if let Err(e) = self.sync_clock(timeout) {
+                log::warn!("Failed to re-sync probe clock: {:?}", e);
+            }
+        }
+
+        if self.pending.is_empty() {
+            self.read_transfer(timeout)?;
+        }
+
+        Ok(self.pending.drain(..).collect())
+    }
+
+    /// Like `read_frames` but without allocating, appending to `frames` and
+    /// returning how many were added. Frames that do not fit are kept for the next read,
+    /// and nothing is read at all while `frames` is full.
+    pub fn read_frames_into<N>(
+        &mut self,
+        frames: &mut heapless::Vec<Frame, N>,
+        timeout: std::time::Duration,
+    ) -> Result<usize>
+    where
+        N: heapless::ArrayLength<Frame>,
+    {
+        let start = frames.len();
+        if start == frames.capacity() {
+            return Ok(0);
+        }
+
+        while let Some(received) = self.pending.pop_front() {
+            if frames.push(received.frame).is_err() {
+                self.pending.push_front(received);
+                break;
+            }
+        }
+
+        if frames.len() > start {
+            return Ok(frames.len() - start);
+        }
+
+        let mut buffer = [0_u8; READ_BUFFER_SIZE];
+        let bytes = self
+            .transport
             .read_bulk(self.info.in_ep, &mut buffer, timeout)?;
+        let host_time = SystemTime::now();
 
-        let (frame, _) = postcard::take_from_bytes(&buffer)?;
+        let clock = &self.clock;
+        let pending = &mut self.pending;
+        decode_frames(&buffer[..bytes], |rx| {
+            if frames.push(rx.frame).is_err() {
+                pending.push_back(received(clock, rx, host_time));
+            }
+        })?;
+
+        Ok(frames.len() - start)
+    }
+
+    /// Reads one bulk transfer into `pending`
+    fn read_transfer(&mut self, timeout: std::time::Duration) -> Result<()> {
+        let mut buffer = [0_u8; READ_BUFFER_SIZE];
+        let bytes = self
+            .transport
+            .read_bulk(self.info.in_ep, &mut buffer, timeout)?;
+        let host_time = SystemTime::now();
 
-        log::trace!("Recieved frame: {:?}", frame);
+        let clock = &self.clock;
+        let pending = &mut self.pending;
+        decode_frames(&buffer[..bytes], |rx| {
+            pending.push_back(received(clock, rx, host_time))
+        })
+    }
 
-        Ok(frame)
+    /// Reads the probe's microsecond counter
+    pub fn device_time(&self, timeout: std::time::Duration) -> Result<u64> {
+        read_device_time(&self.transport, timeout)
     }
 
-    pub fn write_frame(&mut self, frame: CANFrame, timeout: std::time::Duration) -> Result<()> {
+    /// Re-measures the offset between the probe's counter and wall clock time
+    pub fn sync_clock(&mut self, timeout: std::time::Duration) -> Result<&ClockSync> {
+        let (device_time, host_time) = measure_clock(&self.transport, timeout)?;
+        self.clock.update(device_time, host_time);
+        log::debug!("Probe clock drift: {:.1} ppm", self.clock.drift_ppm());
+        Ok(&self.clock)
+    }
+
+    pub fn clock(&self) -> &ClockSync {
+        &self.clock
+    }
+
+    /// Writes a classic or FD frame, FD frames need `fd_enabled` in the settings
+    pub fn write_frame<F>(&mut self, frame: F, timeout: std::time::Duration) -> Result<()>
+    where
+        F: Into<Frame>,
+    {
+        let frame = frame.into();
+        if frame.is_fd() && !*self.settings.fd_enabled() {
+            return Err(AdaptorError::FdDisabledError);
+        }
+
         let vec = postcard::to_stdvec(&frame)?;
         let bytes = self
-            .handle
+            .transport
             .write_bulk(self.info.out_ep, vec.as_slice(), timeout)?;
         log::info!("wrote {:?} bytes", bytes);
         Ok(())
@@ -177,35 +408,91 @@ impl AdaptorHandle {
         self.modify_settings(timeout, |s| *s = settings)
     }
 
+    /// Applies `f` to a copy of the current settings and writes the result to the probe,
+    /// leaving the settings untouched if they fail validation against the hardware
     pub fn modify_settings<F>(&mut self, timeout: std::time::Duration, f: F) -> Result<()>
     where
-        F: Fn(&mut AdaptorSettings) -> (),
+        F: Fn(&mut AdaptorSettings),
     {
-        f(&mut self.settings);
+        let mut settings = self.settings;
+        f(&mut settings);
+        settings.validate(&self.info.limits)?;
+        self.settings = settings;
         self.write_settings(timeout)
     }
 
+    /// Calculates a nominal bit timing for `bitrate` from the probe's peripheral clock
+    pub fn set_bitrate(&mut self, bitrate: u32, timeout: std::time::Duration) -> Result<()> {
+        let limits = self.info.limits;
+        let timing = BitTiming::calculate(
+            bitrate,
+            limits.can_clock,
+            BitTiming::DEFAULT_SAMPLE_POINT,
+            &limits.nominal,
+        )
+        .map_err(SettingsError::BitTiming)?;
+
+        self.modify_settings(timeout, |s| {
+            s.set_bit_timing(Some(timing));
+        })
+    }
+
+    /// Calculates a data phase bit timing for `bitrate` and enables FD
+    pub fn set_data_bitrate(&mut self, bitrate: u32, timeout: std::time::Duration) -> Result<()> {
+        let limits = self.info.limits;
+        let data_limits = limits.data.ok_or(SettingsError::FdUnsupported)?;
+        let timing = BitTiming::calculate(
+            bitrate,
+            limits.can_clock,
+            BitTiming::DEFAULT_SAMPLE_POINT,
+            &data_limits,
+        )
+        .map_err(SettingsError::DataBitTiming)?;
+
+        self.modify_settings(timeout, |s| {
+            s.set_fd_enabled(true);
+            s.set_data_bit_timing(Some(timing));
+        })
+    }
+
+    /// Writes the settings, then each enabled filter since the `Settings`
+    /// request leaves them all disabled
     fn write_settings(&self, timeout: std::time::Duration) -> Result<()> {
-        use rusb::{Direction, Recipient, RequestType};
-        let req_type = rusb::request_type(Direction::Out, RequestType::Vendor, Recipient::Device);
         let vec = postcard::to_stdvec(&self.settings)?;
-        self.handle.write_control(
-            req_type,
+        assert!(
+            vec.len() <= CMD_PACKET_SIZE,
+            "settings exceed a control transfer"
+        );
+        self.transport.write_vendor(
             UsbRequests::Settings.into(),
             0x00,
             0x00,
             vec.as_slice(),
             timeout,
         )?;
+
+        for (slot, filter) in self.settings.filters().iter().enumerate() {
+            if filter.enabled {
+                let vec = postcard::to_stdvec(filter)?;
+                assert!(
+                    vec.len() <= CMD_PACKET_SIZE,
+                    "filter exceeds a control transfer"
+                );
+                self.transport.write_vendor(
+                    UsbRequests::SetFilter.into(),
+                    slot as u16,
+                    0x00,
+                    vec.as_slice(),
+                    timeout,
+                )?;
+            }
+        }
         Ok(())
     }
 
     fn write_running(&mut self, running: bool, timeout: std::time::Duration) -> Result<()> {
-        use rusb::{Direction, Recipient, RequestType};
-        let req_type = rusb::request_type(Direction::Out, RequestType::Vendor, Recipient::Device);
         self.running = running;
-        self.handle.write_control(
-            req_type,
+        self.transport.write_vendor(
             UsbRequests::Run.into(),
             0x00,
             0x00,
@@ -226,20 +513,58 @@ impl AdaptorHandle {
     }
 
     pub fn reset(&self, timeout: std::time::Duration) -> Result<()> {
-        use rusb::{Direction, Recipient, RequestType};
-        let req_type = rusb::request_type(Direction::Out, RequestType::Vendor, Recipient::Device);
-        let _bytes =
-            self.handle
-                .write_control(req_type, UsbRequests::Reset.into(), 0, 0, &[], timeout)?;
+        let _bytes = self
+            .transport
+            .write_vendor(UsbRequests::Reset.into(), 0, 0, &[], timeout)?;
+        Ok(())
+    }
+
+    /// Has the probe send `job` by itself from `slot`, replacing whatever the
+    /// slot held. The probe keeps the timing, so transmissions are not
+    /// delayed by USB or the host's scheduler.
+    pub fn set_periodic(
+        &self,
+        slot: u8,
+        job: &PeriodicTx,
+        timeout: std::time::Duration,
+    ) -> Result<()> {
+        if slot as usize >= MAX_PERIODIC || job.period_us == 0 {
+            return Err(AdaptorError::PeriodicJobError);
+        }
+        if job.frame.is_fd() && !*self.settings.fd_enabled() {
+            return Err(AdaptorError::FdDisabledError);
+        }
+
+        let vec = postcard::to_stdvec(job)?;
+        self.transport.write_vendor(
+            UsbRequests::SetPeriodic.into(),
+            slot as u16,
+            0x00,
+            vec.as_slice(),
+            timeout,
+        )?;
+        Ok(())
+    }
+
+    /// Stops the periodic transmission in `slot`, if there is one
+    pub fn clear_periodic(&self, slot: u8, timeout: std::time::Duration) -> Result<()> {
+        if slot as usize >= MAX_PERIODIC {
+            return Err(AdaptorError::PeriodicJobError);
+        }
+
+        self.transport.write_vendor(
+            UsbRequests::ClearPeriodic.into(),
+            slot as u16,
+            0x00,
+            &[],
+            timeout,
+        )?;
         Ok(())
     }
 
     pub fn get_error(&self, timeout: std::time::Duration) -> Result<u8> {
-        use rusb::{Direction, Recipient, RequestType};
-        let req_type = rusb::request_type(Direction::In, RequestType::Vendor, Recipient::Device);
         let mut buf = [0_u8];
-        let bytes = self.handle.read_control(
-            req_type,
+        let bytes = self.transport.read_vendor(
             UsbRequests::GetError.into(),
             0x00,
             0x00,
@@ -253,8 +578,69 @@ impl AdaptorHandle {
     }
 }
 
-impl Drop for AdaptorHandle {
-    fn drop(&mut self) {
-        let _ = self.handle.release_interface(0);
+/// Decodes every frame in `bytes`, of which there may be none
+fn decode_frames<F>(mut bytes: &[u8], mut f: F) -> Result<()>
+where
+    F: FnMut(RxFrame),
+{
+    while !bytes.is_empty() {
+        let (rx, rest) = postcard::take_from_bytes::<RxFrame>(bytes)?;
+        log::trace!("Recieved frame: {:?}", rx);
+        f(rx);
+        bytes = rest;
+    }
+    Ok(())
+}
+
+fn received(clock: &ClockSync, rx: RxFrame, host_time: SystemTime) -> ReceivedFrame {
+    ReceivedFrame {
+        frame: rx.frame,
+        device_time: rx.timestamp,
+        host_time,
+        timestamp: if clock.has_hardware_clock() {
+            clock.to_system_time(rx.timestamp)
+        } else {
+            host_time
+        },
+    }
+}
+
+fn read_device_time<T: Transport>(transport: &T, timeout: std::time::Duration) -> Result<u64> {
+    let mut buf = [0_u8; 8];
+    let bytes = transport.read_vendor(
+        UsbRequests::GetTimestamp.into(),
+        0x00,
+        0x00,
+        &mut buf,
+        timeout,
+    )?;
+
+    if bytes != buf.len() {
+        return Err(AdaptorError::NotEnoughBytesSent);
+    }
+
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// Pairs a reading of the probe's counter with the host time halfway through
+/// the transfer, keeping the sample with the shortest round trip
+fn measure_clock<T: Transport>(
+    transport: &T,
+    timeout: std::time::Duration,
+) -> Result<(u64, SystemTime)> {
+    let mut best: Option<(std::time::Duration, u64, SystemTime)> = None;
+
+    for _ in 0..CLOCK_SYNC_SAMPLES {
+        let before = SystemTime::now();
+        let start = Instant::now();
+        let device_time = read_device_time(transport, timeout)?;
+        let round_trip = start.elapsed();
+
+        if best.is_none_or(|(fastest, _, _)| round_trip < fastest) {
+            best = Some((round_trip, device_time, before + round_trip / 2));
+        }
     }
+
+    let (_, device_time, host_time) = best.expect("CLOCK_SYNC_SAMPLES is non-zero");
+    Ok((device_time, host_time))
 }
@@ -0,0 +1,219 @@
+use crate::adaptor::AdaptorHandle;
+use crate::clock::ReceivedFrame;
+use crate::transport::{Transport, UsbTransport};
+use crate::AdaptorError;
+use crate::Result;
+
+use adaptor_common::{AdaptorSettings, Frame};
+
+use futures::stream::Stream;
+
+use tokio::sync::mpsc::error::TryRecvError;
+use tokio::sync::{mpsc, oneshot};
+
+use std::thread;
+use std::time::Duration;
+
+/// Requests handled by the USB I/O thread
+enum Command {
+    Send(Frame, oneshot::Sender<Result<()>>),
+    SetSettings(Box<AdaptorSettings>, oneshot::Sender<Result<()>>),
+    SetRunning(bool, oneshot::Sender<Result<()>>),
+    Reset(oneshot::Sender<Result<()>>),
+    GetError(oneshot::Sender<Result<u8>>),
+}
+
+/// Async wrapper around `AdaptorHandle`.
+///
+/// The blocking handle is moved onto a dedicated USB I/O thread which
+/// services commands and, while the probe is running, continuously
+/// forwards received frames to the `frames` stream.
+pub struct AsyncAdaptorHandle {
+    commands: mpsc::UnboundedSender<Command>,
+    frames: mpsc::Receiver<Result<ReceivedFrame>>,
+    settings: AdaptorSettings,
+    running: bool,
+}
+
+impl AsyncAdaptorHandle {
+    /// How long the I/O thread blocks on the IN endpoint before checking for commands
+    pub const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+    /// Number of received frames buffered before new ones are dropped
+    pub const FRAME_BUFFER: usize = 1024;
+
+    /// Opens the first probe found, see `AdaptorHandle::new`
+    pub fn new(settings: AdaptorSettings) -> Result<Self> {
+        Ok(Self::from_handle(AdaptorHandle::<UsbTransport>::new(
+            settings,
+        )?))
+    }
+
+    /// Moves `handle` onto its own I/O thread
+    pub fn from_handle<T>(handle: AdaptorHandle<T>) -> Self
+    where
+        T: Transport + Send + 'static,
+    {
+        let (command_tx, command_rx) = mpsc::unbounded_channel();
+        let (frame_tx, frame_rx) = mpsc::channel(Self::FRAME_BUFFER);
+
+        let settings = *handle.settings();
+        let running = *handle.running();
+
+        thread::Builder::new()
+            .name("adaptor-io".to_owned())
+            .spawn(move || io_thread(handle, command_rx, frame_tx))
+            .expect("Failed to spawn adaptor I/O thread");
+
+        Self {
+            commands: command_tx,
+            frames: frame_rx,
+            settings,
+            running,
+        }
+    }
+
+    pub fn settings(&self) -> &AdaptorSettings {
+        &self.settings
+    }
+
+    pub fn running(&self) -> bool {
+        self.running
+    }
+
+    async fn request<R>(
+        &self,
+        command: impl FnOnce(oneshot::Sender<Result<R>>) -> Command,
+    ) -> Result<R> {
+        let (tx, rx) = oneshot::channel();
+        self.commands
+            .send(command(tx))
+            .map_err(|_| AdaptorError::ConnectionError)?;
+        rx.await.map_err(|_| AdaptorError::ConnectionError)?
+    }
+
+    pub async fn send(&self, frame: impl Into<Frame>) -> Result<()> {
+        let frame = frame.into();
+        self.request(|tx| Command::Send(frame, tx)).await
+    }
+
+    pub async fn set_settings(&mut self, settings: AdaptorSettings) -> Result<()> {
+        self.request(|tx| Command::SetSettings(Box::new(settings), tx))
+            .await?;
+        self.settings = settings;
+        Ok(())
+    }
+
+    pub async fn modify_settings<F>(&mut self, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut AdaptorSettings),
+    {
+        let mut settings = self.settings;
+        f(&mut settings);
+        self.set_settings(settings).await
+    }
+
+    pub async fn start(&mut self) -> Result<()> {
+        self.request(|tx| Command::SetRunning(true, tx)).await?;
+        self.running = true;
+        Ok(())
+    }
+
+    pub async fn stop(&mut self) -> Result<()> {
+        self.request(|tx| Command::SetRunning(false, tx)).await?;
+        self.running = false;
+        Ok(())
+    }
+
+    pub async fn reset(&self) -> Result<()> {
+        self.request(Command::Reset).await
+    }
+
+    pub async fn get_error(&self) -> Result<u8> {
+        self.request(Command::GetError).await
+    }
+
+    /// Waits for the next received frame, `None` once the I/O thread has exited,
+    /// e.g. after the probe was unplugged
+    pub async fn recv(&mut self) -> Option<Result<ReceivedFrame>> {
+        self.frames.recv().await
+    }
+
+    /// Stream of received frames
+    pub fn frames(&mut self) -> impl Stream<Item = Result<ReceivedFrame>> + '_ {
+        futures::stream::poll_fn(move |cx| self.frames.poll_recv(cx))
+    }
+}
+
+fn handle_command<T: Transport>(handle: &mut AdaptorHandle<T>, command: Command) {
+    let timeout = Duration::from_secs(1);
+
+    // the requester may have given up waiting, so failed replies are ignored
+    match command {
+        Command::Send(frame, reply) => {
+            let _ = reply.send(handle.write_frame(frame, timeout));
+        }
+        Command::SetSettings(settings, reply) => {
+            let _ = reply.send(handle.set_settings(*settings, timeout));
+        }
+        Command::SetRunning(true, reply) => {
+            let _ = reply.send(handle.start(timeout));
+        }
+        Command::SetRunning(false, reply) => {
+            let _ = reply.send(handle.stop(timeout));
+        }
+        Command::Reset(reply) => {
+            let _ = reply.send(handle.reset(timeout));
+        }
+        Command::GetError(reply) => {
+            let _ = reply.send(handle.get_error(timeout));
+        }
+    }
+}
+
+fn io_thread<T: Transport>(
+    mut handle: AdaptorHandle<T>,
+    mut commands: mpsc::UnboundedReceiver<Command>,
+    frames: mpsc::Sender<Result<ReceivedFrame>>,
+) {
+    loop {
+        loop {
+            match commands.try_recv() {
+                Ok(command) => handle_command(&mut handle, command),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return,
+            }
+        }
+
+        // nothing to receive while stopped, so sleep until told otherwise
+        if !*handle.running() {
+            match commands.blocking_recv() {
+                Some(command) => handle_command(&mut handle, command),
+                None => return,
+            }
+            continue;
+        }
+
+        let results = match handle.read_received(AsyncAdaptorHandle::POLL_INTERVAL) {
+            Ok(received) => received.into_iter().map(Ok).collect(),
+            Err(AdaptorError::RusbError(rusb::Error::Timeout)) => continue,
+            Err(e @ AdaptorError::RusbError(rusb::Error::NoDevice)) => {
+                // the stream ends after this error, as there is nothing left to read from
+                log::error!("Probe disconnected, stopping I/O thread");
+                let _ = frames.try_send(Err(e));
+                return;
+            }
+            Err(e) => {
+                // don't spin on an endpoint that keeps failing
+                thread::sleep(AsyncAdaptorHandle::POLL_INTERVAL);
+                vec![Err(e)]
+            }
+        };
+
+        for result in results {
+            if let Err(mpsc::error::TrySendError::Full(_)) = frames.try_send(result) {
+                log::warn!("Receive buffer full, dropping frame");
+            }
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use adaptor_common::Frame;
+
+use std::time::{Duration, Instant, SystemTime};
+
+/// A frame received from the probe along with when it happened
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReceivedFrame {
+    pub frame: Frame,
+
+    /// Probe's microsecond counter when the frame came off the bus
+    pub device_time: u64,
+
+    /// When the USB transfer carrying the frame reached the host
+    pub host_time: SystemTime,
+
+    /// `device_time` mapped onto wall clock time, free of USB scheduling jitter.
+    /// Same as `host_time` for probes without a readable counter.
+    pub timestamp: SystemTime,
+}
+
+/// Maps the probe's microsecond counter onto wall clock time.
+///
+/// Each sync pairs a counter value with the host time at the midpoint of
+/// the control transfer that read it. Successive syncs also estimate how
+/// fast the probe's crystal runs relative to the host clock.
+#[derive(Debug, Clone, Copy)]
+pub struct ClockSync {
+    device_ref: u64,
+    host_ref: SystemTime,
+
+    /// Host microseconds per probe tick
+    rate: f64,
+
+    synced_at: Instant,
+
+    /// Whether the probe's counter could be read at all
+    hardware: bool,
+}
+
+impl ClockSync {
+    /// Largest drift between the two clocks that is believed, in parts per million
+    pub const MAX_DRIFT_PPM: f64 = 1000.0;
+
+    /// How often a handle re-syncs while frames are being read
+    pub const RESYNC_INTERVAL: Duration = Duration::from_secs(10);
+
+    pub fn new(device_time: u64, host_time: SystemTime) -> Self {
+        Self {
+            device_ref: device_time,
+            host_ref: host_time,
+            rate: 1.0,
+            synced_at: Instant::now(),
+            hardware: true,
+        }
+    }
+
+    /// For probes that don't answer `GetTimestamp`, frames are then
+    /// stamped with the host time their transfer arrived
+    pub fn host_only() -> Self {
+        Self {
+            hardware: false,
+            ..Self::new(0, SystemTime::now())
+        }
+    }
+
+    pub fn has_hardware_clock(&self) -> bool {
+        self.hardware
+    }
+
+    /// Folds in a newer measurement, re-estimating drift against the previous one
+    pub fn update(&mut self, device_time: u64, host_time: SystemTime) {
+        if !self.hardware {
+            *self = Self::new(device_time, host_time);
+            return;
+        }
+
+        if device_time > self.device_ref {
+            if let Ok(elapsed) = host_time.duration_since(self.host_ref) {
+                let ticks = (device_time - self.device_ref) as f64;
+                let rate = elapsed.as_secs_f64() * 1e6 / ticks;
+                let max = Self::MAX_DRIFT_PPM * 1e-6;
+                if (rate - 1.0).abs() <= max {
+                    self.rate = rate;
+                } else {
+                    log::warn!(
+                        "Ignoring implausible clock drift of {:.0} ppm",
+                        (rate - 1.0) * 1e6
+                    );
+                }
+            }
+        } else {
+            // counter went backwards, so the probe was reset
+            self.rate = 1.0;
+        }
+
+        self.device_ref = device_time;
+        self.host_ref = host_time;
+        self.synced_at = Instant::now();
+    }
+
+    pub fn to_system_time(&self, device_time: u64) -> SystemTime {
+        let ticks = device_time as i128 - self.device_ref as i128;
+        let micros = (ticks as f64 * self.rate).round();
+        if micros >= 0.0 {
+            self.host_ref + Duration::from_micros(micros as u64)
+        } else {
+            self.host_ref - Duration::from_micros(-micros as u64)
+        }
+    }
+
+    /// Estimated drift of the probe's clock in parts per million
+    pub fn drift_ppm(&self) -> f64 {
+        (self.rate - 1.0) * 1e6
+    }
+
+    /// Never true without a hardware clock, as there is nothing to re-sync to
+    pub fn is_stale(&self) -> bool {
+        self.hardware && self.synced_at.elapsed() > Self::RESYNC_INTERVAL
+    }
+}
@@ -3,8 +3,8 @@ use quick_error::quick_error;
 quick_error! {
     #[derive(Debug)]
     pub enum AdaptorError {
-        RusbError {
-            from(rusb::Error)
+        RusbError(err: rusb::Error) {
+            from()
         }
         SerdeError {
             from(postcard::Error)
@@ -12,7 +12,18 @@ quick_error! {
         NoDeviceError {}
         NoEndpointError {}
         ConnectionError {}
-        SettingsError {}
+        SettingsError(err: adaptor_common::SettingsError) {
+            from()
+        }
+        FdDisabledError {}
+        PeriodicJobError {}
         NotEnoughBytesSent {}
     }
 }
+
+impl AdaptorError {
+    /// Whether the error is a USB transfer timing out, which usually just means the bus was quiet
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, AdaptorError::RusbError(rusb::Error::Timeout))
+    }
+}
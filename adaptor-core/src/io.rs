@@ -0,0 +1,44 @@
+use crate::adaptor::AdaptorHandle;
+use crate::transport::Transport;
+use crate::Result;
+
+use adaptor_common::Frame;
+
+use std::time::Duration;
+
+/// Shortest timeout passed to a USB transfer, as libusb treats zero as no timeout at all
+const MIN_TIMEOUT: Duration = Duration::from_millis(1);
+
+/// Anything frames can be sent to and received from, so protocols built on
+/// top of the probe work the same over an `AdaptorHandle`, a
+/// `threaded::Worker` or a test double
+pub trait FrameIo {
+    fn send_frame(&mut self, frame: Frame, timeout: Duration) -> Result<()>;
+
+    /// Waits up to `timeout` for the next frame, `None` if nothing arrived
+    fn recv_frame(&mut self, timeout: Duration) -> Result<Option<Frame>>;
+}
+
+impl<T: Transport> FrameIo for AdaptorHandle<T> {
+    fn send_frame(&mut self, frame: Frame, timeout: Duration) -> Result<()> {
+        self.write_frame(frame, timeout.max(MIN_TIMEOUT))
+    }
+
+    fn recv_frame(&mut self, timeout: Duration) -> Result<Option<Frame>> {
+        match self.read_frame(timeout.max(MIN_TIMEOUT)) {
+            Ok(frame) => Ok(Some(frame)),
+            Err(e) if e.is_timeout() => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl<I: FrameIo + ?Sized> FrameIo for &mut I {
+    fn send_frame(&mut self, frame: Frame, timeout: Duration) -> Result<()> {
+        (**self).send_frame(frame, timeout)
+    }
+
+    fn recv_frame(&mut self, timeout: Duration) -> Result<Option<Frame>> {
+        (**self).recv_frame(timeout)
+    }
+}
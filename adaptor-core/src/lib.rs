@@ -1,11 +1,32 @@
-#![feature(array_methods)]
 #![allow(dead_code)]
 
 pub mod adaptor;
+pub mod async_handle;
+pub mod clock;
 pub mod errors;
+pub mod io;
+pub mod scheduler;
+pub mod transport;
+
+#[cfg(feature = "testing")]
+pub mod testing;
+
+#[cfg(feature = "threaded")]
+pub mod threaded;
 
 pub type Result<T> = std::result::Result<T, errors::AdaptorError>;
 
-pub use adaptor::AdaptorHandle;
-pub use adaptor_common::AdaptorSettings;
+pub use adaptor::{enumerate, AdaptorDescriptor, AdaptorHandle, AdaptorInfo};
+pub use adaptor_common::{AdaptorSettings, CANFrame, FdFrame, Frame};
+pub use adaptor_common::{Checksum, ChecksumKind, Counter, PeriodicTx};
+pub use async_handle::AsyncAdaptorHandle;
+pub use clock::{ClockSync, ReceivedFrame};
 pub use errors::AdaptorError;
+pub use io::FrameIo;
+pub use scheduler::{JobId, Schedule};
+pub use transport::{LoopbackTransport, Transport, UsbTransport};
+
+#[cfg(feature = "threaded")]
+pub use scheduler::Scheduler;
+#[cfg(feature = "threaded")]
+pub use threaded::Worker;
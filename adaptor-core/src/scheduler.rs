@@ -0,0 +1,385 @@
+//! Periodic transmissions timed on the host, for cyclic traffic that does
+//! not fit in the probe's own slots (see `AdaptorHandle::set_periodic`) or
+//! that needs its payload changed more often than it is worth rewriting a slot.
+
+use crate::adaptor::AdaptorHandle;
+use crate::transport::Transport;
+use crate::AdaptorError;
+use crate::Result;
+
+use adaptor_common::{Frame, PeriodicTx};
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Longest sleep between checks of a stop flag
+const MAX_SLEEP: Duration = Duration::from_millis(100);
+
+/// Identifies a job added to a `Schedule` or `Scheduler`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct JobId(usize);
+
+#[derive(Debug, Clone)]
+struct Job {
+    id: JobId,
+    tx: PeriodicTx,
+    next: Instant,
+}
+
+impl Job {
+    fn period(&self) -> Duration {
+        Duration::from_micros(self.tx.period_us as u64)
+    }
+}
+
+/// A set of periodic jobs, each with its own period and phase
+#[derive(Debug, Clone, Default)]
+pub struct Schedule {
+    jobs: Vec<Job>,
+    next_id: usize,
+}
+
+impl Schedule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a job, first due `phase_us` from now
+    pub fn add(&mut self, tx: PeriodicTx) -> Result<JobId> {
+        if tx.period_us == 0 {
+            return Err(AdaptorError::PeriodicJobError);
+        }
+
+        let id = JobId(self.next_id);
+        self.next_id += 1;
+        self.jobs.push(Job {
+            id,
+            tx,
+            next: Instant::now() + Duration::from_micros(tx.phase_us as u64),
+        });
+        Ok(id)
+    }
+
+    pub fn remove(&mut self, id: JobId) -> Option<PeriodicTx> {
+        let index = self.jobs.iter().position(|job| job.id == id)?;
+        Some(self.jobs.remove(index).tx)
+    }
+
+    pub fn get(&self, id: JobId) -> Option<&PeriodicTx> {
+        self.jobs.iter().find(|job| job.id == id).map(|job| &job.tx)
+    }
+
+    /// Changes a job while keeping its place in the schedule, the change is
+    /// rejected if it leaves the job without a period
+    pub fn update<F>(&mut self, id: JobId, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut PeriodicTx),
+    {
+        let job = self
+            .jobs
+            .iter_mut()
+            .find(|job| job.id == id)
+            .ok_or(AdaptorError::PeriodicJobError)?;
+
+        let mut tx = job.tx;
+        f(&mut tx);
+        if tx.period_us == 0 {
+            return Err(AdaptorError::PeriodicJobError);
+        }
+        job.tx = tx;
+        Ok(())
+    }
+
+    /// Replaces the frame a job sends, from its next transmission on
+    pub fn set_frame(&mut self, id: JobId, frame: impl Into<Frame>) -> Result<()> {
+        let frame = frame.into();
+        self.update(id, |tx| tx.frame = frame)
+    }
+
+    pub fn len(&self) -> usize {
+        self.jobs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    /// Realigns every job so it is next due its phase after `now`
+    pub fn restart(&mut self, now: Instant) {
+        for job in &mut self.jobs {
+            job.next = now + Duration::from_micros(job.tx.phase_us as u64);
+        }
+    }
+
+    /// When the earliest job is next due
+    pub fn next_due(&self) -> Option<Instant> {
+        self.jobs.iter().map(|job| job.next).min()
+    }
+
+    /// Passes the frame of every job due at `now` to `send`, returning how
+    /// many were sent. A job that fell more than a period behind skips the
+    /// transmissions it missed rather than sending them in a burst.
+    pub fn poll<F>(&mut self, now: Instant, mut send: F) -> Result<usize>
+    where
+        F: FnMut(Frame) -> Result<()>,
+    {
+        let mut sent = 0;
+        for job in &mut self.jobs {
+            if job.next > now {
+                continue;
+            }
+
+            send(job.tx.next_frame())?;
+            sent += 1;
+
+            let period = job.period();
+            job.next += period;
+            if job.next <= now {
+                // the first deadline after `now` that keeps the job's phase
+                let behind = (now - job.next).as_nanos() % period.as_nanos();
+                job.next = now + (period - Duration::from_nanos(behind as u64));
+            }
+        }
+        Ok(sent)
+    }
+
+    /// Sends the jobs through `handle` until `stop` is set
+    pub fn run_until<T: Transport>(
+        &mut self,
+        handle: &mut AdaptorHandle<T>,
+        timeout: Duration,
+        stop: &AtomicBool,
+    ) -> Result<()> {
+        while !stop.load(Ordering::Relaxed) {
+            let now = Instant::now();
+            self.poll(now, |frame| handle.write_frame(frame, timeout))?;
+
+            let wait = self
+                .next_due()
+                .map_or(MAX_SLEEP, |due| due.saturating_duration_since(now));
+            std::thread::sleep(wait.min(MAX_SLEEP));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "threaded")]
+pub use self::threaded::Scheduler;
+
+#[cfg(feature = "threaded")]
+mod threaded {
+    use super::{JobId, Schedule, MAX_SLEEP};
+    use crate::threaded::FrameSender;
+    use crate::Result;
+
+    use adaptor_common::{Frame, PeriodicTx};
+
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+    use std::thread::{self, JoinHandle};
+    use std::time::Instant;
+
+    #[derive(Debug, Default)]
+    struct Shared {
+        schedule: Mutex<Schedule>,
+        changed: Condvar,
+        shutdown: AtomicBool,
+    }
+
+    /// Background thread feeding a `Schedule` to a `Worker`, so jobs can be
+    /// added and their payloads updated while frames are being received
+    pub struct Scheduler {
+        shared: Arc<Shared>,
+        thread: Option<JoinHandle<()>>,
+    }
+
+    impl Scheduler {
+        pub fn spawn(sender: FrameSender) -> Self {
+            let shared = Arc::new(Shared::default());
+
+            let thread = {
+                let shared = shared.clone();
+                thread::Builder::new()
+                    .name("adaptor-scheduler".to_owned())
+                    .spawn(move || run(&shared, &sender))
+                    .expect("Failed to spawn adaptor scheduler thread")
+            };
+
+            Self {
+                shared,
+                thread: Some(thread),
+            }
+        }
+
+        fn schedule(&self) -> MutexGuard<'_, Schedule> {
+            self.shared
+                .schedule
+                .lock()
+                .expect("scheduler state poisoned")
+        }
+
+        /// Runs `f` on the schedule and wakes the thread to pick up the change
+        fn modify<R>(&self, f: impl FnOnce(&mut Schedule) -> R) -> R {
+            let result = f(&mut self.schedule());
+            self.shared.changed.notify_all();
+            result
+        }
+
+        pub fn add(&self, tx: PeriodicTx) -> Result<JobId> {
+            self.modify(|schedule| schedule.add(tx))
+        }
+
+        pub fn remove(&self, id: JobId) -> Option<PeriodicTx> {
+            self.modify(|schedule| schedule.remove(id))
+        }
+
+        pub fn get(&self, id: JobId) -> Option<PeriodicTx> {
+            self.schedule().get(id).copied()
+        }
+
+        /// See `Schedule::update`
+        pub fn update<F>(&self, id: JobId, f: F) -> Result<()>
+        where
+            F: FnOnce(&mut PeriodicTx),
+        {
+            self.modify(|schedule| schedule.update(id, f))
+        }
+
+        pub fn set_frame(&self, id: JobId, frame: impl Into<Frame>) -> Result<()> {
+            let frame = frame.into();
+            self.modify(|schedule| schedule.set_frame(id, frame))
+        }
+
+        /// Stops sending and returns the schedule
+        pub fn shutdown(mut self) -> Schedule {
+            self.stop();
+            std::mem::take(&mut *self.schedule())
+        }
+
+        fn stop(&mut self) {
+            self.shared.shutdown.store(true, Ordering::Relaxed);
+            self.shared.changed.notify_all();
+            if let Some(thread) = self.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+
+    impl Drop for Scheduler {
+        fn drop(&mut self) {
+            self.stop();
+        }
+    }
+
+    fn run(shared: &Shared, sender: &FrameSender) {
+        let mut schedule = shared.schedule.lock().expect("scheduler state poisoned");
+
+        while !shared.shutdown.load(Ordering::Relaxed) {
+            let now = Instant::now();
+            let mut due = Vec::new();
+            let _ = schedule.poll(now, |frame| {
+                due.push(frame);
+                Ok(())
+            });
+
+            if !due.is_empty() {
+                // let jobs be changed while the frames are queued
+                drop(schedule);
+                for frame in due {
+                    if sender.send(frame).is_err() {
+                        log::error!("Worker stopped, stopping scheduler");
+                        return;
+                    }
+                }
+                schedule = shared.schedule.lock().expect("scheduler state poisoned");
+                continue;
+            }
+
+            let wait = schedule
+                .next_due()
+                .map_or(MAX_SLEEP, |due| due.saturating_duration_since(now));
+            schedule = shared
+                .changed
+                .wait_timeout(schedule, wait.min(MAX_SLEEP))
+                .expect("scheduler state poisoned")
+                .0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use adaptor_common::CANFrame;
+
+    const PERIOD: Duration = Duration::from_millis(10);
+
+    fn schedule(period: Duration, phase_us: u32) -> (Schedule, Instant) {
+        let frame = CANFrame::new(0x123, 1, [0; 8], false, false, false);
+        let mut tx = PeriodicTx::new(frame, period.as_micros() as u32);
+        tx.phase_us = phase_us;
+
+        let mut schedule = Schedule::new();
+        schedule.add(tx).unwrap();
+        let start = Instant::now();
+        schedule.restart(start);
+        (schedule, start)
+    }
+
+    fn poll(schedule: &mut Schedule, now: Instant) -> usize {
+        schedule.poll(now, |_| Ok(())).unwrap()
+    }
+
+    #[test]
+    fn jobs_are_sent_once_per_period_after_their_phase() {
+        let (mut schedule, start) = schedule(PERIOD, 2_000);
+        let phase = Duration::from_millis(2);
+        assert_eq!(schedule.next_due(), Some(start + phase));
+
+        assert_eq!(poll(&mut schedule, start), 0);
+        assert_eq!(poll(&mut schedule, start + phase), 1);
+        assert_eq!(poll(&mut schedule, start + phase), 0);
+        assert_eq!(schedule.next_due(), Some(start + phase + PERIOD));
+        assert_eq!(poll(&mut schedule, start + phase + PERIOD), 1);
+    }
+
+    #[test]
+    fn missed_periods_are_skipped_not_sent_in_a_burst() {
+        let (mut schedule, start) = schedule(PERIOD, 0);
+        assert_eq!(poll(&mut schedule, start), 1);
+
+        let late = start + PERIOD * 10 + PERIOD / 2;
+        assert_eq!(poll(&mut schedule, late), 1);
+        assert_eq!(poll(&mut schedule, late), 0);
+        assert_eq!(schedule.next_due(), Some(start + PERIOD * 11));
+    }
+
+    #[test]
+    fn long_stalls_keep_the_phase() {
+        let period = Duration::from_micros(1);
+        let (mut schedule, start) = schedule(period, 0);
+        assert_eq!(poll(&mut schedule, start), 1);
+
+        // more missed periods than fit in a u32
+        let late = start + Duration::from_secs(5 * 3600) + Duration::from_nanos(300);
+        assert_eq!(poll(&mut schedule, late), 1);
+        assert_eq!(
+            schedule.next_due(),
+            Some(start + Duration::from_secs(5 * 3600) + period)
+        );
+    }
+
+    #[test]
+    fn jobs_need_a_period() {
+        let (mut schedule, _) = schedule(PERIOD, 0);
+        let frame = CANFrame::new(0x123, 0, [0; 8], false, false, false);
+        assert!(schedule.add(PeriodicTx::new(frame, 0)).is_err());
+
+        let id = schedule.add(PeriodicTx::new(frame, 1_000)).unwrap();
+        assert!(schedule.update(id, |tx| tx.period_us = 0).is_err());
+        assert_eq!(schedule.get(id).unwrap().period_us, 1_000);
+        assert_eq!(schedule.remove(id).unwrap().period_us, 1_000);
+        assert_eq!(schedule.len(), 1);
+    }
+}
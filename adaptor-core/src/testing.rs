@@ -0,0 +1,73 @@
+//! `FrameIo` double standing in for the other end of a connection, for
+//! testing protocols built on top of the probe
+
+use crate::io::FrameIo;
+use crate::Result;
+
+use adaptor_common::Frame;
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+type Responder = Box<dyn FnMut(&Frame) -> Vec<Frame>>;
+
+/// Records what is sent and answers each frame with whatever `respond`
+/// returns. Reads give up after sleeping out their timeout when nothing is
+/// queued, so timers behave as they would on a quiet bus.
+pub struct ScriptedIo {
+    pub incoming: VecDeque<Frame>,
+    pub sent: Vec<(Instant, Frame)>,
+    respond: Responder,
+}
+
+impl ScriptedIo {
+    pub fn new() -> Self {
+        Self::responding(|_| Vec::new())
+    }
+
+    pub fn responding<F>(respond: F) -> Self
+    where
+        F: FnMut(&Frame) -> Vec<Frame> + 'static,
+    {
+        Self {
+            incoming: VecDeque::new(),
+            sent: Vec::new(),
+            respond: Box::new(respond),
+        }
+    }
+
+    /// Frames sent so far
+    pub fn sent_frames(&self) -> impl Iterator<Item = &Frame> {
+        self.sent.iter().map(|(_, f)| f)
+    }
+
+    /// Payloads of the frames sent so far
+    pub fn sent_payloads(&self) -> Vec<Vec<u8>> {
+        self.sent_frames().map(|f| f.payload().to_vec()).collect()
+    }
+}
+
+impl Default for ScriptedIo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameIo for ScriptedIo {
+    fn send_frame(&mut self, frame: Frame, _timeout: Duration) -> Result<()> {
+        self.sent.push((Instant::now(), frame));
+        let replies = (self.respond)(&frame);
+        self.incoming.extend(replies);
+        Ok(())
+    }
+
+    fn recv_frame(&mut self, timeout: Duration) -> Result<Option<Frame>> {
+        match self.incoming.pop_front() {
+            Some(frame) => Ok(Some(frame)),
+            None => {
+                std::thread::sleep(timeout);
+                Ok(None)
+            }
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use crate::adaptor::AdaptorHandle;
+use crate::transport::{Transport, UsbTransport};
+use crate::AdaptorError;
+use crate::Result;
+
+use crate::clock::ReceivedFrame;
+use crate::io::FrameIo;
+
+use adaptor_common::Frame;
+
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, TryRecvError, TrySendError};
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Tuning for the background worker
+#[derive(Debug, Clone, Copy)]
+pub struct WorkerConfig {
+    /// Received frames buffered before new ones are dropped
+    pub rx_capacity: usize,
+
+    /// Outbound frames queued before `FrameSender::send` blocks
+    pub tx_capacity: usize,
+
+    /// How long a single read of the IN endpoint blocks, which is also the
+    /// worst case latency before a queued frame is written
+    pub poll_interval: Duration,
+
+    pub write_timeout: Duration,
+}
+
+impl Default for WorkerConfig {
+    fn default() -> Self {
+        Self {
+            rx_capacity: 4096,
+            tx_capacity: 256,
+            poll_interval: Duration::from_millis(5),
+            write_timeout: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Cloneable handle for queueing frames to be written by the worker
+#[derive(Debug, Clone)]
+pub struct FrameSender {
+    tx: Sender<Frame>,
+}
+
+impl FrameSender {
+    /// Queues a frame, blocking while the outbound queue is full
+    pub fn send(&self, frame: impl Into<Frame>) -> Result<()> {
+        self.tx
+            .send(frame.into())
+            .map_err(|_| AdaptorError::ConnectionError)
+    }
+
+    /// Queues a frame, returning it if the outbound queue is full
+    pub fn try_send(&self, frame: impl Into<Frame>) -> Result<Option<Frame>> {
+        match self.tx.try_send(frame.into()) {
+            Ok(()) => Ok(None),
+            Err(TrySendError::Full(frame)) => Ok(Some(frame)),
+            Err(TrySendError::Disconnected(_)) => Err(AdaptorError::ConnectionError),
+        }
+    }
+}
+
+/// Cloneable handle for frames drained from the IN endpoint by the worker.
+/// Clones share one queue, so each frame is delivered to a single receiver.
+#[derive(Debug, Clone)]
+pub struct FrameReceiver {
+    rx: Receiver<ReceivedFrame>,
+}
+
+impl FrameReceiver {
+    pub fn recv(&self) -> Result<ReceivedFrame> {
+        self.rx.recv().map_err(|_| AdaptorError::ConnectionError)
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<ReceivedFrame> {
+        self.rx.recv_timeout(timeout).map_err(|e| match e {
+            RecvTimeoutError::Timeout => AdaptorError::RusbError(rusb::Error::Timeout),
+            RecvTimeoutError::Disconnected => AdaptorError::ConnectionError,
+        })
+    }
+
+    pub fn try_recv(&self) -> Result<Option<ReceivedFrame>> {
+        match self.rx.try_recv() {
+            Ok(frame) => Ok(Some(frame)),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(AdaptorError::ConnectionError),
+        }
+    }
+
+    /// Blocking iterator over received frames, ends when the worker stops
+    pub fn iter(&self) -> impl Iterator<Item = ReceivedFrame> + '_ {
+        self.rx.iter()
+    }
+}
+
+/// Both ends of a worker's queues, for protocols written against `FrameIo`
+#[derive(Debug, Clone)]
+pub struct FrameChannel {
+    pub sender: FrameSender,
+    pub receiver: FrameReceiver,
+}
+
+impl FrameIo for FrameChannel {
+    fn send_frame(&mut self, frame: Frame, _timeout: Duration) -> Result<()> {
+        self.sender.send(frame)
+    }
+
+    fn recv_frame(&mut self, timeout: Duration) -> Result<Option<Frame>> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(received) => Ok(Some(received.frame)),
+            Err(e) if e.is_timeout() => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Background thread owning an `AdaptorHandle`.
+///
+/// The worker continuously drains the IN endpoint into a bounded channel
+/// and writes queued outbound frames in order. The handle should be
+/// configured and started before it is handed over; `shutdown` gives it back.
+pub struct Worker<T: Transport + Send + 'static = UsbTransport> {
+    sender: FrameSender,
+    receiver: FrameReceiver,
+    shutdown: Arc<AtomicBool>,
+    dropped: Arc<AtomicUsize>,
+    thread: Option<JoinHandle<AdaptorHandle<T>>>,
+}
+
+impl<T: Transport + Send + 'static> Worker<T> {
+    pub fn spawn(handle: AdaptorHandle<T>) -> Self {
+        Self::with_config(handle, WorkerConfig::default())
+    }
+
+    pub fn with_config(handle: AdaptorHandle<T>, config: WorkerConfig) -> Self {
+        let (out_tx, out_rx) = crossbeam_channel::bounded(config.tx_capacity);
+        let (in_tx, in_rx) = crossbeam_channel::bounded(config.rx_capacity);
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let dropped = Arc::new(AtomicUsize::new(0));
+
+        let thread = {
+            let shutdown = shutdown.clone();
+            let dropped = dropped.clone();
+            thread::Builder::new()
+                .name("adaptor-worker".to_owned())
+                .spawn(move || run(handle, config, out_rx, in_tx, shutdown, dropped))
+                .expect("Failed to spawn adaptor worker thread")
+        };
+
+        Self {
+            sender: FrameSender { tx: out_tx },
+            receiver: FrameReceiver { rx: in_rx },
+            shutdown,
+            dropped,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn sender(&self) -> FrameSender {
+        self.sender.clone()
+    }
+
+    pub fn receiver(&self) -> FrameReceiver {
+        self.receiver.clone()
+    }
+
+    pub fn channel(&self) -> FrameChannel {
+        FrameChannel {
+            sender: self.sender(),
+            receiver: self.receiver(),
+        }
+    }
+
+    /// Number of received frames dropped because the receive queue was full
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Stops the worker and returns the handle it owned.
+    /// Returns `None` if the worker thread panicked.
+    pub fn shutdown(mut self) -> Option<AdaptorHandle<T>> {
+        self.stop()
+    }
+
+    fn stop(&mut self) -> Option<AdaptorHandle<T>> {
+        self.shutdown.store(true, Ordering::Relaxed);
+        self.thread.take().and_then(|thread| thread.join().ok())
+    }
+}
+
+impl<T: Transport + Send + 'static> Drop for Worker<T> {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+fn run<T: Transport>(
+    mut handle: AdaptorHandle<T>,
+    config: WorkerConfig,
+    outbound: Receiver<Frame>,
+    inbound: Sender<ReceivedFrame>,
+    shutdown: Arc<AtomicBool>,
+    dropped: Arc<AtomicUsize>,
+) -> AdaptorHandle<T> {
+    while !shutdown.load(Ordering::Relaxed) {
+        while let Ok(frame) = outbound.try_recv() {
+            if let Err(e) = handle.write_frame(frame, config.write_timeout) {
+                log::error!("Failed to write frame {:?}: {:?}", frame, e);
+            }
+        }
+
+        match handle.read_received(config.poll_interval) {
+            Ok(frames) => {
+                for frame in frames {
+                    if let Err(TrySendError::Full(frame)) = inbound.try_send(frame) {
+                        dropped.fetch_add(1, Ordering::Relaxed);
+                        log::warn!("Receive queue full, dropping frame {:?}", frame);
+                    }
+                }
+            }
+            Err(AdaptorError::RusbError(rusb::Error::Timeout)) => {}
+            Err(AdaptorError::RusbError(rusb::Error::NoDevice)) => {
+                log::error!("Probe disconnected, stopping worker");
+                break;
+            }
+            Err(e) => log::error!("Failed to read frame: {:?}", e),
+        }
+    }
+
+    handle
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::LoopbackTransport;
+
+    use adaptor_common::{AdaptorSettings, CANFrame};
+
+    use std::time::Instant;
+
+    const TIMEOUT: Duration = Duration::from_millis(500);
+
+    fn frame(id: u32) -> CANFrame {
+        CANFrame::new(id, 1, [id as u8, 0, 0, 0, 0, 0, 0, 0], false, false, false)
+    }
+
+    fn started() -> AdaptorHandle<LoopbackTransport> {
+        let mut handle =
+            AdaptorHandle::with_transport(LoopbackTransport::default(), AdaptorSettings::default())
+                .unwrap();
+        handle.start(TIMEOUT).unwrap();
+        handle
+    }
+
+    #[test]
+    fn frames_round_trip_through_the_worker() {
+        let worker = Worker::spawn(started());
+        let mut channel = worker.channel();
+
+        for id in 0x100..0x104 {
+            worker.sender().send(frame(id)).unwrap();
+        }
+        for id in 0x100..0x104 {
+            let received = worker.receiver().recv_timeout(TIMEOUT).unwrap();
+            assert_eq!(received.frame, Frame::from(frame(id)));
+        }
+
+        channel.send_frame(frame(0x200).into(), TIMEOUT).unwrap();
+        assert_eq!(
+            channel.recv_frame(TIMEOUT).unwrap(),
+            Some(Frame::from(frame(0x200)))
+        );
+        assert_eq!(channel.recv_frame(Duration::from_millis(20)).unwrap(), None);
+        assert_eq!(worker.dropped(), 0);
+    }
+
+    #[test]
+    fn frames_are_dropped_when_the_receive_queue_is_full() {
+        let config = WorkerConfig {
+            rx_capacity: 2,
+            ..WorkerConfig::default()
+        };
+        let worker = Worker::with_config(started(), config);
+
+        for id in 0..5 {
+            worker.sender().send(frame(id)).unwrap();
+        }
+        let deadline = Instant::now() + TIMEOUT;
+        while worker.dropped() < 3 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(worker.dropped(), 3);
+
+        // the oldest frames are the ones kept
+        let receiver = worker.receiver();
+        assert_eq!(receiver.try_recv().unwrap().unwrap().frame.id(), 0);
+        assert_eq!(receiver.try_recv().unwrap().unwrap().frame.id(), 1);
+        assert!(receiver.try_recv().unwrap().is_none());
+    }
+
+    #[test]
+    fn shutdown_returns_the_handle() {
+        let worker = Worker::spawn(started());
+        let receiver = worker.receiver();
+
+        let mut handle = worker.shutdown().expect("worker thread panicked");
+        assert!(handle.transport().is_running());
+        assert!(receiver.try_recv().is_err(), "worker still running");
+
+        handle.write_frame(frame(0x300), TIMEOUT).unwrap();
+        assert_eq!(
+            handle.read_frame(TIMEOUT).unwrap(),
+            Frame::from(frame(0x300))
+        );
+    }
+}
@@ -0,0 +1,377 @@
+use super::Transport;
+
+use crate::Result;
+
+use adaptor_common::{AdaptorSettings, Filter, Frame, RxFrame, UsbRequests};
+use adaptor_common::{CMD_PACKET_SIZE, MAX_FILTERS};
+
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Default)]
+struct LoopbackState {
+    packets: VecDeque<Vec<u8>>,
+    settings: Option<AdaptorSettings>,
+    running: bool,
+    leds: bool,
+    error: u8,
+}
+
+/// In-memory transport that echoes every frame written to the OUT endpoint back
+/// on the IN endpoint and records the vendor requests it receives, so the full
+/// postcard framing and settings protocol can be exercised without a probe plugged in.
+#[derive(Debug)]
+pub struct LoopbackTransport {
+    pid: u16,
+    state: Mutex<LoopbackState>,
+    available: Condvar,
+
+    /// Zero of the emulated microsecond counter
+    epoch: Instant,
+}
+
+impl LoopbackTransport {
+    /// Creates a loopback transport that identifies as the given probe version
+    pub fn new(pid: u16) -> Self {
+        Self {
+            pid,
+            state: Mutex::new(LoopbackState::default()),
+            available: Condvar::new(),
+            epoch: Instant::now(),
+        }
+    }
+
+    fn device_time(&self) -> u64 {
+        self.epoch.elapsed().as_micros() as u64
+    }
+
+    /// Last settings written by the host, if any
+    pub fn settings(&self) -> Option<AdaptorSettings> {
+        self.state.lock().expect("loopback state poisoned").settings
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.state.lock().expect("loopback state poisoned").running
+    }
+
+    pub fn leds_enabled(&self) -> bool {
+        self.state.lock().expect("loopback state poisoned").leds
+    }
+
+    /// Sets the error code reported by the next `GetError` request
+    pub fn set_error(&self, error: u8) {
+        self.state.lock().expect("loopback state poisoned").error = error;
+    }
+
+    /// Number of packets waiting to be read back
+    pub fn pending(&self) -> usize {
+        self.state
+            .lock()
+            .expect("loopback state poisoned")
+            .packets
+            .len()
+    }
+}
+
+impl Default for LoopbackTransport {
+    /// Identifies as a V1 probe
+    fn default() -> Self {
+        Self::new(0x69)
+    }
+}
+
+impl Transport for LoopbackTransport {
+    fn product_id(&self) -> u16 {
+        self.pid
+    }
+
+    fn read_bulk(&self, _endpoint: u8, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+        let state = self.state.lock().expect("loopback state poisoned");
+        let (mut state, _) = self
+            .available
+            .wait_timeout_while(state, timeout, |s| s.packets.is_empty())
+            .expect("loopback state poisoned");
+
+        match state.packets.pop_front() {
+            Some(packet) => {
+                let len = packet.len().min(buf.len());
+                buf[..len].copy_from_slice(&packet[..len]);
+                Ok(len)
+            }
+            None => Err(rusb::Error::Timeout.into()),
+        }
+    }
+
+    fn write_bulk(&self, _endpoint: u8, buf: &[u8], _timeout: Duration) -> Result<usize> {
+        let frame: Frame = postcard::from_bytes(buf)?;
+        let packet = postcard::to_stdvec(&RxFrame::new(self.device_time(), frame))?;
+
+        let mut state = self.state.lock().expect("loopback state poisoned");
+        state.packets.push_back(packet);
+        self.available.notify_one();
+        Ok(buf.len())
+    }
+
+    fn read_vendor(
+        &self,
+        request: u8,
+        _value: u16,
+        _index: u16,
+        buf: &mut [u8],
+        _timeout: Duration,
+    ) -> Result<usize> {
+        let state = self.state.lock().expect("loopback state poisoned");
+        match UsbRequests::try_from(request) {
+            Ok(UsbRequests::GetError) if !buf.is_empty() => {
+                buf[0] = state.error;
+                Ok(1)
+            }
+            Ok(UsbRequests::GetTimestamp) if buf.len() >= 8 => {
+                buf[..8].copy_from_slice(&self.device_time().to_le_bytes());
+                Ok(8)
+            }
+            _ => Err(rusb::Error::NotSupported.into()),
+        }
+    }
+
+    fn write_vendor(
+        &self,
+        request: u8,
+        value: u16,
+        _index: u16,
+        buf: &[u8],
+        _timeout: Duration,
+    ) -> Result<usize> {
+        let mut state = self.state.lock().expect("loopback state poisoned");
+        match UsbRequests::try_from(request) {
+            Ok(UsbRequests::NOP) => {}
+            Ok(UsbRequests::Settings | UsbRequests::SetFilter) if buf.len() > CMD_PACKET_SIZE => {
+                return Err(rusb::Error::Overflow.into());
+            }
+            Ok(UsbRequests::Settings) => {
+                let settings: AdaptorSettings = postcard::from_bytes(buf)?;
+                state.settings = Some(settings);
+            }
+            Ok(UsbRequests::SetFilter) if (value as usize) < MAX_FILTERS => {
+                let filter: Filter = postcard::from_bytes(buf)?;
+                let settings = state.settings.as_mut().ok_or(rusb::Error::Pipe)?;
+                let mut filters = *settings.filters();
+                filters[value as usize] = filter;
+                settings.set_filters(filters);
+            }
+            Ok(UsbRequests::Run) => {
+                state.running = buf.first().is_some_and(|b| *b != 0);
+            }
+            Ok(UsbRequests::LedEnable) => {
+                state.leds = buf.first().is_some_and(|b| *b != 0);
+            }
+            Ok(UsbRequests::Reset) => {
+                *state = LoopbackState::default();
+            }
+            _ => return Err(rusb::Error::NotSupported.into()),
+        }
+        Ok(buf.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AdaptorHandle;
+
+    use adaptor_common::{CANFrame, FdFrame, Filter, MAX_EXT_ID};
+
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    fn open(settings: AdaptorSettings) -> AdaptorHandle<LoopbackTransport> {
+        AdaptorHandle::with_transport(LoopbackTransport::default(), settings).unwrap()
+    }
+
+    #[test]
+    fn opening_pushes_settings() {
+        let mut settings = AdaptorSettings::default();
+        settings.set_leds(false);
+        settings
+            .add_filter(Filter::mask(0x123, 0x7FF, false))
+            .unwrap();
+        let handle = open(settings);
+
+        let sent = handle
+            .transport()
+            .settings()
+            .expect("settings were written");
+        assert!(!sent.leds());
+        assert_eq!(sent.filters()[0], Filter::mask(0x123, 0x7FF, false));
+        assert!(!sent.filters()[1].enabled);
+    }
+
+    #[test]
+    fn settings_round_trip() {
+        let mut handle = open(AdaptorSettings::default());
+        handle.set_bitrate(500_000, TIMEOUT).unwrap();
+        handle.set_data_bitrate(2_000_000, TIMEOUT).unwrap();
+
+        let sent = handle.transport().settings().unwrap();
+        let nominal = sent.bit_timing().expect("nominal timing was written");
+        assert_eq!(nominal.bitrate(80_000_000), 500_000);
+        assert!(*sent.fd_enabled());
+        let data = sent.data_bit_timing().expect("data timing was written");
+        assert_eq!(data.bitrate(80_000_000), 2_000_000);
+    }
+
+    #[test]
+    fn invalid_settings_are_not_written() {
+        let mut handle = open(AdaptorSettings::default());
+        let result = handle.modify_settings(TIMEOUT, |s| {
+            for i in 0..10 {
+                s.add_filter(Filter::mask(i, MAX_EXT_ID, true)).unwrap();
+            }
+        });
+
+        assert!(result.is_err());
+        let sent = handle.transport().settings().unwrap();
+        assert!(sent.filters().iter().all(|f| !f.enabled));
+    }
+
+    #[test]
+    fn run_and_stop() {
+        let mut handle = open(AdaptorSettings::default());
+        assert!(!handle.transport().is_running());
+        handle.start(TIMEOUT).unwrap();
+        assert!(handle.transport().is_running());
+        handle.stop(TIMEOUT).unwrap();
+        assert!(!handle.transport().is_running());
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let mut handle = open(AdaptorSettings::default());
+        handle.start(TIMEOUT).unwrap();
+
+        let classic = CANFrame::new(0x123, 3, [1, 2, 3, 0, 0, 0, 0, 0], false, false, false);
+        let extended = CANFrame::new(0x1234_5678, 0, [0; 8], true, false, true);
+        handle.write_frame(classic, TIMEOUT).unwrap();
+        handle.write_frame(extended, TIMEOUT).unwrap();
+
+        assert_eq!(handle.read_frame(TIMEOUT).unwrap(), Frame::from(classic));
+        assert_eq!(handle.read_frame(TIMEOUT).unwrap(), Frame::from(extended));
+        assert!(handle.read_frame(TIMEOUT).unwrap_err().is_timeout());
+    }
+
+    #[test]
+    fn fd_frames_need_fd_enabled() {
+        let mut handle = open(AdaptorSettings::default());
+        let frame = FdFrame::new(0x100, &[0xAA; 24], true, false, false).unwrap();
+        assert!(handle.write_frame(frame, TIMEOUT).is_err());
+
+        handle.set_data_bitrate(2_000_000, TIMEOUT).unwrap();
+        handle.write_frame(frame, TIMEOUT).unwrap();
+        let received = handle.read_received(TIMEOUT).unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].frame, Frame::from(frame));
+    }
+
+    #[test]
+    fn error_register_and_reset() {
+        let handle = open(AdaptorSettings::default());
+        handle.transport().set_error(0x42);
+        assert_eq!(handle.get_error(TIMEOUT).unwrap(), 0x42);
+
+        handle.reset(TIMEOUT).unwrap();
+        assert_eq!(handle.get_error(TIMEOUT).unwrap(), 0);
+        assert!(handle.transport().settings().is_none());
+    }
+
+    #[test]
+    fn full_buffers_leave_frames_queued() {
+        let mut handle = open(AdaptorSettings::default());
+        handle.start(TIMEOUT).unwrap();
+        let frame = CANFrame::new(0x321, 1, [7, 0, 0, 0, 0, 0, 0, 0], false, false, false);
+        handle.write_frame(frame, TIMEOUT).unwrap();
+
+        let mut frames = heapless::Vec::<Frame, heapless::consts::U1>::new();
+        frames.push(Frame::from(frame)).unwrap();
+        assert_eq!(handle.read_frames_into(&mut frames, TIMEOUT).unwrap(), 0);
+        assert_eq!(handle.read_frame(TIMEOUT).unwrap(), Frame::from(frame));
+    }
+
+    #[test]
+    fn empty_transfers_hold_no_frames() {
+        let mut handle = open(AdaptorSettings::default());
+        handle.start(TIMEOUT).unwrap();
+        for _ in 0..3 {
+            handle
+                .transport()
+                .state
+                .lock()
+                .unwrap()
+                .packets
+                .push_back(Vec::new());
+        }
+
+        assert!(handle.read_frames(TIMEOUT).unwrap().is_empty());
+        let mut frames = heapless::Vec::<Frame, heapless::consts::U4>::new();
+        assert_eq!(handle.read_frames_into(&mut frames, TIMEOUT).unwrap(), 0);
+        assert!(handle.read_frame(TIMEOUT).unwrap_err().is_timeout());
+    }
+
+    /// Loopback probe whose firmware predates `GetTimestamp`
+    struct NoClock(LoopbackTransport);
+
+    impl Transport for NoClock {
+        fn product_id(&self) -> u16 {
+            self.0.product_id()
+        }
+
+        fn read_bulk(&self, endpoint: u8, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+            self.0.read_bulk(endpoint, buf, timeout)
+        }
+
+        fn write_bulk(&self, endpoint: u8, buf: &[u8], timeout: Duration) -> Result<usize> {
+            self.0.write_bulk(endpoint, buf, timeout)
+        }
+
+        fn read_vendor(
+            &self,
+            request: u8,
+            value: u16,
+            index: u16,
+            buf: &mut [u8],
+            timeout: Duration,
+        ) -> Result<usize> {
+            if request == u8::from(UsbRequests::GetTimestamp) {
+                return Err(rusb::Error::Pipe.into());
+            }
+            self.0.read_vendor(request, value, index, buf, timeout)
+        }
+
+        fn write_vendor(
+            &self,
+            request: u8,
+            value: u16,
+            index: u16,
+            buf: &[u8],
+            timeout: Duration,
+        ) -> Result<usize> {
+            self.0.write_vendor(request, value, index, buf, timeout)
+        }
+    }
+
+    #[test]
+    fn missing_hardware_clock_falls_back_to_host_time() {
+        let transport = NoClock(LoopbackTransport::default());
+        let mut handle = AdaptorHandle::with_transport(transport, AdaptorSettings::default())
+            .expect("opening doesn't need the probe's clock");
+        assert!(!handle.clock().has_hardware_clock());
+        assert_eq!(handle.clock().drift_ppm(), 0.0);
+
+        handle.start(TIMEOUT).unwrap();
+        let frame = CANFrame::new(0x42, 0, [0; 8], false, false, false);
+        handle.write_frame(frame, TIMEOUT).unwrap();
+        let received = handle.read_received(TIMEOUT).unwrap();
+        assert_eq!(received[0].timestamp, received[0].host_time);
+        assert!(handle.sync_clock(TIMEOUT).is_err());
+    }
+}
@@ -0,0 +1,43 @@
+mod loopback;
+mod usb;
+
+pub use loopback::LoopbackTransport;
+pub use usb::UsbTransport;
+
+use crate::Result;
+
+use std::time::Duration;
+
+/// The raw USB operations an `AdaptorHandle` needs from a probe.
+///
+/// `UsbTransport` talks to real hardware through rusb, while
+/// `LoopbackTransport` keeps everything in memory so the framing
+/// and settings protocol can be exercised without a device.
+pub trait Transport {
+    /// Product id of the probe on the other end, used to look up its `AdaptorInfo`
+    fn product_id(&self) -> u16;
+
+    fn read_bulk(&self, endpoint: u8, buf: &mut [u8], timeout: Duration) -> Result<usize>;
+
+    fn write_bulk(&self, endpoint: u8, buf: &[u8], timeout: Duration) -> Result<usize>;
+
+    /// Vendor control transfer from the probe to the host
+    fn read_vendor(
+        &self,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize>;
+
+    /// Vendor control transfer from the host to the probe
+    fn write_vendor(
+        &self,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &[u8],
+        timeout: Duration,
+    ) -> Result<usize>;
+}
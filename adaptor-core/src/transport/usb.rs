@@ -0,0 +1,105 @@
+use super::Transport;
+
+use crate::adaptor::AdaptorInfo;
+use crate::AdaptorError;
+use crate::Result;
+
+use rusb;
+
+use std::time::Duration;
+
+/// Transport backed by a real probe on the USB bus
+pub struct UsbTransport {
+    handle: rusb::DeviceHandle<rusb::Context>,
+    pid: u16,
+}
+
+impl UsbTransport {
+    /// Opens `device`, claims its interface and checks that the
+    /// endpoints described by `info` are present
+    pub(crate) fn open(device: &rusb::Device<rusb::Context>, info: &AdaptorInfo) -> Result<Self> {
+        let mut handle = device.open()?;
+        let config = device.active_config_descriptor()?;
+
+        handle.claim_interface(0)?;
+
+        let mut has_in_ep = false;
+        let mut has_out_ep = false;
+
+        if let Some(iface) = config.interfaces().next() {
+            if let Some(desc) = iface.descriptors().next() {
+                for endpoint in desc.endpoint_descriptors() {
+                    let addr = endpoint.address();
+                    if addr == info.out_ep {
+                        has_out_ep = true;
+                    } else if addr == info.in_ep {
+                        has_in_ep = true;
+                    }
+                }
+            }
+        }
+
+        if !has_in_ep {
+            return Err(AdaptorError::NoEndpointError);
+        }
+
+        if !has_out_ep {
+            return Err(AdaptorError::NoEndpointError);
+        }
+
+        Ok(Self {
+            handle,
+            pid: info.pid,
+        })
+    }
+}
+
+impl Transport for UsbTransport {
+    fn product_id(&self) -> u16 {
+        self.pid
+    }
+
+    fn read_bulk(&self, endpoint: u8, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+        Ok(self.handle.read_bulk(endpoint, buf, timeout)?)
+    }
+
+    fn write_bulk(&self, endpoint: u8, buf: &[u8], timeout: Duration) -> Result<usize> {
+        Ok(self.handle.write_bulk(endpoint, buf, timeout)?)
+    }
+
+    fn read_vendor(
+        &self,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize> {
+        use rusb::{Direction, Recipient, RequestType};
+        let req_type = rusb::request_type(Direction::In, RequestType::Vendor, Recipient::Device);
+        Ok(self
+            .handle
+            .read_control(req_type, request, value, index, buf, timeout)?)
+    }
+
+    fn write_vendor(
+        &self,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &[u8],
+        timeout: Duration,
+    ) -> Result<usize> {
+        use rusb::{Direction, Recipient, RequestType};
+        let req_type = rusb::request_type(Direction::Out, RequestType::Vendor, Recipient::Device);
+        Ok(self
+            .handle
+            .write_control(req_type, request, value, index, buf, timeout)?)
+    }
+}
+
+impl Drop for UsbTransport {
+    fn drop(&mut self) {
+        let _ = self.handle.release_interface(0);
+    }
+}
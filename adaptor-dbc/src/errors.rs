@@ -0,0 +1,29 @@
+use quick_error::quick_error;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum DbcError {
+        IoError(err: std::io::Error) {
+            from()
+            display("{}", err)
+        }
+        ParseError(line: usize, msg: String) {
+            display("line {}: {}", line, msg)
+        }
+        UnknownMessage(name: String) {
+            display("no message named {}", name)
+        }
+        UnknownSignal(message: String, signal: String) {
+            display("{} has no signal named {}", message, signal)
+        }
+        OutOfRange(signal: String, value: f64, min: f64, max: f64) {
+            display("{} = {} is outside its range of {} to {}", signal, value, min, max)
+        }
+        InactiveSignal(signal: String) {
+            display("{} is not selected by the value of its multiplexor", signal)
+        }
+        SizeError(name: String) {
+            display("{} does not fit in the frame", name)
+        }
+    }
+}
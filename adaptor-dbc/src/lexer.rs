@@ -0,0 +1,115 @@
+use crate::errors::DbcError;
+use crate::Result;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Ident(String),
+
+    /// Kept as text, whether it is read as an integer or a float depends on where it appears
+    Number(String),
+
+    Str(String),
+    Punct(char),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lexed {
+    pub token: Token,
+    pub line: usize,
+
+    /// First token on its line, statements always start one
+    pub line_start: bool,
+
+    /// First token on its line with whitespace before it
+    pub indented: bool,
+}
+
+/// Splits a DBC file into tokens. A `-` directly followed by a digit starts
+/// a negative number, any other character that is not part of an
+/// identifier, number or string is punctuation.
+pub fn tokenize(text: &str) -> Result<Vec<Lexed>> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut line_start = true;
+    let mut line_begin = 0;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let start_line = line;
+        let begin = i;
+
+        let token = if c == '\n' {
+            line += 1;
+            line_start = true;
+            i += 1;
+            line_begin = i;
+            continue;
+        } else if c.is_whitespace() {
+            i += 1;
+            continue;
+        } else if c == '"' {
+            let mut s = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => {
+                        return Err(DbcError::ParseError(
+                            start_line,
+                            "unterminated string".to_owned(),
+                        ))
+                    }
+                    Some('"') => break,
+                    Some('\\') if matches!(chars.get(i + 1), Some('"') | Some('\\')) => {
+                        s.push(chars[i + 1]);
+                        i += 1;
+                    }
+                    Some(c) => {
+                        if *c == '\n' {
+                            line += 1;
+                        }
+                        s.push(*c);
+                    }
+                }
+                i += 1;
+            }
+            i += 1;
+            Token::Str(s)
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let begin = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            Token::Ident(chars[begin..i].iter().collect())
+        } else if c.is_ascii_digit()
+            || (c == '-' && chars.get(i + 1).is_some_and(|n| n.is_ascii_digit()))
+        {
+            let begin = i;
+            i += 1;
+            while i < chars.len() {
+                let n = chars[i];
+                let exponent_sign = (n == '-' || n == '+') && matches!(chars[i - 1], 'e' | 'E');
+                if n.is_ascii_digit() || n == '.' || n == 'e' || n == 'E' || exponent_sign {
+                    i += 1;
+                } else {
+                    break;
+                }
+            }
+            Token::Number(chars[begin..i].iter().collect())
+        } else {
+            i += 1;
+            Token::Punct(c)
+        };
+
+        tokens.push(Lexed {
+            token,
+            line: start_line,
+            line_start,
+            indented: line_start && begin > line_begin,
+        });
+        line_start = false;
+    }
+
+    Ok(tokens)
+}
@@ -0,0 +1,264 @@
+//! DBC database support, turning raw frames into named physical signal values
+//! and back.
+
+pub mod errors;
+mod lexer;
+pub mod message;
+mod parser;
+pub mod signal;
+
+pub type Result<T> = std::result::Result<T, errors::DbcError>;
+
+pub use errors::DbcError;
+pub use message::{DecodedSignal, Message};
+pub use signal::{ByteOrder, MuxCondition, Signal, ValueType};
+
+use adaptor_common::Frame;
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Default)]
+pub struct Dbc {
+    pub version: String,
+    pub nodes: Vec<String>,
+    pub messages: Vec<Message>,
+
+    /// Named value tables from `VAL_TABLE_`
+    pub value_tables: HashMap<String, BTreeMap<i64, String>>,
+
+    /// Position in `messages` by identifier and whether it is extended
+    index: HashMap<(u32, bool), usize>,
+}
+
+impl Dbc {
+    /// Reads a DBC file. They are often not UTF-8, so invalid bytes are
+    /// replaced rather than rejected.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let bytes = std::fs::read(path)?;
+        String::from_utf8_lossy(&bytes).parse()
+    }
+
+    /// Rebuilds the lookup index, needed after changing `messages`
+    pub fn reindex(&mut self) {
+        self.index = self
+            .messages
+            .iter()
+            .enumerate()
+            .map(|(i, message)| ((message.id, message.is_ext), i))
+            .collect();
+    }
+
+    pub fn message(&self, id: u32, is_ext: bool) -> Option<&Message> {
+        self.index
+            .get(&(id, is_ext))
+            .and_then(|i| self.messages.get(*i))
+    }
+
+    pub fn message_by_name(&self, name: &str) -> Option<&Message> {
+        self.messages.iter().find(|m| m.name == name)
+    }
+
+    /// Decodes a data frame described by the database, `None` for unknown
+    /// identifiers and for remote and error frames
+    pub fn decode(&self, frame: &Frame) -> Option<(&Message, Vec<DecodedSignal<'_>>)> {
+        if frame.is_rtr() || frame.is_err() {
+            return None;
+        }
+
+        let message = self.message(frame.id(), frame.is_ext())?;
+        Some((message, message.decode(frame.payload())))
+    }
+
+    /// Builds a frame for the message called `name` from physical signal
+    /// values, see `Message::encode`
+    pub fn encode(&self, name: &str, values: &[(&str, f64)]) -> Result<Frame> {
+        self.message_by_name(name)
+            .ok_or_else(|| DbcError::UnknownMessage(name.to_owned()))?
+            .to_frame(values)
+    }
+}
+
+impl FromStr for Dbc {
+    type Err = DbcError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        parser::parse(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use adaptor_common::CANFrame;
+
+    pub(crate) const DBC: &str = r#"VERSION "1.0"
+
+NS_ :
+	NS_DESC_
+	CM_
+	BA_DEF_
+	VAL_TABLE_
+	SG_MUL_VAL_
+
+BU_: ECU GW
+
+VAL_TABLE_ Gears 0 "Park" 1 "Reverse" 2 "Neutral" 3 "Drive" ;
+
+BO_ 256 Engine: 8 ECU
+ SG_ Speed : 0|16@1+ (0.1,0) [0|6553.5] "km/h" GW
+ SG_ Temp : 16|8@1- (1,-40) [-168|87] "degC" GW
+ SG_ Gear : 31|4@0+ (1,0) [0|15] "" GW
+
+BO_ 512 Ratio: 8 ECU
+ SG_ Ratio : 0|32@1- (1,0) [0|0] "" GW
+
+BO_ 2147484417 Mux: 8 GW
+ SG_ Selector M : 0|8@1+ (1,0) [0|255] "" ECU
+ SG_ A m1 : 8|16@1+ (1,0) [0|0] "" ECU
+ SG_ B m2 : 8|16@1+ (1,0) [0|0] "" ECU
+ SG_ C m3 : 8|8@1+ (1,0) [0|0] "" ECU
+
+CM_ BO_ 256 "Engine status";
+CM_ SG_ 256 Speed "Vehicle speed";
+VAL_ 256 Gear Gears ;
+SIG_VALTYPE_ 512 Ratio : 1;
+SG_MUL_VAL_ 2147484417 C Selector 3-5, 8-8;
+"#;
+
+    pub(crate) fn dbc() -> Dbc {
+        DBC.parse().unwrap()
+    }
+
+    fn frame(id: u32, is_ext: bool, data: [u8; 8]) -> Frame {
+        CANFrame::new(id, 8, data, false, false, is_ext).into()
+    }
+
+    fn names(dbc: &Dbc, frame: &Frame) -> Vec<String> {
+        let (_, signals) = dbc.decode(frame).unwrap();
+        signals.iter().map(|s| s.name().to_owned()).collect()
+    }
+
+    #[test]
+    fn decodes_scaled_signed_and_labelled_signals() {
+        let dbc = dbc();
+        let data = [0xD2, 0x04, 0xD8, 0x30, 0, 0, 0, 0];
+        let (message, signals) = dbc.decode(&frame(256, false, data)).unwrap();
+        assert_eq!(message.name, "Engine");
+
+        let values: Vec<_> = signals.iter().map(|s| (s.name(), s.value)).collect();
+        assert_eq!(values.len(), 3);
+        assert!((values[0].1 - 123.4).abs() < 1e-9);
+        assert_eq!(values[1], ("Temp", -80.0));
+        assert_eq!(values[2], ("Gear", 3.0));
+        assert_eq!(signals[2].label, Some("Drive"));
+    }
+
+    #[test]
+    fn decodes_float_signals() {
+        let dbc = dbc();
+        let mut data = [0; 8];
+        data[..4].copy_from_slice(&(-1.5_f32).to_le_bytes());
+        let (_, signals) = dbc.decode(&frame(512, false, data)).unwrap();
+        assert_eq!(signals[0].value, -1.5);
+    }
+
+    #[test]
+    fn multiplexed_signals_follow_their_multiplexor() {
+        let dbc = dbc();
+        let with_selector = |selector| frame(0x301, true, [selector, 0x34, 0x12, 0, 0, 0, 0, 0]);
+
+        assert_eq!(names(&dbc, &with_selector(1)), ["Selector", "A"]);
+        assert_eq!(names(&dbc, &with_selector(2)), ["Selector", "B"]);
+        for selector in &[3, 4, 5, 8] {
+            assert_eq!(names(&dbc, &with_selector(*selector)), ["Selector", "C"]);
+        }
+        assert_eq!(names(&dbc, &with_selector(6)), ["Selector"]);
+
+        let (_, signals) = dbc.decode(&with_selector(1)).unwrap();
+        assert_eq!(signals[1].raw, 0x1234);
+    }
+
+    #[test]
+    fn unknown_remote_and_error_frames_are_not_decoded() {
+        let dbc = dbc();
+        assert!(dbc.decode(&frame(256, true, [0; 8])).is_none());
+        assert!(dbc.decode(&frame(0x301, false, [0; 8])).is_none());
+        let remote = CANFrame::new(256, 8, [0; 8], true, false, false).into();
+        assert!(dbc.decode(&remote).is_none());
+    }
+
+    #[test]
+    fn encoded_values_decode_back() {
+        let dbc = dbc();
+        let frame = dbc
+            .encode(
+                "Engine",
+                &[("Speed", 123.4), ("Temp", -80.0), ("Gear", 2.0)],
+            )
+            .unwrap();
+        assert_eq!(frame.payload(), &[0xD2, 0x04, 0xD8, 0x20, 0, 0, 0, 0]);
+
+        let (_, signals) = dbc.decode(&frame).unwrap();
+        assert!((signals[0].value - 123.4).abs() < 1e-9);
+        assert_eq!(signals[1].value, -80.0);
+        assert_eq!(signals[2].label, Some("Neutral"));
+
+        let frame = dbc.encode("Ratio", &[("Ratio", 0.75)]).unwrap();
+        assert_eq!(dbc.decode(&frame).unwrap().1[0].value, 0.75);
+    }
+
+    #[test]
+    fn multiplexed_signals_need_their_multiplexor() {
+        let dbc = dbc();
+        let frame = dbc
+            .encode("Mux", &[("Selector", 4.0), ("C", 0x42 as f64)])
+            .unwrap();
+        assert!(frame.is_ext());
+        assert_eq!(names(&dbc, &frame), ["Selector", "C"]);
+
+        assert!(matches!(
+            dbc.encode("Mux", &[("Selector", 1.0), ("C", 1.0)]),
+            Err(DbcError::InactiveSignal(name)) if name == "C"
+        ));
+        assert!(matches!(
+            dbc.encode("Mux", &[("B", 1.0)]),
+            Err(DbcError::InactiveSignal(name)) if name == "B"
+        ));
+    }
+
+    #[test]
+    fn unknown_names_and_out_of_range_values_are_rejected() {
+        let dbc = dbc();
+        assert!(matches!(
+            dbc.encode("Nope", &[]),
+            Err(DbcError::UnknownMessage(_))
+        ));
+        assert!(matches!(
+            dbc.encode("Engine", &[("Nope", 0.0)]),
+            Err(DbcError::UnknownSignal(_, _))
+        ));
+        assert!(matches!(
+            dbc.encode("Engine", &[("Temp", 100.0)]),
+            Err(DbcError::OutOfRange(..))
+        ));
+    }
+
+    #[test]
+    fn long_messages_become_fd_frames() {
+        let dbc: Dbc = "BO_ 1 Long: 12 ECU\n SG_ Last : 88|8@1+ (1,0) [0|0] \"\" GW\n"
+            .parse()
+            .unwrap();
+        let frame = dbc.encode("Long", &[("Last", 7.0)]).unwrap();
+        match frame {
+            Frame::Fd(f) => {
+                assert!(!f.brs);
+                assert_eq!(f.payload().len(), 12);
+                assert_eq!(f.payload()[11], 7);
+            }
+            _ => panic!("expected an FD frame"),
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use crate::errors::DbcError;
+use crate::signal::Signal;
+use crate::Result;
+
+use adaptor_common::{CANFrame, FdFrame, Frame};
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub id: u32,
+    pub is_ext: bool,
+    pub name: String,
+
+    /// Payload length in bytes
+    pub size: u8,
+
+    pub transmitter: String,
+    pub signals: Vec<Signal>,
+    pub comment: Option<String>,
+}
+
+/// A signal's value pulled out of a payload
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedSignal<'a> {
+    pub signal: &'a Signal,
+    pub raw: u64,
+
+    /// Raw value after scaling, in `signal.unit`
+    pub value: f64,
+
+    /// Description of the raw value from the value table, if any
+    pub label: Option<&'a str>,
+}
+
+impl DecodedSignal<'_> {
+    pub fn name(&self) -> &str {
+        &self.signal.name
+    }
+}
+
+impl fmt::Display for DecodedSignal<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.signal.name, self.value)?;
+        if !self.signal.unit.is_empty() {
+            write!(f, " {}", self.signal.unit)?;
+        }
+        if let Some(label) = self.label {
+            write!(f, " ({})", label)?;
+        }
+        Ok(())
+    }
+}
+
+/// Deepest chain of multiplexors followed before a signal is assumed inactive,
+/// so a DBC with a multiplexing cycle cannot hang the decoder
+const MAX_MUX_DEPTH: usize = 8;
+
+impl Message {
+    pub fn signal(&self, name: &str) -> Option<&Signal> {
+        self.signals.iter().find(|s| s.name == name)
+    }
+
+    /// Whether `signal` is present in `data`, given the multiplexors it depends on
+    pub fn is_active(&self, signal: &Signal, data: &[u8]) -> bool {
+        self.is_active_at(signal, data, 0)
+    }
+
+    fn is_active_at(&self, signal: &Signal, data: &[u8], depth: usize) -> bool {
+        let condition = match &signal.multiplexed_by {
+            Some(condition) => condition,
+            None => return true,
+        };
+        if depth >= MAX_MUX_DEPTH {
+            return false;
+        }
+
+        let multiplexor = match self.signal(&condition.multiplexor) {
+            Some(multiplexor) => multiplexor,
+            None => return false,
+        };
+        self.is_active_at(multiplexor, data, depth + 1)
+            && multiplexor
+                .raw(data)
+                .is_some_and(|raw| condition.matches(raw))
+    }
+
+    /// Decodes every signal present in `data`, skipping multiplexed signals
+    /// that are not selected and signals that run past the end of the payload
+    pub fn decode<'a>(&'a self, data: &[u8]) -> Vec<DecodedSignal<'a>> {
+        self.signals
+            .iter()
+            .filter(|signal| self.is_active(signal, data))
+            .filter_map(|signal| {
+                let raw = signal.raw(data)?;
+                Some(DecodedSignal {
+                    signal,
+                    raw,
+                    value: signal.to_physical(raw),
+                    label: signal.label(raw),
+                })
+            })
+            .collect()
+    }
+
+    /// Builds a payload from physical signal values. Signals left out are
+    /// sent as zero raw bits, and a multiplexed signal is only accepted if
+    /// the multiplexor values given select it.
+    pub fn encode(&self, values: &[(&str, f64)]) -> Result<Vec<u8>> {
+        let mut data = vec![0; self.size as usize];
+        let mut signals = Vec::with_capacity(values.len());
+
+        for (name, value) in values {
+            let signal = self
+                .signal(name)
+                .ok_or_else(|| DbcError::UnknownSignal(self.name.clone(), name.to_string()))?;
+            let raw = signal.from_physical(*value)?;
+            signal
+                .insert(&mut data, raw)
+                .ok_or_else(|| DbcError::SizeError(signal.name.clone()))?;
+            signals.push(signal);
+        }
+
+        match signals.iter().find(|signal| !self.is_active(signal, &data)) {
+            Some(signal) => Err(DbcError::InactiveSignal(signal.name.clone())),
+            None => Ok(data),
+        }
+    }
+
+    /// Encodes `values` into a frame, a CAN FD frame without bit rate
+    /// switching if the message is longer than 8 bytes
+    pub fn to_frame(&self, values: &[(&str, f64)]) -> Result<Frame> {
+        let data = self.encode(values)?;
+        if data.len() <= 8 {
+            let mut buf = [0; 8];
+            buf[..data.len()].copy_from_slice(&data);
+            Ok(CANFrame::new(self.id, data.len() as u8, buf, false, false, self.is_ext).into())
+        } else {
+            FdFrame::new(self.id, &data, false, false, self.is_ext)
+                .map(Frame::from)
+                .ok_or_else(|| DbcError::SizeError(self.name.clone()))
+        }
+    }
+}
@@ -0,0 +1,570 @@
+//! Recursive descent over the token stream. Statements the decoder has no
+//! use for (attributes, environment variables, signal groups, ...) are
+//! skipped up to the next statement keyword.
+
+use crate::errors::DbcError;
+use crate::lexer::{tokenize, Lexed, Token};
+use crate::message::Message;
+use crate::signal::{ByteOrder, MuxCondition, Signal, ValueType};
+use crate::{Dbc, Result};
+
+use std::collections::{BTreeMap, HashMap};
+
+/// Set on `BO_` identifiers of extended frames
+const EXT_ID_FLAG: u32 = 0x8000_0000;
+
+/// Keywords that can start a statement, used to find where a skipped one ends
+const KEYWORDS: &[&str] = &[
+    "VERSION",
+    "NS_",
+    "BS_",
+    "BU_",
+    "VAL_TABLE_",
+    "BO_",
+    "SG_",
+    "BO_TX_BU_",
+    "EV_",
+    "ENVVAR_DATA_",
+    "SGTYPE_",
+    "CM_",
+    "BA_DEF_",
+    "BA_DEF_REL_",
+    "BA_DEF_DEF_",
+    "BA_DEF_DEF_REL_",
+    "BA_",
+    "BA_REL_",
+    "VAL_",
+    "CAT_DEF_",
+    "CAT_",
+    "FILTER",
+    "SIG_GROUP_",
+    "SIG_VALTYPE_",
+    "SIGTYPE_VALTYPE_",
+    "SG_MUL_VAL_",
+];
+
+struct Parser {
+    tokens: Vec<Lexed>,
+    pos: usize,
+    dbc: Dbc,
+    value_tables: HashMap<String, BTreeMap<i64, String>>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|t| &t.token)
+    }
+
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .or_else(|| self.tokens.last())
+            .map_or(1, |t| t.line)
+    }
+
+    fn error<T>(&self, msg: impl Into<String>) -> Result<T> {
+        Err(DbcError::ParseError(self.line(), msg.into()))
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|t| t.token.clone());
+        self.pos += 1;
+        token
+    }
+
+    /// Whether the next token is on the same line as the previous one
+    fn same_line(&self) -> bool {
+        self.tokens.get(self.pos).is_some_and(|t| !t.line_start)
+    }
+
+    /// Whether the next token starts an indented line
+    fn indented(&self) -> bool {
+        self.tokens.get(self.pos).is_some_and(|t| t.indented)
+    }
+
+    fn at_keyword(&self) -> bool {
+        match self.tokens.get(self.pos) {
+            Some(Lexed {
+                token: Token::Ident(ident),
+                line_start: true,
+                ..
+            }) => KEYWORDS.contains(&ident.as_str()),
+            _ => false,
+        }
+    }
+
+    fn punct(&mut self, c: char) -> Result<()> {
+        match self.next() {
+            Some(Token::Punct(p)) if p == c => Ok(()),
+            other => self.error(format!("expected '{}', found {:?}", c, other)),
+        }
+    }
+
+    fn eat_punct(&mut self, c: char) -> bool {
+        if self.peek() == Some(&Token::Punct(c)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn ident(&mut self) -> Result<String> {
+        match self.next() {
+            Some(Token::Ident(ident)) => Ok(ident),
+            other => self.error(format!("expected a name, found {:?}", other)),
+        }
+    }
+
+    fn string(&mut self) -> Result<String> {
+        match self.next() {
+            Some(Token::Str(s)) => Ok(s),
+            other => self.error(format!("expected a string, found {:?}", other)),
+        }
+    }
+
+    fn number<T: std::str::FromStr>(&mut self) -> Result<T> {
+        match self.next() {
+            Some(Token::Number(n)) => match n.parse() {
+                Ok(value) => Ok(value),
+                Err(_) => self.error(format!("'{}' is out of range", n)),
+            },
+            other => self.error(format!("expected a number, found {:?}", other)),
+        }
+    }
+
+    fn skip_statement(&mut self) {
+        self.pos += 1;
+        while self.pos < self.tokens.len() && !self.at_keyword() {
+            if self.next() == Some(Token::Punct(';')) {
+                break;
+            }
+        }
+    }
+
+    fn message_mut(&mut self, id: u32) -> Option<&mut Message> {
+        let is_ext = id & EXT_ID_FLAG != 0;
+        let id = id & !EXT_ID_FLAG;
+        self.dbc
+            .messages
+            .iter_mut()
+            .find(|m| m.id == id && m.is_ext == is_ext)
+    }
+
+    fn signal_mut(&mut self, id: u32, name: &str) -> Option<&mut Signal> {
+        self.message_mut(id)?
+            .signals
+            .iter_mut()
+            .find(|s| s.name == name)
+    }
+
+    fn parse(&mut self) -> Result<()> {
+        while let Some(token) = self.peek() {
+            let keyword = match token {
+                Token::Ident(ident) => ident.clone(),
+                _ => {
+                    self.skip_statement();
+                    continue;
+                }
+            };
+
+            match keyword.as_str() {
+                "VERSION" => {
+                    self.pos += 1;
+                    self.dbc.version = self.string()?;
+                }
+                "NS_" => {
+                    // the new symbols list is an indented column of keywords
+                    self.pos += 1;
+                    while self.same_line() || self.indented() {
+                        self.pos += 1;
+                    }
+                }
+                "BU_" => {
+                    self.pos += 1;
+                    self.punct(':')?;
+                    while self.same_line() {
+                        let node = self.ident()?;
+                        self.dbc.nodes.push(node);
+                    }
+                }
+                "BO_" => self.message()?,
+                "SG_" => self.signal()?,
+                "CM_" => self.comment()?,
+                "VAL_TABLE_" => self.value_table()?,
+                "VAL_" => self.values()?,
+                "SIG_VALTYPE_" => self.value_type()?,
+                "SG_MUL_VAL_" => self.extended_mux()?,
+                _ => self.skip_statement(),
+            }
+        }
+        Ok(())
+    }
+
+    fn message(&mut self) -> Result<()> {
+        self.pos += 1;
+        let raw_id: u32 = self.number()?;
+        let name = self.ident()?;
+        self.punct(':')?;
+        let size = self.number()?;
+        let transmitter = self.ident()?;
+
+        self.dbc.messages.push(Message {
+            id: raw_id & !EXT_ID_FLAG,
+            is_ext: raw_id & EXT_ID_FLAG != 0,
+            name,
+            size,
+            transmitter,
+            signals: Vec::new(),
+            comment: None,
+        });
+        Ok(())
+    }
+
+    fn signal(&mut self) -> Result<()> {
+        self.pos += 1;
+        let name = self.ident()?;
+
+        let mut is_multiplexor = false;
+        let mut multiplexed_by = None;
+        if let Some(Token::Ident(mux)) = self.peek().cloned() {
+            self.pos += 1;
+            let selector = mux.strip_suffix('M').unwrap_or(&mux);
+            is_multiplexor = selector.len() != mux.len();
+
+            if let Some(value) = selector.strip_prefix('m') {
+                let value = match value.parse() {
+                    Ok(value) => value,
+                    Err(_) => return self.error(format!("invalid multiplexer '{}'", mux)),
+                };
+                // the multiplexor is filled in once the whole message is known
+                multiplexed_by = Some(MuxCondition {
+                    multiplexor: String::new(),
+                    ranges: vec![(value, value)],
+                });
+            } else if !selector.is_empty() {
+                return self.error(format!("invalid multiplexer '{}'", mux));
+            }
+        }
+
+        self.punct(':')?;
+        let start_bit = self.number()?;
+        self.punct('|')?;
+        let size = self.number()?;
+        self.punct('@')?;
+        let byte_order = match self.number::<u8>()? {
+            0 => ByteOrder::BigEndian,
+            1 => ByteOrder::LittleEndian,
+            order => return self.error(format!("invalid byte order {}", order)),
+        };
+        let value_type = match self.next() {
+            Some(Token::Punct('+')) => ValueType::Unsigned,
+            Some(Token::Punct('-')) => ValueType::Signed,
+            other => return self.error(format!("expected '+' or '-', found {:?}", other)),
+        };
+
+        self.punct('(')?;
+        let factor: f64 = self.number()?;
+        if factor == 0.0 {
+            return self.error(format!("signal {} has a factor of zero", name));
+        }
+        self.punct(',')?;
+        let offset = self.number()?;
+        self.punct(')')?;
+        self.punct('[')?;
+        let min = self.number()?;
+        self.punct('|')?;
+        let max = self.number()?;
+        self.punct(']')?;
+        let unit = self.string()?;
+
+        let mut receivers = Vec::new();
+        while self.same_line() {
+            if !self.eat_punct(',') {
+                receivers.push(self.ident()?);
+            }
+        }
+
+        let signal = Signal {
+            name,
+            start_bit,
+            size,
+            byte_order,
+            value_type,
+            factor,
+            offset,
+            min,
+            max,
+            unit,
+            receivers,
+            is_multiplexor,
+            multiplexed_by,
+            values: BTreeMap::new(),
+            comment: None,
+        };
+
+        match self.dbc.messages.last_mut() {
+            Some(message) => message.signals.push(signal),
+            None => return self.error("signal outside of a message"),
+        }
+        Ok(())
+    }
+
+    fn comment(&mut self) -> Result<()> {
+        self.pos += 1;
+        match self.peek().cloned() {
+            Some(Token::Ident(kind)) if kind == "BO_" => {
+                self.pos += 1;
+                let id = self.number()?;
+                let text = self.string()?;
+                if let Some(message) = self.message_mut(id) {
+                    message.comment = Some(text);
+                }
+            }
+            Some(Token::Ident(kind)) if kind == "SG_" => {
+                self.pos += 1;
+                let id = self.number()?;
+                let name = self.ident()?;
+                let text = self.string()?;
+                if let Some(signal) = self.signal_mut(id, &name) {
+                    signal.comment = Some(text);
+                }
+            }
+            Some(Token::Str(_)) => {
+                self.pos += 1;
+            }
+            _ => {
+                // node and environment variable comments
+                self.pos -= 1;
+                self.skip_statement();
+                return Ok(());
+            }
+        }
+        self.punct(';')
+    }
+
+    /// Reads `<value> "<description>"` pairs up to the closing `;`
+    fn value_descriptions(&mut self) -> Result<BTreeMap<i64, String>> {
+        let mut values = BTreeMap::new();
+        while !self.eat_punct(';') {
+            // some tools write whole numbers as floats
+            let value: f64 = self.number()?;
+            let description = self.string()?;
+            values.insert(value as i64, description);
+        }
+        Ok(values)
+    }
+
+    fn value_table(&mut self) -> Result<()> {
+        self.pos += 1;
+        let name = self.ident()?;
+        let values = self.value_descriptions()?;
+        self.value_tables.insert(name, values);
+        Ok(())
+    }
+
+    fn values(&mut self) -> Result<()> {
+        self.pos += 1;
+        // `VAL_ <env var> ...` describes environment variables
+        if !matches!(self.peek(), Some(Token::Number(_))) {
+            self.pos -= 1;
+            self.skip_statement();
+            return Ok(());
+        }
+
+        let id = self.number()?;
+        let name = self.ident()?;
+
+        let values = match self.peek().cloned() {
+            // a reference to a `VAL_TABLE_`
+            Some(Token::Ident(table)) => {
+                self.pos += 1;
+                self.punct(';')?;
+                self.value_tables.get(&table).cloned().unwrap_or_default()
+            }
+            _ => self.value_descriptions()?,
+        };
+
+        if let Some(signal) = self.signal_mut(id, &name) {
+            signal.values = values;
+        }
+        Ok(())
+    }
+
+    fn value_type(&mut self) -> Result<()> {
+        self.pos += 1;
+        let id = self.number()?;
+        let name = self.ident()?;
+        self.punct(':')?;
+        let value_type = match self.number::<u8>()? {
+            1 => ValueType::Float32,
+            2 => ValueType::Float64,
+            _ => ValueType::Unsigned,
+        };
+        self.punct(';')?;
+
+        if let Some(signal) = self.signal_mut(id, &name) {
+            if value_type != ValueType::Unsigned {
+                signal.value_type = value_type;
+            }
+        }
+        Ok(())
+    }
+
+    /// `SG_MUL_VAL_ <id> <signal> <multiplexor> <low>-<high>, ... ;`
+    fn extended_mux(&mut self) -> Result<()> {
+        self.pos += 1;
+        let id = self.number()?;
+        let name = self.ident()?;
+        let multiplexor = self.ident()?;
+
+        let mut ranges = Vec::new();
+        while !self.eat_punct(';') {
+            // the lexer reads `3-5` as the numbers 3 and -5
+            let low: i64 = self.number()?;
+            let high: i64 = if self.eat_punct('-') {
+                self.number()?
+            } else {
+                self.number::<i64>()?.abs()
+            };
+            ranges.push((low as u64, high as u64));
+            self.eat_punct(',');
+        }
+
+        if let Some(signal) = self.signal_mut(id, &name) {
+            signal.multiplexed_by = Some(MuxCondition {
+                multiplexor,
+                ranges,
+            });
+        }
+        Ok(())
+    }
+
+    /// Points plain `m<n>` signals at their message's multiplexor
+    fn resolve_multiplexors(&mut self) {
+        for message in &mut self.dbc.messages {
+            let multiplexor = message
+                .signals
+                .iter()
+                .find(|s| s.is_multiplexor && s.multiplexed_by.is_none())
+                .or_else(|| message.signals.iter().find(|s| s.is_multiplexor))
+                .map(|s| s.name.clone());
+
+            for signal in &mut message.signals {
+                if let Some(condition) = &mut signal.multiplexed_by {
+                    if condition.multiplexor.is_empty() {
+                        condition.multiplexor = multiplexor.clone().unwrap_or_default();
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub fn parse(text: &str) -> Result<Dbc> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        pos: 0,
+        dbc: Dbc::default(),
+        value_tables: HashMap::new(),
+    };
+
+    parser.parse()?;
+    parser.resolve_multiplexors();
+
+    let mut dbc = parser.dbc;
+    dbc.value_tables = parser.value_tables;
+    dbc.reindex();
+    Ok(dbc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tests::DBC;
+
+    #[test]
+    fn reads_messages_signals_and_attributes() {
+        let dbc = parse(DBC).unwrap();
+        assert_eq!(dbc.version, "1.0");
+        assert_eq!(dbc.nodes, ["ECU", "GW"]);
+
+        let names: Vec<_> = dbc.messages.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, ["Engine", "Ratio", "Mux"]);
+
+        let engine = dbc.message(256, false).unwrap();
+        assert_eq!(engine.comment.as_deref(), Some("Engine status"));
+        let speed = engine.signal("Speed").unwrap();
+        assert_eq!(speed.byte_order, ByteOrder::LittleEndian);
+        assert_eq!((speed.start_bit, speed.size), (0, 16));
+        assert_eq!((speed.factor, speed.offset), (0.1, 0.0));
+        assert_eq!((speed.min, speed.max), (0.0, 6553.5));
+        assert_eq!(speed.unit, "km/h");
+        assert_eq!(speed.receivers, ["GW"]);
+        assert_eq!(speed.comment.as_deref(), Some("Vehicle speed"));
+
+        let temp = engine.signal("Temp").unwrap();
+        assert_eq!(temp.value_type, ValueType::Signed);
+        assert_eq!(temp.offset, -40.0);
+
+        let gear = engine.signal("Gear").unwrap();
+        assert_eq!(gear.byte_order, ByteOrder::BigEndian);
+        assert_eq!(gear.values, dbc.value_tables["Gears"]);
+        assert_eq!(gear.values.get(&1).map(String::as_str), Some("Reverse"));
+
+        let ratio = dbc.message_by_name("Ratio").unwrap();
+        assert_eq!(ratio.signals[0].value_type, ValueType::Float32);
+    }
+
+    #[test]
+    fn multiplexors_are_resolved() {
+        let dbc = parse(DBC).unwrap();
+        let mux = dbc.message(0x301, true).unwrap();
+        assert!(mux.signal("Selector").unwrap().is_multiplexor);
+
+        let condition = |name| mux.signal(name).unwrap().multiplexed_by.clone().unwrap();
+        assert_eq!(
+            condition("A"),
+            MuxCondition {
+                multiplexor: "Selector".to_owned(),
+                ranges: vec![(1, 1)],
+            }
+        );
+        // SG_MUL_VAL_ replaces the plain m3
+        assert_eq!(condition("C").ranges, [(3, 5), (8, 8)]);
+    }
+
+    #[test]
+    fn new_symbols_end_at_the_first_unindented_line() {
+        let dbc = parse("NS_ :\n\tCM_\n\tBA_\n\nBO_ 1 One: 1 ECU\n").unwrap();
+        assert_eq!(dbc.messages.len(), 1);
+
+        let dbc = parse("NS_ :\n    CM_\nBS_:\nBU_: ECU\nBO_ 1 One: 1 ECU\n").unwrap();
+        assert_eq!(dbc.nodes, ["ECU"]);
+        assert_eq!(dbc.messages.len(), 1);
+    }
+
+    #[test]
+    fn zero_factors_are_rejected() {
+        let text = "BO_ 1 One: 8 ECU\n SG_ Bad : 0|8@1+ (0,0) [0|0] \"\" GW\n";
+        match parse(text) {
+            Err(DbcError::ParseError(2, msg)) => assert!(msg.contains("Bad")),
+            other => panic!("got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn malformed_statements_are_errors() {
+        for text in &[
+            "BO_ 1 One 8 ECU\n",
+            "BO_ 1 One: 8 ECU\n SG_ S : 0|8@2+ (1,0) [0|0] \"\" GW\n",
+            "BO_ 1 One: 8 ECU\n SG_ S x : 0|8@1+ (1,0) [0|0] \"\" GW\n",
+            " SG_ S : 0|8@1+ (1,0) [0|0] \"\" GW\n",
+            "BO_ 99999999999 One: 8 ECU\n",
+            "CM_ BO_ 1 \"unterminated;\n",
+        ] {
+            assert!(parse(text).is_err(), "{:?} parsed", text);
+        }
+    }
+}
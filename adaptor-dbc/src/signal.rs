@@ -0,0 +1,375 @@
+use crate::errors::DbcError;
+use crate::Result;
+
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    /// Intel, `@1`, the start bit is the least significant bit
+    LittleEndian,
+
+    /// Motorola, `@0`, the start bit is the most significant bit
+    BigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    Unsigned,
+    Signed,
+
+    /// IEEE 754 single, set by `SIG_VALTYPE_ ... : 1`
+    Float32,
+
+    /// IEEE 754 double, set by `SIG_VALTYPE_ ... : 2`
+    Float64,
+}
+
+/// When a multiplexed signal is present in a message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MuxCondition {
+    /// Name of the multiplexor signal the condition depends on
+    pub multiplexor: String,
+
+    /// Inclusive ranges of multiplexor values that select the signal
+    pub ranges: Vec<(u64, u64)>,
+}
+
+impl MuxCondition {
+    pub fn matches(&self, value: u64) -> bool {
+        self.ranges
+            .iter()
+            .any(|(low, high)| value >= *low && value <= *high)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signal {
+    pub name: String,
+    pub start_bit: u16,
+    pub size: u16,
+    pub byte_order: ByteOrder,
+    pub value_type: ValueType,
+    pub factor: f64,
+    pub offset: f64,
+    pub min: f64,
+    pub max: f64,
+    pub unit: String,
+    pub receivers: Vec<String>,
+
+    /// Other signals in the message are selected by this one's value
+    pub is_multiplexor: bool,
+
+    /// Set for signals only present for some multiplexor values
+    pub multiplexed_by: Option<MuxCondition>,
+
+    /// Descriptions of raw values, from `VAL_` or a `VAL_TABLE_`
+    pub values: BTreeMap<i64, String>,
+
+    pub comment: Option<String>,
+}
+
+fn sign_extend(raw: u64, size: u16) -> i64 {
+    if size == 0 || size >= 64 {
+        raw as i64
+    } else {
+        let shift = 64 - size as u32;
+        ((raw << shift) as i64) >> shift
+    }
+}
+
+impl Signal {
+    /// Bit positions in the payload from the most to the least significant
+    /// bit of the raw value, numbered `byte * 8 + bit`
+    fn bits(&self) -> Vec<usize> {
+        let start = self.start_bit as usize;
+        let size = self.size as usize;
+
+        match self.byte_order {
+            ByteOrder::LittleEndian => (start..start + size).rev().collect(),
+            ByteOrder::BigEndian => {
+                // Motorola bits run down through a byte then on to the top of the next one
+                let mut bits = Vec::with_capacity(size);
+                let mut bit = start;
+                for _ in 0..size {
+                    bits.push(bit);
+                    bit = if bit.is_multiple_of(8) {
+                        bit + 15
+                    } else {
+                        bit - 1
+                    };
+                }
+                bits
+            }
+        }
+    }
+
+    /// The raw bits of the signal, `None` if it does not fit in `data`
+    pub fn raw(&self, data: &[u8]) -> Option<u64> {
+        if self.size == 0 || self.size > 64 {
+            return None;
+        }
+
+        let mut raw = 0_u64;
+        for bit in self.bits() {
+            let byte = *data.get(bit / 8)?;
+            raw = (raw << 1) | ((byte >> (bit % 8)) & 1) as u64;
+        }
+        Some(raw)
+    }
+
+    /// Writes the raw bits of the signal into `data`, `None` if it does not fit
+    pub fn insert(&self, data: &mut [u8], raw: u64) -> Option<()> {
+        if self.size == 0 || self.size > 64 {
+            return None;
+        }
+
+        let bits = self.bits();
+        if bits.iter().any(|bit| bit / 8 >= data.len()) {
+            return None;
+        }
+        for (i, bit) in bits.into_iter().enumerate() {
+            let mask = 1 << (bit % 8);
+            if (raw >> (self.size as usize - 1 - i)) & 1 == 1 {
+                data[bit / 8] |= mask;
+            } else {
+                data[bit / 8] &= !mask;
+            }
+        }
+        Some(())
+    }
+
+    /// The raw value as the integer value descriptions are keyed by
+    pub fn raw_integer(&self, raw: u64) -> i64 {
+        match self.value_type {
+            ValueType::Signed => sign_extend(raw, self.size),
+            _ => raw as i64,
+        }
+    }
+
+    /// Applies the value type, factor and offset to raw bits
+    pub fn to_physical(&self, raw: u64) -> f64 {
+        let value = match self.value_type {
+            ValueType::Unsigned => raw as f64,
+            ValueType::Signed => sign_extend(raw, self.size) as f64,
+            ValueType::Float32 => f32::from_bits(raw as u32) as f64,
+            ValueType::Float64 => f64::from_bits(raw),
+        };
+        value * self.factor + self.offset
+    }
+
+    /// Physical values the raw bits can hold, lowest first
+    fn raw_range(&self) -> (f64, f64) {
+        let (low, high) = match self.value_type {
+            ValueType::Unsigned => (0.0, 2_f64.powi(self.size as i32) - 1.0),
+            ValueType::Signed => {
+                let half = 2_f64.powi(self.size as i32 - 1);
+                (-half, half - 1.0)
+            }
+            ValueType::Float32 => (f32::MIN as f64, f32::MAX as f64),
+            ValueType::Float64 => (f64::MIN, f64::MAX),
+        };
+        let (low, high) = (
+            low * self.factor + self.offset,
+            high * self.factor + self.offset,
+        );
+        if low <= high {
+            (low, high)
+        } else {
+            (high, low)
+        }
+    }
+
+    /// Converts a physical value to raw bits, the inverse of `to_physical`.
+    /// Values outside `min` and `max` are rejected, unless both are zero
+    /// which DBC files use for signals without a range, as are values the
+    /// signal's bits cannot hold.
+    pub fn from_physical(&self, value: f64) -> Result<u64> {
+        let (min, max) = if self.min == 0.0 && self.max == 0.0 {
+            self.raw_range()
+        } else {
+            (self.min, self.max)
+        };
+        let (low, high) = self.raw_range();
+        if !value.is_finite() || value < min.max(low) || value > max.min(high) {
+            return Err(DbcError::OutOfRange(
+                self.name.clone(),
+                value,
+                min.max(low),
+                max.min(high),
+            ));
+        }
+
+        let scaled = (value - self.offset) / self.factor;
+        let raw = match self.value_type {
+            ValueType::Unsigned => scaled.round() as u64,
+            ValueType::Signed => {
+                let raw = scaled.round() as i64 as u64;
+                if self.size < 64 {
+                    raw & ((1 << self.size) - 1)
+                } else {
+                    raw
+                }
+            }
+            ValueType::Float32 => (scaled as f32).to_bits() as u64,
+            ValueType::Float64 => scaled.to_bits(),
+        };
+        Ok(raw)
+    }
+
+    /// Description of a raw value, if the DBC has one
+    pub fn label(&self, raw: u64) -> Option<&str> {
+        self.values.get(&self.raw_integer(raw)).map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signal(start_bit: u16, size: u16, byte_order: ByteOrder, value_type: ValueType) -> Signal {
+        Signal {
+            name: "S".to_owned(),
+            start_bit,
+            size,
+            byte_order,
+            value_type,
+            factor: 1.0,
+            offset: 0.0,
+            min: 0.0,
+            max: 0.0,
+            unit: String::new(),
+            receivers: Vec::new(),
+            is_multiplexor: false,
+            multiplexed_by: None,
+            values: BTreeMap::new(),
+            comment: None,
+        }
+    }
+
+    #[test]
+    fn intel_signals_count_up_from_the_start_bit() {
+        let s = signal(0, 16, ByteOrder::LittleEndian, ValueType::Unsigned);
+        assert_eq!(s.raw(&[0x34, 0x12]), Some(0x1234));
+
+        let s = signal(4, 8, ByteOrder::LittleEndian, ValueType::Unsigned);
+        assert_eq!(s.raw(&[0xA0, 0x0B]), Some(0xBA));
+
+        let s = signal(9, 3, ByteOrder::LittleEndian, ValueType::Unsigned);
+        assert_eq!(s.raw(&[0xFF, 0b0000_1010]), Some(0b101));
+    }
+
+    #[test]
+    fn motorola_signals_count_down_from_the_start_bit() {
+        let s = signal(7, 16, ByteOrder::BigEndian, ValueType::Unsigned);
+        assert_eq!(s.raw(&[0x12, 0x34]), Some(0x1234));
+
+        // the low nibble of byte 0, then the high nibble of byte 1
+        let s = signal(3, 8, ByteOrder::BigEndian, ValueType::Unsigned);
+        assert_eq!(s.raw(&[0x0A, 0xB0]), Some(0xAB));
+
+        let s = signal(13, 3, ByteOrder::BigEndian, ValueType::Unsigned);
+        assert_eq!(s.raw(&[0xFF, 0b0010_1000]), Some(0b101));
+    }
+
+    #[test]
+    fn signals_past_the_payload_are_not_read() {
+        let s = signal(56, 16, ByteOrder::LittleEndian, ValueType::Unsigned);
+        assert_eq!(s.raw(&[0; 8]), None);
+        assert_eq!(s.insert(&mut [0; 8], 0), None);
+
+        let s = signal(0, 0, ByteOrder::LittleEndian, ValueType::Unsigned);
+        assert_eq!(s.raw(&[0; 8]), None);
+    }
+
+    #[test]
+    fn insert_only_touches_the_signal_bits() {
+        for order in &[ByteOrder::LittleEndian, ByteOrder::BigEndian] {
+            let s = signal(11, 7, *order, ValueType::Unsigned);
+            let mut data = [0xFF; 4];
+            s.insert(&mut data, 0).unwrap();
+            assert_eq!(s.raw(&data), Some(0));
+            assert_eq!(data.iter().map(|b| b.count_zeros()).sum::<u32>(), 7);
+
+            s.insert(&mut data, 0b101_0011).unwrap();
+            assert_eq!(s.raw(&data), Some(0b101_0011));
+        }
+    }
+
+    #[test]
+    fn signed_values_are_sign_extended_before_scaling() {
+        let mut s = signal(0, 12, ByteOrder::LittleEndian, ValueType::Signed);
+        s.factor = 0.5;
+        s.offset = 10.0;
+        assert_eq!(s.to_physical(0xFFF), 9.5);
+        assert_eq!(s.to_physical(0x800), -1014.0);
+        assert_eq!(s.to_physical(0x7FF), 1033.5);
+        assert_eq!(s.raw_integer(0xFFF), -1);
+    }
+
+    #[test]
+    fn float_signals_are_reinterpreted() {
+        let s = signal(0, 32, ByteOrder::LittleEndian, ValueType::Float32);
+        assert_eq!(s.to_physical(2.5_f32.to_bits() as u64), 2.5);
+
+        let s = signal(0, 64, ByteOrder::LittleEndian, ValueType::Float64);
+        let data = (-0.125_f64).to_le_bytes();
+        assert_eq!(s.to_physical(s.raw(&data).unwrap()), -0.125);
+    }
+
+    #[test]
+    fn labels_use_the_signed_raw_value() {
+        let mut s = signal(0, 8, ByteOrder::LittleEndian, ValueType::Signed);
+        s.values.insert(-1, "Invalid".to_owned());
+        s.values.insert(1, "On".to_owned());
+        assert_eq!(s.label(0xFF), Some("Invalid"));
+        assert_eq!(s.label(1), Some("On"));
+        assert_eq!(s.label(2), None);
+    }
+
+    #[test]
+    fn physical_values_convert_back_to_raw() {
+        let mut s = signal(0, 12, ByteOrder::LittleEndian, ValueType::Signed);
+        s.factor = 0.5;
+        s.offset = 10.0;
+        for raw in &[0, 1, 0x7FF, 0x800, 0xFFF] {
+            assert_eq!(s.from_physical(s.to_physical(*raw)).unwrap(), *raw);
+        }
+
+        let s = signal(0, 32, ByteOrder::LittleEndian, ValueType::Float32);
+        assert_eq!(s.from_physical(2.5).unwrap(), 2.5_f32.to_bits() as u64);
+    }
+
+    #[test]
+    fn values_outside_the_range_are_rejected() {
+        let mut s = signal(0, 8, ByteOrder::LittleEndian, ValueType::Unsigned);
+        s.min = 10.0;
+        s.max = 20.0;
+        assert_eq!(s.from_physical(10.0).unwrap(), 10);
+        assert!(matches!(
+            s.from_physical(9.0),
+            Err(DbcError::OutOfRange(_, _, min, max)) if min == 10.0 && max == 20.0
+        ));
+        assert!(s.from_physical(21.0).is_err());
+
+        // without a range, what the bits can hold
+        s.min = 0.0;
+        s.max = 0.0;
+        assert_eq!(s.from_physical(255.0).unwrap(), 255);
+        assert!(s.from_physical(256.0).is_err());
+        assert!(s.from_physical(-1.0).is_err());
+
+        // a range wider than the bits is narrowed to them
+        s.max = 1000.0;
+        assert!(s.from_physical(256.0).is_err());
+    }
+
+    #[test]
+    fn non_finite_values_are_rejected() {
+        for value_type in &[ValueType::Unsigned, ValueType::Float64] {
+            let s = signal(0, 64, ByteOrder::LittleEndian, *value_type);
+            for value in &[f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+                assert!(s.from_physical(*value).is_err());
+            }
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use quick_error::quick_error;
+
+use std::fmt;
+
+/// ISO 15765-2 network layer timers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NTimer {
+    /// Putting a frame on the bus
+    As,
+
+    /// Putting a flow control frame on the bus
+    Ar,
+
+    /// Waiting for flow control after a first frame or a block
+    Bs,
+
+    /// Waiting for the next consecutive frame
+    Cr,
+}
+
+impl fmt::Display for NTimer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            NTimer::As => "N_As",
+            NTimer::Ar => "N_Ar",
+            NTimer::Bs => "N_Bs",
+            NTimer::Cr => "N_Cr",
+        })
+    }
+}
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum DiagError {
+        IoError(err: std::io::Error) {
+            from()
+            display("{}", err)
+        }
+        AdaptorError(err: adaptor_core::AdaptorError) {
+            from()
+            display("{}", err)
+        }
+        Timeout(timer: NTimer) {
+            display("ISO-TP {} timeout", timer)
+        }
+        Overflow {
+            display("ISO-TP message does not fit in the receive buffer")
+        }
+        InvalidFlowStatus(status: u8) {
+            display("invalid ISO-TP flow status {:#x}", status)
+        }
+        WaitLimit {
+            display("too many ISO-TP flow control wait frames")
+        }
+        SequenceError(expected: u8, got: u8) {
+            display("ISO-TP consecutive frame {} received, expected {}", got, expected)
+        }
+        PayloadLength(len: usize) {
+            display("{} bytes cannot be sent over ISO-TP", len)
+        }
+        NoResponse {
+            display("no response from the ECU")
+        }
+        NegativeResponse(service: u8, code: u8) {
+            display("service {:#04x} rejected: {} ({:#04x})", service, crate::uds::nrc_name(*code), code)
+        }
+        InvalidResponse(msg: String) {
+            display("invalid response: {}", msg)
+        }
+        InvalidService(service: u8) {
+            display("{:#04x} is not a request service, its response would not fit in a byte", service)
+        }
+        InvalidSecurityLevel(level: u8) {
+            display("security access level {:#04x} is not an odd level from 0x01 to 0x7d", level)
+        }
+        SecurityKey(msg: String) {
+            display("failed to compute the security key: {}", msg)
+        }
+        ImageError(line: usize, msg: String) {
+            display("image line {}: {}", line, msg)
+        }
+        BadCheckpoint(segment: usize, offset: usize) {
+            display("checkpoint {}:{} is outside the image", segment, offset)
+        }
+        CheckFailed(status: Vec<u8>) {
+            display("ECU rejected the image check with status {:02X?}", status)
+        }
+    }
+}
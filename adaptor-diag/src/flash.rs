@@ -0,0 +1,533 @@
+//! Downloading an image to an ECU with RequestDownload, TransferData and
+//! RequestTransferExit, one download per segment.
+
+use crate::errors::DiagError;
+use crate::image::Image;
+use crate::uds::{RoutineAction, UdsClient};
+use crate::Result;
+
+use adaptor_core::FrameIo;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// Common eraseMemory routine, called with the address and size to erase
+pub const ERASE_MEMORY_ROUTINE: u16 = 0xFF00;
+
+/// Common checkMemory routine, called with the CRC-32 of every segment
+pub const CHECK_MEMORY_ROUTINE: u16 = 0x0202;
+
+/// Four byte address and size in routine parameters
+const ADDRESS_AND_LENGTH_FORMAT: u8 = 0x44;
+
+/// CRC-32 as used by zlib and Ethernet
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0_u32, |crc, byte| {
+        (0..8).fold(crc ^ *byte as u32, |crc, _| {
+            if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FlashConfig {
+    /// Largest TransferData payload, the ECU's own limit applies if smaller
+    pub block_size: Option<usize>,
+
+    /// Compression and encryption method for RequestDownload, 0 for neither
+    pub data_format: u8,
+
+    /// Routine run before writing each segment, with its address and size
+    pub erase_routine: Option<u16>,
+
+    /// Routine run once everything is written, with each segment's CRC-32.
+    /// A status record starting with anything but 0 fails the check.
+    pub check_routine: Option<u16>,
+
+    /// Times a block is repeated after getting no response
+    pub retries: u32,
+}
+
+impl Default for FlashConfig {
+    fn default() -> Self {
+        Self {
+            block_size: None,
+            data_format: 0,
+            erase_routine: Some(ERASE_MEMORY_ROUTINE),
+            check_routine: Some(CHECK_MEMORY_ROUTINE),
+            retries: 3,
+        }
+    }
+}
+
+/// Where a transfer got to, the first byte the ECU has not acknowledged.
+/// Written as `<segment>:<offset>` so it can be saved between runs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub segment: usize,
+    pub offset: usize,
+}
+
+impl fmt::Display for Checkpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.segment, self.offset)
+    }
+}
+
+impl FromStr for Checkpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || format!("'{}' is not a <segment>:<offset> checkpoint", s);
+        let mut parts = s.splitn(2, ':');
+        let segment = parts
+            .next()
+            .and_then(|p| p.parse().ok())
+            .ok_or_else(invalid)?;
+        let offset = parts
+            .next()
+            .and_then(|p| p.parse().ok())
+            .ok_or_else(invalid)?;
+        Ok(Self { segment, offset })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// Segment being written
+    pub segment: usize,
+    pub segments: usize,
+
+    /// Bytes acknowledged by the ECU across all segments
+    pub written: usize,
+    pub total: usize,
+}
+
+/// Writes an image segment by segment, remembering how far it got.
+///
+/// If `run` fails part way, e.g. because the ECU stopped responding, calling
+/// it again restarts with a fresh RequestDownload from the last acknowledged
+/// block, after the caller has restored the session and security level.
+/// Segments that were already started are not erased again.
+pub struct Flasher<'a> {
+    image: &'a Image,
+    config: FlashConfig,
+    checkpoint: Checkpoint,
+}
+
+impl<'a> Flasher<'a> {
+    pub fn new(image: &'a Image, config: FlashConfig) -> Self {
+        Self::resume(image, config, Checkpoint::default())
+    }
+
+    /// Continues a transfer that stopped at `checkpoint` in an earlier run
+    pub fn resume(image: &'a Image, config: FlashConfig, checkpoint: Checkpoint) -> Self {
+        Self {
+            image,
+            config,
+            checkpoint,
+        }
+    }
+
+    pub fn checkpoint(&self) -> Checkpoint {
+        self.checkpoint
+    }
+
+    /// Whether every segment has been written, the check may still be due
+    pub fn is_written(&self) -> bool {
+        self.checkpoint.segment >= self.image.segments.len()
+    }
+
+    fn progress(&self) -> Progress {
+        let segments = &self.image.segments;
+        let done = self.checkpoint.segment.min(segments.len());
+        Progress {
+            segment: self.checkpoint.segment,
+            segments: segments.len(),
+            written: segments[..done].iter().map(|s| s.data.len()).sum::<usize>()
+                + self.checkpoint.offset,
+            total: self.image.len(),
+        }
+    }
+
+    /// Writes what is left of the image and runs the check routine,
+    /// calling `progress` after every block
+    pub fn run<I, F>(&mut self, client: &mut UdsClient<I>, mut progress: F) -> Result<()>
+    where
+        I: FrameIo,
+        F: FnMut(&Progress),
+    {
+        progress(&self.progress());
+        while !self.is_written() {
+            self.write_segment(client, &mut progress)?;
+        }
+
+        if let Some(routine) = self.config.check_routine {
+            let params: Vec<u8> = self
+                .image
+                .segments
+                .iter()
+                .flat_map(|s| crc32(&s.data).to_be_bytes().to_vec())
+                .collect();
+            let status = client.routine_control(RoutineAction::Start, routine, &params)?;
+            if status.first().is_some_and(|result| *result != 0) {
+                return Err(DiagError::CheckFailed(status));
+            }
+        }
+        Ok(())
+    }
+
+    fn write_segment<I, F>(&mut self, client: &mut UdsClient<I>, progress: &mut F) -> Result<()>
+    where
+        I: FrameIo,
+        F: FnMut(&Progress),
+    {
+        let segment = &self.image.segments[self.checkpoint.segment];
+        let offset = self.checkpoint.offset;
+
+        if offset == 0 {
+            if let Some(routine) = self.config.erase_routine {
+                let mut params = vec![ADDRESS_AND_LENGTH_FORMAT];
+                params.extend_from_slice(&segment.address.to_be_bytes());
+                params.extend_from_slice(&(segment.data.len() as u32).to_be_bytes());
+                client.routine_control(RoutineAction::Start, routine, &params)?;
+            }
+        }
+
+        let remaining = segment
+            .data
+            .get(offset..)
+            .ok_or(DiagError::BadCheckpoint(self.checkpoint.segment, offset))?;
+        if remaining.is_empty() {
+            // every block was acknowledged before the transfer dropped
+            self.checkpoint = Checkpoint {
+                segment: self.checkpoint.segment + 1,
+                offset: 0,
+            };
+            return Ok(());
+        }
+
+        let max = client.request_download(
+            segment.address + offset as u32,
+            remaining.len() as u32,
+            self.config.data_format,
+        )?;
+        // the ECU's limit counts the service id and block counter
+        let block_size = self
+            .config
+            .block_size
+            .map_or(max.saturating_sub(2), |size| {
+                size.min(max.saturating_sub(2))
+            });
+        if block_size == 0 {
+            return Err(DiagError::InvalidResponse(format!(
+                "ECU accepts TransferData requests of only {} bytes",
+                max
+            )));
+        }
+
+        let mut counter = 1_u8;
+        for block in remaining.chunks(block_size) {
+            let mut attempts = 0;
+            loop {
+                match client.transfer_data(counter, block) {
+                    Ok(_) => break,
+                    Err(DiagError::NoResponse) | Err(DiagError::Timeout(_))
+                        if attempts < self.config.retries =>
+                    {
+                        attempts += 1;
+                        log::warn!("Repeating block {} after no response", counter);
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            self.checkpoint.offset += block.len();
+            counter = counter.wrapping_add(1);
+            progress(&self.progress());
+        }
+
+        client.request_transfer_exit(&[])?;
+        self.checkpoint = Checkpoint {
+            segment: self.checkpoint.segment + 1,
+            offset: 0,
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::Segment;
+    use crate::isotp::{IsoTp, IsoTpConfig};
+    use crate::testing::{self, ScriptedIo};
+    use crate::uds::{
+        UdsConfig, REQUEST_DOWNLOAD, REQUEST_TRANSFER_EXIT, ROUTINE_CONTROL, TRANSFER_DATA,
+    };
+
+    use std::cell::RefCell;
+    use std::collections::BTreeMap;
+    use std::convert::TryInto;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    /// What the ECU was asked to do
+    #[derive(Debug, Default)]
+    struct Ecu {
+        /// Largest TransferData request accepted, as RequestDownload reports it
+        max_request: u16,
+
+        /// TransferData requests, counting from 1, whose response is lost
+        lost: Vec<usize>,
+
+        check_status: u8,
+
+        memory: BTreeMap<u32, u8>,
+        erased: Vec<(u32, u32)>,
+        downloads: Vec<(u32, u32)>,
+
+        /// Counter and length of every TransferData request, repeats included
+        blocks: Vec<(u8, usize)>,
+        checked: Option<Vec<u8>>,
+
+        address: u32,
+        last_counter: Option<u8>,
+    }
+
+    fn be_u32(bytes: &[u8]) -> u32 {
+        u32::from_be_bytes(bytes[..4].try_into().unwrap())
+    }
+
+    impl Ecu {
+        fn respond(&mut self, request: &[u8]) -> Option<Vec<u8>> {
+            match request {
+                [ROUTINE_CONTROL, 0x01, 0xFF, 0x00, ADDRESS_AND_LENGTH_FORMAT, params @ ..] => {
+                    self.erased.push((be_u32(params), be_u32(&params[4..])));
+                    Some(vec![0x71, 0x01, 0xFF, 0x00])
+                }
+                [ROUTINE_CONTROL, 0x01, 0x02, 0x02, crcs @ ..] => {
+                    self.checked = Some(crcs.to_vec());
+                    Some(vec![0x71, 0x01, 0x02, 0x02, self.check_status])
+                }
+                [REQUEST_DOWNLOAD, 0x00, 0x44, params @ ..] => {
+                    self.address = be_u32(params);
+                    self.last_counter = None;
+                    self.downloads.push((self.address, be_u32(&params[4..])));
+                    let max = self.max_request.to_be_bytes();
+                    Some(vec![0x74, 0x20, max[0], max[1]])
+                }
+                [TRANSFER_DATA, counter, data @ ..] => {
+                    self.blocks.push((*counter, data.len()));
+                    // a repeated block was already written
+                    if self.last_counter != Some(*counter) {
+                        for byte in data {
+                            self.memory.insert(self.address, *byte);
+                            self.address += 1;
+                        }
+                        self.last_counter = Some(*counter);
+                    }
+                    if self.lost.contains(&self.blocks.len()) {
+                        None
+                    } else {
+                        Some(vec![0x76, *counter])
+                    }
+                }
+                [REQUEST_TRANSFER_EXIT] => Some(vec![0x77]),
+                _ => Some(vec![0x7F, request[0], 0x11]),
+            }
+        }
+    }
+
+    fn ecu(max_request: u16) -> (Rc<RefCell<Ecu>>, UdsClient<ScriptedIo>) {
+        let ecu = Rc::new(RefCell::new(Ecu {
+            max_request,
+            ..Ecu::default()
+        }));
+        let io = {
+            let ecu = ecu.clone();
+            testing::ecu(move |request| ecu.borrow_mut().respond(request))
+        };
+        let config = UdsConfig {
+            p2: Duration::from_millis(50),
+            ..UdsConfig::default()
+        };
+        let client = UdsClient::new(IsoTp::new(io, IsoTpConfig::default()), config);
+        (ecu, client)
+    }
+
+    fn image() -> Image {
+        Image {
+            segments: vec![
+                Segment {
+                    address: 0x1000,
+                    data: (0..40).collect(),
+                },
+                Segment {
+                    address: 0x8000,
+                    data: vec![0xA5; 5],
+                },
+            ],
+        }
+    }
+
+    fn memory(image: &Image) -> BTreeMap<u32, u8> {
+        image
+            .segments
+            .iter()
+            .flat_map(|s| (s.address..).zip(s.data.iter().copied()))
+            .collect()
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(&[]), 0);
+    }
+
+    #[test]
+    fn checkpoints_round_trip_as_text() {
+        let checkpoint = Checkpoint {
+            segment: 2,
+            offset: 4096,
+        };
+        assert_eq!(checkpoint.to_string(), "2:4096");
+        assert_eq!("2:4096".parse(), Ok(checkpoint));
+        assert!("2".parse::<Checkpoint>().is_err());
+        assert!("a:1".parse::<Checkpoint>().is_err());
+    }
+
+    #[test]
+    fn blocks_fit_the_ecu_limit() {
+        let image = image();
+        let (ecu, mut client) = ecu(18);
+        let mut last = None;
+        Flasher::new(&image, FlashConfig::default())
+            .run(&mut client, |p| last = Some(*p))
+            .unwrap();
+
+        let ecu = ecu.borrow();
+        assert_eq!(ecu.erased, [(0x1000, 40), (0x8000, 5)]);
+        assert_eq!(ecu.downloads, [(0x1000, 40), (0x8000, 5)]);
+        // 18 byte requests leave 16 bytes for data
+        assert_eq!(ecu.blocks, [(1, 16), (2, 16), (3, 8), (1, 5)]);
+        assert_eq!(ecu.memory, memory(&image));
+
+        let mut crcs = crc32(&image.segments[0].data).to_be_bytes().to_vec();
+        crcs.extend_from_slice(&crc32(&image.segments[1].data).to_be_bytes());
+        assert_eq!(ecu.checked, Some(crcs));
+
+        let last = last.unwrap();
+        assert_eq!((last.written, last.total), (45, 45));
+    }
+
+    #[test]
+    fn configured_block_size_applies_below_the_ecu_limit() {
+        let image = image();
+        let (ecu, mut client) = ecu(0x0FFF);
+        let config = FlashConfig {
+            block_size: Some(10),
+            erase_routine: None,
+            check_routine: None,
+            ..FlashConfig::default()
+        };
+        Flasher::new(&image, config)
+            .run(&mut client, |_| {})
+            .unwrap();
+
+        let ecu = ecu.borrow();
+        assert!(ecu.erased.is_empty());
+        assert_eq!(ecu.checked, None);
+        assert_eq!(ecu.blocks, [(1, 10), (2, 10), (3, 10), (4, 10), (1, 5)]);
+    }
+
+    #[test]
+    fn an_ecu_limit_without_room_for_data_is_rejected() {
+        let image = image();
+        let (_, mut client) = ecu(2);
+        assert!(matches!(
+            Flasher::new(&image, FlashConfig::default()).run(&mut client, |_| {}),
+            Err(DiagError::InvalidResponse(_))
+        ));
+    }
+
+    #[test]
+    fn blocks_are_repeated_with_the_same_counter() {
+        let image = image();
+        let (ecu, mut client) = ecu(18);
+        ecu.borrow_mut().lost = vec![2];
+        Flasher::new(&image, FlashConfig::default())
+            .run(&mut client, |_| {})
+            .unwrap();
+
+        let ecu = ecu.borrow();
+        assert_eq!(ecu.blocks, [(1, 16), (2, 16), (2, 16), (3, 8), (1, 5)]);
+        assert_eq!(ecu.memory, memory(&image));
+    }
+
+    #[test]
+    fn dropped_transfers_resume_from_the_checkpoint() {
+        let image = image();
+        let (ecu, mut client) = ecu(18);
+        ecu.borrow_mut().lost = vec![2];
+        let config = FlashConfig {
+            retries: 0,
+            ..FlashConfig::default()
+        };
+
+        let mut flasher = Flasher::new(&image, config);
+        assert!(matches!(
+            flasher.run(&mut client, |_| {}),
+            Err(DiagError::NoResponse)
+        ));
+        let checkpoint = flasher.checkpoint();
+        assert_eq!(
+            checkpoint,
+            Checkpoint {
+                segment: 0,
+                offset: 16
+            }
+        );
+
+        // as if saved and restored by a later run
+        let checkpoint = checkpoint.to_string().parse().unwrap();
+        let mut flasher = Flasher::resume(&image, config, checkpoint);
+        flasher.run(&mut client, |_| {}).unwrap();
+        assert!(flasher.is_written());
+
+        let ecu = ecu.borrow();
+        // the started segment is not erased again
+        assert_eq!(ecu.erased, [(0x1000, 40), (0x8000, 5)]);
+        assert_eq!(ecu.downloads, [(0x1000, 40), (0x1010, 24), (0x8000, 5)]);
+        assert_eq!(ecu.blocks, [(1, 16), (2, 16), (1, 16), (2, 8), (1, 5)]);
+        assert_eq!(ecu.memory, memory(&image));
+    }
+
+    #[test]
+    fn checkpoints_past_the_segment_are_rejected() {
+        let image = image();
+        let (_, mut client) = ecu(18);
+        let checkpoint = Checkpoint {
+            segment: 0,
+            offset: 41,
+        };
+        assert!(matches!(
+            Flasher::resume(&image, FlashConfig::default(), checkpoint).run(&mut client, |_| {}),
+            Err(DiagError::BadCheckpoint(0, 41))
+        ));
+    }
+
+    #[test]
+    fn failed_checks_are_errors() {
+        let image = image();
+        let (ecu, mut client) = ecu(18);
+        ecu.borrow_mut().check_status = 0x01;
+        assert!(matches!(
+            Flasher::new(&image, FlashConfig::default()).run(&mut client, |_| {}),
+            Err(DiagError::CheckFailed(status)) if status == [0x01]
+        ));
+    }
+}
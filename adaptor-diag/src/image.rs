@@ -0,0 +1,425 @@
+//! Firmware images read from Intel HEX, Motorola S-record or raw binary
+//! files, as contiguous segments of memory.
+
+use crate::errors::DiagError;
+use crate::Result;
+
+use std::path::Path;
+
+/// A contiguous block of memory in an image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+impl Segment {
+    /// Address one past the last byte
+    pub fn end(&self) -> u64 {
+        self.address as u64 + self.data.len() as u64
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Image {
+    /// Segments sorted by address, never overlapping or touching
+    pub segments: Vec<Segment>,
+}
+
+/// Decodes pairs of hex digits, `None` if there is an odd number of them
+/// or anything else in `digits`
+pub fn decode_hex(digits: &str) -> Option<Vec<u8>> {
+    // also rules out multi-byte characters, which slicing would panic on
+    if !digits.len().is_multiple_of(2) || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).ok())
+        .collect()
+}
+
+fn parse_hex_bytes(line: usize, digits: &str) -> Result<Vec<u8>> {
+    if digits.is_ascii() && !digits.len().is_multiple_of(2) {
+        return Err(DiagError::ImageError(
+            line,
+            "odd number of hex digits".to_owned(),
+        ));
+    }
+    decode_hex(digits)
+        .ok_or_else(|| DiagError::ImageError(line, format!("invalid hex '{}'", digits)))
+}
+
+impl Image {
+    /// An image holding `data` at `address`
+    pub fn from_binary(address: u32, data: Vec<u8>) -> Self {
+        Self {
+            segments: vec![Segment { address, data }],
+        }
+    }
+
+    /// Reads a file, picking the format from its extension: `.hex` and
+    /// `.ihex` for Intel HEX, `.s19`, `.s28`, `.s37`, `.srec` and `.mot` for
+    /// S-records, anything else as binary loaded at `base`
+    pub fn from_file(path: &Path, base: u32) -> Result<Self> {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("hex") | Some("ihex") => {
+                Self::parse_ihex(&String::from_utf8_lossy(&std::fs::read(path)?))
+            }
+            Some("s19") | Some("s28") | Some("s37") | Some("srec") | Some("mot") => {
+                Self::parse_srec(&String::from_utf8_lossy(&std::fs::read(path)?))
+            }
+            _ => Ok(Self::from_binary(base, std::fs::read(path)?)),
+        }
+    }
+
+    pub fn parse_ihex(text: &str) -> Result<Self> {
+        let mut image = Self::default();
+        let mut base = 0_u32;
+
+        for (i, line) in text.lines().enumerate() {
+            let line_no = i + 1;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if !line.starts_with(':') {
+                return Err(DiagError::ImageError(line_no, "missing ':'".to_owned()));
+            }
+
+            let record = parse_hex_bytes(line_no, &line[1..])?;
+            if record.len() < 5 || record.len() != record[0] as usize + 5 {
+                return Err(DiagError::ImageError(
+                    line_no,
+                    "bad record length".to_owned(),
+                ));
+            }
+            if record.iter().fold(0_u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+                return Err(DiagError::ImageError(line_no, "bad checksum".to_owned()));
+            }
+
+            let offset = u16::from_be_bytes([record[1], record[2]]) as u32;
+            let data = &record[4..record.len() - 1];
+            match record[3] {
+                0x00 => image.insert(line_no, base.wrapping_add(offset), data)?,
+                0x01 => break,
+                0x02 if data.len() == 2 => {
+                    base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 4
+                }
+                0x04 if data.len() == 2 => {
+                    base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 16
+                }
+                // start addresses mean nothing to a download
+                0x03 | 0x05 => {}
+                kind => {
+                    return Err(DiagError::ImageError(
+                        line_no,
+                        format!("unsupported record type {:02X}", kind),
+                    ))
+                }
+            }
+        }
+        Ok(image)
+    }
+
+    pub fn parse_srec(text: &str) -> Result<Self> {
+        let mut image = Self::default();
+
+        for (i, line) in text.lines().enumerate() {
+            let line_no = i + 1;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            // the record type is sliced out by byte position below
+            if !line.is_ascii() || line.len() < 4 || !line.starts_with('S') {
+                return Err(DiagError::ImageError(line_no, "not an S-record".to_owned()));
+            }
+
+            let record = parse_hex_bytes(line_no, &line[2..])?;
+            if record.is_empty() || record.len() != record[0] as usize + 1 {
+                return Err(DiagError::ImageError(
+                    line_no,
+                    "bad record length".to_owned(),
+                ));
+            }
+            if record.iter().fold(0_u8, |sum, b| sum.wrapping_add(*b)) != 0xFF {
+                return Err(DiagError::ImageError(line_no, "bad checksum".to_owned()));
+            }
+
+            let address_len = match &line[1..2] {
+                "1" => 2,
+                "2" => 3,
+                "3" => 4,
+                // header, record counts and start addresses
+                "0" | "5" | "6" | "7" | "8" | "9" => continue,
+                kind => {
+                    return Err(DiagError::ImageError(
+                        line_no,
+                        format!("unsupported record type S{}", kind),
+                    ))
+                }
+            };
+            if record.len() < address_len + 2 {
+                return Err(DiagError::ImageError(
+                    line_no,
+                    "record too short".to_owned(),
+                ));
+            }
+
+            let address = record[1..=address_len]
+                .iter()
+                .fold(0_u32, |acc, b| (acc << 8) | *b as u32);
+            image.insert(line_no, address, &record[address_len + 1..record.len() - 1])?;
+        }
+        Ok(image)
+    }
+
+    /// Adds `data` at `address`, joining it to any segment it touches
+    fn insert(&mut self, line: usize, address: u32, data: &[u8]) -> Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let end = address as u64 + data.len() as u64;
+        if end > u32::MAX as u64 + 1 {
+            return Err(DiagError::ImageError(
+                line,
+                "data past the end of memory".to_owned(),
+            ));
+        }
+
+        // records are nearly always in order, so check the last segment first
+        let index = match self.segments.last() {
+            Some(last) if last.address <= address => self.segments.len(),
+            _ => self.segments.partition_point(|s| s.address <= address),
+        };
+        let overlaps_prev = index > 0 && self.segments[index - 1].end() > address as u64;
+        let overlaps_next =
+            index < self.segments.len() && end > self.segments[index].address as u64;
+        if overlaps_prev || overlaps_next {
+            return Err(DiagError::ImageError(
+                line,
+                format!("data at {:#x} overlaps", address),
+            ));
+        }
+
+        let joined = if index > 0 && self.segments[index - 1].end() == address as u64 {
+            self.segments[index - 1].data.extend_from_slice(data);
+            index - 1
+        } else {
+            self.segments.insert(
+                index,
+                Segment {
+                    address,
+                    data: data.to_vec(),
+                },
+            );
+            index
+        };
+
+        // the new data may have closed the gap to the next segment
+        if joined + 1 < self.segments.len()
+            && self.segments[joined].end() == self.segments[joined + 1].address as u64
+        {
+            let next = self.segments.remove(joined + 1);
+            self.segments[joined].data.extend(next.data);
+        }
+        Ok(())
+    }
+
+    /// Total number of bytes in every segment
+    pub fn len(&self) -> usize {
+        self.segments.iter().map(|s| s.data.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_checksum(mut record: Vec<u8>, complement: u8) -> String {
+        let sum = record.iter().fold(0_u8, |sum, b| sum.wrapping_add(*b));
+        record.push(complement.wrapping_sub(sum));
+        record.iter().map(|b| format!("{:02X}", b)).collect()
+    }
+
+    fn ihex(kind: u8, offset: u16, data: &[u8]) -> String {
+        let mut record = vec![data.len() as u8];
+        record.extend_from_slice(&offset.to_be_bytes());
+        record.push(kind);
+        record.extend_from_slice(data);
+        format!(":{}", with_checksum(record, 0))
+    }
+
+    fn srec(kind: char, address: &[u8], data: &[u8]) -> String {
+        let mut record = vec![(address.len() + data.len() + 1) as u8];
+        record.extend_from_slice(address);
+        record.extend_from_slice(data);
+        format!("S{}{}", kind, with_checksum(record, 0xFF))
+    }
+
+    fn image_error(result: Result<Image>) -> (usize, String) {
+        match result {
+            Err(DiagError::ImageError(line, msg)) => (line, msg),
+            other => panic!("expected an image error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn known_intel_hex_records() {
+        let text = ":0400000001020304F2\n:00000001FF\n";
+        let image = Image::parse_ihex(text).unwrap();
+        assert_eq!(image, Image::from_binary(0, vec![1, 2, 3, 4]));
+    }
+
+    #[test]
+    fn intel_hex_extended_addresses() {
+        let text = [
+            ihex(0x04, 0, &[0x08, 0x00]),
+            ihex(0x00, 0x0010, &[0xAA, 0xBB]),
+            ihex(0x02, 0, &[0x12, 0x34]),
+            ihex(0x00, 0x0002, &[0xCC]),
+            ihex(0x05, 0, &[0x08, 0x00, 0x01, 0x00]),
+            ihex(0x01, 0, &[]),
+            // nothing after the end of file record is read
+            ihex(0x00, 0, &[0xDD]),
+        ]
+        .join("\n");
+        let image = Image::parse_ihex(&text).unwrap();
+        assert_eq!(
+            image.segments,
+            [
+                Segment {
+                    address: 0x0001_2342,
+                    data: vec![0xCC],
+                },
+                Segment {
+                    address: 0x0800_0010,
+                    data: vec![0xAA, 0xBB],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn adjacent_records_are_merged() {
+        let text = [
+            ihex(0x00, 0x0100, &[1, 2]),
+            ihex(0x00, 0x0106, &[7, 8]),
+            // fills the gap between the two, joining all three
+            ihex(0x00, 0x0102, &[3, 4, 5, 6]),
+            ihex(0x00, 0x0200, &[9]),
+        ]
+        .join("\n");
+        let image = Image::parse_ihex(&text).unwrap();
+        assert_eq!(
+            image.segments,
+            [
+                Segment {
+                    address: 0x100,
+                    data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+                },
+                Segment {
+                    address: 0x200,
+                    data: vec![9],
+                },
+            ]
+        );
+        assert_eq!(image.len(), 9);
+    }
+
+    #[test]
+    fn overlapping_records_are_rejected() {
+        for second in &[0x0101, 0x00FF] {
+            let text = [ihex(0x00, 0x0100, &[1, 2]), ihex(0x00, *second, &[3, 4])].join("\n");
+            let (line, msg) = image_error(Image::parse_ihex(&text));
+            assert_eq!(line, 2);
+            assert!(msg.contains("overlaps"), "{}", msg);
+        }
+    }
+
+    #[test]
+    fn bad_intel_hex_records_are_rejected() {
+        let good = ihex(0x00, 0, &[1, 2]);
+        let mut bad_checksum = good.clone();
+        bad_checksum.replace_range(good.len() - 2.., "00");
+
+        for (text, expected) in &[
+            (good[1..].to_owned(), "missing ':'"),
+            (bad_checksum, "bad checksum"),
+            (good[..good.len() - 2].to_owned(), "bad record length"),
+            (format!("{}0", good), "odd number of hex digits"),
+            (":0100000001FG".to_owned(), "invalid hex"),
+            (ihex(0x06, 0, &[]), "unsupported record type 06"),
+            (":01000000\u{e9}1FE".to_owned(), "invalid hex"),
+        ] {
+            let (line, msg) = image_error(Image::parse_ihex(&format!("\n{}", text)));
+            assert_eq!(line, 2);
+            assert!(msg.contains(expected), "{}: {}", text, msg);
+        }
+    }
+
+    #[test]
+    fn s_records_of_every_address_size() {
+        let text = [
+            srec('0', &[0, 0], b"hdr"),
+            srec('1', &[0x12, 0x34], &[1, 2]),
+            srec('2', &[0x01, 0x00, 0x00], &[3]),
+            srec('3', &[0x08, 0x00, 0x00, 0x00], &[4, 5, 6]),
+            srec('3', &[0x08, 0x00, 0x00, 0x03], &[7]),
+            srec('5', &[0, 4], &[]),
+            srec('7', &[0x08, 0, 0, 0], &[]),
+        ]
+        .join("\r\n");
+        let image = Image::parse_srec(&text).unwrap();
+        assert_eq!(
+            image.segments,
+            [
+                Segment {
+                    address: 0x1234,
+                    data: vec![1, 2],
+                },
+                Segment {
+                    address: 0x01_0000,
+                    data: vec![3],
+                },
+                Segment {
+                    address: 0x0800_0000,
+                    data: vec![4, 5, 6, 7],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn bad_s_records_are_rejected() {
+        let good = srec('1', &[0, 0], &[1]);
+        let mut bad_checksum = good.clone();
+        bad_checksum.replace_range(good.len() - 2.., "00");
+
+        for (text, expected) in &[
+            ("S\u{e9}0000".to_owned(), "not an S-record"),
+            ("X1030000FC".to_owned(), "not an S-record"),
+            (bad_checksum, "bad checksum"),
+            ("S1050000".to_owned(), "bad record length"),
+            (srec('4', &[0, 0], &[]), "unsupported record type S4"),
+            (srec('3', &[0, 0], &[]), "record too short"),
+            (
+                srec('3', &[0xFF, 0xFF, 0xFF, 0xFF], &[1, 2]),
+                "past the end of memory",
+            ),
+        ] {
+            let (line, msg) = image_error(Image::parse_srec(&format!("\n{}", text)));
+            assert_eq!(line, 2);
+            assert!(msg.contains(expected), "{}: {}", text, msg);
+        }
+    }
+}
@@ -0,0 +1,634 @@
+//! ISO 15765-2 transport, splitting messages into single, first and
+//! consecutive frames paced by the receiver's flow control.
+
+use crate::errors::{DiagError, NTimer};
+use crate::Result;
+
+use adaptor_common::{CANFrame, Frame};
+use adaptor_core::FrameIo;
+
+use std::time::{Duration, Instant};
+
+const SINGLE_FRAME: u8 = 0x0;
+const FIRST_FRAME: u8 = 0x1;
+const CONSECUTIVE_FRAME: u8 = 0x2;
+const FLOW_CONTROL: u8 = 0x3;
+
+const FC_CONTINUE: u8 = 0x0;
+const FC_WAIT: u8 = 0x1;
+const FC_OVERFLOW: u8 = 0x2;
+
+/// Longest message a first frame can announce without the 32 bit escape
+const MAX_SHORT_LEN: usize = 0xFFF;
+
+/// Decodes an STmin byte, reserved values mean the longest time, 127 ms
+pub fn decode_st_min(byte: u8) -> Duration {
+    match byte {
+        0x00..=0x7F => Duration::from_millis(byte as u64),
+        0xF1..=0xF9 => Duration::from_micros((byte - 0xF0) as u64 * 100),
+        _ => Duration::from_millis(0x7F),
+    }
+}
+
+/// Encodes the shortest STmin at least as long as `st_min`
+pub fn encode_st_min(st_min: Duration) -> u8 {
+    let micros = st_min.as_micros();
+    if micros == 0 {
+        0
+    } else if micros < 1000 {
+        0xF0 + micros.div_ceil(100) as u8
+    } else {
+        micros.div_ceil(1000).min(0x7F) as u8
+    }
+}
+
+/// Where the ISO-TP data starts in a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Addressing {
+    /// The whole payload is ISO-TP data
+    Normal,
+
+    /// The first byte is the target address, `tx` on frames we send and
+    /// `rx` on frames addressed to us
+    Extended { tx: u8, rx: u8 },
+
+    /// The first byte is an address extension, the same in both directions
+    Mixed(u8),
+}
+
+impl Addressing {
+    fn tx_byte(&self) -> Option<u8> {
+        match self {
+            Addressing::Normal => None,
+            Addressing::Extended { tx, .. } => Some(*tx),
+            Addressing::Mixed(extension) => Some(*extension),
+        }
+    }
+
+    fn rx_byte(&self) -> Option<u8> {
+        match self {
+            Addressing::Normal => None,
+            Addressing::Extended { rx, .. } => Some(*rx),
+            Addressing::Mixed(extension) => Some(*extension),
+        }
+    }
+
+    /// Bytes taken from the start of every frame
+    fn len(&self) -> usize {
+        match self {
+            Addressing::Normal => 0,
+            _ => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct IsoTpConfig {
+    /// Identifier of the frames we send
+    pub tx_id: u32,
+
+    /// Identifier of the frames we accept, everything else is ignored
+    pub rx_id: u32,
+
+    pub is_ext: bool,
+    pub addressing: Addressing,
+
+    /// Pads every frame to 8 bytes with this value, `None` sends frames no
+    /// longer than their data
+    pub padding: Option<u8>,
+
+    /// Consecutive frames we let the sender send between flow control
+    /// frames, 0 for no limit
+    pub block_size: u8,
+
+    /// Gap we ask the sender to leave between consecutive frames
+    pub st_min: Duration,
+
+    /// Longest message accepted, the sender is told to abort anything longer
+    pub max_len: usize,
+
+    /// Flow control wait frames tolerated in a row, N_WFTmax
+    pub max_wait_frames: u32,
+
+    pub n_as: Duration,
+    pub n_ar: Duration,
+    pub n_bs: Duration,
+    pub n_cr: Duration,
+}
+
+impl Default for IsoTpConfig {
+    fn default() -> Self {
+        Self {
+            tx_id: 0x7E0,
+            rx_id: 0x7E8,
+            is_ext: false,
+            addressing: Addressing::Normal,
+            padding: Some(0xCC),
+            block_size: 0,
+            st_min: Duration::from_millis(0),
+            max_len: 0xFFFF,
+            max_wait_frames: 10,
+            n_as: Duration::from_millis(1000),
+            n_ar: Duration::from_millis(1000),
+            n_bs: Duration::from_millis(1000),
+            n_cr: Duration::from_millis(1000),
+        }
+    }
+}
+
+/// How a multi frame reception ended
+enum Reception {
+    Complete(Vec<u8>),
+
+    /// The sender started a new message, given by its first frame
+    Interrupted(Vec<u8>),
+}
+
+/// One ISO-TP connection, sending on `tx_id` and receiving on `rx_id`
+pub struct IsoTp<I: FrameIo> {
+    io: I,
+    config: IsoTpConfig,
+}
+
+impl<I: FrameIo> IsoTp<I> {
+    pub fn new(io: I, config: IsoTpConfig) -> Self {
+        Self { io, config }
+    }
+
+    pub fn config(&self) -> &IsoTpConfig {
+        &self.config
+    }
+
+    pub fn config_mut(&mut self) -> &mut IsoTpConfig {
+        &mut self.config
+    }
+
+    pub fn io_mut(&mut self) -> &mut I {
+        &mut self.io
+    }
+
+    pub fn into_inner(self) -> I {
+        self.io
+    }
+
+    /// Sends `data` as a single frame, or as a first frame and consecutive
+    /// frames at the pace the receiver asks for
+    pub fn send(&mut self, data: &[u8]) -> Result<()> {
+        let address_len = self.config.addressing.len();
+        let frame_data = 8 - address_len;
+
+        if data.is_empty() || data.len() > u32::MAX as usize {
+            return Err(DiagError::PayloadLength(data.len()));
+        }
+
+        if data.len() < frame_data {
+            let mut single = vec![(SINGLE_FRAME << 4) | data.len() as u8];
+            single.extend_from_slice(data);
+            return self.write(&single, NTimer::As);
+        }
+
+        let mut first = if data.len() <= MAX_SHORT_LEN {
+            vec![
+                (FIRST_FRAME << 4) | (data.len() >> 8) as u8,
+                data.len() as u8,
+            ]
+        } else {
+            let mut first = vec![FIRST_FRAME << 4, 0];
+            first.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            first
+        };
+        let (head, mut rest) = data.split_at(frame_data - first.len());
+        first.extend_from_slice(head);
+        self.write(&first, NTimer::As)?;
+
+        let mut sequence = 1;
+        while !rest.is_empty() {
+            let (block_size, st_min) = self.wait_flow_control()?;
+
+            let mut sent = 0;
+            while !rest.is_empty() && (block_size == 0 || sent < block_size as usize) {
+                if sent > 0 {
+                    std::thread::sleep(st_min);
+                }
+
+                let (chunk, remaining) = rest.split_at(rest.len().min(frame_data - 1));
+                let mut consecutive = vec![(CONSECUTIVE_FRAME << 4) | sequence];
+                consecutive.extend_from_slice(chunk);
+                self.write(&consecutive, NTimer::As)?;
+
+                rest = remaining;
+                sequence = (sequence + 1) & 0x0F;
+                sent += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Waits up to `timeout` for a message to start and reads all of it,
+    /// `None` if nothing started in time
+    pub fn recv(&mut self, timeout: Duration) -> Result<Option<Vec<u8>>> {
+        let deadline = Instant::now() + timeout;
+        let mut next = None;
+
+        loop {
+            let bytes = match next.take() {
+                Some(bytes) => bytes,
+                None => match self.read(deadline)? {
+                    Some(bytes) => bytes,
+                    None => return Ok(None),
+                },
+            };
+
+            match bytes[0] >> 4 {
+                SINGLE_FRAME => {
+                    let len = (bytes[0] & 0x0F) as usize;
+                    if len == 0 || len >= bytes.len() {
+                        log::warn!("Ignoring single frame with invalid length {}", len);
+                        continue;
+                    }
+                    return Ok(Some(bytes[1..=len].to_vec()));
+                }
+                FIRST_FRAME => match self.recv_segmented(&bytes)? {
+                    Reception::Complete(data) => return Ok(Some(data)),
+                    Reception::Interrupted(first) => next = Some(first),
+                },
+                _ => log::trace!("Ignoring {:02X?} outside of a message", bytes),
+            }
+        }
+    }
+
+    /// Sends a request and waits up to `timeout` for the response to start
+    pub fn request(&mut self, data: &[u8], timeout: Duration) -> Result<Option<Vec<u8>>> {
+        self.send(data)?;
+        self.recv(timeout)
+    }
+
+    fn recv_segmented(&mut self, first: &[u8]) -> Result<Reception> {
+        let short_len = (((first[0] & 0x0F) as usize) << 8) | *first.get(1).unwrap_or(&0) as usize;
+        let (len, header) = if short_len == 0 && first.len() >= 6 {
+            let mut len = [0; 4];
+            len.copy_from_slice(&first[2..6]);
+            (u32::from_be_bytes(len) as usize, 6)
+        } else {
+            (short_len, 2)
+        };
+
+        if len > self.config.max_len {
+            self.write_flow_control(FC_OVERFLOW)?;
+            return Err(DiagError::Overflow);
+        }
+
+        let mut data = Vec::with_capacity(len);
+        data.extend(first.iter().skip(header).take(len));
+        self.write_flow_control(FC_CONTINUE)?;
+
+        let mut sequence = 1;
+        let mut in_block = 0;
+        while data.len() < len {
+            let bytes = match self.read(Instant::now() + self.config.n_cr)? {
+                Some(bytes) => bytes,
+                None => return Err(DiagError::Timeout(NTimer::Cr)),
+            };
+
+            match bytes[0] >> 4 {
+                CONSECUTIVE_FRAME => {}
+                SINGLE_FRAME | FIRST_FRAME => {
+                    log::warn!("New message started before the last one was complete");
+                    return Ok(Reception::Interrupted(bytes));
+                }
+                _ => continue,
+            }
+
+            if bytes[0] & 0x0F != sequence {
+                return Err(DiagError::SequenceError(sequence, bytes[0] & 0x0F));
+            }
+            let missing = len - data.len();
+            data.extend(bytes.iter().skip(1).take(missing));
+            sequence = (sequence + 1) & 0x0F;
+
+            if self.config.block_size != 0 {
+                in_block += 1;
+                if in_block == self.config.block_size && data.len() < len {
+                    self.write_flow_control(FC_CONTINUE)?;
+                    in_block = 0;
+                }
+            }
+        }
+
+        Ok(Reception::Complete(data))
+    }
+
+    /// Waits for the receiver to let us continue, returning its block size and STmin
+    fn wait_flow_control(&mut self) -> Result<(u8, Duration)> {
+        let mut waits = 0;
+        loop {
+            let deadline = Instant::now() + self.config.n_bs;
+            let bytes = loop {
+                match self.read(deadline)? {
+                    Some(bytes) if bytes[0] >> 4 == FLOW_CONTROL => break bytes,
+                    Some(_) => continue,
+                    None => return Err(DiagError::Timeout(NTimer::Bs)),
+                }
+            };
+
+            match bytes[0] & 0x0F {
+                FC_CONTINUE => {
+                    let block_size = bytes.get(1).copied().unwrap_or(0);
+                    let st_min = decode_st_min(bytes.get(2).copied().unwrap_or(0));
+                    return Ok((block_size, st_min));
+                }
+                FC_WAIT => {
+                    waits += 1;
+                    if waits > self.config.max_wait_frames {
+                        return Err(DiagError::WaitLimit);
+                    }
+                }
+                FC_OVERFLOW => return Err(DiagError::Overflow),
+                status => return Err(DiagError::InvalidFlowStatus(status)),
+            }
+        }
+    }
+
+    fn write_flow_control(&mut self, status: u8) -> Result<()> {
+        let flow_control = [
+            (FLOW_CONTROL << 4) | status,
+            self.config.block_size,
+            encode_st_min(self.config.st_min),
+        ];
+        self.write(&flow_control, NTimer::Ar)
+    }
+
+    /// Sends ISO-TP bytes behind the address byte, if any
+    fn write(&mut self, bytes: &[u8], timer: NTimer) -> Result<()> {
+        let mut data = [self.config.padding.unwrap_or(0); 8];
+        let mut len = 0;
+        if let Some(address) = self.config.addressing.tx_byte() {
+            data[0] = address;
+            len = 1;
+        }
+        data[len..len + bytes.len()].copy_from_slice(bytes);
+        len += bytes.len();
+
+        let dlc = if self.config.padding.is_some() {
+            8
+        } else {
+            len
+        };
+        let frame = CANFrame::new(
+            self.config.tx_id,
+            dlc as u8,
+            data,
+            false,
+            false,
+            self.config.is_ext,
+        );
+
+        let timeout = match timer {
+            NTimer::Ar => self.config.n_ar,
+            _ => self.config.n_as,
+        };
+        self.io
+            .send_frame(frame.into(), timeout)
+            .map_err(|e| match e {
+                e if e.is_timeout() => DiagError::Timeout(timer),
+                e => e.into(),
+            })
+    }
+
+    /// Reads until a frame addressed to us arrives, returning the ISO-TP
+    /// bytes after the address byte, or `None` at `deadline`
+    fn read(&mut self, deadline: Instant) -> Result<Option<Vec<u8>>> {
+        loop {
+            let remaining = match deadline.checked_duration_since(Instant::now()) {
+                Some(remaining) if remaining > Duration::from_secs(0) => remaining,
+                _ => return Ok(None),
+            };
+            let frame = match self.io.recv_frame(remaining)? {
+                Some(frame) => frame,
+                None => return Ok(None),
+            };
+            if !self.is_ours(&frame) {
+                continue;
+            }
+
+            let payload = frame.payload();
+            let payload = match self.config.addressing.rx_byte() {
+                Some(address) => match payload.split_first() {
+                    Some((first, rest)) if *first == address => rest,
+                    _ => continue,
+                },
+                None => payload,
+            };
+            if !payload.is_empty() {
+                return Ok(Some(payload.to_vec()));
+            }
+        }
+    }
+
+    fn is_ours(&self, frame: &Frame) -> bool {
+        frame.id() == self.config.rx_id
+            && frame.is_ext() == self.config.is_ext
+            && !frame.is_rtr()
+            && !frame.is_err()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{padded, ScriptedIo};
+
+    const RX: u32 = 0x7E8;
+
+    fn config() -> IsoTpConfig {
+        IsoTpConfig {
+            n_bs: Duration::from_millis(20),
+            n_cr: Duration::from_millis(20),
+            ..IsoTpConfig::default()
+        }
+    }
+
+    fn message(len: usize) -> Vec<u8> {
+        (0..len).map(|i| i as u8).collect()
+    }
+
+    /// First frame and consecutive frames carrying `data`, as an ECU sends them
+    fn segmented(data: &[u8]) -> Vec<Frame> {
+        let mut first = vec![0x10 | (data.len() >> 8) as u8, data.len() as u8];
+        first.extend_from_slice(&data[..6]);
+        let mut frames = vec![padded(RX, &first)];
+        for (i, chunk) in data[6..].chunks(7).enumerate() {
+            let mut consecutive = vec![0x20 | ((i + 1) & 0x0F) as u8];
+            consecutive.extend_from_slice(chunk);
+            frames.push(padded(RX, &consecutive));
+        }
+        frames
+    }
+
+    #[test]
+    fn st_min_encoding() {
+        assert_eq!(decode_st_min(0x0A), Duration::from_millis(10));
+        assert_eq!(decode_st_min(0xF3), Duration::from_micros(300));
+        assert_eq!(decode_st_min(0x80), Duration::from_millis(127));
+        assert_eq!(encode_st_min(Duration::from_micros(250)), 0xF3);
+        assert_eq!(encode_st_min(Duration::from_millis(10)), 0x0A);
+        assert_eq!(encode_st_min(Duration::from_secs(1)), 0x7F);
+    }
+
+    #[test]
+    fn single_frames() {
+        let mut io = ScriptedIo::new();
+        io.incoming.push_back(padded(0x7E9, &[0x02, 0x7F, 0x10]));
+        io.incoming
+            .push_back(padded(RX, &[0x06, 0x50, 0x03, 0x00, 0x32, 0x01, 0xF4]));
+        let mut isotp = IsoTp::new(io, config());
+
+        isotp.send(&[0x10, 0x03]).unwrap();
+        let response = isotp.recv(Duration::from_millis(50)).unwrap();
+        assert_eq!(response, Some(vec![0x50, 0x03, 0x00, 0x32, 0x01, 0xF4]));
+        assert_eq!(
+            isotp.io_mut().sent_payloads(),
+            vec![vec![0x02, 0x10, 0x03, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC]]
+        );
+        assert_eq!(isotp.recv(Duration::from_millis(10)).unwrap(), None);
+    }
+
+    #[test]
+    fn segmented_send_follows_flow_control() {
+        // block size 2, STmin 5 ms
+        let flow_control = padded(RX, &[0x30, 0x02, 0x05]);
+        let mut consecutive = 0;
+        let io = ScriptedIo::responding(move |frame| match frame.payload()[0] >> 4 {
+            FIRST_FRAME => vec![flow_control],
+            CONSECUTIVE_FRAME => {
+                consecutive += 1;
+                if consecutive % 2 == 0 {
+                    vec![flow_control]
+                } else {
+                    Vec::new()
+                }
+            }
+            _ => Vec::new(),
+        });
+        let mut isotp = IsoTp::new(io, config());
+
+        let data = message(30);
+        isotp.send(&data).unwrap();
+
+        let io = isotp.into_inner();
+        let sent = io.sent_payloads();
+        assert_eq!(sent.len(), 5);
+        assert_eq!(&sent[0][..2], &[0x10, 30]);
+        let pcis: Vec<u8> = sent[1..].iter().map(|p| p[0]).collect();
+        assert_eq!(pcis, vec![0x21, 0x22, 0x23, 0x24]);
+
+        let mut reassembled = sent[0][2..].to_vec();
+        for payload in &sent[1..] {
+            reassembled.extend_from_slice(&payload[1..]);
+        }
+        reassembled.truncate(30);
+        assert_eq!(reassembled, data);
+
+        // STmin only applies within a block
+        let gap = |a: usize, b: usize| io.sent[b].0 - io.sent[a].0;
+        assert!(gap(1, 2) >= Duration::from_millis(5));
+        assert!(gap(3, 4) >= Duration::from_millis(5));
+    }
+
+    #[test]
+    fn wait_frames_delay_sending() {
+        let io = ScriptedIo::responding(|frame| match frame.payload()[0] >> 4 {
+            FIRST_FRAME => vec![padded(RX, &[0x31]), padded(RX, &[0x30, 0x00, 0x00])],
+            _ => Vec::new(),
+        });
+        let mut isotp = IsoTp::new(io, config());
+        isotp.send(&message(12)).unwrap();
+        assert_eq!(isotp.io_mut().sent.len(), 2);
+
+        isotp.config_mut().max_wait_frames = 0;
+        assert!(matches!(
+            isotp.send(&message(12)),
+            Err(DiagError::WaitLimit)
+        ));
+    }
+
+    #[test]
+    fn segmented_receive_sends_flow_control_per_block() {
+        let data = message(30);
+        let mut io = ScriptedIo::new();
+        io.incoming.extend(segmented(&data));
+        let mut isotp = IsoTp::new(
+            io,
+            IsoTpConfig {
+                block_size: 2,
+                st_min: Duration::from_millis(10),
+                ..config()
+            },
+        );
+
+        assert_eq!(isotp.recv(Duration::from_millis(50)).unwrap(), Some(data));
+        let flow_control = vec![0x30, 0x02, 0x0A, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC];
+        assert_eq!(
+            isotp.io_mut().sent_payloads(),
+            vec![flow_control.clone(), flow_control]
+        );
+    }
+
+    #[test]
+    fn long_messages_are_refused() {
+        let mut io = ScriptedIo::new();
+        io.incoming.extend(segmented(&message(30)));
+        let mut isotp = IsoTp::new(
+            io,
+            IsoTpConfig {
+                max_len: 20,
+                ..config()
+            },
+        );
+
+        assert!(matches!(
+            isotp.recv(Duration::from_millis(50)),
+            Err(DiagError::Overflow)
+        ));
+        assert_eq!(isotp.io_mut().sent_payloads()[0][0], 0x32);
+    }
+
+    #[test]
+    fn sequence_errors() {
+        let mut frames = segmented(&message(20));
+        frames.remove(1);
+        let mut io = ScriptedIo::new();
+        io.incoming.extend(frames);
+        let mut isotp = IsoTp::new(io, config());
+
+        assert!(matches!(
+            isotp.recv(Duration::from_millis(50)),
+            Err(DiagError::SequenceError(1, 2))
+        ));
+    }
+
+    #[test]
+    fn missing_flow_control_times_out() {
+        let mut isotp = IsoTp::new(ScriptedIo::new(), config());
+        assert!(matches!(
+            isotp.send(&message(20)),
+            Err(DiagError::Timeout(NTimer::Bs))
+        ));
+        assert_eq!(isotp.io_mut().sent.len(), 1);
+    }
+
+    #[test]
+    fn missing_consecutive_frame_times_out() {
+        let mut frames = segmented(&message(20));
+        frames.truncate(2);
+        let mut io = ScriptedIo::new();
+        io.incoming.extend(frames);
+        let mut isotp = IsoTp::new(io, config());
+
+        assert!(matches!(
+            isotp.recv(Duration::from_millis(50)),
+            Err(DiagError::Timeout(NTimer::Cr))
+        ));
+    }
+}
@@ -0,0 +1,22 @@
+//! Diagnostic protocols on top of the probe: the ISO-TP transport that
+//! carries messages longer than one frame, a UDS client built on it, ECU
+//! flashing with that client, and OBD-II queries.
+
+pub mod errors;
+pub mod flash;
+pub mod image;
+pub mod isotp;
+pub mod obd;
+pub mod uds;
+
+#[cfg(test)]
+mod testing;
+
+pub type Result<T> = std::result::Result<T, errors::DiagError>;
+
+pub use errors::{DiagError, NTimer};
+pub use flash::{Checkpoint, FlashConfig, Flasher, Progress};
+pub use image::{Image, Segment};
+pub use isotp::{Addressing, IsoTp, IsoTpConfig};
+pub use obd::{ObdAddress, ObdClient, Pid};
+pub use uds::{Dtc, ResetType, RoutineAction, Session, UdsClient, UdsConfig};
@@ -0,0 +1,615 @@
+//! SAE J1979 / ISO 15765-4 OBD-II queries: live data, trouble codes and the
+//! vehicle identification number.
+
+use crate::errors::DiagError;
+use crate::isotp::{IsoTp, IsoTpConfig};
+use crate::uds::{UdsClient, UdsConfig};
+use crate::Result;
+
+use adaptor_core::FrameIo;
+
+use std::convert::TryFrom;
+use std::time::Duration;
+
+pub const CURRENT_DATA: u8 = 0x01;
+pub const STORED_DTCS: u8 = 0x03;
+pub const PENDING_DTCS: u8 = 0x07;
+pub const VEHICLE_INFO: u8 = 0x09;
+
+/// Mode 09 info type holding the VIN
+const VIN_INFO_TYPE: u8 = 0x02;
+
+/// Address of one ECU, physically addressed so multi-frame responses
+/// such as the VIN get their flow control
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObdAddress {
+    /// 11 bit identifiers, ECU number 0 to 7 requested on 0x7E0 + n and
+    /// responding on 0x7E8 + n
+    Standard(u8),
+
+    /// 29 bit identifiers, ECU address requested on 0x18DAxxF1 and
+    /// responding on 0x18DAF1xx, the engine ECU is usually 0x10
+    Extended(u8),
+}
+
+impl Default for ObdAddress {
+    fn default() -> Self {
+        ObdAddress::Standard(0)
+    }
+}
+
+impl ObdAddress {
+    pub fn isotp_config(&self) -> IsoTpConfig {
+        match *self {
+            ObdAddress::Standard(ecu) => IsoTpConfig {
+                tx_id: 0x7E0 + (ecu & 0x07) as u32,
+                rx_id: 0x7E8 + (ecu & 0x07) as u32,
+                is_ext: false,
+                ..IsoTpConfig::default()
+            },
+            ObdAddress::Extended(ecu) => IsoTpConfig {
+                tx_id: 0x18DA_00F1 | ((ecu as u32) << 8),
+                rx_id: 0x18DA_F100 | ecu as u32,
+                is_ext: true,
+                ..IsoTpConfig::default()
+            },
+        }
+    }
+}
+
+/// A mode 01 parameter and how to turn its bytes into a value
+#[derive(Debug, Clone, Copy)]
+pub struct Pid {
+    pub pid: u8,
+
+    /// Short name used on the command line, e.g. `RPM`
+    pub name: &'static str,
+    pub description: &'static str,
+    pub unit: &'static str,
+    pub len: usize,
+    decode: fn(&[u8]) -> f64,
+}
+
+impl Pid {
+    /// Decodes the data bytes of a response, `None` if there are too few
+    pub fn decode(&self, data: &[u8]) -> Option<f64> {
+        if data.len() < self.len {
+            return None;
+        }
+        Some((self.decode)(data))
+    }
+}
+
+fn percent(d: &[u8]) -> f64 {
+    d[0] as f64 * 100.0 / 255.0
+}
+
+fn fuel_trim(d: &[u8]) -> f64 {
+    d[0] as f64 * 100.0 / 128.0 - 100.0
+}
+
+fn temperature(d: &[u8]) -> f64 {
+    d[0] as f64 - 40.0
+}
+
+fn byte(d: &[u8]) -> f64 {
+    d[0] as f64
+}
+
+fn word(d: &[u8]) -> f64 {
+    u16::from_be_bytes([d[0], d[1]]) as f64
+}
+
+pub const PIDS: &[Pid] = &[
+    Pid {
+        pid: 0x04,
+        name: "LOAD",
+        description: "Calculated engine load",
+        unit: "%",
+        len: 1,
+        decode: percent,
+    },
+    Pid {
+        pid: 0x05,
+        name: "COOLANT",
+        description: "Engine coolant temperature",
+        unit: "°C",
+        len: 1,
+        decode: temperature,
+    },
+    Pid {
+        pid: 0x06,
+        name: "STFT1",
+        description: "Short term fuel trim, bank 1",
+        unit: "%",
+        len: 1,
+        decode: fuel_trim,
+    },
+    Pid {
+        pid: 0x07,
+        name: "LTFT1",
+        description: "Long term fuel trim, bank 1",
+        unit: "%",
+        len: 1,
+        decode: fuel_trim,
+    },
+    Pid {
+        pid: 0x08,
+        name: "STFT2",
+        description: "Short term fuel trim, bank 2",
+        unit: "%",
+        len: 1,
+        decode: fuel_trim,
+    },
+    Pid {
+        pid: 0x09,
+        name: "LTFT2",
+        description: "Long term fuel trim, bank 2",
+        unit: "%",
+        len: 1,
+        decode: fuel_trim,
+    },
+    Pid {
+        pid: 0x0A,
+        name: "FUEL_PRESSURE",
+        description: "Fuel pressure",
+        unit: "kPa",
+        len: 1,
+        decode: |d| d[0] as f64 * 3.0,
+    },
+    Pid {
+        pid: 0x0B,
+        name: "MAP",
+        description: "Intake manifold absolute pressure",
+        unit: "kPa",
+        len: 1,
+        decode: byte,
+    },
+    Pid {
+        pid: 0x0C,
+        name: "RPM",
+        description: "Engine speed",
+        unit: "rpm",
+        len: 2,
+        decode: |d| word(d) / 4.0,
+    },
+    Pid {
+        pid: 0x0D,
+        name: "SPEED",
+        description: "Vehicle speed",
+        unit: "km/h",
+        len: 1,
+        decode: byte,
+    },
+    Pid {
+        pid: 0x0E,
+        name: "TIMING",
+        description: "Timing advance",
+        unit: "°",
+        len: 1,
+        decode: |d| d[0] as f64 / 2.0 - 64.0,
+    },
+    Pid {
+        pid: 0x0F,
+        name: "IAT",
+        description: "Intake air temperature",
+        unit: "°C",
+        len: 1,
+        decode: temperature,
+    },
+    Pid {
+        pid: 0x10,
+        name: "MAF",
+        description: "Mass air flow rate",
+        unit: "g/s",
+        len: 2,
+        decode: |d| word(d) / 100.0,
+    },
+    Pid {
+        pid: 0x11,
+        name: "THROTTLE",
+        description: "Throttle position",
+        unit: "%",
+        len: 1,
+        decode: percent,
+    },
+    Pid {
+        pid: 0x1F,
+        name: "RUNTIME",
+        description: "Run time since engine start",
+        unit: "s",
+        len: 2,
+        decode: word,
+    },
+    Pid {
+        pid: 0x21,
+        name: "MIL_DISTANCE",
+        description: "Distance traveled with MIL on",
+        unit: "km",
+        len: 2,
+        decode: word,
+    },
+    Pid {
+        pid: 0x2F,
+        name: "FUEL",
+        description: "Fuel tank level",
+        unit: "%",
+        len: 1,
+        decode: percent,
+    },
+    Pid {
+        pid: 0x31,
+        name: "CLEARED_DISTANCE",
+        description: "Distance traveled since codes cleared",
+        unit: "km",
+        len: 2,
+        decode: word,
+    },
+    Pid {
+        pid: 0x33,
+        name: "BARO",
+        description: "Barometric pressure",
+        unit: "kPa",
+        len: 1,
+        decode: byte,
+    },
+    Pid {
+        pid: 0x42,
+        name: "VOLTAGE",
+        description: "Control module voltage",
+        unit: "V",
+        len: 2,
+        decode: |d| word(d) / 1000.0,
+    },
+    Pid {
+        pid: 0x45,
+        name: "REL_THROTTLE",
+        description: "Relative throttle position",
+        unit: "%",
+        len: 1,
+        decode: percent,
+    },
+    Pid {
+        pid: 0x46,
+        name: "AMBIENT",
+        description: "Ambient air temperature",
+        unit: "°C",
+        len: 1,
+        decode: temperature,
+    },
+    Pid {
+        pid: 0x49,
+        name: "PEDAL",
+        description: "Accelerator pedal position D",
+        unit: "%",
+        len: 1,
+        decode: percent,
+    },
+    Pid {
+        pid: 0x5C,
+        name: "OIL_TEMP",
+        description: "Engine oil temperature",
+        unit: "°C",
+        len: 1,
+        decode: temperature,
+    },
+    Pid {
+        pid: 0x5E,
+        name: "FUEL_RATE",
+        description: "Engine fuel rate",
+        unit: "L/h",
+        len: 2,
+        decode: |d| word(d) / 20.0,
+    },
+];
+
+/// Looks a PID up by name, ignoring case, or by its hex number
+pub fn find_pid(name: &str) -> Option<&'static Pid> {
+    PIDS.iter()
+        .find(|pid| pid.name.eq_ignore_ascii_case(name))
+        .or_else(|| {
+            let digits = name.trim_start_matches("0x").trim_start_matches("0X");
+            let number = u8::from_str_radix(digits, 16).ok()?;
+            PIDS.iter().find(|pid| pid.pid == number)
+        })
+}
+
+/// Client for one ECU's OBD-II services. OBD uses the same request and
+/// response framing as UDS, so this drives a `UdsClient`.
+pub struct ObdClient<I: FrameIo> {
+    client: UdsClient<I>,
+}
+
+impl<I: FrameIo> ObdClient<I> {
+    pub fn new(isotp: IsoTp<I>) -> Self {
+        // ISO 15765-4 gives ECUs 50 ms to answer
+        let config = UdsConfig {
+            p2: Duration::from_millis(50),
+            ..UdsConfig::default()
+        };
+        Self {
+            client: UdsClient::new(isotp, config),
+        }
+    }
+
+    pub fn into_inner(self) -> UdsClient<I> {
+        self.client
+    }
+
+    /// Reads the raw data bytes of a mode 01 PID
+    pub fn read_pid(&mut self, pid: u8) -> Result<Vec<u8>> {
+        let response = self.client.request(&[CURRENT_DATA, pid])?;
+        if response.get(1) != Some(&pid) {
+            return Err(DiagError::InvalidResponse(format!(
+                "expected PID {:02X}, got {:02X?}",
+                pid, response
+            )));
+        }
+        Ok(response[2..].to_vec())
+    }
+
+    /// Reads and decodes a PID from the table
+    pub fn read(&mut self, pid: &Pid) -> Result<f64> {
+        let data = self.read_pid(pid.pid)?;
+        pid.decode(&data).ok_or_else(|| {
+            DiagError::InvalidResponse(format!(
+                "{} needs {} bytes, got {}",
+                pid.name,
+                pid.len,
+                data.len()
+            ))
+        })
+    }
+
+    /// Lists the mode 01 PIDs the ECU supports, following the support
+    /// bitmaps at 0x00, 0x20 and so on
+    pub fn supported_pids(&mut self) -> Result<Vec<u8>> {
+        let mut supported = Vec::new();
+        let mut base = 0_u8;
+        loop {
+            let data = self.read_pid(base)?;
+            if data.len() < 4 {
+                return Err(DiagError::InvalidResponse(format!(
+                    "short support bitmap for PID {:02X}",
+                    base
+                )));
+            }
+            let bitmap = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+            supported.extend(
+                (0..32_u16)
+                    .filter(|bit| bitmap & (0x8000_0000 >> bit) != 0)
+                    // the continuation bit of the bitmap at 0xE0 has no PID
+                    .filter_map(|bit| u8::try_from(base as u16 + bit + 1).ok()),
+            );
+
+            // the last bit says whether the next bitmap is supported
+            if bitmap & 1 == 0 || base == 0xE0 {
+                return Ok(supported);
+            }
+            base += 0x20;
+        }
+    }
+
+    /// Whether the malfunction indicator lamp is on and how many DTCs are stored
+    pub fn monitor_status(&mut self) -> Result<(bool, u8)> {
+        let data = self.read_pid(0x01)?;
+        let status = *data
+            .first()
+            .ok_or_else(|| DiagError::InvalidResponse("empty monitor status".to_owned()))?;
+        Ok((status & 0x80 != 0, status & 0x7F))
+    }
+
+    fn read_dtcs(&mut self, mode: u8) -> Result<Vec<u16>> {
+        let response = self.client.request(&[mode])?;
+        // on CAN the codes follow a count byte, unused slots are zero
+        Ok(response
+            .get(2..)
+            .unwrap_or_default()
+            .chunks_exact(2)
+            .map(|code| u16::from_be_bytes([code[0], code[1]]))
+            .filter(|code| *code != 0)
+            .collect())
+    }
+
+    /// Mode 03, confirmed DTCs, formatted with `uds::format_dtc`
+    pub fn stored_dtcs(&mut self) -> Result<Vec<u16>> {
+        self.read_dtcs(STORED_DTCS)
+    }
+
+    /// Mode 07, DTCs detected during the current or last drive cycle
+    pub fn pending_dtcs(&mut self) -> Result<Vec<u16>> {
+        self.read_dtcs(PENDING_DTCS)
+    }
+
+    /// Mode 09 vehicle identification number
+    pub fn vin(&mut self) -> Result<String> {
+        let response = self.client.request(&[VEHICLE_INFO, VIN_INFO_TYPE])?;
+        if response.get(1) != Some(&VIN_INFO_TYPE) || response.len() < 3 {
+            return Err(DiagError::InvalidResponse(format!(
+                "bad VIN response {:02X?}",
+                response
+            )));
+        }
+        // skip the number of data items, some ECUs pad the VIN with zeros
+        let vin: String = response[3..]
+            .iter()
+            .filter(|b| **b != 0)
+            .map(|b| *b as char)
+            .collect();
+        Ok(vin)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, ScriptedIo};
+    use crate::uds::format_dtc;
+
+    /// Client for an ECU answering mode 01, 03, 07 and 09 requests from `respond`
+    fn ecu<F>(respond: F) -> ObdClient<ScriptedIo>
+    where
+        F: FnMut(&[u8]) -> Option<Vec<u8>> + 'static,
+    {
+        let io = testing::ecu(respond);
+        ObdClient::new(IsoTp::new(io, ObdAddress::default().isotp_config()))
+    }
+
+    /// An ECU reporting these support bitmaps for PIDs 0x00, 0x20 and so on
+    fn bitmaps(bitmaps: &'static [u32]) -> ObdClient<ScriptedIo> {
+        ecu(move |request| match request {
+            [CURRENT_DATA, pid] if pid % 0x20 == 0 => {
+                let bitmap = bitmaps.get((pid / 0x20) as usize)?;
+                let mut response = vec![0x41, *pid];
+                response.extend_from_slice(&bitmap.to_be_bytes());
+                Some(response)
+            }
+            _ => None,
+        })
+    }
+
+    fn decode(name: &str, data: &[u8]) -> Option<f64> {
+        find_pid(name).unwrap().decode(data)
+    }
+
+    #[test]
+    fn addresses() {
+        let config = ObdAddress::Standard(2).isotp_config();
+        assert_eq!(
+            (config.tx_id, config.rx_id, config.is_ext),
+            (0x7E2, 0x7EA, false)
+        );
+        let config = ObdAddress::Extended(0x10).isotp_config();
+        assert_eq!(
+            (config.tx_id, config.rx_id, config.is_ext),
+            (0x18DA_10F1, 0x18DA_F110, true)
+        );
+    }
+
+    #[test]
+    fn pids_are_found_by_name_or_number() {
+        assert_eq!(find_pid("rpm").unwrap().pid, 0x0C);
+        assert_eq!(find_pid("0x0d").unwrap().name, "SPEED");
+        assert_eq!(find_pid("05").unwrap().name, "COOLANT");
+        assert!(find_pid("0x03").is_none());
+        assert!(find_pid("BOOST").is_none());
+    }
+
+    #[test]
+    fn pids_decode() {
+        assert_eq!(decode("RPM", &[0x1A, 0xF8]), Some(1726.0));
+        assert_eq!(decode("SPEED", &[0x50]), Some(80.0));
+        assert_eq!(decode("COOLANT", &[0x00]), Some(-40.0));
+        assert_eq!(decode("LOAD", &[0xFF]), Some(100.0));
+        assert_eq!(decode("STFT1", &[0x80]), Some(0.0));
+        assert_eq!(decode("STFT1", &[0x00]), Some(-100.0));
+        assert_eq!(decode("TIMING", &[0x90]), Some(8.0));
+        assert_eq!(decode("MAF", &[0x01, 0xF4]), Some(5.0));
+        assert_eq!(decode("VOLTAGE", &[0x36, 0xB0]), Some(14.0));
+        // extra bytes are ignored, missing ones are not
+        assert_eq!(decode("SPEED", &[0x50, 0x00]), Some(80.0));
+        assert_eq!(decode("RPM", &[0x1A]), None);
+    }
+
+    #[test]
+    fn reads_decode_the_response() {
+        let mut client = ecu(|request| match request {
+            [CURRENT_DATA, 0x0C] => Some(vec![0x41, 0x0C, 0x1A, 0xF8]),
+            [CURRENT_DATA, 0x01] => Some(vec![0x41, 0x01, 0x83, 0x07, 0xE5, 0x00]),
+            [CURRENT_DATA, 0x0D] => Some(vec![0x41, 0x0C, 0x50]),
+            [CURRENT_DATA, 0x10] => Some(vec![0x41, 0x10, 0x01]),
+            _ => None,
+        });
+        assert_eq!(client.read(find_pid("RPM").unwrap()).unwrap(), 1726.0);
+        assert_eq!(client.monitor_status().unwrap(), (true, 3));
+        // response for the wrong PID
+        assert!(matches!(
+            client.read_pid(0x0D),
+            Err(DiagError::InvalidResponse(_))
+        ));
+        assert!(matches!(
+            client.read(find_pid("MAF").unwrap()),
+            Err(DiagError::InvalidResponse(_))
+        ));
+    }
+
+    #[test]
+    fn supported_pids_follow_the_bitmaps() {
+        // 0x01, 0x0C, 0x0D and 0x20 then 0x21 and 0x2F
+        let mut client = bitmaps(&[0x8018_0001, 0x8002_0000]);
+        assert_eq!(
+            client.supported_pids().unwrap(),
+            [0x01, 0x0C, 0x0D, 0x20, 0x21, 0x2F]
+        );
+
+        let mut client = bitmaps(&[0x0000_0000]);
+        assert!(client.supported_pids().unwrap().is_empty());
+
+        let mut client = ecu(|_| Some(vec![0x41, 0x00, 0xFF]));
+        assert!(matches!(
+            client.supported_pids(),
+            Err(DiagError::InvalidResponse(_))
+        ));
+    }
+
+    #[test]
+    fn supported_pids_stop_at_the_last_bitmap() {
+        // every bitmap claims the next, including the one at 0xE0
+        let mut client = bitmaps(&[0x0000_0001; 8]);
+        assert_eq!(
+            client.supported_pids().unwrap(),
+            [0x20, 0x40, 0x60, 0x80, 0xA0, 0xC0, 0xE0]
+        );
+
+        let mut client = bitmaps(&[0xFFFF_FFFF; 8]);
+        let supported = client.supported_pids().unwrap();
+        assert_eq!(supported, (0x01..=0xFF).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn trouble_codes() {
+        let mut client = ecu(|request| match request {
+            // count, then P0103, C0300 and an unused slot
+            [STORED_DTCS] => Some(vec![0x43, 0x02, 0x01, 0x03, 0x43, 0x00, 0x00, 0x00]),
+            [PENDING_DTCS] => Some(vec![0x47, 0x00]),
+            _ => None,
+        });
+        let stored: Vec<_> = client
+            .stored_dtcs()
+            .unwrap()
+            .into_iter()
+            .map(format_dtc)
+            .collect();
+        assert_eq!(stored, ["P0103", "C0300"]);
+        assert!(client.pending_dtcs().unwrap().is_empty());
+    }
+
+    #[test]
+    fn vin_spans_several_frames() {
+        let mut client = ecu(|request| match request {
+            [VEHICLE_INFO, VIN_INFO_TYPE] => {
+                let mut response = vec![0x49, VIN_INFO_TYPE, 0x01];
+                response.extend_from_slice(b"1G1JC5444R7252367");
+                Some(response)
+            }
+            _ => None,
+        });
+        assert_eq!(client.vin().unwrap(), "1G1JC5444R7252367");
+
+        let sent = client
+            .into_inner()
+            .into_inner()
+            .into_inner()
+            .sent_payloads();
+        assert_eq!(sent[1][0], 0x30, "flow control follows the first frame");
+    }
+
+    #[test]
+    fn vin_padding_is_dropped() {
+        let mut client = ecu(|_| Some(vec![0x49, VIN_INFO_TYPE, 0x01, 0x00, 0x00, b'A', b'B']));
+        assert_eq!(client.vin().unwrap(), "AB");
+
+        let mut client = ecu(|_| Some(vec![0x49, 0x04, 0x01]));
+        assert!(matches!(client.vin(), Err(DiagError::InvalidResponse(_))));
+    }
+}
@@ -0,0 +1,74 @@
+//! ISO-TP ECU standing in for the other end of a diagnostic connection
+
+pub use adaptor_core::testing::ScriptedIo;
+
+use adaptor_common::{CANFrame, Frame};
+
+/// Identifier `ecu` responds on, the default ISO-TP receive id
+pub const ECU_ID: u32 = 0x7E8;
+
+/// An ECU answering each ISO-TP request, single or segmented, with
+/// whatever `respond` returns, or nothing for `None` as if the response
+/// was lost. Segmented responses send every consecutive frame after the
+/// first flow control.
+pub fn ecu<F>(mut respond: F) -> ScriptedIo
+where
+    F: FnMut(&[u8]) -> Option<Vec<u8>> + 'static,
+{
+    let mut request = Vec::new();
+    let mut len = 0;
+    let mut consecutive = Vec::new();
+    ScriptedIo::responding(move |frame| {
+        let payload = frame.payload();
+        match payload[0] >> 4 {
+            0 => {
+                len = (payload[0] & 0x0F) as usize;
+                request = payload[1..=len].to_vec();
+            }
+            1 => {
+                len = ((payload[0] & 0x0F) as usize) << 8 | payload[1] as usize;
+                request = payload[2..].to_vec();
+                return vec![padded(ECU_ID, &[0x30, 0x00, 0x00])];
+            }
+            2 if request.len() < len => {
+                request.extend_from_slice(&payload[1..]);
+                if request.len() < len {
+                    return Vec::new();
+                }
+            }
+            3 => return std::mem::take(&mut consecutive),
+            _ => return Vec::new(),
+        }
+
+        request.truncate(len);
+        match respond(&request) {
+            Some(response) if response.len() <= 7 => {
+                let mut single = vec![response.len() as u8];
+                single.extend_from_slice(&response);
+                vec![padded(ECU_ID, &single)]
+            }
+            Some(response) => {
+                let mut first = vec![0x10 | (response.len() >> 8) as u8, response.len() as u8];
+                first.extend_from_slice(&response[..6]);
+                consecutive = response[6..]
+                    .chunks(7)
+                    .zip(1..)
+                    .map(|(chunk, sn): (_, u8)| {
+                        let mut cf = vec![0x20 | (sn & 0x0F)];
+                        cf.extend_from_slice(chunk);
+                        padded(ECU_ID, &cf)
+                    })
+                    .collect();
+                vec![padded(ECU_ID, &first)]
+            }
+            None => Vec::new(),
+        }
+    })
+}
+
+/// Frame on `id` padded to 8 bytes with 0xCC
+pub fn padded(id: u32, bytes: &[u8]) -> Frame {
+    let mut data = [0xCC; 8];
+    data[..bytes.len()].copy_from_slice(bytes);
+    CANFrame::new(id, 8, data, false, false, false).into()
+}
@@ -0,0 +1,596 @@
+//! ISO 14229 unified diagnostic services client.
+
+use crate::errors::DiagError;
+use crate::isotp::IsoTp;
+use crate::Result;
+
+use adaptor_core::FrameIo;
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+pub const DIAGNOSTIC_SESSION_CONTROL: u8 = 0x10;
+pub const ECU_RESET: u8 = 0x11;
+pub const CLEAR_DIAGNOSTIC_INFORMATION: u8 = 0x14;
+pub const READ_DTC_INFORMATION: u8 = 0x19;
+pub const READ_DATA_BY_IDENTIFIER: u8 = 0x22;
+pub const SECURITY_ACCESS: u8 = 0x27;
+pub const WRITE_DATA_BY_IDENTIFIER: u8 = 0x2E;
+pub const ROUTINE_CONTROL: u8 = 0x31;
+pub const REQUEST_DOWNLOAD: u8 = 0x34;
+pub const TRANSFER_DATA: u8 = 0x36;
+pub const REQUEST_TRANSFER_EXIT: u8 = 0x37;
+pub const TESTER_PRESENT: u8 = 0x3E;
+
+const NEGATIVE_RESPONSE: u8 = 0x7F;
+const POSITIVE_RESPONSE_OFFSET: u8 = 0x40;
+
+/// Set in a sub-function to ask the ECU not to send a positive response
+const SUPPRESS_POSITIVE_RESPONSE: u8 = 0x80;
+
+/// Negative response code telling the client to keep waiting
+pub const RESPONSE_PENDING: u8 = 0x78;
+
+/// Four byte memory address and four byte memory size
+const ADDRESS_AND_LENGTH_FORMAT: u8 = 0x44;
+
+/// ReadDTCInformation sub-function reporting DTCs by status mask
+const REPORT_DTC_BY_STATUS_MASK: u8 = 0x02;
+
+/// Name of a negative response code from ISO 14229-1
+pub fn nrc_name(code: u8) -> &'static str {
+    match code {
+        0x10 => "general reject",
+        0x11 => "service not supported",
+        0x12 => "sub-function not supported",
+        0x13 => "incorrect message length or invalid format",
+        0x14 => "response too long",
+        0x21 => "busy, repeat request",
+        0x22 => "conditions not correct",
+        0x24 => "request sequence error",
+        0x25 => "no response from sub-net component",
+        0x26 => "failure prevents execution of requested action",
+        0x31 => "request out of range",
+        0x33 => "security access denied",
+        0x35 => "invalid key",
+        0x36 => "exceeded number of attempts",
+        0x37 => "required time delay not expired",
+        0x70 => "upload/download not accepted",
+        0x71 => "transfer data suspended",
+        0x72 => "general programming failure",
+        0x73 => "wrong block sequence counter",
+        0x78 => "response pending",
+        0x7E => "sub-function not supported in active session",
+        0x7F => "service not supported in active session",
+        0x81 => "RPM too high",
+        0x82 => "RPM too low",
+        0x83 => "engine is running",
+        0x84 => "engine is not running",
+        0x88 => "vehicle speed too high",
+        0x92 | 0x93 => "voltage out of range",
+        _ => "unknown",
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Session {
+    Default,
+    Programming,
+    Extended,
+    SafetySystem,
+    Other(u8),
+}
+
+impl Session {
+    pub fn value(&self) -> u8 {
+        match self {
+            Session::Default => 0x01,
+            Session::Programming => 0x02,
+            Session::Extended => 0x03,
+            Session::SafetySystem => 0x04,
+            Session::Other(value) => *value,
+        }
+    }
+}
+
+impl From<u8> for Session {
+    fn from(value: u8) -> Self {
+        match value {
+            0x01 => Session::Default,
+            0x02 => Session::Programming,
+            0x03 => Session::Extended,
+            0x04 => Session::SafetySystem,
+            value => Session::Other(value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetType {
+    Hard,
+    KeyOffOn,
+    Soft,
+    Other(u8),
+}
+
+impl ResetType {
+    pub fn value(&self) -> u8 {
+        match self {
+            ResetType::Hard => 0x01,
+            ResetType::KeyOffOn => 0x02,
+            ResetType::Soft => 0x03,
+            ResetType::Other(value) => *value,
+        }
+    }
+}
+
+impl From<u8> for ResetType {
+    fn from(value: u8) -> Self {
+        match value {
+            0x01 => ResetType::Hard,
+            0x02 => ResetType::KeyOffOn,
+            0x03 => ResetType::Soft,
+            value => ResetType::Other(value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoutineAction {
+    Start,
+    Stop,
+    RequestResults,
+}
+
+impl RoutineAction {
+    pub fn value(&self) -> u8 {
+        match self {
+            RoutineAction::Start => 0x01,
+            RoutineAction::Stop => 0x02,
+            RoutineAction::RequestResults => 0x03,
+        }
+    }
+}
+
+/// A diagnostic trouble code as reported by ReadDTCInformation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dtc {
+    /// Three byte code, the top two bytes are the SAE J2012 code and the
+    /// last one the failure type
+    pub code: u32,
+    pub status: u8,
+}
+
+impl Dtc {
+    /// Test failed at the time of the request
+    pub fn is_active(&self) -> bool {
+        self.status & 0x01 != 0
+    }
+
+    /// Confirmed over enough operation cycles to be stored
+    pub fn is_confirmed(&self) -> bool {
+        self.status & 0x08 != 0
+    }
+}
+
+/// Formats the two byte SAE J2012 part of a DTC, e.g. `P0301`
+pub fn format_dtc(code: u16) -> String {
+    let system = ['P', 'C', 'B', 'U'][(code >> 14) as usize];
+    format!("{}{:04X}", system, code & 0x3FFF)
+}
+
+impl fmt::Display for Dtc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{:02X} status {:#04x}",
+            format_dtc((self.code >> 8) as u16),
+            self.code & 0xFF,
+            self.status
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct UdsConfig {
+    /// How long the ECU has to start responding, P2
+    pub p2: Duration,
+
+    /// How long to keep waiting after the ECU answers response pending, P2*
+    pub p2_star: Duration,
+
+    /// Idle time after which `keep_alive` sends tester present, well under
+    /// the ECU's five second S3 session timeout
+    pub tester_present_interval: Duration,
+}
+
+impl Default for UdsConfig {
+    fn default() -> Self {
+        Self {
+            p2: Duration::from_millis(1000),
+            p2_star: Duration::from_millis(5000),
+            tester_present_interval: Duration::from_millis(2000),
+        }
+    }
+}
+
+/// Client for one ECU, reached over an ISO-TP connection
+pub struct UdsClient<I: FrameIo> {
+    isotp: IsoTp<I>,
+    config: UdsConfig,
+    last_request: Instant,
+}
+
+impl<I: FrameIo> UdsClient<I> {
+    pub fn new(isotp: IsoTp<I>, config: UdsConfig) -> Self {
+        Self {
+            isotp,
+            config,
+            last_request: Instant::now(),
+        }
+    }
+
+    pub fn config(&self) -> &UdsConfig {
+        &self.config
+    }
+
+    pub fn isotp_mut(&mut self) -> &mut IsoTp<I> {
+        &mut self.isotp
+    }
+
+    pub fn into_inner(self) -> IsoTp<I> {
+        self.isotp
+    }
+
+    /// Sends a raw request and returns the positive response, waiting
+    /// through response pending replies. Negative responses are errors.
+    pub fn request(&mut self, request: &[u8]) -> Result<Vec<u8>> {
+        let service = *request.first().ok_or(DiagError::PayloadLength(0))?;
+        let positive = service
+            .checked_add(POSITIVE_RESPONSE_OFFSET)
+            .ok_or(DiagError::InvalidService(service))?;
+
+        self.isotp.send(request)?;
+        self.last_request = Instant::now();
+
+        let mut deadline = Instant::now() + self.config.p2;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let response = self.isotp.recv(timeout)?.ok_or(DiagError::NoResponse)?;
+
+            match response.as_slice() {
+                [NEGATIVE_RESPONSE, rejected, RESPONSE_PENDING, ..] if *rejected == service => {
+                    log::debug!("Service {:#04x} response pending", service);
+                    deadline = Instant::now() + self.config.p2_star;
+                }
+                [NEGATIVE_RESPONSE, rejected, code, ..] if *rejected == service => {
+                    return Err(DiagError::NegativeResponse(service, *code));
+                }
+                [first, ..] if *first == positive => {
+                    return Ok(response);
+                }
+                _ => log::warn!("Ignoring unexpected response {:02X?}", response),
+            }
+        }
+    }
+
+    /// Checks that a positive response echoes the request's parameters
+    fn expect_echo(response: &[u8], echo: &[u8]) -> Result<()> {
+        if response.len() < echo.len() + 1 || &response[1..=echo.len()] != echo {
+            return Err(DiagError::InvalidResponse(format!(
+                "expected a response to {:02X?}, got {:02X?}",
+                echo, response
+            )));
+        }
+        Ok(())
+    }
+
+    /// Switches session, adopting the ECU's P2* if it asks for more time
+    pub fn diagnostic_session_control(&mut self, session: Session) -> Result<()> {
+        let response = self.request(&[DIAGNOSTIC_SESSION_CONTROL, session.value()])?;
+        Self::expect_echo(&response, &[session.value()])?;
+
+        if let [_, _, _, _, high, low, ..] = response.as_slice() {
+            let p2_star = Duration::from_millis(u16::from_be_bytes([*high, *low]) as u64 * 10);
+            self.config.p2_star = self.config.p2_star.max(p2_star);
+        }
+        Ok(())
+    }
+
+    pub fn ecu_reset(&mut self, reset: ResetType) -> Result<()> {
+        let response = self.request(&[ECU_RESET, reset.value()])?;
+        Self::expect_echo(&response, &[reset.value()])
+    }
+
+    pub fn read_data_by_identifier(&mut self, did: u16) -> Result<Vec<u8>> {
+        let id = did.to_be_bytes();
+        let response = self.request(&[READ_DATA_BY_IDENTIFIER, id[0], id[1]])?;
+        Self::expect_echo(&response, &id)?;
+        Ok(response[3..].to_vec())
+    }
+
+    pub fn write_data_by_identifier(&mut self, did: u16, data: &[u8]) -> Result<()> {
+        let id = did.to_be_bytes();
+        let mut request = vec![WRITE_DATA_BY_IDENTIFIER, id[0], id[1]];
+        request.extend_from_slice(data);
+        let response = self.request(&request)?;
+        Self::expect_echo(&response, &id)
+    }
+
+    /// Unlocks security `level`, the odd request seed sub-function from 0x01
+    /// to 0x7D, with the key `key` computes from the seed. An all zero seed
+    /// means the level is already unlocked and no key is sent. `key` can fail
+    /// with `DiagError::SecurityKey`.
+    pub fn security_access<F>(&mut self, level: u8, key: F) -> Result<()>
+    where
+        F: FnOnce(&[u8]) -> Result<Vec<u8>>,
+    {
+        if !(0x01..=0x7D).contains(&level) || level.is_multiple_of(2) {
+            return Err(DiagError::InvalidSecurityLevel(level));
+        }
+
+        let response = self.request(&[SECURITY_ACCESS, level])?;
+        Self::expect_echo(&response, &[level])?;
+
+        let seed = &response[2..];
+        if seed.iter().all(|b| *b == 0) {
+            return Ok(());
+        }
+
+        let mut request = vec![SECURITY_ACCESS, level + 1];
+        request.extend(key(seed)?);
+        let response = self.request(&request)?;
+        Self::expect_echo(&response, &[level + 1])
+    }
+
+    /// Returns the routine status record following the routine identifier
+    pub fn routine_control(
+        &mut self,
+        action: RoutineAction,
+        routine: u16,
+        params: &[u8],
+    ) -> Result<Vec<u8>> {
+        let id = routine.to_be_bytes();
+        let mut request = vec![ROUTINE_CONTROL, action.value(), id[0], id[1]];
+        request.extend_from_slice(params);
+        let response = self.request(&request)?;
+        Self::expect_echo(&response, &[action.value(), id[0], id[1]])?;
+        Ok(response[4..].to_vec())
+    }
+
+    /// Reads every DTC with a status matching `mask`, 0xFF for all of them
+    pub fn read_dtc_by_status_mask(&mut self, mask: u8) -> Result<Vec<Dtc>> {
+        let response = self.request(&[READ_DTC_INFORMATION, REPORT_DTC_BY_STATUS_MASK, mask])?;
+        Self::expect_echo(&response, &[REPORT_DTC_BY_STATUS_MASK])?;
+
+        // skip the status availability mask
+        Ok(response
+            .get(3..)
+            .unwrap_or_default()
+            .chunks_exact(4)
+            .map(|record| Dtc {
+                code: u32::from_be_bytes([0, record[0], record[1], record[2]]),
+                status: record[3],
+            })
+            .collect())
+    }
+
+    /// Clears the DTCs in `group`, 0xFFFFFF for all of them
+    pub fn clear_diagnostic_information(&mut self, group: u32) -> Result<()> {
+        let group = group.to_be_bytes();
+        self.request(&[CLEAR_DIAGNOSTIC_INFORMATION, group[1], group[2], group[3]])?;
+        Ok(())
+    }
+
+    /// Starts a download of `size` bytes to `address`, `data_format` being
+    /// the compression and encryption method, 0 for neither. Returns the
+    /// largest TransferData request the ECU accepts, including the service
+    /// id and block sequence counter.
+    pub fn request_download(&mut self, address: u32, size: u32, data_format: u8) -> Result<usize> {
+        let mut request = vec![REQUEST_DOWNLOAD, data_format, ADDRESS_AND_LENGTH_FORMAT];
+        request.extend_from_slice(&address.to_be_bytes());
+        request.extend_from_slice(&size.to_be_bytes());
+        let response = self.request(&request)?;
+
+        let len = response.get(1).map_or(0, |format| (format >> 4) as usize);
+        match response.get(2..2 + len) {
+            Some(max) if (1..=8).contains(&len) => {
+                Ok(max.iter().fold(0, |acc, b| (acc << 8) | *b as usize))
+            }
+            _ => Err(DiagError::InvalidResponse(format!(
+                "bad RequestDownload response {:02X?}",
+                response
+            ))),
+        }
+    }
+
+    /// Sends one block of a download, `counter` starting at 1 and wrapping
+    /// to 0 after 0xFF. Repeating the last block with the same counter is
+    /// allowed after a lost response.
+    pub fn transfer_data(&mut self, counter: u8, data: &[u8]) -> Result<Vec<u8>> {
+        let mut request = Vec::with_capacity(data.len() + 2);
+        request.extend_from_slice(&[TRANSFER_DATA, counter]);
+        request.extend_from_slice(data);
+        let response = self.request(&request)?;
+        Self::expect_echo(&response, &[counter])?;
+        Ok(response[2..].to_vec())
+    }
+
+    pub fn request_transfer_exit(&mut self, params: &[u8]) -> Result<Vec<u8>> {
+        let mut request = vec![REQUEST_TRANSFER_EXIT];
+        request.extend_from_slice(params);
+        let response = self.request(&request)?;
+        Ok(response[1..].to_vec())
+    }
+
+    pub fn tester_present(&mut self) -> Result<()> {
+        let response = self.request(&[TESTER_PRESENT, 0x00])?;
+        Self::expect_echo(&response, &[0x00])
+    }
+
+    /// Keeps a non-default session open, sending tester present without a
+    /// response if nothing has been sent for `tester_present_interval`.
+    /// Call it regularly while the session is idle.
+    pub fn keep_alive(&mut self) -> Result<()> {
+        if self.last_request.elapsed() < self.config.tester_present_interval {
+            return Ok(());
+        }
+
+        self.isotp
+            .send(&[TESTER_PRESENT, SUPPRESS_POSITIVE_RESPONSE])?;
+        self.last_request = Instant::now();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isotp::IsoTpConfig;
+    use crate::testing::{padded, ScriptedIo};
+
+    fn client(io: ScriptedIo) -> UdsClient<ScriptedIo> {
+        let config = UdsConfig {
+            p2: Duration::from_millis(50),
+            ..UdsConfig::default()
+        };
+        UdsClient::new(IsoTp::new(io, IsoTpConfig::default()), config)
+    }
+
+    /// Answers each single frame request with the single frame `respond` returns
+    fn ecu<F>(mut respond: F) -> ScriptedIo
+    where
+        F: FnMut(&[u8]) -> Vec<u8> + 'static,
+    {
+        ecu_replies(move |request| vec![respond(request)])
+    }
+
+    /// Like `ecu`, with a single frame for each response `respond` returns
+    fn ecu_replies<F>(mut respond: F) -> ScriptedIo
+    where
+        F: FnMut(&[u8]) -> Vec<Vec<u8>> + 'static,
+    {
+        ScriptedIo::responding(move |frame| {
+            let payload = frame.payload();
+            let request = &payload[1..=(payload[0] & 0x0F) as usize];
+            respond(request)
+                .into_iter()
+                .map(|mut response| {
+                    response.insert(0, response.len() as u8);
+                    padded(0x7E8, &response)
+                })
+                .collect()
+        })
+    }
+
+    #[test]
+    fn response_pending_is_waited_through() {
+        let mut uds = client(ecu_replies(|request| {
+            vec![
+                vec![NEGATIVE_RESPONSE, request[0], RESPONSE_PENDING],
+                vec![NEGATIVE_RESPONSE, request[0], RESPONSE_PENDING],
+                vec![0x51, 0x01],
+            ]
+        }));
+        uds.ecu_reset(ResetType::Hard).unwrap();
+    }
+
+    #[test]
+    fn response_pending_extends_the_deadline_to_p2_star() {
+        let mut uds = client(ecu(|request| {
+            vec![NEGATIVE_RESPONSE, request[0], RESPONSE_PENDING]
+        }));
+        uds.config.p2_star = Duration::from_millis(200);
+
+        let start = Instant::now();
+        assert!(matches!(
+            uds.request(&[ECU_RESET, 0x01]),
+            Err(DiagError::NoResponse)
+        ));
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn negative_responses_are_errors() {
+        let mut uds = client(ecu(|request| vec![NEGATIVE_RESPONSE, request[0], 0x31]));
+        assert!(matches!(
+            uds.read_data_by_identifier(0xF190),
+            Err(DiagError::NegativeResponse(READ_DATA_BY_IDENTIFIER, 0x31))
+        ));
+    }
+
+    #[test]
+    fn responses_to_other_services_are_ignored() {
+        let mut uds = client(ecu_replies(|_| {
+            vec![
+                vec![NEGATIVE_RESPONSE, DIAGNOSTIC_SESSION_CONTROL, 0x31],
+                vec![0x50, 0x03],
+                vec![0x62, 0xF1, 0x90, b'V', b'I', b'N'],
+            ]
+        }));
+        assert_eq!(uds.read_data_by_identifier(0xF190).unwrap(), b"VIN");
+    }
+
+    #[test]
+    fn dtcs_are_decoded() {
+        let mut uds = client(ecu(|request| match request {
+            [READ_DTC_INFORMATION, REPORT_DTC_BY_STATUS_MASK, 0xFF] => {
+                vec![0x59, 0x02, 0xFF, 0x01, 0x03, 0x01, 0x09]
+            }
+            _ => vec![0x59, 0x02, 0xFF],
+        }));
+
+        let dtcs = uds.read_dtc_by_status_mask(0xFF).unwrap();
+        assert_eq!(
+            dtcs,
+            [Dtc {
+                code: 0x01_0301,
+                status: 0x09
+            }]
+        );
+        assert!(dtcs[0].is_active() && dtcs[0].is_confirmed());
+        assert_eq!(dtcs[0].to_string(), "P0103-01 status 0x09");
+
+        assert!(uds.read_dtc_by_status_mask(0x08).unwrap().is_empty());
+    }
+
+    #[test]
+    fn responses_to_the_highest_services() {
+        let mut uds = client(ecu(|request| vec![request[0] + 0x40]));
+        assert_eq!(uds.request(&[0xBF]).unwrap(), vec![0xFF]);
+
+        for service in &[0xC0, 0xFF] {
+            assert!(matches!(
+                uds.request(&[*service]),
+                Err(DiagError::InvalidService(s)) if s == *service
+            ));
+        }
+        assert_eq!(uds.into_inner().into_inner().sent.len(), 1);
+    }
+
+    #[test]
+    fn security_access_levels() {
+        let mut uds = client(ecu(|_| vec![0x67, 0x01, 0x00, 0x00]));
+        for level in &[0x00, 0x02, 0x7F, 0xFF] {
+            assert!(matches!(
+                uds.security_access(*level, |seed| Ok(seed.to_vec())),
+                Err(DiagError::InvalidSecurityLevel(l)) if l == *level
+            ));
+        }
+        assert!(uds.into_inner().into_inner().sent.is_empty());
+    }
+
+    #[test]
+    fn security_access_sends_the_key() {
+        let mut uds = client(ecu(|request| match request {
+            [SECURITY_ACCESS, 0x7D] => vec![0x67, 0x7D, 0x12, 0x34],
+            [SECURITY_ACCESS, 0x7E, ..] => vec![0x67, 0x7E],
+            _ => vec![NEGATIVE_RESPONSE, request[0], 0x12],
+        }));
+
+        uds.security_access(0x7D, |seed| Ok(seed.iter().map(|b| !b).collect()))
+            .unwrap();
+        let sent = uds.into_inner().into_inner().sent_payloads();
+        assert_eq!(&sent[1][..5], &[0x04, SECURITY_ACCESS, 0x7E, 0xED, 0xCB]);
+    }
+}
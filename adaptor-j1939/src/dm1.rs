@@ -0,0 +1,160 @@
+//! DM1 active diagnostic trouble codes from SAE J1939-73.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LampState {
+    Off,
+    On,
+    Error,
+    NotAvailable,
+}
+
+impl LampState {
+    fn from_bits(bits: u8) -> Self {
+        match bits & 0x03 {
+            0 => LampState::Off,
+            1 => LampState::On,
+            2 => LampState::Error,
+            _ => LampState::NotAvailable,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lamps {
+    pub malfunction: LampState,
+    pub red_stop: LampState,
+    pub amber_warning: LampState,
+    pub protect: LampState,
+}
+
+/// One trouble code, a suspect parameter and how it failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dtc {
+    /// Suspect parameter number, 19 bits
+    pub spn: u32,
+
+    /// Failure mode identifier, 5 bits
+    pub fmi: u8,
+
+    /// Occurrence count, 127 if not available
+    pub occurrences: u8,
+}
+
+impl Dtc {
+    fn from_bytes(b: &[u8]) -> Self {
+        Self {
+            spn: b[0] as u32 | ((b[1] as u32) << 8) | (((b[2] as u32) & 0xE0) << 11),
+            fmi: b[2] & 0x1F,
+            occurrences: b[3] & 0x7F,
+        }
+    }
+}
+
+impl fmt::Display for Dtc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "SPN {} FMI {} ({}), {} occurrences",
+            self.spn,
+            self.fmi,
+            fmi_description(self.fmi),
+            self.occurrences
+        )
+    }
+}
+
+/// Meaning of a failure mode identifier
+pub fn fmi_description(fmi: u8) -> &'static str {
+    match fmi {
+        0 => "data valid but above normal operational range, most severe",
+        1 => "data valid but below normal operational range, most severe",
+        2 => "data erratic, intermittent or incorrect",
+        3 => "voltage above normal, or shorted to high source",
+        4 => "voltage below normal, or shorted to low source",
+        5 => "current below normal or open circuit",
+        6 => "current above normal or grounded circuit",
+        7 => "mechanical system not responding or out of adjustment",
+        8 => "abnormal frequency or pulse width or period",
+        9 => "abnormal update rate",
+        10 => "abnormal rate of change",
+        11 => "root cause not known",
+        12 => "bad intelligent device or component",
+        13 => "out of calibration",
+        14 => "special instructions",
+        15 => "data valid but above normal operating range, least severe",
+        16 => "data valid but above normal operating range, moderately severe",
+        17 => "data valid but below normal operating range, least severe",
+        18 => "data valid but below normal operating range, moderately severe",
+        19 => "received network data in error",
+        20 => "data drifted high",
+        21 => "data drifted low",
+        31 => "condition exists",
+        _ => "reserved",
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dm1 {
+    pub lamps: Lamps,
+    pub dtcs: Vec<Dtc>,
+}
+
+impl Dm1 {
+    /// Decodes a DM1 message, `None` if it is shorter than the lamp status
+    /// and one code. A single all zero code means nothing is active.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < 6 {
+            return None;
+        }
+        let lamps = Lamps {
+            malfunction: LampState::from_bits(data[0] >> 6),
+            red_stop: LampState::from_bits(data[0] >> 4),
+            amber_warning: LampState::from_bits(data[0] >> 2),
+            protect: LampState::from_bits(data[0]),
+        };
+        let dtcs = data[2..]
+            .chunks_exact(4)
+            .map(Dtc::from_bytes)
+            .filter(|dtc| dtc.spn != 0 || dtc.fmi != 0)
+            .collect();
+        Some(Self { lamps, dtcs })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spn_and_fmi_bit_layout() {
+        // SPN 0x51234: low 16 bits first, the top 3 bits above the FMI
+        let data = [0b01_00_11_10, 0xFF, 0x34, 0x12, 0xA9, 0x83, 0, 0, 0, 0];
+        let dm1 = Dm1::parse(&data).unwrap();
+
+        assert_eq!(
+            dm1.lamps,
+            Lamps {
+                malfunction: LampState::On,
+                red_stop: LampState::Off,
+                amber_warning: LampState::NotAvailable,
+                protect: LampState::Error,
+            }
+        );
+        assert_eq!(
+            dm1.dtcs,
+            vec![Dtc {
+                spn: 0x5_1234,
+                fmi: 9,
+                occurrences: 3,
+            }]
+        );
+    }
+
+    #[test]
+    fn no_active_codes() {
+        assert_eq!(Dm1::parse(&[0, 0xFF, 0, 0, 0, 0]).unwrap().dtcs, vec![]);
+        assert!(Dm1::parse(&[0, 0xFF, 0, 0, 0]).is_none());
+    }
+}
@@ -0,0 +1,26 @@
+use quick_error::quick_error;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum J1939Error {
+        AdaptorError(err: adaptor_core::AdaptorError) {
+            from()
+            display("{}", err)
+        }
+        NoAddress {
+            display("no address has been claimed")
+        }
+        AddressClaimFailed(address: u8) {
+            display("cannot claim address {:#04x} or any other", address)
+        }
+        PayloadLength(len: usize) {
+            display("{} bytes cannot be sent in one J1939 message", len)
+        }
+        Aborted(reason: u8) {
+            display("transfer aborted: {} ({})", crate::transport::abort_reason(*reason), reason)
+        }
+        Timeout {
+            display("transport protocol timeout")
+        }
+    }
+}
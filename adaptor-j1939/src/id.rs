@@ -0,0 +1,90 @@
+//! Splitting 29 bit identifiers into priority, PGN and addresses.
+
+use adaptor_common::{CANFrame, Frame, MAX_EXT_ID};
+
+/// Destination address of broadcast messages
+pub const GLOBAL_ADDRESS: u8 = 0xFF;
+
+/// Source address of a node without one, e.g. in Cannot Claim Address
+pub const NULL_ADDRESS: u8 = 0xFE;
+
+/// Default priority of control messages
+pub const DEFAULT_PRIORITY: u8 = 6;
+
+/// Whether a PGN is peer to peer, with the destination in the PS field
+pub fn is_pdu1(pgn: u32) -> bool {
+    (pgn >> 8) & 0xFF < 240
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct J1939Id {
+    /// 0 is the highest priority, 7 the lowest
+    pub priority: u8,
+
+    /// Parameter group number, with the PS field zeroed for PDU1 groups
+    pub pgn: u32,
+    pub source: u8,
+
+    /// Destination of PDU1 groups, always `GLOBAL_ADDRESS` for PDU2 ones
+    pub destination: u8,
+}
+
+impl J1939Id {
+    pub fn new(priority: u8, pgn: u32, source: u8, destination: u8) -> Self {
+        let destination = if is_pdu1(pgn) {
+            destination
+        } else {
+            GLOBAL_ADDRESS
+        };
+        Self {
+            priority: priority & 0x07,
+            pgn: if is_pdu1(pgn) {
+                pgn & 0x3FF00
+            } else {
+                pgn & 0x3FFFF
+            },
+            source,
+            destination,
+        }
+    }
+
+    /// Decomposes a 29 bit identifier
+    pub fn from_id(id: u32) -> Self {
+        let pgn = (id >> 8) & 0x3FFFF;
+        Self::new((id >> 26) as u8, pgn, id as u8, (pgn & 0xFF) as u8)
+    }
+
+    /// Decomposes a frame's identifier, `None` for 11 bit frames which J1939
+    /// leaves to proprietary use
+    pub fn from_frame(frame: &Frame) -> Option<Self> {
+        if frame.is_ext() && !frame.is_err() {
+            Some(Self::from_id(frame.id()))
+        } else {
+            None
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        let ps = if is_pdu1(self.pgn) {
+            self.destination as u32
+        } else {
+            self.pgn & 0xFF
+        };
+        (((self.priority & 0x07) as u32) << 26)
+            | ((self.pgn & 0x3FF00) << 8)
+            | (ps << 8)
+            | self.source as u32
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        self.destination == GLOBAL_ADDRESS
+    }
+
+    /// Builds a frame carrying up to eight bytes of `data`
+    pub fn frame(&self, data: &[u8]) -> Frame {
+        let len = data.len().min(8);
+        let mut buf = [0_u8; 8];
+        buf[..len].copy_from_slice(&data[..len]);
+        CANFrame::new(self.id() & MAX_EXT_ID, len as u8, buf, false, false, true).into()
+    }
+}
@@ -0,0 +1,21 @@
+//! SAE J1939 on top of the probe: identifier decomposition, address
+//! claiming, the BAM and RTS/CTS transport protocol and DM1 trouble codes.
+//!
+//! `J1939Node` works over any `FrameIo`, including an `AdaptorHandle` on a
+//! `LoopbackTransport`, which echoes the node's frames back to it.
+
+pub mod dm1;
+pub mod errors;
+pub mod id;
+pub mod name;
+pub mod node;
+pub mod pgn;
+pub mod transport;
+
+pub type Result<T> = std::result::Result<T, errors::J1939Error>;
+
+pub use dm1::Dm1;
+pub use errors::J1939Error;
+pub use id::{J1939Id, GLOBAL_ADDRESS, NULL_ADDRESS};
+pub use name::Name;
+pub use node::{J1939Config, J1939Node, Message};
@@ -0,0 +1,83 @@
+//! The 64 bit NAME every node claims its address with.
+
+use std::cmp::Ordering;
+
+/// A node's NAME. When two nodes claim the same address the one whose NAME
+/// is numerically lower keeps it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Name {
+    /// Serial number, 21 bits
+    pub identity_number: u32,
+
+    /// SAE assigned manufacturer, 11 bits
+    pub manufacturer_code: u16,
+
+    /// 3 bits
+    pub ecu_instance: u8,
+
+    /// 5 bits
+    pub function_instance: u8,
+    pub function: u8,
+
+    /// 7 bits
+    pub vehicle_system: u8,
+
+    /// 4 bits
+    pub vehicle_system_instance: u8,
+
+    /// 3 bits, e.g. 1 for on-highway and 2 for agricultural equipment
+    pub industry_group: u8,
+
+    /// Whether the node may move to another address after losing a claim
+    pub arbitrary_address_capable: bool,
+}
+
+impl Name {
+    pub fn from_u64(raw: u64) -> Self {
+        Self {
+            identity_number: (raw & 0x1F_FFFF) as u32,
+            manufacturer_code: ((raw >> 21) & 0x7FF) as u16,
+            ecu_instance: ((raw >> 32) & 0x07) as u8,
+            function_instance: ((raw >> 35) & 0x1F) as u8,
+            function: (raw >> 40) as u8,
+            vehicle_system: ((raw >> 49) & 0x7F) as u8,
+            vehicle_system_instance: ((raw >> 56) & 0x0F) as u8,
+            industry_group: ((raw >> 60) & 0x07) as u8,
+            arbitrary_address_capable: raw >> 63 != 0,
+        }
+    }
+
+    pub fn to_u64(&self) -> u64 {
+        (self.identity_number as u64 & 0x1F_FFFF)
+            | ((self.manufacturer_code as u64 & 0x7FF) << 21)
+            | ((self.ecu_instance as u64 & 0x07) << 32)
+            | ((self.function_instance as u64 & 0x1F) << 35)
+            | ((self.function as u64) << 40)
+            | ((self.vehicle_system as u64 & 0x7F) << 49)
+            | ((self.vehicle_system_instance as u64 & 0x0F) << 56)
+            | ((self.industry_group as u64 & 0x07) << 60)
+            | ((self.arbitrary_address_capable as u64) << 63)
+    }
+
+    /// The NAME as sent in Address Claimed, least significant byte first
+    pub fn to_bytes(&self) -> [u8; 8] {
+        self.to_u64().to_le_bytes()
+    }
+
+    pub fn from_bytes(bytes: [u8; 8]) -> Self {
+        Self::from_u64(u64::from_le_bytes(bytes))
+    }
+}
+
+impl PartialOrd for Name {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Name {
+    /// Orders by arbitration priority, the lesser NAME wins
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.to_u64().cmp(&other.to_u64())
+    }
+}
@@ -0,0 +1,856 @@
+//! A node on a J1939 network: claims an address, then sends and receives
+//! messages of any length, reassembling transport protocol sessions.
+
+use crate::errors::J1939Error;
+use crate::id::{is_pdu1, J1939Id, DEFAULT_PRIORITY, GLOBAL_ADDRESS, NULL_ADDRESS};
+use crate::name::Name;
+use crate::pgn::{ADDRESS_CLAIMED, REQUEST, TP_CM, TP_DT};
+use crate::transport::{
+    self, ControlMessage, ABORT_BAD_SEQUENCE, ABORT_RESOURCES, ABORT_TIMEOUT, MAX_TP_LEN, T1, T2,
+    T3, T4, TP_PRIORITY,
+};
+use crate::Result;
+
+use adaptor_common::Frame;
+use adaptor_core::FrameIo;
+
+use std::collections::{HashMap, VecDeque};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long a claim has to go unchallenged before the address is ours
+const CLAIM_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// Timeout for putting one frame on the bus
+const SEND_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Addresses a node that lost its claim may pick from
+const ARBITRARY_ADDRESSES: std::ops::RangeInclusive<u8> = 128..=247;
+
+#[derive(Debug, Clone, Copy)]
+pub struct J1939Config {
+    pub name: Name,
+
+    /// Address `claim_address` tries first
+    pub preferred_address: u8,
+
+    /// Pause between BAM data packets, 50 to 200 ms
+    pub bam_interval: Duration,
+
+    /// Packets asked for in each CTS while receiving
+    pub packets_per_cts: u8,
+}
+
+impl Default for J1939Config {
+    fn default() -> Self {
+        Self {
+            name: Name::default(),
+            preferred_address: 0x80,
+            bam_interval: Duration::from_millis(50),
+            packets_per_cts: 16,
+        }
+    }
+}
+
+/// A complete message, either a single frame or a reassembled transfer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub priority: u8,
+    pub pgn: u32,
+    pub source: u8,
+    pub destination: u8,
+    pub data: Vec<u8>,
+}
+
+/// Transport protocol transfer being received, keyed by source and destination
+#[derive(Debug)]
+struct Session {
+    pgn: u32,
+    priority: u8,
+    size: usize,
+    packets: u8,
+    data: Vec<u8>,
+
+    /// Next expected sequence number
+    next: u8,
+
+    /// Last packet of the current CTS window, for transfers to this node
+    window_end: u8,
+    deadline: Instant,
+}
+
+/// Runs J1939 over any `FrameIo`, e.g. an `AdaptorHandle`.
+///
+/// Until an address is claimed the node only listens, which still
+/// reassembles BAM and RTS/CTS transfers between other nodes. Frames the
+/// transport echoes back, as `LoopbackTransport` does, are handled like
+/// any other, so a node can send RTS/CTS transfers to itself.
+pub struct J1939Node<I: FrameIo> {
+    io: I,
+    config: J1939Config,
+    address: Option<u8>,
+
+    /// Address being claimed and whether a lower NAME has taken it
+    claim: Option<(u8, bool)>,
+
+    /// NAMEs of the other nodes by address, from their claims
+    nodes: HashMap<u8, Name>,
+    sessions: HashMap<(u8, u8), Session>,
+    received: VecDeque<Message>,
+}
+
+impl<I: FrameIo> J1939Node<I> {
+    pub fn new(io: I, config: J1939Config) -> Self {
+        Self {
+            io,
+            config,
+            address: None,
+            claim: None,
+            nodes: HashMap::new(),
+            sessions: HashMap::new(),
+            received: VecDeque::new(),
+        }
+    }
+
+    pub fn config(&self) -> &J1939Config {
+        &self.config
+    }
+
+    /// Claimed address, `None` while only listening
+    pub fn address(&self) -> Option<u8> {
+        self.address
+    }
+
+    /// Other nodes seen claiming addresses
+    pub fn nodes(&self) -> &HashMap<u8, Name> {
+        &self.nodes
+    }
+
+    pub fn io_mut(&mut self) -> &mut I {
+        &mut self.io
+    }
+
+    pub fn into_inner(self) -> I {
+        self.io
+    }
+
+    /// Claims `preferred_address`. If a node with a lower NAME holds it and
+    /// our NAME is arbitrary address capable, moves on to a free address
+    /// from 128 to 247, otherwise announces it cannot claim one.
+    pub fn claim_address(&mut self) -> Result<u8> {
+        let mut candidate = self.config.preferred_address;
+        loop {
+            self.claim = Some((candidate, false));
+            self.send_claim(candidate)?;
+
+            let deadline = Instant::now() + CLAIM_TIMEOUT;
+            let lost = loop {
+                if self.claim.is_some_and(|(_, lost)| lost) {
+                    break true;
+                }
+                let now = Instant::now();
+                if now >= deadline {
+                    break false;
+                }
+                if let Some(frame) = self.io.recv_frame(deadline - now)? {
+                    self.handle_frame(frame)?;
+                }
+            };
+            self.claim = None;
+
+            if !lost {
+                log::debug!("Claimed address {:#04x}", candidate);
+                self.address = Some(candidate);
+                return Ok(candidate);
+            }
+
+            let next = ARBITRARY_ADDRESSES
+                .skip_while(|address| {
+                    ARBITRARY_ADDRESSES.contains(&candidate) && *address <= candidate
+                })
+                .find(|address| !self.nodes.contains_key(address));
+            match next {
+                Some(next) if self.config.name.arbitrary_address_capable => candidate = next,
+                _ => {
+                    self.send_claim(NULL_ADDRESS)?;
+                    return Err(J1939Error::AddressClaimFailed(
+                        self.config.preferred_address,
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Sends a message, splitting it into a BAM broadcast or an RTS/CTS
+    /// transfer if it is longer than eight bytes. Transfers always use
+    /// priority 7.
+    pub fn send(&mut self, pgn: u32, priority: u8, destination: u8, data: &[u8]) -> Result<()> {
+        let source = self.address.ok_or(J1939Error::NoAddress)?;
+        let destination = if is_pdu1(pgn) {
+            destination
+        } else {
+            GLOBAL_ADDRESS
+        };
+
+        if data.len() <= 8 {
+            self.send_raw(J1939Id::new(priority, pgn, source, destination), data)
+        } else if data.len() > MAX_TP_LEN {
+            Err(J1939Error::PayloadLength(data.len()))
+        } else if destination == GLOBAL_ADDRESS {
+            self.send_bam(source, pgn, data)
+        } else {
+            self.send_rts(source, destination, pgn, data)
+        }
+    }
+
+    /// Asks `destination`, or everyone, to send a parameter group
+    pub fn request(&mut self, pgn: u32, destination: u8) -> Result<()> {
+        let bytes = pgn.to_le_bytes();
+        self.send(REQUEST, DEFAULT_PRIORITY, destination, &bytes[..3])
+    }
+
+    /// Waits up to `timeout` for the next complete message, handling
+    /// address claims and transport sessions along the way
+    pub fn recv(&mut self, timeout: Duration) -> Result<Option<Message>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            self.expire_sessions()?;
+            if let Some(message) = self.received.pop_front() {
+                return Ok(Some(message));
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            if let Some(frame) = self.io.recv_frame(deadline - now)? {
+                self.handle_frame(frame)?;
+            }
+        }
+    }
+
+    fn send_raw(&mut self, id: J1939Id, data: &[u8]) -> Result<()> {
+        self.io.send_frame(id.frame(data), SEND_TIMEOUT)?;
+        Ok(())
+    }
+
+    fn send_claim(&mut self, address: u8) -> Result<()> {
+        let id = J1939Id::new(DEFAULT_PRIORITY, ADDRESS_CLAIMED, address, GLOBAL_ADDRESS);
+        let name = self.config.name.to_bytes();
+        self.send_raw(id, &name)
+    }
+
+    fn send_control(&mut self, source: u8, destination: u8, message: ControlMessage) -> Result<()> {
+        let id = J1939Id::new(TP_PRIORITY, TP_CM, source, destination);
+        self.send_raw(id, &message.to_bytes())
+    }
+
+    fn send_bam(&mut self, source: u8, pgn: u32, data: &[u8]) -> Result<()> {
+        let packets = transport::packets(data.len());
+        let announce = ControlMessage::Bam {
+            size: data.len() as u16,
+            packets,
+            pgn,
+        };
+        self.send_control(source, GLOBAL_ADDRESS, announce)?;
+
+        let id = J1939Id::new(TP_PRIORITY, TP_DT, source, GLOBAL_ADDRESS);
+        for sequence in 1..=packets {
+            thread::sleep(self.config.bam_interval);
+            self.send_raw(id, &transport::data_packet(data, sequence))?;
+        }
+        Ok(())
+    }
+
+    fn send_rts(&mut self, source: u8, destination: u8, pgn: u32, data: &[u8]) -> Result<()> {
+        let packets = transport::packets(data.len());
+        let rts = ControlMessage::Rts {
+            size: data.len() as u16,
+            packets,
+            max_per_cts: 0xFF,
+            pgn,
+        };
+        self.send_control(source, destination, rts)?;
+
+        let data_id = J1939Id::new(TP_PRIORITY, TP_DT, source, destination);
+        let mut deadline = Instant::now() + T3;
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                let abort = ControlMessage::Abort {
+                    reason: ABORT_TIMEOUT,
+                    pgn,
+                };
+                self.send_control(source, destination, abort)?;
+                return Err(J1939Error::Timeout);
+            }
+            let frame = match self.io.recv_frame(deadline - now)? {
+                Some(frame) => frame,
+                None => continue,
+            };
+
+            let reply = J1939Id::from_frame(&frame)
+                .filter(|id| {
+                    id.pgn == TP_CM && id.source == destination && id.destination == source
+                })
+                .and_then(|_| ControlMessage::parse(frame.payload()))
+                .filter(|message| message.pgn() == pgn);
+            match reply {
+                Some(ControlMessage::Cts { packets: 0, .. }) => deadline = now + T4,
+                Some(ControlMessage::Cts {
+                    packets: count,
+                    next,
+                    ..
+                }) => {
+                    let last = (next as u16 + count as u16 - 1).min(packets as u16) as u8;
+                    for sequence in next.max(1)..=last {
+                        self.send_raw(data_id, &transport::data_packet(data, sequence))?;
+                    }
+                    deadline = Instant::now() + T3;
+                }
+                Some(ControlMessage::EndOfMsgAck { .. }) => return Ok(()),
+                Some(ControlMessage::Abort { reason, .. }) => {
+                    return Err(J1939Error::Aborted(reason))
+                }
+                _ => self.handle_frame(frame)?,
+            }
+        }
+    }
+
+    fn handle_frame(&mut self, frame: Frame) -> Result<()> {
+        let id = match J1939Id::from_frame(&frame) {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+        let data = frame.payload();
+
+        match id.pgn {
+            TP_CM => self.handle_control(id, data),
+            TP_DT => self.handle_data(id, data),
+            ADDRESS_CLAIMED if data.len() >= 8 => {
+                let mut name = [0; 8];
+                name.copy_from_slice(&data[..8]);
+                self.handle_claim(id.source, Name::from_bytes(name))?;
+                self.queue(id, data.to_vec());
+                Ok(())
+            }
+            REQUEST if data.len() >= 3 => {
+                let requested = u32::from_le_bytes([data[0], data[1], data[2], 0]);
+                let to_us = id.is_broadcast() || Some(id.destination) == self.address;
+                if requested == ADDRESS_CLAIMED && to_us {
+                    if let Some(address) = self.address {
+                        self.send_claim(address)?;
+                    }
+                }
+                self.queue(id, data.to_vec());
+                Ok(())
+            }
+            _ => {
+                self.queue(id, data.to_vec());
+                Ok(())
+            }
+        }
+    }
+
+    fn queue(&mut self, id: J1939Id, data: Vec<u8>) {
+        self.received.push_back(Message {
+            priority: id.priority,
+            pgn: id.pgn,
+            source: id.source,
+            destination: id.destination,
+            data,
+        });
+    }
+
+    fn handle_claim(&mut self, source: u8, name: Name) -> Result<()> {
+        // our own claim echoed back
+        if name == self.config.name {
+            return Ok(());
+        }
+        self.nodes.retain(|_, other| *other != name);
+        if source != NULL_ADDRESS {
+            self.nodes.insert(source, name);
+        }
+
+        let contested = match self.claim {
+            Some((candidate, _)) => Some(candidate),
+            None => self.address,
+        };
+        if contested != Some(source) {
+            return Ok(());
+        }
+
+        if name < self.config.name {
+            match &mut self.claim {
+                Some((_, lost)) => *lost = true,
+                None => {
+                    log::warn!("Lost address {:#04x} to a node with a lower NAME", source);
+                    self.address = None;
+                    self.send_claim(NULL_ADDRESS)?;
+                }
+            }
+            Ok(())
+        } else {
+            // we win, say so again
+            self.send_claim(source)
+        }
+    }
+
+    fn handle_control(&mut self, id: J1939Id, data: &[u8]) -> Result<()> {
+        let message = match ControlMessage::parse(data) {
+            Some(message) => message,
+            None => return Ok(()),
+        };
+        let key = (id.source, id.destination);
+        let to_us = !id.is_broadcast() && Some(id.destination) == self.address;
+
+        match message {
+            ControlMessage::Bam { size, packets, pgn } if id.is_broadcast() => {
+                self.open_session(key, id.priority, pgn, size, packets, packets);
+            }
+            ControlMessage::Rts {
+                size, packets, pgn, ..
+            } if !id.is_broadcast() => {
+                if to_us && (packets == 0 || size as usize > MAX_TP_LEN) {
+                    let abort = ControlMessage::Abort {
+                        reason: ABORT_RESOURCES,
+                        pgn,
+                    };
+                    return self.send_control(id.destination, id.source, abort);
+                }
+
+                let window = if to_us {
+                    packets.min(self.config.packets_per_cts.max(1))
+                } else {
+                    packets
+                };
+                self.open_session(key, id.priority, pgn, size, packets, window);
+                if to_us {
+                    let cts = ControlMessage::Cts {
+                        packets: window,
+                        next: 1,
+                        pgn,
+                    };
+                    self.send_control(id.destination, id.source, cts)?;
+                }
+            }
+            // either side can abort, the receiver acknowledges the end
+            ControlMessage::Abort { .. } => {
+                self.sessions.remove(&key);
+                self.sessions.remove(&(id.destination, id.source));
+            }
+            ControlMessage::EndOfMsgAck { .. } => {
+                self.sessions.remove(&(id.destination, id.source));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn open_session(
+        &mut self,
+        key: (u8, u8),
+        priority: u8,
+        pgn: u32,
+        size: u16,
+        packets: u8,
+        window_end: u8,
+    ) {
+        if self.sessions.contains_key(&key) {
+            log::debug!("Replacing transfer from {:#04x} to {:#04x}", key.0, key.1);
+        }
+        self.sessions.insert(
+            key,
+            Session {
+                pgn,
+                priority,
+                size: size as usize,
+                packets,
+                data: Vec::with_capacity(size as usize),
+                next: 1,
+                window_end,
+                deadline: Instant::now() + T2,
+            },
+        );
+    }
+
+    fn handle_data(&mut self, id: J1939Id, data: &[u8]) -> Result<()> {
+        let key = (id.source, id.destination);
+        let to_us = !id.is_broadcast() && Some(id.destination) == self.address;
+        let session = match self.sessions.get_mut(&key) {
+            Some(session) => session,
+            None => return Ok(()),
+        };
+        let sequence = match data.first() {
+            Some(sequence) => *sequence,
+            None => return Ok(()),
+        };
+        let pgn = session.pgn;
+
+        if sequence != session.next {
+            log::debug!(
+                "Dropping transfer from {:#04x}, packet {} arrived instead of {}",
+                id.source,
+                sequence,
+                session.next
+            );
+            self.sessions.remove(&key);
+            if to_us {
+                let abort = ControlMessage::Abort {
+                    reason: ABORT_BAD_SEQUENCE,
+                    pgn,
+                };
+                self.send_control(id.destination, id.source, abort)?;
+            }
+            return Ok(());
+        }
+
+        session.data.extend_from_slice(&data[1..]);
+        session.next = session.next.wrapping_add(1);
+        session.deadline = Instant::now() + T1;
+
+        if sequence == session.packets {
+            let mut session = self.sessions.remove(&key).expect("session looked up above");
+            session.data.truncate(session.size);
+            if to_us {
+                let ack = ControlMessage::EndOfMsgAck {
+                    size: session.size as u16,
+                    packets: session.packets,
+                    pgn,
+                };
+                self.send_control(id.destination, id.source, ack)?;
+            }
+            self.received.push_back(Message {
+                priority: session.priority,
+                pgn,
+                source: id.source,
+                destination: id.destination,
+                data: session.data,
+            });
+        } else if to_us && sequence == session.window_end {
+            let window = (session.packets - sequence).min(self.config.packets_per_cts.max(1));
+            session.window_end = sequence + window;
+            session.deadline = Instant::now() + T2;
+            let cts = ControlMessage::Cts {
+                packets: window,
+                next: sequence + 1,
+                pgn,
+            };
+            self.send_control(id.destination, id.source, cts)?;
+        }
+        Ok(())
+    }
+
+    /// Drops transfers that stopped arriving, aborting ones sent to us
+    fn expire_sessions(&mut self) -> Result<()> {
+        let now = Instant::now();
+        let expired: Vec<(u8, u8)> = self
+            .sessions
+            .iter()
+            .filter(|(_, session)| session.deadline <= now)
+            .map(|(key, _)| *key)
+            .collect();
+
+        for (source, destination) in expired {
+            let session = self
+                .sessions
+                .remove(&(source, destination))
+                .expect("key collected above");
+            log::debug!(
+                "Transfer of PGN {:#x} from {:#04x} timed out",
+                session.pgn,
+                source
+            );
+            if destination != GLOBAL_ADDRESS && Some(destination) == self.address {
+                let abort = ControlMessage::Abort {
+                    reason: ABORT_TIMEOUT,
+                    pgn: session.pgn,
+                };
+                self.send_control(destination, source, abort)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use adaptor_common::AdaptorSettings;
+    use adaptor_core::testing::ScriptedIo;
+    use adaptor_core::{AdaptorHandle, LoopbackTransport};
+
+    const PEER: u8 = 0x20;
+    const PGN: u32 = 0xFEE6;
+
+    fn name(identity_number: u32, arbitrary_address_capable: bool) -> Name {
+        Name {
+            identity_number,
+            arbitrary_address_capable,
+            ..Name::default()
+        }
+    }
+
+    fn node(name: Name, packets_per_cts: u8) -> J1939Node<ScriptedIo> {
+        let config = J1939Config {
+            name,
+            packets_per_cts,
+            ..J1939Config::default()
+        };
+        J1939Node::new(ScriptedIo::default(), config)
+    }
+
+    fn claim(source: u8, name: Name) -> Frame {
+        J1939Id::new(DEFAULT_PRIORITY, ADDRESS_CLAIMED, source, GLOBAL_ADDRESS)
+            .frame(&name.to_bytes())
+    }
+
+    fn control(destination: u8, message: ControlMessage) -> Frame {
+        J1939Id::new(TP_PRIORITY, TP_CM, PEER, destination).frame(&message.to_bytes())
+    }
+
+    fn data(destination: u8, payload: &[u8], sequence: u8) -> Frame {
+        J1939Id::new(TP_PRIORITY, TP_DT, PEER, destination)
+            .frame(&transport::data_packet(payload, sequence))
+    }
+
+    /// Sources of the address claims sent so far
+    fn claims_sent(io: &ScriptedIo) -> Vec<u8> {
+        io.sent_frames()
+            .filter_map(J1939Id::from_frame)
+            .filter(|id| id.pgn == ADDRESS_CLAIMED)
+            .map(|id| id.source)
+            .collect()
+    }
+
+    /// Connection management messages sent so far, all of which must go
+    /// from `source` to `PEER`
+    fn control_sent(io: &ScriptedIo, source: u8) -> Vec<ControlMessage> {
+        io.sent_frames()
+            .filter(|f| J1939Id::from_frame(f).is_some_and(|id| id.pgn == TP_CM))
+            .map(|f| {
+                let id = J1939Id::from_frame(f).unwrap();
+                assert_eq!((id.source, id.destination), (source, PEER));
+                ControlMessage::parse(f.payload()).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unchallenged_claim_is_won() {
+        let mut node = node(name(100, false), 16);
+        assert_eq!(node.claim_address().unwrap(), 0x80);
+        assert_eq!(node.address(), Some(0x80));
+        assert_eq!(claims_sent(node.io_mut()), vec![0x80]);
+    }
+
+    #[test]
+    fn higher_name_is_answered_and_loses() {
+        let ours = name(100, false);
+        let mut node = node(ours, 16);
+        node.io_mut()
+            .incoming
+            .push_back(claim(0x80, name(200, false)));
+
+        assert_eq!(node.claim_address().unwrap(), 0x80);
+        assert_eq!(claims_sent(node.io_mut()), vec![0x80, 0x80]);
+        assert_eq!(node.nodes().get(&0x80), Some(&name(200, false)));
+    }
+
+    #[test]
+    fn claim_lost_to_a_lower_name() {
+        let mut node = node(name(100, false), 16);
+        node.io_mut()
+            .incoming
+            .push_back(claim(0x80, name(1, false)));
+
+        match node.claim_address() {
+            Err(J1939Error::AddressClaimFailed(0x80)) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(node.address(), None);
+        assert_eq!(claims_sent(node.io_mut()), vec![0x80, NULL_ADDRESS]);
+    }
+
+    #[test]
+    fn lost_claim_moves_to_a_free_arbitrary_address() {
+        let mut node = node(name(100, true), 16);
+        let io = node.io_mut();
+        // 0x81 is already taken by a third node when 0x80 is lost
+        io.incoming.push_back(claim(0x81, name(2, false)));
+        io.incoming.push_back(claim(0x80, name(1, false)));
+
+        assert_eq!(node.claim_address().unwrap(), 0x82);
+        assert_eq!(claims_sent(node.io_mut()), vec![0x80, 0x82]);
+    }
+
+    #[test]
+    fn bam_is_reassembled() {
+        let payload: Vec<u8> = (0..17).collect();
+        let mut node = node(name(100, false), 16);
+        let io = node.io_mut();
+        io.incoming.push_back(control(
+            GLOBAL_ADDRESS,
+            ControlMessage::Bam {
+                size: 17,
+                packets: 3,
+                pgn: PGN,
+            },
+        ));
+        for sequence in 1..=3 {
+            io.incoming
+                .push_back(data(GLOBAL_ADDRESS, &payload, sequence));
+        }
+
+        let message = node.recv(Duration::from_millis(10)).unwrap().unwrap();
+        assert_eq!(
+            message,
+            Message {
+                priority: TP_PRIORITY,
+                pgn: PGN,
+                source: PEER,
+                destination: GLOBAL_ADDRESS,
+                data: payload,
+            }
+        );
+        // broadcasts are never answered
+        assert!(node.io_mut().sent.is_empty());
+    }
+
+    #[test]
+    fn rts_cts_in_windows_smaller_than_the_transfer() {
+        let payload: Vec<u8> = (0..30).collect();
+        let mut node = node(name(100, false), 2);
+        node.address = Some(0x80);
+        let io = node.io_mut();
+        io.incoming.push_back(control(
+            0x80,
+            ControlMessage::Rts {
+                size: 30,
+                packets: 5,
+                max_per_cts: 0xFF,
+                pgn: PGN,
+            },
+        ));
+        for sequence in 1..=5 {
+            io.incoming.push_back(data(0x80, &payload, sequence));
+        }
+
+        let message = node.recv(Duration::from_millis(10)).unwrap().unwrap();
+        assert_eq!((message.source, message.destination), (PEER, 0x80));
+        assert_eq!(message.data, payload);
+
+        let cts = |packets, next| ControlMessage::Cts {
+            packets,
+            next,
+            pgn: PGN,
+        };
+        assert_eq!(
+            control_sent(node.io_mut(), 0x80),
+            vec![
+                cts(2, 1),
+                cts(2, 3),
+                cts(1, 5),
+                ControlMessage::EndOfMsgAck {
+                    size: 30,
+                    packets: 5,
+                    pgn: PGN,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn out_of_sequence_packet_aborts() {
+        let payload = [0; 20];
+        let mut node = node(name(100, false), 16);
+        node.address = Some(0x80);
+        let io = node.io_mut();
+        io.incoming.push_back(control(
+            0x80,
+            ControlMessage::Rts {
+                size: 20,
+                packets: 3,
+                max_per_cts: 0xFF,
+                pgn: PGN,
+            },
+        ));
+        io.incoming.push_back(data(0x80, &payload, 1));
+        io.incoming.push_back(data(0x80, &payload, 3));
+
+        assert_eq!(node.recv(Duration::from_millis(10)).unwrap(), None);
+        assert!(node.sessions.is_empty());
+        assert_eq!(
+            control_sent(node.io_mut(), 0x80),
+            vec![
+                ControlMessage::Cts {
+                    packets: 3,
+                    next: 1,
+                    pgn: PGN,
+                },
+                ControlMessage::Abort {
+                    reason: ABORT_BAD_SEQUENCE,
+                    pgn: PGN,
+                },
+            ]
+        );
+    }
+
+    fn loopback_node() -> J1939Node<AdaptorHandle<LoopbackTransport>> {
+        let mut handle =
+            AdaptorHandle::with_transport(LoopbackTransport::default(), AdaptorSettings::default())
+                .unwrap();
+        handle.start(SEND_TIMEOUT).unwrap();
+        let config = J1939Config {
+            name: name(100, false),
+            bam_interval: Duration::from_millis(1),
+            packets_per_cts: 2,
+            ..J1939Config::default()
+        };
+        let mut node = J1939Node::new(handle, config);
+        assert_eq!(node.claim_address().unwrap(), 0x80);
+        node
+    }
+
+    /// Next message for `pgn`, skipping the echoes of our own claims
+    fn recv_pgn<I: FrameIo>(node: &mut J1939Node<I>, pgn: u32) -> Message {
+        loop {
+            let message = node.recv(Duration::from_millis(100)).unwrap().unwrap();
+            if message.pgn == pgn {
+                return message;
+            }
+            assert_eq!(message.pgn, ADDRESS_CLAIMED);
+        }
+    }
+
+    #[test]
+    fn bam_over_loopback() {
+        let payload: Vec<u8> = (0..40).collect();
+        let mut node = loopback_node();
+        node.send(PGN, DEFAULT_PRIORITY, GLOBAL_ADDRESS, &payload)
+            .unwrap();
+
+        let message = recv_pgn(&mut node, PGN);
+        assert_eq!(
+            (message.source, message.destination, message.priority),
+            (0x80, GLOBAL_ADDRESS, TP_PRIORITY)
+        );
+        assert_eq!(message.data, payload);
+        assert!(node.sessions.is_empty());
+    }
+
+    #[test]
+    fn rts_cts_to_itself_over_loopback() {
+        // proprietary A, so destination specific
+        let pgn = 0xEF00;
+        let payload: Vec<u8> = (0..100).collect();
+        let mut node = loopback_node();
+        node.send(pgn, DEFAULT_PRIORITY, 0x80, &payload).unwrap();
+
+        let message = recv_pgn(&mut node, pgn);
+        assert_eq!((message.source, message.destination), (0x80, 0x80));
+        assert_eq!(message.data, payload);
+        assert!(node.sessions.is_empty());
+    }
+}
@@ -0,0 +1,65 @@
+//! Parameter group numbers used by the stack, and names for common ones.
+
+pub const ACKNOWLEDGEMENT: u32 = 0xE800;
+pub const REQUEST: u32 = 0xEA00;
+pub const TP_DT: u32 = 0xEB00;
+pub const TP_CM: u32 = 0xEC00;
+pub const ADDRESS_CLAIMED: u32 = 0xEE00;
+pub const DM1: u32 = 0xFECA;
+pub const DM2: u32 = 0xFECB;
+
+/// Acronym and description of well known PGNs from SAE J1939-71 and -73
+const NAMES: &[(u32, &str, &str)] = &[
+    (0xE800, "ACKM", "Acknowledgement"),
+    (0xEA00, "RQST", "Request"),
+    (0xEB00, "TP.DT", "Transport Protocol - Data Transfer"),
+    (
+        0xEC00,
+        "TP.CM",
+        "Transport Protocol - Connection Management",
+    ),
+    (0xEE00, "ACL", "Address Claimed"),
+    (0xF001, "EBC1", "Electronic Brake Controller 1"),
+    (0xF002, "ETC1", "Electronic Transmission Controller 1"),
+    (0xF003, "EEC2", "Electronic Engine Controller 2"),
+    (0xF004, "EEC1", "Electronic Engine Controller 1"),
+    (0xF005, "ETC2", "Electronic Transmission Controller 2"),
+    (0xFECA, "DM1", "Active Diagnostic Trouble Codes"),
+    (0xFECB, "DM2", "Previously Active Diagnostic Trouble Codes"),
+    (
+        0xFECC,
+        "DM3",
+        "Diagnostic Data Clear/Reset of Previously Active DTCs",
+    ),
+    (0xFEDA, "SOFT", "Software Identification"),
+    (0xFEE0, "VD", "Vehicle Distance"),
+    (0xFEE5, "HOURS", "Engine Hours, Revolutions"),
+    (0xFEE6, "TD", "Time/Date"),
+    (0xFEE9, "LFC", "Fuel Consumption (Liquid)"),
+    (0xFEEC, "VI", "Vehicle Identification"),
+    (0xFEEE, "ET1", "Engine Temperature 1"),
+    (0xFEEF, "EFL/P1", "Engine Fluid Level/Pressure 1"),
+    (0xFEF1, "CCVS", "Cruise Control/Vehicle Speed"),
+    (0xFEF2, "LFE", "Fuel Economy (Liquid)"),
+    (0xFEF3, "VP", "Vehicle Position"),
+    (0xFEF5, "AMB", "Ambient Conditions"),
+    (0xFEF6, "IC1", "Inlet/Exhaust Conditions 1"),
+    (0xFEF7, "VEP1", "Vehicle Electrical Power 1"),
+    (0xFEFC, "DD", "Dash Display"),
+];
+
+/// Acronym of a PGN, e.g. `EEC1`
+pub fn acronym(pgn: u32) -> Option<&'static str> {
+    NAMES
+        .iter()
+        .find(|(number, _, _)| *number == pgn)
+        .map(|(_, acronym, _)| *acronym)
+}
+
+/// Full name of a PGN, e.g. `Electronic Engine Controller 1`
+pub fn description(pgn: u32) -> Option<&'static str> {
+    NAMES
+        .iter()
+        .find(|(number, _, _)| *number == pgn)
+        .map(|(_, _, description)| *description)
+}
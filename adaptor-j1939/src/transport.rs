@@ -0,0 +1,182 @@
+//! SAE J1939-21 transport protocol connection management messages, used to
+//! carry messages of 9 to 1785 bytes as BAM broadcasts or RTS/CTS sessions.
+
+use std::time::Duration;
+
+/// Largest message the transport protocol carries, 255 packets of 7 bytes
+pub const MAX_TP_LEN: usize = 255 * 7;
+
+/// Priority connection management and data packets are sent with
+pub const TP_PRIORITY: u8 = 7;
+
+/// Longest gap between data packets, or after CTS, before giving up
+pub const T1: Duration = Duration::from_millis(750);
+
+/// Receiver waiting for data after sending CTS
+pub const T2: Duration = Duration::from_millis(1250);
+
+/// Sender waiting for CTS or end of message acknowledgement
+pub const T3: Duration = Duration::from_millis(1250);
+
+/// Sender waiting for the next CTS after a CTS asking it to hold
+pub const T4: Duration = Duration::from_millis(1050);
+
+const RTS: u8 = 16;
+const CTS: u8 = 17;
+const END_OF_MSG_ACK: u8 = 19;
+const BAM: u8 = 32;
+const ABORT: u8 = 255;
+
+pub const ABORT_BUSY: u8 = 1;
+pub const ABORT_RESOURCES: u8 = 2;
+pub const ABORT_TIMEOUT: u8 = 3;
+pub const ABORT_BAD_SEQUENCE: u8 = 7;
+
+/// Meaning of a connection abort reason
+pub fn abort_reason(reason: u8) -> &'static str {
+    match reason {
+        1 => "already in a session",
+        2 => "system resources needed elsewhere",
+        3 => "timeout",
+        4 => "CTS received while transferring data",
+        5 => "maximum retransmit requests reached",
+        6 => "unexpected data transfer packet",
+        7 => "bad sequence number",
+        8 => "duplicate sequence number",
+        9 => "message size greater than 1785 bytes",
+        _ => "unknown",
+    }
+}
+
+/// Number of 7 byte data packets a message of `len` bytes needs
+pub fn packets(len: usize) -> u8 {
+    len.div_ceil(7) as u8
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlMessage {
+    /// Request to send, opening a session with one node
+    Rts {
+        size: u16,
+        packets: u8,
+
+        /// Most packets the sender will send per CTS, 0xFF for no limit
+        max_per_cts: u8,
+        pgn: u32,
+    },
+
+    /// Clear to send `packets` packets starting at `next`, 0 packets to hold
+    Cts {
+        packets: u8,
+        next: u8,
+        pgn: u32,
+    },
+
+    EndOfMsgAck {
+        size: u16,
+        packets: u8,
+        pgn: u32,
+    },
+
+    /// Broadcast announce message, the data packets follow unprompted
+    Bam {
+        size: u16,
+        packets: u8,
+        pgn: u32,
+    },
+
+    Abort {
+        reason: u8,
+        pgn: u32,
+    },
+}
+
+fn pgn_from(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0])
+}
+
+impl ControlMessage {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < 8 {
+            return None;
+        }
+        let size = u16::from_le_bytes([data[1], data[2]]);
+        let pgn = pgn_from(&data[5..8]);
+        match data[0] {
+            RTS => Some(ControlMessage::Rts {
+                size,
+                packets: data[3],
+                max_per_cts: data[4],
+                pgn,
+            }),
+            CTS => Some(ControlMessage::Cts {
+                packets: data[1],
+                next: data[2],
+                pgn,
+            }),
+            END_OF_MSG_ACK => Some(ControlMessage::EndOfMsgAck {
+                size,
+                packets: data[3],
+                pgn,
+            }),
+            BAM => Some(ControlMessage::Bam {
+                size,
+                packets: data[3],
+                pgn,
+            }),
+            ABORT => Some(ControlMessage::Abort {
+                reason: data[1],
+                pgn,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn pgn(&self) -> u32 {
+        match *self {
+            ControlMessage::Rts { pgn, .. }
+            | ControlMessage::Cts { pgn, .. }
+            | ControlMessage::EndOfMsgAck { pgn, .. }
+            | ControlMessage::Bam { pgn, .. }
+            | ControlMessage::Abort { pgn, .. } => pgn,
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 8] {
+        let (head, pgn) = match *self {
+            ControlMessage::Rts {
+                size,
+                packets,
+                max_per_cts,
+                pgn,
+            } => {
+                let size = size.to_le_bytes();
+                ([RTS, size[0], size[1], packets, max_per_cts], pgn)
+            }
+            ControlMessage::Cts { packets, next, pgn } => ([CTS, packets, next, 0xFF, 0xFF], pgn),
+            ControlMessage::EndOfMsgAck { size, packets, pgn } => {
+                let size = size.to_le_bytes();
+                ([END_OF_MSG_ACK, size[0], size[1], packets, 0xFF], pgn)
+            }
+            ControlMessage::Bam { size, packets, pgn } => {
+                let size = size.to_le_bytes();
+                ([BAM, size[0], size[1], packets, 0xFF], pgn)
+            }
+            ControlMessage::Abort { reason, pgn } => ([ABORT, reason, 0xFF, 0xFF, 0xFF], pgn),
+        };
+        let pgn = pgn.to_le_bytes();
+        [
+            head[0], head[1], head[2], head[3], head[4], pgn[0], pgn[1], pgn[2],
+        ]
+    }
+}
+
+/// Data packet `sequence`, counting from 1, padded with 0xFF
+pub fn data_packet(data: &[u8], sequence: u8) -> [u8; 8] {
+    let mut packet = [0xFF; 8];
+    packet[0] = sequence;
+    let start = (sequence as usize - 1) * 7;
+    let chunk = &data[start..(start + 7).min(data.len())];
+    packet[1..=chunk.len()].copy_from_slice(chunk);
+    packet
+}
@@ -0,0 +1,510 @@
+//! Vector ASCII trace files (`.asc`), as written by CANalyzer and CANoe.
+//!
+//! ```text
+//! date Wed Oct 15 10:30:00.000 am 2026
+//! base hex  timestamps absolute
+//! internal events logged
+//! Begin Triggerblock Wed Oct 15 10:30:00.000 am 2026
+//!    0.000000 Start of measurement
+//!    0.010000 1  123             Rx   d 8 01 02 03 04 05 06 07 08
+//!    0.020000 1  12345678x       Tx   r 0
+//!    0.030000 1  ErrorFrame
+//!    0.040000 CANFD   1 Rx        123  1 0 c 12 00 01 02 03 04 05 06 07 08 09 0A 0B 0 0 3000 0 0 0 0 0
+//! End TriggerBlock
+//! ```
+//!
+//! Event times are seconds since the `date` in the header. Lines the reader
+//! does not understand, such as status and statistics events, are skipped.
+
+use crate::calendar::{DateTime, MONTHS, WEEKDAYS};
+use crate::errors::LogError;
+use crate::{Direction, LogEntry, LogWriter, Result};
+
+use adaptor_common::{CANFrame, FdFrame, Frame, FD_MAX_LEN};
+
+use std::io::{BufRead, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const FD_FLAG_EDL: u32 = 0x1000;
+const FD_FLAG_BRS: u32 = 0x2000;
+const FD_FLAG_ESI: u32 = 0x4000;
+
+fn format_date(time: SystemTime) -> String {
+    let date = DateTime::from_system_time(time);
+    let hour = match date.hour % 12 {
+        0 => 12,
+        hour => hour,
+    };
+    format!(
+        "{} {} {} {:02}:{:02}:{:02}.{:03} {} {}",
+        WEEKDAYS[date.weekday as usize],
+        MONTHS[date.month as usize - 1],
+        date.day,
+        hour,
+        date.minute,
+        date.second,
+        date.millis,
+        if date.hour < 12 { "am" } else { "pm" },
+        date.year
+    )
+}
+
+/// Parses the fields after `date`, with or without a weekday and am/pm
+fn parse_date(fields: &[&str]) -> Option<SystemTime> {
+    let fields = match fields.first() {
+        Some(first) if WEEKDAYS.contains(first) => &fields[1..],
+        _ => fields,
+    };
+
+    let month = MONTHS.iter().position(|m| Some(m) == fields.first())? as u16 + 1;
+    let day = fields.get(1)?.parse().ok()?;
+
+    let mut clock = fields.get(2)?.split([':', '.']);
+    let mut hour: u16 = clock.next()?.parse().ok()?;
+    let minute = clock.next()?.parse().ok()?;
+    let second = clock.next()?.parse().ok()?;
+    let millis = clock.next().map_or(Some(0), |ms| ms.parse().ok())?;
+
+    let year = match fields.get(3).map(|f| f.to_ascii_lowercase()) {
+        Some(meridiem) if meridiem == "am" || meridiem == "pm" => {
+            hour %= 12;
+            if meridiem == "pm" {
+                hour += 12;
+            }
+            fields.get(4)?.parse().ok()?
+        }
+        Some(year) => year.parse().ok()?,
+        None => return None,
+    };
+
+    DateTime {
+        year,
+        month,
+        weekday: 0,
+        day,
+        hour,
+        minute,
+        second,
+        millis,
+    }
+    .to_system_time()
+}
+
+/// Writes entries as an ASC trace on a single channel
+pub struct AscWriter<W: Write> {
+    writer: W,
+    channel: u8,
+    start: Option<SystemTime>,
+    finished: bool,
+}
+
+impl<W: Write> AscWriter<W> {
+    /// Vector channels count from 1
+    pub fn new(writer: W, channel: u8) -> Self {
+        Self {
+            writer,
+            channel,
+            start: None,
+            finished: false,
+        }
+    }
+
+    /// The header carries the start time, so it is written with the first entry
+    fn start(&mut self, time: SystemTime) -> Result<SystemTime> {
+        if let Some(start) = self.start {
+            return Ok(start);
+        }
+
+        let start = DateTime::truncate(time);
+        let date = format_date(start);
+        writeln!(self.writer, "date {}", date)?;
+        writeln!(self.writer, "base hex  timestamps absolute")?;
+        writeln!(self.writer, "internal events logged")?;
+        writeln!(self.writer, "Begin Triggerblock {}", date)?;
+        writeln!(self.writer, "{:>11.6} Start of measurement", 0.0)?;
+
+        self.start = Some(start);
+        Ok(start)
+    }
+}
+
+impl<W: Write> LogWriter for AscWriter<W> {
+    fn write_entry(&mut self, entry: &LogEntry) -> Result<()> {
+        let start = self.start(entry.timestamp)?;
+        let time = entry
+            .timestamp
+            .duration_since(start)
+            .unwrap_or_default()
+            .as_secs_f64();
+
+        let direction = match entry.direction {
+            Direction::Rx => "Rx",
+            Direction::Tx => "Tx",
+        };
+        let data: Vec<String> = entry
+            .frame
+            .payload()
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect();
+        let id = if entry.frame.is_ext() {
+            format!("{:X}x", entry.frame.id())
+        } else {
+            format!("{:X}", entry.frame.id())
+        };
+
+        match &entry.frame {
+            Frame::Classic(f) if f.is_err => {
+                writeln!(self.writer, "{:>11.6} {}  ErrorFrame", time, self.channel)?;
+            }
+            Frame::Classic(f) if f.is_rtr => {
+                writeln!(
+                    self.writer,
+                    "{:>11.6} {}  {:<15} {:<4} r {:X}",
+                    time, self.channel, id, direction, f.dlc
+                )?;
+            }
+            Frame::Classic(f) => {
+                writeln!(
+                    self.writer,
+                    "{:>11.6} {}  {:<15} {:<4} d {:X} {}",
+                    time,
+                    self.channel,
+                    id,
+                    direction,
+                    f.dlc,
+                    data.join(" ")
+                )?;
+            }
+            Frame::Fd(f) => {
+                let flags = FD_FLAG_EDL
+                    | if f.brs { FD_FLAG_BRS } else { 0 }
+                    | if f.esi { FD_FLAG_ESI } else { 0 };
+                // message duration, length, flags, CRC and the bit timing
+                // registers follow the data, only the flags are known here
+                writeln!(
+                    self.writer,
+                    "{:>11.6} CANFD {:>3} {:<4} {:>8}  {} {} {:x} {:>2} {} 0 0 {:X} 0 0 0 0 0",
+                    time,
+                    self.channel,
+                    direction,
+                    id,
+                    f.brs as u8,
+                    f.esi as u8,
+                    f.dlc,
+                    f.len(),
+                    data.join(" "),
+                    flags
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+
+        self.start(SystemTime::now())?;
+        writeln!(self.writer, "End TriggerBlock")?;
+        self.writer.flush()?;
+        self.finished = true;
+        Ok(())
+    }
+}
+
+impl<W: Write> Drop for AscWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+/// Reads the CAN and CAN FD events out of an ASC trace
+pub struct AscReader<R: BufRead> {
+    lines: std::io::Lines<R>,
+    line: usize,
+    hex: bool,
+    relative: bool,
+    start: SystemTime,
+    last: f64,
+}
+
+impl<R: BufRead> AscReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            lines: reader.lines(),
+            line: 0,
+            hex: true,
+            relative: false,
+            start: UNIX_EPOCH,
+            last: 0.0,
+        }
+    }
+
+    fn parse_byte(&self, s: &str) -> std::result::Result<u8, String> {
+        let radix = if self.hex { 16 } else { 10 };
+        u8::from_str_radix(s, radix).map_err(|_| format!("invalid data byte '{}'", s))
+    }
+
+    fn parse_data(&self, fields: &[&str], len: usize) -> std::result::Result<Vec<u8>, String> {
+        if fields.len() < len {
+            return Err(format!("expected {} data bytes", len));
+        }
+        fields[..len].iter().map(|b| self.parse_byte(b)).collect()
+    }
+
+    fn parse_id(&self, s: &str) -> std::result::Result<(u32, bool), String> {
+        let (digits, is_ext) = match s.strip_suffix(|c| c == 'x' || c == 'X') {
+            Some(digits) => (digits, true),
+            None => (s, false),
+        };
+        let radix = if self.hex { 16 } else { 10 };
+        let id =
+            u32::from_str_radix(digits, radix).map_err(|_| format!("invalid CAN id '{}'", s))?;
+        Ok((id, is_ext))
+    }
+
+    fn parse_classic(
+        &self,
+        id: &str,
+        kind: &str,
+        fields: &[&str],
+    ) -> std::result::Result<Frame, String> {
+        let (id, is_ext) = self.parse_id(id)?;
+        let dlc = match fields.first() {
+            Some(dlc) => {
+                u8::from_str_radix(dlc, 16).map_err(|_| format!("invalid DLC '{}'", dlc))?
+            }
+            None if kind == "r" => 0,
+            None => return Err("missing DLC".to_owned()),
+        };
+
+        if kind == "r" {
+            return Ok(CANFrame::new(id, dlc.min(8), [0; 8], true, false, is_ext).into());
+        }
+
+        let len = (dlc as usize).min(8);
+        let data = self.parse_data(&fields[1..], len)?;
+        let mut buf = [0_u8; 8];
+        buf[..len].copy_from_slice(&data);
+        Ok(CANFrame::new(id, len as u8, buf, false, false, is_ext).into())
+    }
+
+    fn parse_fd(&self, id: &str, fields: &[&str]) -> std::result::Result<Frame, String> {
+        let (id, is_ext) = self.parse_id(id)?;
+
+        // an optional symbolic message name sits between the id and BRS
+        let fields = match fields.first() {
+            Some(&"0") | Some(&"1") => fields,
+            Some(_) => &fields[1..],
+            None => fields,
+        };
+        if fields.len() < 4 {
+            return Err("truncated CAN FD event".to_owned());
+        }
+
+        let brs = fields[0] == "1";
+        let esi = fields[1] == "1";
+        let len: usize = fields[3]
+            .parse()
+            .ok()
+            .filter(|len| *len <= FD_MAX_LEN)
+            .ok_or_else(|| format!("invalid data length '{}'", fields[3]))?;
+        let data = self.parse_data(&fields[4..], len)?;
+
+        let frame = FdFrame::new(id, &data, brs, esi, is_ext).expect("length checked above");
+        Ok(frame.into())
+    }
+
+    /// `Ok(None)` for anything that is not a frame
+    fn parse_line(&mut self, line: &str) -> std::result::Result<Option<LogEntry>, String> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+
+        match fields.as_slice() {
+            ["date", date @ ..] => {
+                if let Some(start) = parse_date(date) {
+                    self.start = start;
+                }
+                return Ok(None);
+            }
+            ["base", base, "timestamps", mode, ..] => {
+                self.hex = *base != "dec";
+                self.relative = *mode == "relative";
+                return Ok(None);
+            }
+            _ => {}
+        }
+
+        let (time, fields) = match fields.split_first() {
+            Some((time, fields)) => match time.parse::<f64>() {
+                Ok(time) if time.is_finite() && time >= 0.0 => (time, fields),
+                _ => return Ok(None),
+            },
+            None => return Ok(None),
+        };
+
+        let is_channel = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+        let error = || Frame::from(CANFrame::new(0, 0, [0; 8], false, true, false));
+
+        let (direction, frame) = match fields {
+            ["CANFD", _, direction, "ErrorFrame", ..] => (*direction, error()),
+            ["CANFD", _, direction, id, rest @ ..] => (*direction, self.parse_fd(id, rest)?),
+            [channel, "ErrorFrame", ..] if is_channel(channel) => ("Rx", error()),
+            [channel, id, direction, kind, rest @ ..]
+                if is_channel(channel) && (*kind == "d" || *kind == "r") =>
+            {
+                (*direction, self.parse_classic(id, kind, rest)?)
+            }
+            _ => return Ok(None),
+        };
+
+        let direction = match direction {
+            "Rx" => Direction::Rx,
+            "Tx" => Direction::Tx,
+            // transmit requests are followed by the Tx event once the frame is sent
+            _ => return Ok(None),
+        };
+
+        let offset = if self.relative {
+            self.last + time
+        } else {
+            time
+        };
+        let timestamp = Duration::try_from_secs_f64(offset)
+            .ok()
+            .and_then(|offset| self.start.checked_add(offset))
+            .ok_or_else(|| format!("timestamp {} out of range", offset))?;
+        self.last = offset;
+
+        Ok(Some(LogEntry::new(timestamp, direction, frame)))
+    }
+}
+
+impl<R: BufRead> Iterator for AscReader<R> {
+    type Item = Result<LogEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e.into())),
+            };
+            self.line += 1;
+
+            match self.parse_line(&line) {
+                Ok(Some(entry)) => return Some(Ok(entry)),
+                Ok(None) => continue,
+                Err(msg) => return Some(Err(LogError::ParseError(self.line, msg))),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Wed Oct 15 10:30:00 2025
+    fn start() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_760_524_200)
+    }
+
+    /// Multiples of 1/64 s survive the trip through six decimal places exactly
+    fn at(ticks: u64) -> SystemTime {
+        start() + Duration::from_micros(ticks * 15_625)
+    }
+
+    fn read(text: &str) -> Vec<Result<LogEntry>> {
+        AscReader::new(text.as_bytes()).collect()
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let entries = [
+            LogEntry::new(
+                at(0),
+                Direction::Rx,
+                CANFrame::new(0x123, 8, [1, 2, 3, 4, 5, 6, 7, 8], false, false, false).into(),
+            ),
+            LogEntry::new(
+                at(1),
+                Direction::Tx,
+                CANFrame::new(0x1234_5678, 2, [0; 8], true, false, true).into(),
+            ),
+            LogEntry::new(
+                at(2),
+                Direction::Rx,
+                CANFrame::new(0, 0, [0; 8], false, true, false).into(),
+            ),
+            LogEntry::new(
+                at(3),
+                Direction::Rx,
+                FdFrame::new(0x123, &[0xA5; 12], true, false, false)
+                    .unwrap()
+                    .into(),
+            ),
+            LogEntry::new(
+                at(200),
+                Direction::Tx,
+                FdFrame::new(0x1ABC_DEF0, &[0x5A; 64], false, true, true)
+                    .unwrap()
+                    .into(),
+            ),
+        ];
+
+        let mut writer = AscWriter::new(Vec::new(), 1);
+        for entry in &entries {
+            writer.write_entry(entry).unwrap();
+        }
+        writer.finish().unwrap();
+        let text = String::from_utf8(std::mem::take(&mut writer.writer)).unwrap();
+
+        let read: Vec<_> = read(&text).into_iter().collect::<Result<_>>().unwrap();
+        assert_eq!(read, entries);
+    }
+
+    #[test]
+    fn relative_and_decimal_timestamps() {
+        let text = "date Wed Oct 15 10:30:00.000 am 2025\n\
+                    base dec  timestamps relative\n\
+                    Begin Triggerblock Wed Oct 15 10:30:00.000 am 2025\n\
+                    \x20  0.500000 Start of measurement\n\
+                    \x20  0.500000 1  291             Rx   d 2 1 255\n\
+                    \x20  0.250000 1  291x            Tx   d 0\n\
+                    End TriggerBlock\n";
+        let entries: Vec<_> = read(text).into_iter().collect::<Result<_>>().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].timestamp, at(32));
+        assert_eq!(entries[0].direction, Direction::Rx);
+        assert_eq!(entries[0].frame.id(), 0x123);
+        assert_eq!(entries[0].frame.payload(), &[1, 255]);
+        assert_eq!(entries[1].timestamp, at(48));
+        assert_eq!(entries[1].direction, Direction::Tx);
+        assert!(entries[1].frame.is_ext());
+    }
+
+    #[test]
+    fn corrupt_events_are_errors() {
+        for line in &[
+            "   0.010000 1  123             Rx   d 8 01 02",
+            "   0.010000 1  12G             Rx   d 1 01",
+            "   0.010000 CANFD   1 Rx        123  1 0 f 65",
+            "   0.010000 CANFD   1 Rx        123  1 0",
+            "1e300 1  123             Rx   d 0",
+            // fits a Duration but not a SystemTime
+            "18000000000000000000 1  123             Rx   d 0",
+        ] {
+            match read(line).as_slice() {
+                [Err(LogError::ParseError(1, _))] => {}
+                other => panic!("'{}' gave {:?}", line, other),
+            }
+        }
+    }
+
+    #[test]
+    fn non_finite_timestamps_are_skipped() {
+        assert!(read("inf 1  123             Rx   d 0").is_empty());
+        assert!(read("NaN 1  123             Rx   d 0").is_empty());
+    }
+}
@@ -0,0 +1,778 @@
+//! Vector binary logging format (`.blf`).
+//!
+//! A 144 byte `LOGG` file header is followed by `LOBJ` log containers, each
+//! holding a zlib compressed run of `LOBJ` objects, one per frame. Objects
+//! may straddle containers. Written files use `CAN_MESSAGE`, `CAN_ERROR_EXT`
+//! and `CAN_FD_MESSAGE` objects with nanosecond timestamps relative to the
+//! start time in the file header, the reader also understands
+//! `CAN_MESSAGE2` and `CAN_FD_MESSAGE_64`. Other object types are skipped.
+
+use crate::calendar::DateTime;
+use crate::errors::LogError;
+use crate::{Direction, LogEntry, LogWriter, Result};
+
+use adaptor_common::{CANFrame, FdFrame, Frame, FD_MAX_LEN, MAX_EXT_ID};
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use std::convert::TryInto;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const FILE_SIGNATURE: &[u8; 4] = b"LOGG";
+const OBJ_SIGNATURE: &[u8; 4] = b"LOBJ";
+
+const FILE_HEADER_SIZE: usize = 144;
+const OBJ_HEADER_BASE_SIZE: usize = 16;
+const OBJ_HEADER_V1_SIZE: usize = 16;
+const LOG_CONTAINER_SIZE: usize = 16;
+
+/// Uncompressed bytes collected before a container is written out
+const MAX_CONTAINER_SIZE: usize = 128 * 1024;
+
+/// Largest header, object or uncompressed container the reader accepts,
+/// well above anything CANalyzer or this writer produce
+const MAX_OBJECT_SIZE: usize = 4 * 1024 * 1024;
+
+const APPLICATION_ID: u8 = 5;
+
+const CAN_MESSAGE: u32 = 1;
+const LOG_CONTAINER: u32 = 10;
+const CAN_ERROR_EXT: u32 = 73;
+const CAN_MESSAGE2: u32 = 86;
+const CAN_FD_MESSAGE: u32 = 100;
+const CAN_FD_MESSAGE_64: u32 = 101;
+
+const NO_COMPRESSION: u16 = 0;
+const ZLIB_DEFLATE: u16 = 2;
+
+const TIME_TEN_MICS: u32 = 0x1;
+const TIME_ONE_NANS: u32 = 0x2;
+
+/// Set on the identifier of extended frames
+const CAN_MSG_EXT: u32 = 0x8000_0000;
+
+const DIR_TX: u8 = 0x01;
+const REMOTE_FLAG: u8 = 0x80;
+
+const FD_EDL: u8 = 0x01;
+const FD_BRS: u8 = 0x02;
+const FD_ESI: u8 = 0x04;
+
+const FD64_REMOTE: u32 = 0x0010;
+const FD64_EDL: u32 = 0x1000;
+const FD64_BRS: u32 = 0x2000;
+const FD64_ESI: u32 = 0x4000;
+
+fn u16_at(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(buf[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(buf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+fn put_systemtime(buf: &mut Vec<u8>, time: SystemTime) {
+    let date = DateTime::from_system_time(time);
+    for field in &[
+        date.year,
+        date.month,
+        date.weekday,
+        date.day,
+        date.hour,
+        date.minute,
+        date.second,
+        date.millis,
+    ] {
+        buf.extend_from_slice(&field.to_le_bytes());
+    }
+}
+
+fn systemtime_at(buf: &[u8], offset: usize) -> Option<SystemTime> {
+    let field = |i: usize| u16_at(buf, offset + i * 2);
+    DateTime {
+        year: field(0),
+        month: field(1),
+        weekday: field(2),
+        day: field(3),
+        hour: field(4),
+        minute: field(5),
+        second: field(6),
+        millis: field(7),
+    }
+    .to_system_time()
+}
+
+/// Like `read_exact`, but a clean end of file before the first byte is `Ok(false)`
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<bool> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(read == buf.len())
+}
+
+/// Writes entries as a BLF file on a single channel. A provisional file
+/// header is written up front and refreshed after every container, so a
+/// file cut short is readable up to its last container. `finish`, which
+/// also happens on drop, writes out the rest.
+pub struct BlfWriter<W: Write + Seek> {
+    writer: W,
+    channel: u16,
+    start: Option<SystemTime>,
+    stop: SystemTime,
+    buffer: Vec<u8>,
+    object_count: u32,
+    uncompressed_size: u64,
+    finished: bool,
+}
+
+impl<W: Write + Seek> BlfWriter<W> {
+    /// Vector channels count from 1
+    pub fn new(writer: W, channel: u16) -> Result<Self> {
+        let mut blf = Self {
+            writer,
+            channel,
+            start: None,
+            stop: UNIX_EPOCH,
+            buffer: Vec::new(),
+            object_count: 0,
+            uncompressed_size: FILE_HEADER_SIZE as u64,
+            finished: false,
+        };
+        blf.write_header(FILE_HEADER_SIZE as u64)?;
+        blf.writer.flush()?;
+        Ok(blf)
+    }
+
+    fn push_object(&mut self, obj_type: u32, timestamp: u64, data: &[u8]) {
+        let header_size = OBJ_HEADER_BASE_SIZE + OBJ_HEADER_V1_SIZE;
+        let obj_size = header_size + data.len();
+
+        self.buffer.extend_from_slice(OBJ_SIGNATURE);
+        self.buffer
+            .extend_from_slice(&(header_size as u16).to_le_bytes());
+        self.buffer.extend_from_slice(&1_u16.to_le_bytes());
+        self.buffer
+            .extend_from_slice(&(obj_size as u32).to_le_bytes());
+        self.buffer.extend_from_slice(&obj_type.to_le_bytes());
+
+        self.buffer.extend_from_slice(&TIME_ONE_NANS.to_le_bytes());
+        self.buffer.extend_from_slice(&0_u16.to_le_bytes());
+        self.buffer.extend_from_slice(&0_u16.to_le_bytes());
+        self.buffer.extend_from_slice(&timestamp.to_le_bytes());
+
+        self.buffer.extend_from_slice(data);
+        self.buffer.resize(self.buffer.len() + obj_size % 4, 0);
+        self.object_count += 1;
+    }
+
+    fn flush_container(&mut self) -> Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&self.buffer)?;
+        let compressed = encoder.finish()?;
+
+        let obj_size = OBJ_HEADER_BASE_SIZE + LOG_CONTAINER_SIZE + compressed.len();
+        let mut header = Vec::with_capacity(OBJ_HEADER_BASE_SIZE + LOG_CONTAINER_SIZE);
+        header.extend_from_slice(OBJ_SIGNATURE);
+        header.extend_from_slice(&(OBJ_HEADER_BASE_SIZE as u16).to_le_bytes());
+        header.extend_from_slice(&1_u16.to_le_bytes());
+        header.extend_from_slice(&(obj_size as u32).to_le_bytes());
+        header.extend_from_slice(&LOG_CONTAINER.to_le_bytes());
+        header.extend_from_slice(&ZLIB_DEFLATE.to_le_bytes());
+        header.extend_from_slice(&[0; 6]);
+        header.extend_from_slice(&(self.buffer.len() as u32).to_le_bytes());
+        header.extend_from_slice(&[0; 4]);
+
+        self.writer.write_all(&header)?;
+        self.writer.write_all(&compressed)?;
+        self.writer.write_all(&[0; 3][..obj_size % 4])?;
+
+        self.uncompressed_size += (header.len() + self.buffer.len()) as u64;
+        self.buffer.clear();
+
+        let file_size = self.writer.stream_position()?;
+        self.write_header(file_size)
+    }
+
+    /// Writes the file header for what has been written so far,
+    /// leaving the writer at `file_size`
+    fn write_header(&mut self, file_size: u64) -> Result<()> {
+        let (start, stop) = match self.start {
+            Some(start) => (start, self.stop),
+            None => {
+                let now = DateTime::truncate(SystemTime::now());
+                (now, now)
+            }
+        };
+
+        let mut header = Vec::with_capacity(FILE_HEADER_SIZE);
+        header.extend_from_slice(FILE_SIGNATURE);
+        header.extend_from_slice(&(FILE_HEADER_SIZE as u32).to_le_bytes());
+        header.extend_from_slice(&[APPLICATION_ID, 0, 0, 0]);
+        // version of the binlog library the format follows
+        header.extend_from_slice(&[2, 6, 8, 1]);
+        header.extend_from_slice(&file_size.to_le_bytes());
+        header.extend_from_slice(&self.uncompressed_size.to_le_bytes());
+        header.extend_from_slice(&self.object_count.to_le_bytes());
+        header.extend_from_slice(&0_u32.to_le_bytes());
+        put_systemtime(&mut header, start);
+        put_systemtime(&mut header, stop);
+        header.resize(FILE_HEADER_SIZE, 0);
+
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.writer.write_all(&header)?;
+        self.writer.seek(SeekFrom::Start(file_size))?;
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> LogWriter for BlfWriter<W> {
+    fn write_entry(&mut self, entry: &LogEntry) -> Result<()> {
+        let start = *self
+            .start
+            .get_or_insert_with(|| DateTime::truncate(entry.timestamp));
+        let timestamp = entry
+            .timestamp
+            .duration_since(start)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        self.stop = self.stop.max(entry.timestamp);
+
+        let dir = match entry.direction {
+            Direction::Rx => 0,
+            Direction::Tx => DIR_TX,
+        };
+        let id = if entry.frame.is_ext() {
+            entry.frame.id() | CAN_MSG_EXT
+        } else {
+            entry.frame.id()
+        };
+
+        let mut data = Vec::with_capacity(84);
+        match &entry.frame {
+            Frame::Classic(f) if f.is_err => {
+                data.extend_from_slice(&self.channel.to_le_bytes());
+                // length, flags, ECC, position
+                data.extend_from_slice(&[0; 8]);
+                data.push(f.dlc);
+                data.push(0);
+                // frame length
+                data.extend_from_slice(&[0; 4]);
+                data.extend_from_slice(&(f.id & MAX_EXT_ID).to_le_bytes());
+                // extended flags, padding
+                data.extend_from_slice(&[0; 4]);
+                data.extend_from_slice(&f.data);
+                self.push_object(CAN_ERROR_EXT, timestamp, &data);
+            }
+            Frame::Classic(f) => {
+                data.extend_from_slice(&self.channel.to_le_bytes());
+                data.push(dir | if f.is_rtr { REMOTE_FLAG } else { 0 });
+                data.push(f.dlc);
+                data.extend_from_slice(&id.to_le_bytes());
+                data.extend_from_slice(&f.data);
+                self.push_object(CAN_MESSAGE, timestamp, &data);
+            }
+            Frame::Fd(f) => {
+                data.extend_from_slice(&self.channel.to_le_bytes());
+                data.push(dir);
+                data.push(f.dlc);
+                data.extend_from_slice(&id.to_le_bytes());
+                // frame length, bit count
+                data.extend_from_slice(&[0; 5]);
+                data.push(FD_EDL | if f.brs { FD_BRS } else { 0 } | if f.esi { FD_ESI } else { 0 });
+                data.push(f.len() as u8);
+                data.extend_from_slice(&[0; 5]);
+                data.extend_from_slice(&f.data);
+                self.push_object(CAN_FD_MESSAGE, timestamp, &data);
+            }
+        }
+
+        if self.buffer.len() >= MAX_CONTAINER_SIZE {
+            self.flush_container()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+
+        self.flush_container()?;
+        let file_size = self.writer.stream_position()?;
+        self.write_header(file_size)?;
+        self.writer.flush()?;
+        self.finished = true;
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> Drop for BlfWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+/// Reads the CAN and CAN FD messages out of a BLF file
+pub struct BlfReader<R: Read> {
+    reader: R,
+    start: SystemTime,
+
+    /// Uncompressed container contents not parsed yet, from `pos` on
+    data: Vec<u8>,
+    pos: usize,
+
+    /// Objects seen so far, for error messages
+    objects: usize,
+
+    /// Set after an error, the rest of the file cannot be trusted
+    failed: bool,
+}
+
+impl<R: Read> BlfReader<R> {
+    /// Reads the file header
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut header = [0_u8; FILE_HEADER_SIZE];
+        if !read_full(&mut reader, &mut header[..8])? || &header[..4] != FILE_SIGNATURE {
+            return Err(LogError::ParseError(0, "not a BLF file".to_owned()));
+        }
+
+        let header_size = u32_at(&header, 4) as usize;
+        if header_size < 72 {
+            return Err(LogError::ParseError(
+                0,
+                "BLF file header too short".to_owned(),
+            ));
+        }
+        if header_size > MAX_OBJECT_SIZE {
+            return Err(LogError::ParseError(
+                0,
+                "BLF file header too large".to_owned(),
+            ));
+        }
+
+        // the fields this reader uses all sit in the first 144 bytes
+        let mut rest = vec![0_u8; header_size - 8];
+        if !read_full(&mut reader, &mut rest)? {
+            return Err(LogError::ParseError(
+                0,
+                "truncated BLF file header".to_owned(),
+            ));
+        }
+        let len = rest.len().min(FILE_HEADER_SIZE - 8);
+        header[8..8 + len].copy_from_slice(&rest[..len]);
+
+        Ok(Self {
+            reader,
+            start: systemtime_at(&header, 40).unwrap_or(UNIX_EPOCH),
+            data: Vec::new(),
+            pos: 0,
+            objects: 0,
+            failed: false,
+        })
+    }
+
+    /// Reads the next top level log container into `data`, `Ok(false)` at the end of the file
+    fn next_container(&mut self) -> Result<bool> {
+        loop {
+            let mut base = [0_u8; OBJ_HEADER_BASE_SIZE];
+            if !read_full(&mut self.reader, &mut base)? {
+                return Ok(false);
+            }
+            if &base[..4] != OBJ_SIGNATURE {
+                return Err(self.error("missing object signature"));
+            }
+
+            let obj_size = u32_at(&base, 8) as usize;
+            let obj_type = u32_at(&base, 12);
+            if obj_size < OBJ_HEADER_BASE_SIZE {
+                return Err(self.error("object smaller than its header"));
+            }
+            if obj_size > MAX_OBJECT_SIZE {
+                return Err(self.error("object too large"));
+            }
+
+            let mut body = vec![0_u8; obj_size - OBJ_HEADER_BASE_SIZE];
+            if !read_full(&mut self.reader, &mut body)? {
+                return Ok(false);
+            }
+            let mut padding = [0_u8; 3];
+            read_full(&mut self.reader, &mut padding[..obj_size % 4])?;
+
+            if obj_type != LOG_CONTAINER {
+                continue;
+            }
+            if body.len() < LOG_CONTAINER_SIZE {
+                return Err(self.error("truncated log container"));
+            }
+
+            let contents = match u16_at(&body, 0) {
+                NO_COMPRESSION => body[LOG_CONTAINER_SIZE..].to_vec(),
+                ZLIB_DEFLATE => {
+                    let size = (u32_at(&body, 8) as usize).min(MAX_OBJECT_SIZE);
+                    let mut out = Vec::with_capacity(size);
+                    ZlibDecoder::new(&body[LOG_CONTAINER_SIZE..])
+                        .take(MAX_OBJECT_SIZE as u64 + 1)
+                        .read_to_end(&mut out)?;
+                    if out.len() > MAX_OBJECT_SIZE {
+                        return Err(self.error("log container too large"));
+                    }
+                    out
+                }
+                method => {
+                    return Err(self.error(&format!("unsupported compression method {}", method)))
+                }
+            };
+
+            // `pos` can run past the end by the padding of an object split across containers
+            let skip = self.pos.saturating_sub(self.data.len());
+            self.data.drain(..self.pos.min(self.data.len()));
+            self.data
+                .extend_from_slice(&contents[skip.min(contents.len())..]);
+            self.pos = 0;
+            return Ok(true);
+        }
+    }
+
+    fn error(&self, msg: &str) -> LogError {
+        LogError::ParseError(self.objects, msg.to_owned())
+    }
+
+    /// Parses the next object in `data`, `Ok(None)` if it is incomplete
+    fn next_object(&mut self) -> Result<Option<Option<LogEntry>>> {
+        let data = &self.data[self.pos.min(self.data.len())..];
+        if data.len() < OBJ_HEADER_BASE_SIZE {
+            return Ok(None);
+        }
+        if &data[..4] != OBJ_SIGNATURE {
+            return Err(self.error("missing object signature"));
+        }
+
+        let header_size = u16_at(data, 4) as usize;
+        let obj_size = u32_at(data, 8) as usize;
+        let obj_type = u32_at(data, 12);
+        if header_size < OBJ_HEADER_BASE_SIZE + OBJ_HEADER_V1_SIZE
+            || obj_size < header_size
+            || obj_size > MAX_OBJECT_SIZE
+        {
+            return Err(self.error("invalid object header"));
+        }
+        if data.len() < obj_size {
+            return Ok(None);
+        }
+
+        // version 1 and 2 headers both keep the flags and timestamp here
+        let flags = u32_at(data, 16);
+        let ticks = u64_at(data, 24);
+        let nanos = if flags & TIME_TEN_MICS != 0 {
+            ticks.saturating_mul(10_000)
+        } else {
+            ticks
+        };
+        let timestamp = self.start + Duration::from_nanos(nanos);
+
+        let entry = parse_object(obj_type, &data[header_size..obj_size])
+            .map(|(direction, frame)| LogEntry::new(timestamp, direction, frame));
+
+        self.pos += obj_size + obj_size % 4;
+        self.objects += 1;
+        Ok(Some(entry))
+    }
+}
+
+/// Decodes the objects that carry frames, `None` for anything else
+fn parse_object(obj_type: u32, data: &[u8]) -> Option<(Direction, Frame)> {
+    let direction = |tx: bool| if tx { Direction::Tx } else { Direction::Rx };
+
+    match obj_type {
+        CAN_MESSAGE | CAN_MESSAGE2 if data.len() >= 16 => {
+            let flags = data[2];
+            let dlc = data[3].min(8);
+            let id = u32_at(data, 4);
+            let mut buf = [0_u8; 8];
+            buf.copy_from_slice(&data[8..16]);
+
+            let frame = CANFrame::new(
+                id & MAX_EXT_ID,
+                dlc,
+                buf,
+                flags & REMOTE_FLAG != 0,
+                false,
+                id & CAN_MSG_EXT != 0,
+            );
+            Some((direction(flags & DIR_TX != 0), frame.into()))
+        }
+        CAN_ERROR_EXT if data.len() >= 32 => {
+            let dlc = data[10].min(8);
+            let mut buf = [0_u8; 8];
+            buf.copy_from_slice(&data[24..32]);
+
+            let frame = CANFrame::new(u32_at(data, 16) & MAX_EXT_ID, dlc, buf, false, true, false);
+            Some((Direction::Rx, frame.into()))
+        }
+        CAN_FD_MESSAGE if data.len() >= 20 => {
+            let flags = data[2];
+            let dlc = data[3];
+            let id = u32_at(data, 4);
+            let fd_flags = data[13];
+            let len = (data[14] as usize).min(FD_MAX_LEN).min(data.len() - 20);
+            let payload = &data[20..20 + len];
+            let is_ext = id & CAN_MSG_EXT != 0;
+
+            let frame: Frame = if fd_flags & FD_EDL != 0 {
+                FdFrame::new(
+                    id & MAX_EXT_ID,
+                    payload,
+                    fd_flags & FD_BRS != 0,
+                    fd_flags & FD_ESI != 0,
+                    is_ext,
+                )?
+                .into()
+            } else {
+                let len = len.min(8);
+                let mut buf = [0_u8; 8];
+                buf[..len].copy_from_slice(&payload[..len]);
+                let dlc = if flags & REMOTE_FLAG != 0 {
+                    dlc.min(8)
+                } else {
+                    len as u8
+                };
+                CANFrame::new(
+                    id & MAX_EXT_ID,
+                    dlc,
+                    buf,
+                    flags & REMOTE_FLAG != 0,
+                    false,
+                    is_ext,
+                )
+                .into()
+            };
+            Some((direction(flags & DIR_TX != 0), frame))
+        }
+        CAN_FD_MESSAGE_64 if data.len() >= 40 => {
+            let dlc = data[1];
+            let id = u32_at(data, 4);
+            let flags = u32_at(data, 12);
+            let tx = data[34] != 0;
+            let len = (data[2] as usize).min(FD_MAX_LEN).min(data.len() - 40);
+            let payload = &data[40..40 + len];
+            let is_ext = id & CAN_MSG_EXT != 0;
+            let is_rtr = flags & FD64_REMOTE != 0;
+
+            let frame: Frame = if flags & FD64_EDL != 0 {
+                FdFrame::new(
+                    id & MAX_EXT_ID,
+                    payload,
+                    flags & FD64_BRS != 0,
+                    flags & FD64_ESI != 0,
+                    is_ext,
+                )?
+                .into()
+            } else {
+                let len = len.min(8);
+                let mut buf = [0_u8; 8];
+                buf[..len].copy_from_slice(&payload[..len]);
+                let dlc = if is_rtr { dlc.min(8) } else { len as u8 };
+                CANFrame::new(id & MAX_EXT_ID, dlc, buf, is_rtr, false, is_ext).into()
+            };
+            Some((direction(tx), frame))
+        }
+        _ => None,
+    }
+}
+
+impl<R: Read> Iterator for BlfReader<R> {
+    type Item = Result<LogEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.failed {
+            let result = match self.next_object() {
+                Ok(Some(Some(entry))) => return Some(Ok(entry)),
+                Ok(Some(None)) => continue,
+                Ok(None) => self.next_container(),
+                Err(e) => Err(e),
+            };
+
+            match result {
+                Ok(true) => continue,
+                Ok(false) => return None,
+                Err(e) => {
+                    self.failed = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    fn start() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_760_524_200)
+    }
+
+    fn at(micros: u64) -> SystemTime {
+        start() + Duration::from_micros(micros)
+    }
+
+    fn writer() -> BlfWriter<Cursor<Vec<u8>>> {
+        BlfWriter::new(Cursor::new(Vec::new()), 1).unwrap()
+    }
+
+    fn finish(mut writer: BlfWriter<Cursor<Vec<u8>>>) -> Vec<u8> {
+        writer.finish().unwrap();
+        writer.writer.get_ref().clone()
+    }
+
+    fn write(entries: &[LogEntry]) -> Vec<u8> {
+        let mut writer = writer();
+        for entry in entries {
+            writer.write_entry(entry).unwrap();
+        }
+        finish(writer)
+    }
+
+    fn read(file: &[u8]) -> Vec<LogEntry> {
+        BlfReader::new(file)
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap()
+    }
+
+    /// Types of the objects at the top level of the file, after the header
+    fn top_level_objects(file: &[u8]) -> Vec<u32> {
+        let mut types = Vec::new();
+        let mut pos = FILE_HEADER_SIZE;
+        while pos < file.len() {
+            let obj_size = u32_at(file, pos + 8) as usize;
+            types.push(u32_at(file, pos + 12));
+            pos += obj_size + obj_size % 4;
+        }
+        types
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let entries = [
+            LogEntry::new(
+                at(0),
+                Direction::Rx,
+                CANFrame::new(0x123, 8, [1, 2, 3, 4, 5, 6, 7, 8], false, false, false).into(),
+            ),
+            LogEntry::new(
+                at(1_500),
+                Direction::Tx,
+                CANFrame::new(0x1234_5678, 2, [0; 8], true, false, true).into(),
+            ),
+            LogEntry::new(
+                at(2_000),
+                Direction::Rx,
+                CANFrame::new(0, 8, [0, 0x10, 0, 0, 0, 0, 0, 0], false, true, false).into(),
+            ),
+            LogEntry::new(
+                at(3_250),
+                Direction::Tx,
+                FdFrame::new(0x1ABC_DEF0, &[0x5A; 64], true, true, true)
+                    .unwrap()
+                    .into(),
+            ),
+        ];
+        let file = write(&entries);
+        assert_eq!(top_level_objects(&file), [LOG_CONTAINER]);
+        assert_eq!(read(&file), entries);
+    }
+
+    #[test]
+    fn can_fd_message_64_is_read() {
+        let mut data = vec![0_u8; 40];
+        data[0] = 1;
+        data[1] = 9;
+        data[2] = 12;
+        data[4..8].copy_from_slice(&(0x0123_4567 | CAN_MSG_EXT).to_le_bytes());
+        data[12..16].copy_from_slice(&(FD64_EDL | FD64_BRS).to_le_bytes());
+        data[34] = 1;
+        data.extend_from_slice(&[0xC3; 12]);
+
+        let mut writer = writer();
+        writer.start = Some(start());
+        writer.push_object(CAN_FD_MESSAGE_64, 5_000_000, &data);
+        let entries = read(&finish(writer));
+
+        let expected = FdFrame::new(0x0123_4567, &[0xC3; 12], true, false, true).unwrap();
+        assert_eq!(
+            entries,
+            [LogEntry::new(at(5_000), Direction::Tx, expected.into())]
+        );
+    }
+
+    #[test]
+    fn writes_span_several_containers() {
+        let entries: Vec<_> = (0..4000_u32)
+            .map(|i| {
+                let frame = CANFrame::new(i & 0x7FF, 4, [i as u8; 8], false, false, false);
+                LogEntry::new(at(i as u64 * 100), Direction::Rx, frame.into())
+            })
+            .collect();
+        let file = write(&entries);
+
+        let objects = top_level_objects(&file);
+        assert!(objects.len() > 1);
+        assert!(objects.iter().all(|t| *t == LOG_CONTAINER));
+
+        let read = read(&file);
+        assert_eq!(read.len(), entries.len());
+        for (read, written) in read.iter().zip(&entries) {
+            assert_eq!(read.timestamp, written.timestamp);
+            assert_eq!(read.frame.payload(), written.frame.payload());
+        }
+    }
+
+    #[test]
+    fn corrupt_files_are_errors() {
+        assert!(BlfReader::new(&b"LOGX"[..]).is_err());
+        assert!(BlfReader::new(&b"LOGG\x90\x00\x00\x00"[..]).is_err());
+
+        let mut huge_header = b"LOGG".to_vec();
+        huge_header.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert!(BlfReader::new(huge_header.as_slice()).is_err());
+
+        let file = write(&[LogEntry::new(
+            at(0),
+            Direction::Rx,
+            CANFrame::new(0x123, 0, [0; 8], false, false, false).into(),
+        )]);
+
+        let mut bad_signature = file.clone();
+        bad_signature[FILE_HEADER_SIZE] = b'X';
+        let mut huge_object = file;
+        huge_object[FILE_HEADER_SIZE + 8..FILE_HEADER_SIZE + 12]
+            .copy_from_slice(&u32::MAX.to_le_bytes());
+
+        for file in &[bad_signature, huge_object] {
+            let mut reader = BlfReader::new(file.as_slice()).unwrap();
+            assert!(matches!(reader.next(), Some(Err(LogError::ParseError(..)))));
+            assert!(reader.next().is_none());
+        }
+    }
+}
@@ -0,0 +1,101 @@
+//! Just enough calendar arithmetic for the wall clock dates in trace headers,
+//! all in UTC.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+pub const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Broken down time, laid out like the Windows `SYSTEMTIME` BLF files use
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DateTime {
+    pub year: u16,
+    /// 1 to 12
+    pub month: u16,
+    /// 0 is Sunday
+    pub weekday: u16,
+    /// 1 to 31
+    pub day: u16,
+    pub hour: u16,
+    pub minute: u16,
+    pub second: u16,
+    pub millis: u16,
+}
+
+// Days since 1970-01-01 to and from a proleptic Gregorian date, after
+// Howard Hinnant's `days_from_civil`/`civil_from_days`.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+impl DateTime {
+    /// Times before 1970 are clamped to the epoch
+    pub fn from_system_time(time: SystemTime) -> Self {
+        let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let secs = since.as_secs() as i64;
+        let days = secs / 86_400;
+        let rem = secs % 86_400;
+        let (year, month, day) = civil_from_days(days);
+
+        Self {
+            year: year as u16,
+            month: month as u16,
+            weekday: ((days + 4) % 7) as u16,
+            day: day as u16,
+            hour: (rem / 3600) as u16,
+            minute: (rem % 3600 / 60) as u16,
+            second: (rem % 60) as u16,
+            millis: since.subsec_millis() as u16,
+        }
+    }
+
+    /// The weekday is ignored, `None` if any field is out of range
+    pub fn to_system_time(self) -> Option<SystemTime> {
+        if !(1..=12).contains(&self.month)
+            || !(1..=31).contains(&self.day)
+            || self.hour > 23
+            || self.minute > 59
+            || self.second > 60
+            || self.millis > 999
+            || self.year < 1970
+        {
+            return None;
+        }
+
+        let days = days_from_civil(self.year as i64, self.month as i64, self.day as i64);
+        let secs = days as u64 * 86_400
+            + self.hour as u64 * 3600
+            + self.minute as u64 * 60
+            + self.second as u64;
+        Some(UNIX_EPOCH + Duration::new(secs, self.millis as u32 * 1_000_000))
+    }
+
+    /// Drops everything below a millisecond, which is all these dates can hold
+    pub fn truncate(time: SystemTime) -> SystemTime {
+        Self::from_system_time(time)
+            .to_system_time()
+            .unwrap_or(UNIX_EPOCH)
+    }
+}
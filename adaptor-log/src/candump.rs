@@ -0,0 +1,349 @@
+//! The `candump -l` log format from can-utils, one frame per line:
+//!
+//! ```text
+//! (1436509052.249713) can0 044#2A366C2A
+//! (1436509052.250112) can0 12345678#R
+//! (1436509052.250478) can0 123##1DEADBEEF
+//! ```
+//!
+//! The format has no direction, so everything read back is `Direction::Rx`.
+
+use crate::errors::LogError;
+use crate::{Direction, LogEntry, LogWriter, Result};
+
+use adaptor_common::{CANFrame, FdFrame, Frame, FD_MAX_LEN, MAX_EXT_ID, MAX_STD_ID};
+
+use std::fmt::Write as _;
+use std::io::{BufRead, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Set in the printed identifier of error frames, as in SocketCAN
+pub const CAN_ERR_FLAG: u32 = 0x2000_0000;
+
+const FD_BRS: u32 = 0x01;
+const FD_ESI: u32 = 0x02;
+
+/// Formats a frame in the compact `cansend` syntax used in `candump -l` logs
+pub fn format_frame(frame: &Frame) -> String {
+    let mut out = if frame.is_err() {
+        format!("{:08X}#", (frame.id() & MAX_EXT_ID) | CAN_ERR_FLAG)
+    } else if frame.is_ext() {
+        format!("{:08X}#", frame.id() & MAX_EXT_ID)
+    } else {
+        format!("{:03X}#", frame.id() & MAX_STD_ID)
+    };
+
+    match frame {
+        Frame::Classic(f) if f.is_rtr => {
+            out.push('R');
+            if f.dlc > 0 {
+                let _ = write!(out, "{:X}", f.dlc);
+            }
+            return out;
+        }
+        Frame::Classic(_) => {}
+        Frame::Fd(f) => {
+            let flags = if f.brs { FD_BRS } else { 0 } | if f.esi { FD_ESI } else { 0 };
+            let _ = write!(out, "#{:X}", flags);
+        }
+    }
+
+    for byte in frame.payload() {
+        let _ = write!(out, "{:02X}", byte);
+    }
+    out
+}
+
+fn parse_id(s: &str) -> std::result::Result<(u32, bool, bool), String> {
+    let id = u32::from_str_radix(s, 16).map_err(|_| format!("invalid CAN id '{}'", s))?;
+    match s.len() {
+        3 if id <= MAX_STD_ID => Ok((id, false, false)),
+        8 if id & CAN_ERR_FLAG != 0 => Ok((id & MAX_EXT_ID, false, true)),
+        8 if id <= MAX_EXT_ID => Ok((id, true, false)),
+        _ => Err(format!(
+            "CAN id '{}' must be 3 (standard) or 8 (extended) hex digits",
+            s
+        )),
+    }
+}
+
+fn parse_data(s: &str) -> std::result::Result<Vec<u8>, String> {
+    let digits: Vec<char> = s.chars().filter(|c| *c != '.').collect();
+    if !digits.len().is_multiple_of(2) {
+        return Err(format!("odd number of hex digits in '{}'", s));
+    }
+
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let byte: String = pair.iter().collect();
+            u8::from_str_radix(&byte, 16).map_err(|_| format!("invalid data byte '{}'", byte))
+        })
+        .collect()
+}
+
+/// Parses the `cansend` frame syntax:
+///
+/// - `<can_id>#{data}` classic frame, bytes may be separated by `.`
+/// - `<can_id>#R{len}` remote request
+/// - `<can_id>##<flags>{data}` FD frame, flags `1` for BRS and `2` for ESI
+///
+/// Identifiers are 3 hex digits for standard frames and 8 for extended ones.
+/// An 8 digit identifier with `CAN_ERR_FLAG` set is an error frame.
+pub fn parse_frame(s: &str) -> std::result::Result<Frame, String> {
+    let hash = s
+        .find('#')
+        .ok_or_else(|| format!("missing '#' in frame '{}'", s))?;
+    let (id, is_ext, is_err) = parse_id(&s[..hash])?;
+    let rest = &s[hash + 1..];
+
+    if let Some(fd) = rest.strip_prefix('#') {
+        let mut chars = fd.chars();
+        let flags = chars
+            .next()
+            .and_then(|c| c.to_digit(16))
+            .ok_or_else(|| format!("missing FD flags in '{}'", s))?;
+        let data = parse_data(chars.as_str())?;
+        if data.len() > FD_MAX_LEN {
+            return Err(format!("FD frames carry at most {} bytes", FD_MAX_LEN));
+        }
+
+        let frame = FdFrame::new(id, &data, flags & FD_BRS != 0, flags & FD_ESI != 0, is_ext)
+            .expect("length checked above");
+        return Ok(frame.into());
+    }
+
+    if let Some(len) = rest.strip_prefix('R') {
+        let dlc = if len.is_empty() {
+            0
+        } else {
+            u8::from_str_radix(len, 16)
+                .ok()
+                .filter(|l| *l <= 8)
+                .ok_or_else(|| format!("invalid remote request length '{}'", len))?
+        };
+        return Ok(CANFrame::new(id, dlc, [0; 8], true, false, is_ext).into());
+    }
+
+    let data = parse_data(rest)?;
+    if data.len() > 8 {
+        return Err("classic frames carry at most 8 bytes, use ## for FD".to_owned());
+    }
+
+    let mut buf = [0_u8; 8];
+    buf[..data.len()].copy_from_slice(&data);
+    Ok(CANFrame::new(id, data.len() as u8, buf, false, is_err, is_ext).into())
+}
+
+/// Parses one `(<secs>.<micros>) <interface> <frame>` log line
+pub fn parse_line(line: &str) -> std::result::Result<(SystemTime, String, Frame), String> {
+    let mut fields = line.split_whitespace();
+    let (stamp, interface, frame) = match (fields.next(), fields.next(), fields.next()) {
+        (Some(stamp), Some(interface), Some(frame)) => (stamp, interface, frame),
+        _ => return Err("expected '(<timestamp>) <interface> <frame>'".to_owned()),
+    };
+
+    let stamp = stamp
+        .strip_prefix('(')
+        .and_then(|s| s.strip_suffix(')'))
+        .ok_or_else(|| format!("timestamp '{}' is not in parentheses", stamp))?;
+    let (secs, micros) = match stamp.find('.') {
+        Some(dot) => (&stamp[..dot], &stamp[dot + 1..]),
+        None => (stamp, ""),
+    };
+    let invalid = || format!("invalid timestamp '{}'", stamp);
+    if !micros.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let secs: u64 = secs.parse().map_err(|_| invalid())?;
+    // candump always writes 6 digits, but scale whatever is there
+    let fraction = format!("{:0<9}", micros);
+    let nanos: u32 = fraction[..9].parse().map_err(|_| invalid())?;
+
+    let timestamp = UNIX_EPOCH + Duration::new(secs, nanos);
+    Ok((timestamp, interface.to_owned(), parse_frame(frame)?))
+}
+
+/// Writes entries as `candump -l` lines under a fixed interface name
+pub struct CandumpWriter<W: Write> {
+    writer: W,
+    interface: String,
+}
+
+impl<W: Write> CandumpWriter<W> {
+    pub fn new(writer: W, interface: &str) -> Self {
+        Self {
+            writer,
+            interface: interface.to_owned(),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> LogWriter for CandumpWriter<W> {
+    fn write_entry(&mut self, entry: &LogEntry) -> Result<()> {
+        let time = entry
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        writeln!(
+            self.writer,
+            "({}.{:06}) {} {}",
+            time.as_secs(),
+            time.subsec_micros(),
+            self.interface,
+            format_frame(&entry.frame)
+        )?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Reads a `candump -l` log, skipping blank lines
+pub struct CandumpReader<R: BufRead> {
+    lines: std::io::Lines<R>,
+    line: usize,
+}
+
+impl<R: BufRead> CandumpReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            lines: reader.lines(),
+            line: 0,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for CandumpReader<R> {
+    type Item = Result<LogEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e.into())),
+            };
+            self.line += 1;
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            return Some(
+                parse_line(&line)
+                    .map(|(timestamp, _, frame)| LogEntry::new(timestamp, Direction::Rx, frame))
+                    .map_err(|msg| LogError::ParseError(self.line, msg)),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(micros: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_micros(1_436_509_052_000_000 + micros)
+    }
+
+    fn round_trip(entries: &[LogEntry]) -> Vec<LogEntry> {
+        let mut writer = CandumpWriter::new(Vec::new(), "can0");
+        for entry in entries {
+            writer.write_entry(entry).unwrap();
+        }
+        writer.finish().unwrap();
+        let text = writer.into_inner();
+        CandumpReader::new(text.as_slice())
+            .collect::<Result<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let entries = [
+            LogEntry::new(
+                at(249_713),
+                Direction::Rx,
+                CANFrame::new(
+                    0x044,
+                    4,
+                    [0x2A, 0x36, 0x6C, 0x2A, 0, 0, 0, 0],
+                    false,
+                    false,
+                    false,
+                )
+                .into(),
+            ),
+            LogEntry::new(
+                at(250_112),
+                Direction::Rx,
+                CANFrame::new(0x1234_5678, 3, [0; 8], true, false, true).into(),
+            ),
+            LogEntry::new(
+                at(250_300),
+                Direction::Rx,
+                CANFrame::new(0x004, 8, [0, 0x10, 0, 0, 0, 0, 0, 0], false, true, false).into(),
+            ),
+            LogEntry::new(
+                at(250_478),
+                Direction::Rx,
+                FdFrame::new(0x123, &[0xDE, 0xAD, 0xBE, 0xEF], true, false, false)
+                    .unwrap()
+                    .into(),
+            ),
+            LogEntry::new(
+                at(251_000),
+                Direction::Rx,
+                FdFrame::new(0x1ABC_DEF0, &[0x55; 48], false, true, true)
+                    .unwrap()
+                    .into(),
+            ),
+        ];
+        assert_eq!(round_trip(&entries), entries);
+    }
+
+    #[test]
+    fn known_lines_parse() {
+        let text = "(1436509052.249713) can0 044#2A.36.6C.2A\n\
+                    \n\
+                    (1436509052.250478) can0 123##1DEADBEEF\n";
+        let entries: Vec<_> = CandumpReader::new(text.as_bytes())
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].timestamp, at(249_713));
+        assert_eq!(entries[0].frame.payload(), &[0x2A, 0x36, 0x6C, 0x2A]);
+        match entries[1].frame {
+            Frame::Fd(f) => assert!(f.brs && !f.esi),
+            _ => panic!("expected an FD frame"),
+        }
+    }
+
+    #[test]
+    fn corrupt_lines_are_errors() {
+        for line in &[
+            "(1436509052.249713) can0",
+            "1436509052.249713 can0 123#00",
+            "(1436509052.2x9713) can0 123#00",
+            "(1436509052.249713) can0 1234#00",
+            "(1436509052.249713) can0 123#0",
+            "(1436509052.249713) can0 123#000102030405060708",
+            "(1436509052.249713) can0 123#R9",
+            "(1436509052.249713) can0 123##",
+            "(1436509052.249713) can0 123#\u{e9}0",
+        ] {
+            let mut reader = CandumpReader::new(line.as_bytes());
+            match reader.next() {
+                Some(Err(LogError::ParseError(1, _))) => {}
+                other => panic!("'{}' gave {:?}", line, other),
+            }
+        }
+    }
+}
@@ -0,0 +1,20 @@
+use quick_error::quick_error;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum LogError {
+        IoError(err: std::io::Error) {
+            from()
+            display("{}", err)
+        }
+        /// A line or record that could not be understood, `line` is 1-based
+        /// for text formats and the record index for binary ones
+        ParseError(line: usize, msg: String) {
+            display("line {}: {}", line, msg)
+        }
+        AdaptorError(err: adaptor_core::AdaptorError) {
+            from()
+            display("{}", err)
+        }
+    }
+}
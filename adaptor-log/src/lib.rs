@@ -0,0 +1,139 @@
+//! Trace files for frames captured with the probe, in formats other CAN
+//! tooling can open.
+
+pub mod asc;
+pub mod blf;
+mod calendar;
+pub mod candump;
+pub mod errors;
+pub mod pcap;
+pub mod replay;
+
+pub type Result<T> = std::result::Result<T, errors::LogError>;
+
+pub use asc::{AscReader, AscWriter};
+pub use blf::{BlfReader, BlfWriter};
+pub use candump::{CandumpReader, CandumpWriter};
+pub use errors::LogError;
+pub use pcap::{PcapReader, PcapWriter, PcapngReader, PcapngWriter};
+pub use replay::{Replay, ReplayOptions, ReplayStats};
+
+use adaptor_common::Frame;
+use adaptor_core::ReceivedFrame;
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+/// Whether a logged frame came off the bus or was sent by us
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Rx,
+    Tx,
+}
+
+/// One frame in a trace
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogEntry {
+    pub timestamp: SystemTime,
+    pub direction: Direction,
+    pub frame: Frame,
+}
+
+impl LogEntry {
+    pub fn new(timestamp: SystemTime, direction: Direction, frame: Frame) -> Self {
+        Self {
+            timestamp,
+            direction,
+            frame,
+        }
+    }
+}
+
+impl From<ReceivedFrame> for LogEntry {
+    fn from(received: ReceivedFrame) -> Self {
+        Self::new(received.timestamp, Direction::Rx, received.frame)
+    }
+}
+
+/// Something frames can be logged to, one per trace format
+pub trait LogWriter {
+    fn write_entry(&mut self, entry: &LogEntry) -> Result<()>;
+
+    /// Writes anything buffered and, for formats with a trailer, finishes the file
+    fn finish(&mut self) -> Result<()>;
+}
+
+/// Interface name written to candump logs and PCAPNG captures
+pub const CANDUMP_INTERFACE: &str = "can0";
+
+/// Channel written to Vector traces
+pub const VECTOR_CHANNEL: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Candump,
+    Asc,
+    Blf,
+    Pcap,
+    Pcapng,
+}
+
+impl LogFormat {
+    /// Guesses the format from a file extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "log" => Some(LogFormat::Candump),
+            "asc" => Some(LogFormat::Asc),
+            "blf" => Some(LogFormat::Blf),
+            "pcap" => Some(LogFormat::Pcap),
+            "pcapng" => Some(LogFormat::Pcapng),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "candump" | "log" => Ok(LogFormat::Candump),
+            "asc" => Ok(LogFormat::Asc),
+            "blf" => Ok(LogFormat::Blf),
+            "pcap" => Ok(LogFormat::Pcap),
+            "pcapng" => Ok(LogFormat::Pcapng),
+            _ => Err(format!(
+                "unknown log format '{}', expected candump, asc, blf, pcap or pcapng",
+                s
+            )),
+        }
+    }
+}
+
+/// Creates a log file at `path` in the given format
+pub fn create(path: &Path, format: LogFormat) -> Result<Box<dyn LogWriter>> {
+    let file = BufWriter::new(File::create(path)?);
+    Ok(match format {
+        LogFormat::Candump => Box::new(CandumpWriter::new(file, CANDUMP_INTERFACE)),
+        LogFormat::Asc => Box::new(AscWriter::new(file, VECTOR_CHANNEL)),
+        LogFormat::Blf => Box::new(BlfWriter::new(file, VECTOR_CHANNEL as u16)?),
+        LogFormat::Pcap => Box::new(PcapWriter::new(file)?),
+        LogFormat::Pcapng => Box::new(PcapngWriter::new(file, CANDUMP_INTERFACE)?),
+    })
+}
+
+/// Opens the log file at `path` and iterates over its frames
+pub fn open(path: &Path, format: LogFormat) -> Result<Box<dyn Iterator<Item = Result<LogEntry>>>> {
+    let file = BufReader::new(File::open(path)?);
+    Ok(match format {
+        LogFormat::Candump => Box::new(CandumpReader::new(file)),
+        LogFormat::Asc => Box::new(AscReader::new(file)),
+        LogFormat::Blf => Box::new(BlfReader::new(file)?),
+        LogFormat::Pcap => Box::new(PcapReader::new(file)?),
+        LogFormat::Pcapng => Box::new(PcapngReader::new(file)),
+    })
+}
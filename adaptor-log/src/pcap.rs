@@ -0,0 +1,746 @@
+//! PCAP and PCAPNG captures with the `LINKTYPE_CAN_SOCKETCAN` link type, as
+//! Wireshark and tcpdump produce for Linux CAN interfaces.
+//!
+//! Each packet is a SocketCAN `can_frame` (16 bytes) or `canfd_frame` (72
+//! bytes) with the identifier and its EFF/RTR/ERR flag bits in network byte
+//! order. PCAPNG packets also record the direction, classic PCAP ones are
+//! always read back as `Direction::Rx`.
+
+use crate::errors::LogError;
+use crate::{Direction, LogEntry, LogWriter, Result};
+
+use adaptor_common::socketcan::{self, IdOrder, CANFD_MTU};
+use adaptor_common::Frame;
+
+use std::convert::TryInto;
+use std::io::{Read, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const LINKTYPE_CAN_SOCKETCAN: u16 = 227;
+
+const SNAPLEN: u32 = CANFD_MTU as u32;
+
+const PCAP_MAGIC_MICROS: u32 = 0xA1B2_C3D4;
+const PCAP_MAGIC_NANOS: u32 = 0xA1B2_3C4D;
+
+const BLOCK_SHB: u32 = 0x0A0D_0D0A;
+const BLOCK_IDB: u32 = 0x0000_0001;
+const BLOCK_EPB: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+
+const OPT_END: u16 = 0;
+const OPT_IF_NAME: u16 = 2;
+const OPT_IF_TSRESOL: u16 = 9;
+const OPT_EPB_FLAGS: u16 = 2;
+
+const EPB_INBOUND: u32 = 0x1;
+const EPB_OUTBOUND: u32 = 0x2;
+
+/// Encodes a frame as a SocketCAN `can_frame` or `canfd_frame`
+pub fn encode_frame(frame: &Frame) -> Vec<u8> {
+    let mut buf = [0; CANFD_MTU];
+    let len = socketcan::encode(frame, IdOrder::Network, &mut buf);
+    buf[..len].to_vec()
+}
+
+/// Decodes a SocketCAN `can_frame` or `canfd_frame`
+pub fn decode_frame(data: &[u8]) -> std::result::Result<Frame, String> {
+    socketcan::decode(data, IdOrder::Network).map_err(|e| e.to_string())
+}
+
+fn since_epoch(time: SystemTime) -> Duration {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+/// Reads into `buf`, `Ok(false)` on a clean end of file
+fn read_record<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<bool> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) if read == 0 => return Ok(false),
+            Ok(0) => {
+                return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+            }
+            Ok(n) => read += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(true)
+}
+
+/// Writes a classic PCAP file with microsecond timestamps
+pub struct PcapWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Writes the file header
+    pub fn new(mut writer: W) -> Result<Self> {
+        writer.write_all(&PCAP_MAGIC_MICROS.to_le_bytes())?;
+        writer.write_all(&2_u16.to_le_bytes())?;
+        writer.write_all(&4_u16.to_le_bytes())?;
+        // time zone offset and timestamp accuracy
+        writer.write_all(&[0; 8])?;
+        writer.write_all(&SNAPLEN.to_le_bytes())?;
+        writer.write_all(&(LINKTYPE_CAN_SOCKETCAN as u32).to_le_bytes())?;
+        Ok(Self { writer })
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> LogWriter for PcapWriter<W> {
+    fn write_entry(&mut self, entry: &LogEntry) -> Result<()> {
+        let time = since_epoch(entry.timestamp);
+        let packet = encode_frame(&entry.frame);
+
+        self.writer
+            .write_all(&(time.as_secs() as u32).to_le_bytes())?;
+        self.writer.write_all(&time.subsec_micros().to_le_bytes())?;
+        self.writer
+            .write_all(&(packet.len() as u32).to_le_bytes())?;
+        self.writer
+            .write_all(&(packet.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&packet)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Reads a classic PCAP file in either byte order and timestamp resolution
+pub struct PcapReader<R: Read> {
+    reader: R,
+    big_endian: bool,
+    nanos: bool,
+    packet: usize,
+    failed: bool,
+}
+
+impl<R: Read> PcapReader<R> {
+    /// Reads the file header, failing for link types other than SocketCAN
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut header = [0_u8; 24];
+        if !read_record(&mut reader, &mut header)? {
+            return Err(LogError::ParseError(0, "empty PCAP file".to_owned()));
+        }
+
+        let magic = u32::from_le_bytes(header[..4].try_into().unwrap());
+        let (big_endian, nanos) = match magic {
+            PCAP_MAGIC_MICROS => (false, false),
+            PCAP_MAGIC_NANOS => (false, true),
+            m if m.swap_bytes() == PCAP_MAGIC_MICROS => (true, false),
+            m if m.swap_bytes() == PCAP_MAGIC_NANOS => (true, true),
+            _ => return Err(LogError::ParseError(0, "not a PCAP file".to_owned())),
+        };
+
+        let this = Self {
+            reader,
+            big_endian,
+            nanos,
+            packet: 0,
+            failed: false,
+        };
+
+        let linktype = this.u32_at(&header, 20) & 0xFFFF;
+        if linktype != LINKTYPE_CAN_SOCKETCAN as u32 {
+            return Err(LogError::ParseError(
+                0,
+                format!("link type {} is not SocketCAN", linktype),
+            ));
+        }
+        Ok(this)
+    }
+
+    fn u32_at(&self, buf: &[u8], offset: usize) -> u32 {
+        let bytes = buf[offset..offset + 4].try_into().unwrap();
+        if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+
+    fn next_entry(&mut self) -> Result<Option<LogEntry>> {
+        let mut header = [0_u8; 16];
+        if !read_record(&mut self.reader, &mut header)? {
+            return Ok(None);
+        }
+        self.packet += 1;
+
+        let secs = self.u32_at(&header, 0) as u64;
+        let fraction = self.u32_at(&header, 4);
+        let captured = self.u32_at(&header, 8) as usize;
+        if captured > 0x10000 {
+            return Err(LogError::ParseError(
+                self.packet,
+                format!("{} byte packet is implausibly large", captured),
+            ));
+        }
+
+        let mut packet = vec![0_u8; captured];
+        if !read_record(&mut self.reader, &mut packet)? && captured > 0 {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+
+        let nanos = if self.nanos {
+            fraction
+        } else {
+            fraction.saturating_mul(1000)
+        };
+        let timestamp = UNIX_EPOCH + Duration::new(secs, 0) + Duration::from_nanos(nanos as u64);
+        let frame = decode_frame(&packet).map_err(|msg| LogError::ParseError(self.packet, msg))?;
+        Ok(Some(LogEntry::new(timestamp, Direction::Rx, frame)))
+    }
+}
+
+impl<R: Read> Iterator for PcapReader<R> {
+    type Item = Result<LogEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let result = self.next_entry().transpose();
+        self.failed = matches!(result, Some(Err(_)));
+        result
+    }
+}
+
+/// Writes a PCAPNG file with one SocketCAN interface and nanosecond timestamps
+pub struct PcapngWriter<W: Write> {
+    writer: W,
+}
+
+fn push_option(block: &mut Vec<u8>, code: u16, value: &[u8]) {
+    block.extend_from_slice(&code.to_le_bytes());
+    block.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    block.extend_from_slice(value);
+    block.resize(block.len() + (4 - value.len() % 4) % 4, 0);
+}
+
+impl<W: Write> PcapngWriter<W> {
+    /// Writes the section header and the interface description
+    pub fn new(mut writer: W, interface: &str) -> Result<Self> {
+        let mut shb = Vec::new();
+        shb.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        shb.extend_from_slice(&1_u16.to_le_bytes());
+        shb.extend_from_slice(&0_u16.to_le_bytes());
+        // section length is not known up front
+        shb.extend_from_slice(&(-1_i64).to_le_bytes());
+        push_option(&mut shb, OPT_END, &[]);
+        Self::write_block(&mut writer, BLOCK_SHB, &shb)?;
+
+        let mut idb = Vec::new();
+        idb.extend_from_slice(&LINKTYPE_CAN_SOCKETCAN.to_le_bytes());
+        idb.extend_from_slice(&0_u16.to_le_bytes());
+        idb.extend_from_slice(&SNAPLEN.to_le_bytes());
+        push_option(&mut idb, OPT_IF_NAME, interface.as_bytes());
+        push_option(&mut idb, OPT_IF_TSRESOL, &[9]);
+        push_option(&mut idb, OPT_END, &[]);
+        Self::write_block(&mut writer, BLOCK_IDB, &idb)?;
+
+        Ok(Self { writer })
+    }
+
+    fn write_block(writer: &mut W, block_type: u32, body: &[u8]) -> Result<()> {
+        let total = (12 + body.len()) as u32;
+        writer.write_all(&block_type.to_le_bytes())?;
+        writer.write_all(&total.to_le_bytes())?;
+        writer.write_all(body)?;
+        writer.write_all(&total.to_le_bytes())?;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> LogWriter for PcapngWriter<W> {
+    fn write_entry(&mut self, entry: &LogEntry) -> Result<()> {
+        let nanos = since_epoch(entry.timestamp).as_nanos() as u64;
+        let packet = encode_frame(&entry.frame);
+        let flags = match entry.direction {
+            Direction::Rx => EPB_INBOUND,
+            Direction::Tx => EPB_OUTBOUND,
+        };
+
+        let mut epb = Vec::with_capacity(32 + packet.len());
+        // interface 0
+        epb.extend_from_slice(&0_u32.to_le_bytes());
+        epb.extend_from_slice(&((nanos >> 32) as u32).to_le_bytes());
+        epb.extend_from_slice(&(nanos as u32).to_le_bytes());
+        epb.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        epb.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        epb.extend_from_slice(&packet);
+        epb.resize(epb.len() + (4 - packet.len() % 4) % 4, 0);
+        push_option(&mut epb, OPT_EPB_FLAGS, &flags.to_le_bytes());
+        push_option(&mut epb, OPT_END, &[]);
+
+        Self::write_block(&mut self.writer, BLOCK_EPB, &epb)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Interface described by an IDB, in the order they appear in the section
+struct Interface {
+    linktype: u16,
+
+    /// Timestamp units per second
+    units: u64,
+}
+
+/// Reads the SocketCAN packets out of a PCAPNG file. Packets on interfaces
+/// with other link types and blocks other than enhanced packets are skipped.
+pub struct PcapngReader<R: Read> {
+    reader: R,
+    big_endian: bool,
+    interfaces: Vec<Interface>,
+    block: usize,
+    failed: bool,
+}
+
+impl<R: Read> PcapngReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            big_endian: false,
+            interfaces: Vec::new(),
+            block: 0,
+            failed: false,
+        }
+    }
+
+    fn u16_at(&self, buf: &[u8], offset: usize) -> u16 {
+        let bytes = buf[offset..offset + 2].try_into().unwrap();
+        if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        }
+    }
+
+    fn u32_at(&self, buf: &[u8], offset: usize) -> u32 {
+        let bytes = buf[offset..offset + 4].try_into().unwrap();
+        if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+
+    fn error(&self, msg: &str) -> LogError {
+        LogError::ParseError(self.block, msg.to_owned())
+    }
+
+    /// Calls `f` with the code and value of each option in `options`
+    fn options<F: FnMut(u16, &[u8])>(&self, mut options: &[u8], mut f: F) {
+        while options.len() >= 4 {
+            let code = self.u16_at(options, 0);
+            let len = self.u16_at(options, 2) as usize;
+            if code == OPT_END || options.len() < 4 + len {
+                break;
+            }
+            f(code, &options[4..4 + len]);
+            let next = ((4 + len + 3) & !3).min(options.len());
+            options = &options[next..];
+        }
+    }
+
+    fn interface(&self, body: &[u8]) -> Result<Interface> {
+        if body.len() < 8 {
+            return Err(self.error("truncated interface description"));
+        }
+
+        let mut units = 1_000_000;
+        self.options(&body[8..], |code, value| {
+            if code == OPT_IF_TSRESOL && !value.is_empty() {
+                let exponent = (value[0] & 0x7F) as u32;
+                let base: u64 = if value[0] & 0x80 != 0 { 2 } else { 10 };
+                units = base.checked_pow(exponent).unwrap_or(units);
+            }
+        });
+
+        Ok(Interface {
+            linktype: self.u16_at(body, 0),
+            units,
+        })
+    }
+
+    fn packet(&self, body: &[u8]) -> Result<Option<LogEntry>> {
+        if body.len() < 20 {
+            return Err(self.error("truncated packet block"));
+        }
+
+        let interface = self
+            .interfaces
+            .get(self.u32_at(body, 0) as usize)
+            .ok_or_else(|| self.error("packet on an undescribed interface"))?;
+        if interface.linktype != LINKTYPE_CAN_SOCKETCAN {
+            return Ok(None);
+        }
+
+        let ticks = ((self.u32_at(body, 4) as u64) << 32) | self.u32_at(body, 8) as u64;
+        let captured = self.u32_at(body, 12) as usize;
+        let padded = (captured + 3) & !3;
+        if body.len() < 20 + captured {
+            return Err(self.error("packet longer than its block"));
+        }
+
+        let mut direction = Direction::Rx;
+        if body.len() > 20 + padded {
+            self.options(&body[20 + padded..], |code, value| {
+                if code == OPT_EPB_FLAGS && value.len() == 4 {
+                    let flags = self.u32_at(value, 0);
+                    if flags & 0x3 == EPB_OUTBOUND {
+                        direction = Direction::Tx;
+                    }
+                }
+            });
+        }
+
+        let secs = ticks / interface.units;
+        let nanos = (ticks % interface.units) as u128 * 1_000_000_000 / interface.units as u128;
+        let timestamp = UNIX_EPOCH + Duration::new(secs, nanos as u32);
+
+        let frame = decode_frame(&body[20..20 + captured]).map_err(|msg| self.error(&msg))?;
+        Ok(Some(LogEntry::new(timestamp, direction, frame)))
+    }
+
+    fn next_entry(&mut self) -> Result<Option<LogEntry>> {
+        loop {
+            let mut header = [0_u8; 8];
+            if !read_record(&mut self.reader, &mut header)? {
+                return Ok(None);
+            }
+            self.block += 1;
+
+            let block_type = self.u32_at(&header, 0);
+            if block_type == BLOCK_SHB {
+                // the byte order magic follows the length and applies to the whole section
+                let mut magic = [0_u8; 4];
+                if !read_record(&mut self.reader, &mut magic)? {
+                    return Err(self.error("truncated section header"));
+                }
+                self.big_endian = match u32::from_le_bytes(magic) {
+                    BYTE_ORDER_MAGIC => false,
+                    m if m.swap_bytes() == BYTE_ORDER_MAGIC => true,
+                    _ => return Err(self.error("invalid byte order magic")),
+                };
+                self.interfaces.clear();
+
+                let total = self.u32_at(&header, 4) as usize;
+                if total < 16 || !total.is_multiple_of(4) {
+                    return Err(self.error("invalid block length"));
+                }
+                let mut rest = vec![0_u8; total - 12];
+                if !read_record(&mut self.reader, &mut rest)? {
+                    return Err(self.error("truncated section header"));
+                }
+                continue;
+            }
+
+            if self.block == 1 {
+                return Err(self.error("not a PCAPNG file"));
+            }
+
+            let total = self.u32_at(&header, 4) as usize;
+            if total < 12 || !total.is_multiple_of(4) || total > 0x0100_0000 {
+                return Err(self.error("invalid block length"));
+            }
+            let mut body = vec![0_u8; total - 8];
+            if !read_record(&mut self.reader, &mut body)? {
+                return Err(self.error("truncated block"));
+            }
+            let body = &body[..total - 12];
+
+            match block_type {
+                BLOCK_IDB => {
+                    let interface = self.interface(body)?;
+                    self.interfaces.push(interface);
+                }
+                BLOCK_EPB => {
+                    if let Some(entry) = self.packet(body)? {
+                        return Ok(Some(entry));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for PcapngReader<R> {
+    type Item = Result<LogEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let result = self.next_entry().transpose();
+        self.failed = matches!(result, Some(Err(_)));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use adaptor_common::{CANFrame, FdFrame};
+
+    fn at(micros: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_micros(1_760_524_200_000_000 + micros)
+    }
+
+    fn entries() -> Vec<LogEntry> {
+        vec![
+            LogEntry::new(
+                at(0),
+                Direction::Rx,
+                CANFrame::new(0x123, 3, [1, 2, 3, 0, 0, 0, 0, 0], false, false, false).into(),
+            ),
+            LogEntry::new(
+                at(1_500),
+                Direction::Tx,
+                CANFrame::new(0x1234_5678, 2, [0; 8], true, false, true).into(),
+            ),
+            LogEntry::new(
+                at(2_000),
+                Direction::Rx,
+                CANFrame::new(0x004, 8, [0, 0x10, 0, 0, 0, 0, 0, 0], false, true, false).into(),
+            ),
+            LogEntry::new(
+                at(3_250),
+                Direction::Tx,
+                FdFrame::new(0x1ABC_DEF0, &[0x5A; 64], true, false, true)
+                    .unwrap()
+                    .into(),
+            ),
+        ]
+    }
+
+    /// Builds files in either byte order for the formats' big endian variants
+    struct Bytes {
+        big_endian: bool,
+        buf: Vec<u8>,
+    }
+
+    impl Bytes {
+        fn new(big_endian: bool) -> Self {
+            Self {
+                big_endian,
+                buf: Vec::new(),
+            }
+        }
+
+        fn u16(&mut self, value: u16) -> &mut Self {
+            let bytes = if self.big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            };
+            self.buf.extend_from_slice(&bytes);
+            self
+        }
+
+        fn u32(&mut self, value: u32) -> &mut Self {
+            let bytes = if self.big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            };
+            self.buf.extend_from_slice(&bytes);
+            self
+        }
+
+        fn raw(&mut self, bytes: &[u8]) -> &mut Self {
+            self.buf.extend_from_slice(bytes);
+            self
+        }
+
+        /// A PCAPNG block around `body`
+        fn block(&mut self, block_type: u32, body: &[u8]) -> &mut Self {
+            let total = 12 + body.len() as u32;
+            self.u32(block_type).u32(total).raw(body).u32(total)
+        }
+    }
+
+    #[test]
+    fn pcap_round_trips() {
+        let mut writer = PcapWriter::new(Vec::new()).unwrap();
+        for entry in &entries() {
+            writer.write_entry(entry).unwrap();
+        }
+        writer.finish().unwrap();
+        let file = writer.into_inner();
+
+        let read: Vec<_> = PcapReader::new(file.as_slice())
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        // classic PCAP has no direction
+        let expected: Vec<_> = entries()
+            .into_iter()
+            .map(|e| LogEntry::new(e.timestamp, Direction::Rx, e.frame))
+            .collect();
+        assert_eq!(read, expected);
+    }
+
+    #[test]
+    fn pcap_reads_either_byte_order() {
+        let entry = &entries()[3];
+        let since = since_epoch(entry.timestamp);
+        let packet = encode_frame(&entry.frame);
+
+        for big_endian in &[false, true] {
+            let mut file = Bytes::new(*big_endian);
+            file.u32(PCAP_MAGIC_NANOS)
+                .u16(2)
+                .u16(4)
+                .u32(0)
+                .u32(0)
+                .u32(SNAPLEN)
+                .u32(LINKTYPE_CAN_SOCKETCAN as u32)
+                .u32(since.as_secs() as u32)
+                .u32(since.subsec_nanos())
+                .u32(packet.len() as u32)
+                .u32(packet.len() as u32)
+                .raw(&packet);
+
+            let read: Vec<_> = PcapReader::new(file.buf.as_slice())
+                .unwrap()
+                .collect::<Result<_>>()
+                .unwrap();
+            assert_eq!(
+                read,
+                [LogEntry::new(entry.timestamp, Direction::Rx, entry.frame)]
+            );
+        }
+    }
+
+    #[test]
+    fn pcapng_round_trips() {
+        let mut writer = PcapngWriter::new(Vec::new(), "can0").unwrap();
+        for entry in &entries() {
+            writer.write_entry(entry).unwrap();
+        }
+        writer.finish().unwrap();
+        let file = writer.into_inner();
+
+        let read: Vec<_> = PcapngReader::new(file.as_slice())
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(read, entries());
+    }
+
+    #[test]
+    fn pcapng_reads_either_byte_order() {
+        let entry = &entries()[3];
+        let micros = since_epoch(entry.timestamp).as_micros() as u64;
+        let packet = encode_frame(&entry.frame);
+
+        for big_endian in &[false, true] {
+            let mut shb = Bytes::new(*big_endian);
+            shb.u32(BYTE_ORDER_MAGIC).u16(1).u16(0).raw(&[0xFF; 8]);
+
+            // microsecond timestamps, the default resolution
+            let mut idb = Bytes::new(*big_endian);
+            idb.u16(LINKTYPE_CAN_SOCKETCAN)
+                .u16(0)
+                .u32(SNAPLEN)
+                .u16(OPT_END)
+                .u16(0);
+
+            let mut epb = Bytes::new(*big_endian);
+            epb.u32(0)
+                .u32((micros >> 32) as u32)
+                .u32(micros as u32)
+                .u32(packet.len() as u32)
+                .u32(packet.len() as u32)
+                .raw(&packet)
+                .u16(OPT_EPB_FLAGS)
+                .u16(4)
+                .u32(EPB_OUTBOUND)
+                .u16(OPT_END)
+                .u16(0);
+
+            let mut file = Bytes::new(*big_endian);
+            file.block(BLOCK_SHB, &shb.buf)
+                .block(BLOCK_IDB, &idb.buf)
+                .block(BLOCK_EPB, &epb.buf);
+
+            let read: Vec<_> = PcapngReader::new(file.buf.as_slice())
+                .collect::<Result<_>>()
+                .unwrap();
+            assert_eq!(read, [*entry]);
+        }
+    }
+
+    #[test]
+    fn corrupt_pcap_files_are_errors() {
+        assert!(PcapReader::new(&[0_u8; 10][..]).is_err());
+        assert!(PcapReader::new(&[0_u8; 24][..]).is_err());
+
+        let mut other_link = Bytes::new(false);
+        other_link
+            .u32(PCAP_MAGIC_MICROS)
+            .u16(2)
+            .u16(4)
+            .raw(&[0; 8])
+            .u32(SNAPLEN)
+            .u32(1);
+        assert!(PcapReader::new(other_link.buf.as_slice()).is_err());
+
+        let mut writer = PcapWriter::new(Vec::new()).unwrap();
+        writer.write_entry(&entries()[0]).unwrap();
+        let file = writer.into_inner();
+
+        let mut huge = file.clone();
+        huge[24 + 8..24 + 12].copy_from_slice(&u32::MAX.to_le_bytes());
+        let truncated = &file[..file.len() - 4];
+
+        for file in &[&huge[..], truncated] {
+            let mut reader = PcapReader::new(*file).unwrap();
+            assert!(matches!(reader.next(), Some(Err(_))));
+            assert!(reader.next().is_none());
+        }
+    }
+
+    #[test]
+    fn corrupt_pcapng_files_are_errors() {
+        let mut writer = PcapngWriter::new(Vec::new(), "can0").unwrap();
+        writer.write_entry(&entries()[0]).unwrap();
+        let file = writer.into_inner();
+
+        // an interface description where the section header should be
+        let shb_len = u32::from_le_bytes(file[4..8].try_into().unwrap()) as usize;
+        let no_section = &file[shb_len..];
+        let truncated = &file[..file.len() - 4];
+        let mut bad_magic = file.clone();
+        bad_magic[8] ^= 0xFF;
+        let mut bad_length = file.clone();
+        bad_length[shb_len + 4] = 13;
+
+        for file in &[no_section, truncated, &bad_magic[..], &bad_length[..]] {
+            let mut reader = PcapngReader::new(*file);
+            assert!(
+                matches!(reader.next(), Some(Err(_))),
+                "{:02X?} was read",
+                file
+            );
+            assert!(reader.next().is_none());
+        }
+    }
+}
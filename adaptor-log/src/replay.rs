@@ -0,0 +1,188 @@
+//! Plays a recorded trace back onto the bus with its original timing.
+//!
+//! Frames are sent at the offset they were logged at, measured from the
+//! first frame of the trace and divided by the speed multiplier. A send that
+//! falls behind schedule does not shift the frames after it, so a slow
+//! transfer never accumulates into drift over a long trace.
+
+use crate::{LogEntry, LogFormat, Result};
+
+use adaptor_common::Frame;
+use adaptor_core::{AdaptorHandle, Transport};
+
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// What part of a trace to play and how fast
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayOptions {
+    /// 2.0 plays twice as fast as recorded, zero sends frames back to back
+    pub speed: f64,
+
+    /// Times to play the trace, `None` to repeat until stopped
+    pub loops: Option<u32>,
+
+    /// Only frames with these identifiers are sent, all frames if empty
+    pub ids: Vec<u32>,
+
+    /// Frames logged before this offset into the trace are skipped
+    pub start: Duration,
+
+    /// Frames logged after this offset into the trace are skipped
+    pub end: Option<Duration>,
+}
+
+impl Default for ReplayOptions {
+    fn default() -> Self {
+        Self {
+            speed: 1.0,
+            loops: Some(1),
+            ids: Vec::new(),
+            start: Duration::from_secs(0),
+            end: None,
+        }
+    }
+}
+
+/// How a replay went
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReplayStats {
+    pub sent: usize,
+
+    /// Completed passes over the trace
+    pub loops: u32,
+
+    /// Furthest any frame went out behind its scheduled time
+    pub max_lag: Duration,
+}
+
+/// A trace prepared for playback, holding each frame with its offset from
+/// the start of the selected range
+#[derive(Debug, Clone)]
+pub struct Replay {
+    frames: Vec<(Duration, Frame)>,
+    options: ReplayOptions,
+}
+
+impl Replay {
+    /// Selects the frames to play out of `entries`. Error frames cannot be
+    /// transmitted and are always left out.
+    pub fn new<I>(entries: I, options: ReplayOptions) -> Self
+    where
+        I: IntoIterator<Item = LogEntry>,
+    {
+        let mut entries = entries.into_iter().peekable();
+        let first = entries.peek().map(|entry| entry.timestamp);
+
+        let frames = entries
+            .filter_map(|entry| {
+                let offset = entry.timestamp.duration_since(first?).unwrap_or_default();
+                Some((offset, entry.frame))
+            })
+            .filter(|(offset, _)| {
+                *offset >= options.start && options.end.is_none_or(|end| *offset <= end)
+            })
+            .filter(|(_, frame)| {
+                !frame.is_err() && (options.ids.is_empty() || options.ids.contains(&frame.id()))
+            })
+            .map(|(offset, frame)| (offset - options.start, frame))
+            .collect();
+
+        Self { frames, options }
+    }
+
+    /// Reads a whole trace file, any error in it fails the replay up front
+    pub fn from_file(path: &Path, format: LogFormat, options: ReplayOptions) -> Result<Self> {
+        let entries = crate::open(path, format)?.collect::<Result<Vec<_>>>()?;
+        Ok(Self::new(entries, options))
+    }
+
+    pub fn options(&self) -> &ReplayOptions {
+        &self.options
+    }
+
+    /// Frames sent per pass
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Length of one pass at the configured speed
+    pub fn duration(&self) -> Duration {
+        self.frames
+            .last()
+            .map_or(Duration::from_secs(0), |(offset, _)| self.scale(*offset))
+    }
+
+    fn scale(&self, offset: Duration) -> Duration {
+        if self.options.speed > 0.0 && self.options.speed.is_finite() {
+            Duration::from_secs_f64(offset.as_secs_f64() / self.options.speed)
+        } else {
+            Duration::from_secs(0)
+        }
+    }
+
+    /// Plays the trace through a started handle, blocking until it is done
+    pub fn run<T: Transport>(
+        &self,
+        handle: &mut AdaptorHandle<T>,
+        timeout: Duration,
+    ) -> Result<ReplayStats> {
+        let stop = AtomicBool::new(false);
+        self.run_until(handle, timeout, &stop)
+    }
+
+    /// Like `run`, but gives up between frames once `stop` is set
+    pub fn run_until<T: Transport>(
+        &self,
+        handle: &mut AdaptorHandle<T>,
+        timeout: Duration,
+        stop: &AtomicBool,
+    ) -> Result<ReplayStats> {
+        self.run_with(stop, |frame| handle.write_frame(*frame, timeout))
+    }
+
+    /// Plays the trace through `send`, for sinks other than a handle such
+    /// as a `threaded::FrameSender`
+    pub fn run_with<F>(&self, stop: &AtomicBool, mut send: F) -> Result<ReplayStats>
+    where
+        F: FnMut(&Frame) -> adaptor_core::Result<()>,
+    {
+        let mut stats = ReplayStats::default();
+        if self.frames.is_empty() {
+            return Ok(stats);
+        }
+
+        while self.options.loops.is_none_or(|loops| stats.loops < loops) {
+            let begin = Instant::now();
+
+            for (offset, frame) in &self.frames {
+                let due = begin + self.scale(*offset);
+                while let Some(wait) = due.checked_duration_since(Instant::now()) {
+                    if stop.load(Ordering::Relaxed) {
+                        return Ok(stats);
+                    }
+                    // wake up regularly so `stop` is noticed during long gaps
+                    std::thread::sleep(wait.min(Duration::from_millis(100)));
+                }
+                if stop.load(Ordering::Relaxed) {
+                    return Ok(stats);
+                }
+
+                let lag = Instant::now().saturating_duration_since(due);
+                stats.max_lag = stats.max_lag.max(lag);
+
+                send(frame)?;
+                stats.sent += 1;
+            }
+
+            stats.loops += 1;
+        }
+
+        Ok(stats)
+    }
+}
@@ -0,0 +1,164 @@
+use adaptor_common::{AdaptorSettings, Frame, PeriodicTx, RxFrame, MAX_PERIODIC};
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// State of the emulated probe firmware
+#[derive(Debug)]
+pub(crate) struct Firmware {
+    pub settings: AdaptorSettings,
+    pub running: bool,
+    pub leds: bool,
+    pub error: u8,
+    pub loopback: bool,
+
+    /// Frames seen on the bus, waiting to be picked up by the host
+    pub rx: VecDeque<RxFrame>,
+
+    /// Frames the host asked the probe to put on the bus
+    pub transmitted: Vec<Frame>,
+
+    /// Scripted traffic as offsets from when the probe was started
+    pub script: VecDeque<(Duration, Frame)>,
+
+    pub started: Option<Instant>,
+
+    /// Periodic transmission slots, each with when it is next due
+    pub periodic: [Option<(PeriodicTx, Instant)>; MAX_PERIODIC],
+
+    /// Zero of the microsecond counter, restarted on reset
+    pub epoch: Instant,
+}
+
+impl Default for Firmware {
+    fn default() -> Self {
+        let settings = AdaptorSettings::default();
+        Self {
+            leds: *settings.leds(),
+            settings,
+            running: false,
+            error: 0,
+            loopback: false,
+            rx: VecDeque::new(),
+            transmitted: Vec::new(),
+            script: VecDeque::new(),
+            started: None,
+            periodic: [None; MAX_PERIODIC],
+            epoch: Instant::now(),
+        }
+    }
+}
+
+impl Firmware {
+    /// Power cycles the probe, keeping only the log of transmitted frames
+    pub fn reset(&mut self) {
+        let transmitted = std::mem::take(&mut self.transmitted);
+        *self = Self {
+            transmitted,
+            ..Self::default()
+        };
+    }
+
+    pub fn set_running(&mut self, running: bool) {
+        if running && !self.running {
+            let now = Instant::now();
+            self.started = Some(now);
+            for (tx, next) in self.periodic.iter_mut().flatten() {
+                *next = now + Duration::from_micros(tx.phase_us as u64);
+            }
+        }
+        self.running = running;
+    }
+
+    /// Value of the microsecond counter at `instant`
+    pub fn device_time(&self, instant: Instant) -> u64 {
+        instant.saturating_duration_since(self.epoch).as_micros() as u64
+    }
+
+    /// Puts a frame on the bus, stamping it with the current counter value
+    pub fn receive(&mut self, frame: Frame) {
+        let timestamp = self.device_time(Instant::now());
+        self.rx.push_back(RxFrame::new(timestamp, frame));
+    }
+
+    /// Mirrors the acceptance filter in the CAN peripheral
+    pub fn accepts(&self, frame: &Frame) -> bool {
+        if frame.is_fd() && !*self.settings.fd_enabled() {
+            return false;
+        }
+
+        frame.is_err() || self.settings.accepts(frame.id(), frame.is_ext())
+    }
+
+    /// Fills a periodic slot, the first transmission is `phase_us` from now
+    pub fn set_periodic(&mut self, slot: usize, tx: PeriodicTx) {
+        let next = Instant::now() + Duration::from_micros(tx.phase_us as u64);
+        self.periodic[slot] = Some((tx, next));
+    }
+
+    /// Moves scripted frames whose time has come onto the bus and sends
+    /// every periodic transmission that is due, including any the host
+    /// missed by not polling
+    pub fn release_due(&mut self, now: Instant) {
+        let started = match self.started {
+            Some(started) if self.running => started,
+            _ => return,
+        };
+
+        let mut due = Vec::new();
+        for (tx, next) in self.periodic.iter_mut().flatten() {
+            while *next <= now {
+                due.push(tx.next_frame());
+                *next += Duration::from_micros(tx.period_us as u64);
+            }
+        }
+        for frame in due {
+            self.transmit(frame);
+        }
+
+        while let Some((offset, _)) = self.script.front() {
+            let due = started + *offset;
+            if due > now {
+                break;
+            }
+            let (_, frame) = self.script.pop_front().unwrap();
+            let timestamp = self.device_time(due);
+            self.rx.push_back(RxFrame::new(timestamp, frame));
+        }
+    }
+
+    /// Time until the next scripted or periodic frame is due, if any
+    pub fn next_due(&self, now: Instant) -> Option<Duration> {
+        let started = self.started.filter(|_| self.running)?;
+        self.script
+            .front()
+            .map(|(offset, _)| started + *offset)
+            .into_iter()
+            .chain(self.periodic.iter().flatten().map(|(_, next)| *next))
+            .min()
+            .map(|due| due.saturating_duration_since(now))
+    }
+
+    /// Next frame to hand to the host, skipping anything the filter rejects
+    pub fn next_rx(&mut self) -> Option<RxFrame> {
+        if !self.running {
+            return None;
+        }
+
+        while let Some(rx) = self.rx.pop_front() {
+            if self.accepts(&rx.frame) {
+                return Some(rx);
+            }
+            log::trace!("Filtered frame: {:?}", rx.frame);
+        }
+        None
+    }
+
+    pub fn transmit(&mut self, frame: Frame) {
+        log::trace!("Transmitted frame: {:?}", frame);
+        if self.loopback {
+            self.receive(frame);
+        }
+        self.transmitted.push(frame);
+    }
+}
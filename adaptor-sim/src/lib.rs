@@ -0,0 +1,283 @@
+//! Emulates the probe firmware so `AdaptorHandle` and the tools built on
+//! top of it can be driven end to end without hardware.
+//!
+//! A `Simulator` is a cheap handle onto shared firmware state: give one
+//! clone to `AdaptorHandle::with_transport` and keep another to inject bus
+//! traffic and errors or to inspect what the host sent.
+
+mod firmware;
+
+use firmware::Firmware;
+
+use adaptor_common::{AdaptorSettings, CANFrame, Frame, PeriodicTx, UsbRequests};
+use adaptor_common::{CMD_PACKET_SIZE, MAX_FILTERS, MAX_PERIODIC};
+use adaptor_core::{AdaptorHandle, Result, Transport};
+
+use std::convert::TryFrom;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Default)]
+struct Shared {
+    firmware: Mutex<Firmware>,
+    available: Condvar,
+}
+
+#[derive(Debug, Clone)]
+pub struct Simulator {
+    pid: u16,
+    shared: Arc<Shared>,
+}
+
+impl Simulator {
+    /// Creates a simulator that identifies as a V1 probe
+    pub fn new() -> Self {
+        Self::with_product_id(0x69)
+    }
+
+    pub fn with_product_id(pid: u16) -> Self {
+        Self {
+            pid,
+            shared: Arc::new(Shared::default()),
+        }
+    }
+
+    /// Opens an `AdaptorHandle` talking to this simulator
+    pub fn open(&self, settings: AdaptorSettings) -> Result<AdaptorHandle<Simulator>> {
+        AdaptorHandle::with_transport(self.clone(), settings)
+    }
+
+    fn firmware(&self) -> MutexGuard<'_, Firmware> {
+        self.shared
+            .firmware
+            .lock()
+            .expect("simulator state poisoned")
+    }
+
+    /// Puts a frame on the bus as if another node had sent it
+    pub fn inject_frame(&self, frame: impl Into<Frame>) {
+        self.firmware().receive(frame.into());
+        self.shared.available.notify_all();
+    }
+
+    /// Latches `code` in the error register and reports it to the host as an error frame
+    pub fn inject_error(&self, code: u8) {
+        let mut firmware = self.firmware();
+        firmware.error = code;
+        let mut data = [0_u8; 8];
+        data[0] = code;
+        firmware.receive(CANFrame::new(0, 1, data, false, true, false).into());
+        self.shared.available.notify_all();
+    }
+
+    /// Schedules traffic on the bus, each frame offset from the moment the probe is started.
+    /// If the probe is already running the offsets count from now.
+    pub fn script<I, F>(&self, frames: I)
+    where
+        I: IntoIterator<Item = (Duration, F)>,
+        F: Into<Frame>,
+    {
+        let mut firmware = self.firmware();
+        let base = match firmware.started {
+            Some(started) if firmware.running => started.elapsed(),
+            _ => Duration::from_secs(0),
+        };
+
+        let mut script: Vec<_> = firmware.script.drain(..).collect();
+        script.extend(frames.into_iter().map(|(t, f)| (base + t, f.into())));
+        script.sort_by_key(|(t, _)| *t);
+        firmware.script = script.into();
+
+        self.shared.available.notify_all();
+    }
+
+    /// Echo frames sent by the host back to it, like the peripheral's loopback mode
+    pub fn set_loopback(&self, loopback: bool) {
+        self.firmware().loopback = loopback;
+    }
+
+    /// Frames the host has put on the bus so far, directly or through periodic slots
+    pub fn transmitted(&self) -> Vec<Frame> {
+        let mut firmware = self.firmware();
+        firmware.release_due(Instant::now());
+        firmware.transmitted.clone()
+    }
+
+    pub fn take_transmitted(&self) -> Vec<Frame> {
+        let mut firmware = self.firmware();
+        firmware.release_due(Instant::now());
+        std::mem::take(&mut firmware.transmitted)
+    }
+
+    /// Periodic transmissions the host has set up, by slot
+    pub fn periodic(&self) -> Vec<Option<PeriodicTx>> {
+        self.firmware()
+            .periodic
+            .iter()
+            .map(|slot| slot.map(|(tx, _)| tx))
+            .collect()
+    }
+
+    pub fn settings(&self) -> AdaptorSettings {
+        self.firmware().settings
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.firmware().running
+    }
+
+    pub fn leds_enabled(&self) -> bool {
+        self.firmware().leds
+    }
+
+    pub fn error(&self) -> u8 {
+        self.firmware().error
+    }
+}
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transport for Simulator {
+    fn product_id(&self) -> u16 {
+        self.pid
+    }
+
+    fn read_bulk(&self, _endpoint: u8, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+        let deadline = Instant::now() + timeout;
+        let mut firmware = self.firmware();
+
+        loop {
+            let now = Instant::now();
+            firmware.release_due(now);
+
+            if let Some(rx) = firmware.next_rx() {
+                let vec = postcard::to_stdvec(&rx)?;
+                let mut len = vec.len().min(buf.len());
+                buf[..len].copy_from_slice(&vec[..len]);
+
+                // like the firmware, pack as many waiting frames as fit into one transfer
+                while let Some(rx) = firmware.next_rx() {
+                    let vec = postcard::to_stdvec(&rx)?;
+                    if len + vec.len() > buf.len() {
+                        firmware.rx.push_front(rx);
+                        break;
+                    }
+                    buf[len..len + vec.len()].copy_from_slice(&vec);
+                    len += vec.len();
+                }
+                return Ok(len);
+            }
+
+            if now >= deadline {
+                return Err(rusb::Error::Timeout.into());
+            }
+
+            let remaining = deadline - now;
+            let wait = firmware
+                .next_due(now)
+                .map_or(remaining, |due| due.min(remaining));
+
+            firmware = self
+                .shared
+                .available
+                .wait_timeout(firmware, wait)
+                .expect("simulator state poisoned")
+                .0;
+        }
+    }
+
+    fn write_bulk(&self, _endpoint: u8, buf: &[u8], _timeout: Duration) -> Result<usize> {
+        let mut firmware = self.firmware();
+        if !firmware.running {
+            log::warn!("Frame written while the probe is stopped");
+            return Err(rusb::Error::Pipe.into());
+        }
+
+        let frame: Frame = postcard::from_bytes(buf)?;
+        if frame.is_fd() && !*firmware.settings.fd_enabled() {
+            log::warn!("FD frame written while FD is disabled");
+            return Err(rusb::Error::Pipe.into());
+        }
+
+        firmware.transmit(frame);
+        self.shared.available.notify_all();
+        Ok(buf.len())
+    }
+
+    fn read_vendor(
+        &self,
+        request: u8,
+        _value: u16,
+        _index: u16,
+        buf: &mut [u8],
+        _timeout: Duration,
+    ) -> Result<usize> {
+        let firmware = self.firmware();
+        match UsbRequests::try_from(request) {
+            Ok(UsbRequests::GetError) if !buf.is_empty() => {
+                buf[0] = firmware.error;
+                Ok(1)
+            }
+            Ok(UsbRequests::GetTimestamp) if buf.len() >= 8 => {
+                let now = firmware.device_time(Instant::now());
+                buf[..8].copy_from_slice(&now.to_le_bytes());
+                Ok(8)
+            }
+            _ => Err(rusb::Error::Pipe.into()),
+        }
+    }
+
+    fn write_vendor(
+        &self,
+        request: u8,
+        value: u16,
+        _index: u16,
+        buf: &[u8],
+        _timeout: Duration,
+    ) -> Result<usize> {
+        let mut firmware = self.firmware();
+        match UsbRequests::try_from(request) {
+            Ok(UsbRequests::NOP) => {}
+            Ok(UsbRequests::Settings | UsbRequests::SetFilter) if buf.len() > CMD_PACKET_SIZE => {
+                return Err(rusb::Error::Overflow.into());
+            }
+            Ok(UsbRequests::Settings) => {
+                let settings: AdaptorSettings = postcard::from_bytes(buf)?;
+                firmware.leds = *settings.leds();
+                firmware.settings = settings;
+            }
+            Ok(UsbRequests::SetFilter) if (value as usize) < MAX_FILTERS => {
+                let mut filters = *firmware.settings.filters();
+                filters[value as usize] = postcard::from_bytes(buf)?;
+                firmware.settings.set_filters(filters);
+            }
+            Ok(UsbRequests::Run) => {
+                firmware.set_running(buf.first().is_some_and(|b| *b != 0));
+            }
+            Ok(UsbRequests::LedEnable) => {
+                firmware.leds = buf.first().is_some_and(|b| *b != 0);
+            }
+            Ok(UsbRequests::Reset) => {
+                firmware.reset();
+            }
+            Ok(UsbRequests::SetPeriodic) if (value as usize) < MAX_PERIODIC => {
+                let tx: PeriodicTx = postcard::from_bytes(buf)?;
+                if tx.period_us == 0 || (tx.frame.is_fd() && !*firmware.settings.fd_enabled()) {
+                    log::warn!("Rejected periodic transmission {:?}", tx);
+                    return Err(rusb::Error::Pipe.into());
+                }
+                firmware.set_periodic(value as usize, tx);
+            }
+            Ok(UsbRequests::ClearPeriodic) if (value as usize) < MAX_PERIODIC => {
+                firmware.periodic[value as usize] = None;
+            }
+            _ => return Err(rusb::Error::Pipe.into()),
+        }
+        self.shared.available.notify_all();
+        Ok(buf.len())
+    }
+}
@@ -0,0 +1,191 @@
+use adaptor_common::{AdaptorSettings, CANFrame, Filter, Frame, PeriodicTx};
+use adaptor_sim::Simulator;
+
+use heapless::consts::U4;
+
+use std::time::{Duration, Instant};
+
+const TIMEOUT: Duration = Duration::from_millis(200);
+
+fn frame(id: u32, data: &[u8]) -> CANFrame {
+    let mut buf = [0_u8; 8];
+    buf[..data.len()].copy_from_slice(data);
+    CANFrame::new(id, data.len() as u8, buf, false, false, false)
+}
+
+#[test]
+fn settings_reach_the_firmware() {
+    let sim = Simulator::new();
+    let mut settings = AdaptorSettings::default();
+    settings.set_leds(false);
+    let mut handle = sim.open(settings).unwrap();
+    assert!(!sim.leds_enabled());
+
+    handle.set_bitrate(250_000, TIMEOUT).unwrap();
+    let timing = sim.settings().bit_timing().expect("bit timing was written");
+    assert_eq!(timing.bitrate(80_000_000), 250_000);
+    assert!(!*sim.settings().fd_enabled());
+}
+
+#[test]
+fn nothing_is_received_or_sent_while_stopped() {
+    let sim = Simulator::new();
+    let mut handle = sim.open(AdaptorSettings::default()).unwrap();
+
+    sim.inject_frame(frame(0x100, &[1]));
+    assert!(handle.read_frame(TIMEOUT).unwrap_err().is_timeout());
+    assert!(handle.write_frame(frame(0x200, &[2]), TIMEOUT).is_err());
+
+    handle.start(TIMEOUT).unwrap();
+    assert!(sim.is_running());
+    assert_eq!(
+        handle.read_frame(TIMEOUT).unwrap(),
+        Frame::from(frame(0x100, &[1]))
+    );
+    handle.write_frame(frame(0x200, &[2]), TIMEOUT).unwrap();
+    assert_eq!(
+        sim.take_transmitted(),
+        vec![Frame::from(frame(0x200, &[2]))]
+    );
+
+    handle.stop(TIMEOUT).unwrap();
+    assert!(!sim.is_running());
+}
+
+#[test]
+fn acceptance_filter() {
+    let sim = Simulator::new();
+    let mut settings = AdaptorSettings::default();
+    settings
+        .add_filter(Filter::mask(0x100, 0x700, false))
+        .unwrap();
+    let mut handle = sim.open(settings).unwrap();
+    handle.start(TIMEOUT).unwrap();
+
+    for id in &[0x123, 0x223, 0x1FF, 0x100] {
+        sim.inject_frame(frame(*id, &[]));
+    }
+
+    let ids: Vec<u32> = handle
+        .read_frames(TIMEOUT)
+        .unwrap()
+        .iter()
+        .map(|f| f.id())
+        .collect();
+    assert_eq!(ids, vec![0x123, 0x1FF, 0x100]);
+}
+
+#[test]
+fn injected_bus_errors() {
+    let sim = Simulator::new();
+    let mut handle = sim.open(AdaptorSettings::default()).unwrap();
+    handle.start(TIMEOUT).unwrap();
+
+    sim.inject_error(0x05);
+    let frame = handle.read_frame(TIMEOUT).unwrap();
+    assert!(frame.is_err());
+    assert_eq!(frame.payload()[0], 0x05);
+    assert_eq!(handle.get_error(TIMEOUT).unwrap(), 0x05);
+}
+
+#[test]
+fn scripted_traffic_keeps_its_timing() {
+    let sim = Simulator::new();
+    let mut handle = sim.open(AdaptorSettings::default()).unwrap();
+    sim.script(vec![
+        (Duration::from_millis(0), frame(0x10, &[0])),
+        (Duration::from_millis(20), frame(0x11, &[1])),
+        (Duration::from_millis(40), frame(0x12, &[2])),
+    ]);
+
+    let start = Instant::now();
+    handle.start(TIMEOUT).unwrap();
+    let mut received = Vec::new();
+    while received.len() < 3 {
+        received.extend(handle.read_received(TIMEOUT).unwrap());
+    }
+    assert!(start.elapsed() >= Duration::from_millis(40));
+
+    let ids: Vec<u32> = received.iter().map(|r| r.frame.id()).collect();
+    assert_eq!(ids, vec![0x10, 0x11, 0x12]);
+    let gap = received[2].device_time - received[0].device_time;
+    assert!((39_000..60_000).contains(&gap), "gap of {} us", gap);
+}
+
+#[test]
+fn waiting_frames_are_batched_into_one_transfer() {
+    let sim = Simulator::new();
+    let mut handle = sim.open(AdaptorSettings::default()).unwrap();
+    handle.start(TIMEOUT).unwrap();
+
+    for i in 0..6 {
+        sim.inject_frame(frame(0x300 + i, &[i as u8; 8]));
+    }
+    let frames = handle.read_frames(TIMEOUT).unwrap();
+    assert!(frames.len() > 1, "only {} frame per transfer", frames.len());
+
+    let mut ids: Vec<u32> = frames.iter().map(|f| f.id()).collect();
+    while ids.len() < 6 {
+        ids.extend(handle.read_frames(TIMEOUT).unwrap().iter().map(|f| f.id()));
+    }
+    assert_eq!(ids, (0x300..0x306).collect::<Vec<_>>());
+}
+
+#[test]
+fn batched_reads_into_a_fixed_buffer() {
+    let sim = Simulator::new();
+    let mut handle = sim.open(AdaptorSettings::default()).unwrap();
+    handle.start(TIMEOUT).unwrap();
+
+    for i in 0..6 {
+        sim.inject_frame(frame(0x400 + i, &[]));
+    }
+
+    let mut frames = heapless::Vec::<Frame, U4>::new();
+    assert_eq!(handle.read_frames_into(&mut frames, TIMEOUT).unwrap(), 4);
+    let mut ids: Vec<u32> = frames.iter().map(|f| f.id()).collect();
+
+    let mut frames = heapless::Vec::<Frame, U4>::new();
+    assert_eq!(handle.read_frames_into(&mut frames, TIMEOUT).unwrap(), 2);
+    ids.extend(frames.iter().map(|f| f.id()));
+    assert_eq!(ids, (0x400..0x406).collect::<Vec<_>>());
+}
+
+#[test]
+fn loopback_echoes_transmitted_frames() {
+    let sim = Simulator::new();
+    sim.set_loopback(true);
+    let mut handle = sim.open(AdaptorSettings::default()).unwrap();
+    handle.start(TIMEOUT).unwrap();
+
+    handle
+        .write_frame(frame(0x7DF, &[2, 1, 0]), TIMEOUT)
+        .unwrap();
+    assert_eq!(
+        handle.read_frame(TIMEOUT).unwrap(),
+        Frame::from(frame(0x7DF, &[2, 1, 0]))
+    );
+    assert_eq!(sim.transmitted().len(), 1);
+}
+
+#[test]
+fn periodic_transmissions() {
+    let sim = Simulator::new();
+    let mut handle = sim.open(AdaptorSettings::default()).unwrap();
+    let tx = PeriodicTx::new(frame(0x500, &[0xAA]), 10_000);
+    handle.set_periodic(0, &tx, TIMEOUT).unwrap();
+    assert_eq!(sim.periodic()[0].map(|tx| tx.period_us), Some(10_000));
+
+    handle.start(TIMEOUT).unwrap();
+    std::thread::sleep(Duration::from_millis(55));
+    let sent = sim.take_transmitted();
+    assert!(
+        (4..=7).contains(&sent.len()),
+        "{} transmissions",
+        sent.len()
+    );
+    assert!(sent.iter().all(|f| f.id() == 0x500));
+
+    handle.clear_periodic(0, TIMEOUT).unwrap();
+    assert!(sim.periodic()[0].is_none());
+}
@@ -0,0 +1,185 @@
+use crate::errors::SocketError;
+use crate::socket::CanSocket;
+use crate::Result;
+
+use adaptor_core::threaded::{FrameReceiver, FrameSender, Worker};
+use adaptor_core::{AdaptorError, AdaptorHandle, Transport, UsbTransport};
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How long each side blocks waiting for a frame before checking for shutdown
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Default)]
+struct Counters {
+    to_socket: AtomicUsize,
+    to_probe: AtomicUsize,
+    rejected: AtomicUsize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BridgeStats {
+    /// Frames received by the probe and written to the interface
+    pub to_socket: usize,
+
+    /// Frames read from the interface and queued for the probe
+    pub to_probe: usize,
+
+    /// Frames the interface refused, e.g. FD frames on a classic interface
+    pub rejected: usize,
+
+    /// Frames dropped because the worker's receive queue was full
+    pub dropped: usize,
+}
+
+/// Copies frames between a probe and a SocketCAN interface in both directions.
+///
+/// The probe's handle is run by a `Worker`, with one thread forwarding what
+/// it receives to the socket and another queueing what the socket reads for
+/// the probe. The handle should be configured and started beforehand, and
+/// `shutdown` gives it back.
+pub struct Bridge<T: Transport + Send + 'static = UsbTransport> {
+    worker: Option<Worker<T>>,
+    shutdown: Arc<AtomicBool>,
+    counters: Arc<Counters>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl<T: Transport + Send + 'static> Bridge<T> {
+    pub fn spawn(handle: AdaptorHandle<T>, socket: CanSocket) -> Result<Self> {
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+
+        let worker = Worker::spawn(handle);
+        let socket = Arc::new(socket);
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let counters = Arc::new(Counters::default());
+
+        let to_socket = {
+            let receiver = worker.receiver();
+            let socket = socket.clone();
+            let shutdown = shutdown.clone();
+            let counters = counters.clone();
+            thread::Builder::new()
+                .name("bridge-to-socket".to_owned())
+                .spawn(move || probe_to_socket(&receiver, &socket, &shutdown, &counters))
+                .expect("Failed to spawn bridge thread")
+        };
+
+        let to_probe = {
+            let sender = worker.sender();
+            let shutdown = shutdown.clone();
+            let counters = counters.clone();
+            thread::Builder::new()
+                .name("bridge-to-probe".to_owned())
+                .spawn(move || socket_to_probe(&socket, &sender, &shutdown, &counters))
+                .expect("Failed to spawn bridge thread")
+        };
+
+        Ok(Self {
+            worker: Some(worker),
+            shutdown,
+            counters,
+            threads: vec![to_socket, to_probe],
+        })
+    }
+
+    /// False once either direction has stopped, e.g. because the probe was
+    /// unplugged or the interface went down
+    pub fn is_running(&self) -> bool {
+        !self.shutdown.load(Ordering::Relaxed)
+    }
+
+    pub fn stats(&self) -> BridgeStats {
+        BridgeStats {
+            to_socket: self.counters.to_socket.load(Ordering::Relaxed),
+            to_probe: self.counters.to_probe.load(Ordering::Relaxed),
+            rejected: self.counters.rejected.load(Ordering::Relaxed),
+            dropped: self.worker.as_ref().map_or(0, |worker| worker.dropped()),
+        }
+    }
+
+    /// Stops both directions and returns the probe's handle.
+    /// Returns `None` if the worker thread panicked.
+    pub fn shutdown(mut self) -> Option<AdaptorHandle<T>> {
+        self.stop()
+    }
+
+    fn stop(&mut self) -> Option<AdaptorHandle<T>> {
+        self.shutdown.store(true, Ordering::Relaxed);
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+        self.worker.take().and_then(Worker::shutdown)
+    }
+}
+
+impl<T: Transport + Send + 'static> Drop for Bridge<T> {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+fn probe_to_socket(
+    receiver: &FrameReceiver,
+    socket: &CanSocket,
+    shutdown: &AtomicBool,
+    counters: &Counters,
+) {
+    while !shutdown.load(Ordering::Relaxed) {
+        let received = match receiver.recv_timeout(POLL_INTERVAL) {
+            Ok(received) => received,
+            Err(e) if e.is_timeout() => continue,
+            Err(AdaptorError::ConnectionError) => {
+                log::error!("Worker stopped, stopping bridge");
+                break;
+            }
+            Err(e) => {
+                log::error!("Failed to receive from the probe: {}", e);
+                break;
+            }
+        };
+
+        match socket.write_frame(&received.frame) {
+            Ok(()) => {
+                counters.to_socket.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => {
+                counters.rejected.fetch_add(1, Ordering::Relaxed);
+                log::debug!("Interface rejected {:?}: {}", received.frame, e);
+            }
+        }
+    }
+    shutdown.store(true, Ordering::Relaxed);
+}
+
+fn socket_to_probe(
+    socket: &CanSocket,
+    sender: &FrameSender,
+    shutdown: &AtomicBool,
+    counters: &Counters,
+) {
+    while !shutdown.load(Ordering::Relaxed) {
+        let frame = match socket.read_frame() {
+            Ok(frame) => frame,
+            Err(e) if e.is_timeout() => continue,
+            Err(SocketError::InvalidFrame(msg)) => {
+                log::warn!("Skipping frame from the interface: {}", msg);
+                continue;
+            }
+            Err(e) => {
+                log::error!("Failed to read from the interface: {}", e);
+                break;
+            }
+        };
+
+        if sender.send(frame).is_err() {
+            log::error!("Worker stopped, stopping bridge");
+            break;
+        }
+        counters.to_probe.fetch_add(1, Ordering::Relaxed);
+    }
+    shutdown.store(true, Ordering::Relaxed);
+}
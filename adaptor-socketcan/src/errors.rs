@@ -0,0 +1,30 @@
+use quick_error::quick_error;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum SocketError {
+        IoError(err: std::io::Error) {
+            from()
+            display("{}", err)
+        }
+        AdaptorError(err: adaptor_core::AdaptorError) {
+            from()
+            display("{}", err)
+        }
+        InvalidFrame(msg: String) {
+            display("{}", msg)
+        }
+    }
+}
+
+impl SocketError {
+    /// Whether a read gave up because nothing arrived within the socket's read timeout
+    pub fn is_timeout(&self) -> bool {
+        matches!(
+            self,
+            SocketError::IoError(e)
+                if e.kind() == std::io::ErrorKind::WouldBlock
+                    || e.kind() == std::io::ErrorKind::TimedOut
+        )
+    }
+}
@@ -0,0 +1,20 @@
+//! Bridges a probe to a Linux SocketCAN interface, so tools written for
+//! SocketCAN such as can-utils and python-can can use it unchanged.
+//!
+//! Any CAN interface works, the usual setup is a virtual one:
+//!
+//! ```text
+//! ip link add dev vcan0 type vcan
+//! ip link set vcan0 mtu 72    # only needed for CAN FD
+//! ip link set up vcan0
+//! ```
+
+pub mod bridge;
+pub mod errors;
+pub mod socket;
+
+pub type Result<T> = std::result::Result<T, errors::SocketError>;
+
+pub use bridge::{Bridge, BridgeStats};
+pub use errors::SocketError;
+pub use socket::CanSocket;
@@ -0,0 +1,113 @@
+use adaptor_core::{AdaptorHandle, AdaptorSettings};
+use adaptor_socketcan::{Bridge, CanSocket};
+
+use structopt::StructOpt;
+
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Parses a USB location given as `<bus>:<address>`
+fn parse_path(s: &str) -> Result<(u8, u8), String> {
+    let invalid = || format!("'{}' is not a <bus>:<address> path", s);
+    let mut parts = s.splitn(2, ':');
+    let bus = parts
+        .next()
+        .and_then(|p| p.parse().ok())
+        .ok_or_else(invalid)?;
+    let address = parts
+        .next()
+        .and_then(|p| p.parse().ok())
+        .ok_or_else(invalid)?;
+    Ok((bus, address))
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "can-probe-socketcan",
+    about = "Bridges a probe to a SocketCAN interface such as vcan0"
+)]
+struct Opt {
+    /// SocketCAN interface to bridge to, e.g. vcan0
+    interface: String,
+
+    /// Open the probe with this USB serial number
+    #[structopt(short, long)]
+    serial: Option<String>,
+
+    /// Open the probe at this <bus>:<address>
+    #[structopt(short, long, parse(try_from_str = parse_path))]
+    path: Option<(u8, u8)>,
+
+    /// Nominal bit rate in bits per second
+    #[structopt(short, long)]
+    bitrate: Option<u32>,
+
+    /// CAN FD data phase bit rate in bits per second, enables FD
+    #[structopt(short, long)]
+    data_bitrate: Option<u32>,
+
+    /// Enable CAN FD keeping the probe's default data bit rate
+    #[structopt(long)]
+    fd: bool,
+}
+
+fn open(opt: &Opt) -> Result<AdaptorHandle, Box<dyn Error>> {
+    let settings = AdaptorSettings::default();
+    let mut handle = match (&opt.serial, opt.path) {
+        (Some(serial), _) => AdaptorHandle::open_by_serial(serial, settings)?,
+        (None, Some((bus, address))) => AdaptorHandle::open_by_path(bus, address, settings)?,
+        (None, None) => AdaptorHandle::new(settings)?,
+    };
+
+    if let Some(bitrate) = opt.bitrate {
+        handle.set_bitrate(bitrate, TIMEOUT)?;
+    }
+    if let Some(bitrate) = opt.data_bitrate {
+        handle.set_data_bitrate(bitrate, TIMEOUT)?;
+    } else if opt.fd {
+        handle.modify_settings(TIMEOUT, |s| {
+            s.set_fd_enabled(true);
+        })?;
+    }
+    Ok(handle)
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    env_logger::init();
+
+    let opt = Opt::from_args();
+    let socket = CanSocket::open(&opt.interface)?;
+    if (opt.fd || opt.data_bitrate.is_some()) && !socket.fd_frames() {
+        log::warn!("Kernel does not support CAN FD sockets, FD frames will not be bridged");
+    }
+
+    let mut handle = open(&opt)?;
+    handle.start(TIMEOUT)?;
+
+    let stop = Arc::new(AtomicBool::new(false));
+    {
+        let stop = stop.clone();
+        ctrlc::set_handler(move || stop.store(true, Ordering::Relaxed))?;
+    }
+
+    let bridge = Bridge::spawn(handle, socket)?;
+    log::info!("Bridging probe to {}", opt.interface);
+
+    while bridge.is_running() && !stop.load(Ordering::Relaxed) {
+        std::thread::sleep(Duration::from_millis(100));
+    }
+
+    let stats = bridge.stats();
+    if let Some(mut handle) = bridge.shutdown() {
+        handle.stop(TIMEOUT)?;
+    }
+    println!(
+        "{} frames to {}, {} frames to the probe, {} rejected, {} dropped",
+        stats.to_socket, opt.interface, stats.to_probe, stats.rejected, stats.dropped
+    );
+    Ok(())
+}
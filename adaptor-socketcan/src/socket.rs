@@ -0,0 +1,171 @@
+use crate::errors::SocketError;
+use crate::Result;
+
+use adaptor_common::socketcan::{self, IdOrder, CANFD_MTU, CAN_MTU};
+use adaptor_common::Frame;
+
+use std::ffi::CString;
+use std::io;
+use std::mem::size_of;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Duration;
+
+const PF_CAN: libc::c_int = 29;
+const CAN_RAW: libc::c_int = 1;
+const SOL_CAN_RAW: libc::c_int = 101;
+const CAN_RAW_FD_FRAMES: libc::c_int = 5;
+
+/// `struct sockaddr_can`, the address union is unused by raw sockets
+#[repr(C)]
+struct SockaddrCan {
+    can_family: libc::sa_family_t,
+    can_ifindex: libc::c_int,
+    addr: [u64; 2],
+}
+
+/// Decodes a `can_frame` or `canfd_frame` read from a socket
+fn decode_frame(data: &[u8]) -> Result<Frame> {
+    if data.len() != CAN_MTU && data.len() != CANFD_MTU {
+        return Err(SocketError::InvalidFrame(format!(
+            "read {} bytes, not a CAN or CAN FD frame",
+            data.len()
+        )));
+    }
+    socketcan::decode(data, IdOrder::Host).map_err(|e| SocketError::InvalidFrame(e.to_string()))
+}
+
+fn check(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+/// Raw SocketCAN socket bound to one interface, receiving every frame on it
+/// except the ones it sent itself
+#[derive(Debug)]
+pub struct CanSocket {
+    fd: RawFd,
+
+    /// Whether the socket accepted `CAN_RAW_FD_FRAMES`
+    fd_frames: bool,
+}
+
+impl CanSocket {
+    /// Opens a raw socket on `interface`, e.g. `vcan0`, with FD frames
+    /// enabled when the kernel supports them
+    pub fn open(interface: &str) -> Result<Self> {
+        let name = CString::new(interface).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "interface name contains a nul byte",
+            )
+        })?;
+        let ifindex = unsafe { libc::if_nametoindex(name.as_ptr()) };
+        if ifindex == 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        let fd =
+            check(unsafe { libc::socket(PF_CAN, libc::SOCK_RAW | libc::SOCK_CLOEXEC, CAN_RAW) })?;
+        let mut socket = Self {
+            fd,
+            fd_frames: false,
+        };
+
+        let enable: libc::c_int = 1;
+        socket.fd_frames = unsafe {
+            libc::setsockopt(
+                fd,
+                SOL_CAN_RAW,
+                CAN_RAW_FD_FRAMES,
+                &enable as *const _ as *const libc::c_void,
+                size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        } == 0;
+
+        let addr = SockaddrCan {
+            can_family: PF_CAN as libc::sa_family_t,
+            can_ifindex: ifindex as libc::c_int,
+            addr: [0; 2],
+        };
+        check(unsafe {
+            libc::bind(
+                fd,
+                &addr as *const _ as *const libc::sockaddr,
+                size_of::<SockaddrCan>() as libc::socklen_t,
+            )
+        })?;
+
+        Ok(socket)
+    }
+
+    /// Whether FD frames can be sent and received, which also needs the
+    /// interface's MTU to be 72, e.g. `ip link set vcan0 mtu 72`
+    pub fn fd_frames(&self) -> bool {
+        self.fd_frames
+    }
+
+    /// Makes `read_frame` give up after `timeout`, `None` blocks forever
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        let timeout = timeout.unwrap_or_default();
+        let tv = libc::timeval {
+            tv_sec: timeout.as_secs() as libc::time_t,
+            tv_usec: timeout.subsec_micros() as libc::suseconds_t,
+        };
+        check(unsafe {
+            libc::setsockopt(
+                self.fd,
+                libc::SOL_SOCKET,
+                libc::SO_RCVTIMEO,
+                &tv as *const _ as *const libc::c_void,
+                size_of::<libc::timeval>() as libc::socklen_t,
+            )
+        })?;
+        Ok(())
+    }
+
+    pub fn read_frame(&self) -> Result<Frame> {
+        let mut buf = [0_u8; CANFD_MTU];
+        let bytes =
+            unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        if bytes < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        decode_frame(&buf[..bytes as usize])
+    }
+
+    pub fn write_frame(&self, frame: &Frame) -> Result<()> {
+        if frame.is_fd() && !self.fd_frames {
+            return Err(SocketError::InvalidFrame(
+                "the socket does not support CAN FD frames".to_owned(),
+            ));
+        }
+
+        let mut buf = [0_u8; CANFD_MTU];
+        let len = socketcan::encode(frame, IdOrder::Host, &mut buf);
+        let bytes = unsafe { libc::write(self.fd, buf.as_ptr() as *const libc::c_void, len) };
+        if bytes < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        if bytes as usize != len {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "frame partially written").into());
+        }
+        Ok(())
+    }
+}
+
+impl AsRawFd for CanSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for CanSocket {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
@@ -0,0 +1,77 @@
+//! Bridges a simulated probe to `vcan0`. The test is ignored by default as
+//! it needs the interface, set it up and run the ignored tests with:
+//!
+//! ```text
+//! sudo modprobe vcan
+//! sudo ip link add dev vcan0 type vcan && sudo ip link set up vcan0
+//! cargo test -p adaptor-socketcan -- --ignored
+//! ```
+
+use adaptor_common::{AdaptorSettings, CANFrame, Frame};
+use adaptor_sim::Simulator;
+use adaptor_socketcan::{Bridge, CanSocket};
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+const INTERFACE: &str = "vcan0";
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+fn open_socket() -> CanSocket {
+    CanSocket::open(INTERFACE)
+        .unwrap_or_else(|e| panic!("{} is unavailable, see the module docs: {}", INTERFACE, e))
+}
+
+fn wait_for<F: FnMut() -> bool>(mut done: F) -> bool {
+    let start = Instant::now();
+    while start.elapsed() < TIMEOUT {
+        if done() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    false
+}
+
+#[test]
+#[ignore = "needs a vcan0 interface"]
+fn bridges_frames_both_ways() {
+    let bridge_socket = open_socket();
+    let peer = open_socket();
+    peer.set_read_timeout(Some(TIMEOUT)).unwrap();
+
+    let sim = Simulator::new();
+    let mut handle = sim.open(AdaptorSettings::default()).unwrap();
+    handle.start(TIMEOUT).unwrap();
+    let bridge = Bridge::spawn(handle, bridge_socket).unwrap();
+
+    // probe to interface
+    let received = CANFrame::new(
+        0x123,
+        2,
+        [0xAB, 0xCD, 0, 0, 0, 0, 0, 0],
+        false,
+        false,
+        false,
+    );
+    sim.inject_frame(received);
+    assert_eq!(peer.read_frame().unwrap(), Frame::from(received));
+
+    // interface to probe
+    let sent = CANFrame::new(
+        0x1ABC_DEF0,
+        1,
+        [0x42, 0, 0, 0, 0, 0, 0, 0],
+        false,
+        false,
+        true,
+    );
+    peer.write_frame(&Frame::from(sent)).unwrap();
+    assert!(wait_for(|| !sim.transmitted().is_empty()));
+    assert_eq!(sim.transmitted(), vec![Frame::from(sent)]);
+
+    let stats = bridge.stats();
+    assert_eq!((stats.to_socket, stats.to_probe), (1, 1));
+    let handle = bridge.shutdown().expect("worker exited cleanly");
+    assert!(*handle.running());
+}